@@ -12,13 +12,17 @@ fn main() {
                     .expect("Couldn't parse target triple!");
 
 
-        let boot_path = if arch_name == "x86_64" {
-            format!("boot/target/x86_32-sos-bootstrap-gnu/{}/", profile)
-        } else {
-            panic!("target arch {} not yet supported, sorry!", arch_name);
-        };
-        println!("cargo:rustc-link-search=native={}", boot_path);
-        println!("cargo:rustc-link-lib=static=boot");
+        match arch_name {
+            "x86_64" => {
+                let boot_path = format!( "boot/target/{}/{}/"
+                                       , "x86_32-sos-bootstrap-gnu", profile);
+                println!("cargo:rustc-link-search=native={}", boot_path);
+                println!("cargo:rustc-link-lib=static=boot");
+            }
+            // the 32-bit kernel boots itself, so it needs no bootstrap lib
+          , "x86_32" => { }
+          , _ => panic!("target arch {} not yet supported, sorry!", arch_name)
+        }
     }
 
 
@@ -14,7 +14,9 @@
 #![feature(slice_patterns)]
 #![feature(linkage)]
 #![feature(stmt_expr_attributes)]
-#![cfg_attr(target_arch = "x86_64", feature(abi_x86_interrupt))]
+#![feature(repr_align, attr_literals)]
+#![cfg_attr( any(target_arch = "x86_64", target_arch = "x86")
+           , feature(abi_x86_interrupt))]
 #![no_std]
 
 //-- non-SOS dependencies ----------------------------------------------------
@@ -31,12 +33,11 @@ use core::marker::PhantomData;
 
 //-- re-exports --------------------------------------------------------------
 // 64-bit x86_64 (long mode)
-#[cfg(target_arch="x86_64")] mod x86_64;
+#[cfg(target_arch="x86_64")] #[macro_use] mod x86_64;
 #[cfg(target_arch="x86_64")] pub use self::x86_64::*;
 
 // 32-bit x86 (protected mode)
-// TODO: NYI
-#[cfg(target_arch = "x86")] mod x86;
+#[cfg(target_arch = "x86")] #[macro_use] mod x86;
 #[cfg(target_arch = "x86")] pub use self::x86::*;
 
 // ARM v7
@@ -0,0 +1,231 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! `x86` execution contexts.
+//!
+//! These mirror the `x86_64` ones, with the 32-bit general-purpose registers.
+
+use core::fmt;
+use super::flags::{Flags as EFlags};
+use super::segment;
+
+/// Registers pushed to the stack when handling an interrupt or context switch.
+///
+/// This contains all of the general-purpose registers except for `%esp`, which
+/// is saved separately.
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+pub struct Registers { pub edi: u32
+                     , pub esi: u32
+                     , pub ebp: u32
+                     , pub ebx: u32
+                     , pub edx: u32
+                     , pub ecx: u32
+                     , pub eax: u32
+                     }
+
+impl Registers {
+    /// Create a new empty set of Registers
+    pub const fn empty() -> Self {
+        Registers { edi: 0, esi: 0, ebp: 0
+                  , ebx: 0, edx: 0, ecx: 0
+                  , eax: 0
+                  }
+    }
+
+    /// Returns the saved frame pointer (`%ebp`).
+    #[inline] pub fn frame_pointer(&self) -> usize { self.ebp as usize }
+
+    /// Push the general-purpose registers to the stack
+    /// (such as when handling a context switch or interrupt).
+    ///
+    /// THIS FUNCTION IS NAKED. DO NOT CALL IT NORMALLY.
+    #[naked]
+    #[inline(always)]
+    pub unsafe fn push() {
+        asm!( "push eax
+               push ecx
+               push edx
+               push ebx
+               push ebp
+               push esi
+               push edi"
+            :::: "intel"
+               , "volatile");
+    }
+
+    /// Pop the general-purpose registers off the stack
+    /// (such as when handling a context switch or interrupt).
+    ///
+    /// THIS FUNCTION IS NAKED. DO NOT CALL IT NORMALLY.
+    #[naked]
+    #[inline(always)]
+    pub unsafe fn pop() {
+        asm!( "pop edi
+               pop esi
+               pop ebp
+               pop ebx
+               pop edx
+               pop ecx
+               pop eax"
+            :::: "intel"
+               , "volatile");
+    }
+}
+
+impl fmt::Debug for Registers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!( f
+              , "    EAX: {:#010x} EBX: {:#010x} ECX: {:#010x}\n    \
+                     EDX: {:#010x} ESI: {:#010x} EDI: {:#010x}\n    \
+                     EBP: {:#010x}"
+              , self.eax, self.ebx, self.ecx
+              , self.edx, self.esi, self.edi
+              , self.ebp)
+    }
+}
+
+/// The frame pushed by the CPU when it handles an interrupt.
+///
+/// In protected mode, `%esp` and `%ss` are only pushed if the interrupt
+/// changed privilege levels. We never take interrupts from user mode yet, so
+/// they're not included here.
+#[repr(C, packed)]
+pub struct InterruptFrame {
+    /// Value of the instruction pointer (`$eip`) register
+    pub eip: *const u8
+  , /// Value of the code segment (`$cs`) register
+    pub cs: segment::Selector
+  , __pad_1: u16
+  , /// Value of the CPU flags (`$eflags`) register
+    pub eflags: EFlags
+}
+
+impl InterruptFrame {
+    /// Returns the instruction pointer at the time of the interrupt.
+    #[inline] pub fn instruction_pointer(&self) -> *const u8 { self.eip }
+}
+
+impl fmt::Debug for InterruptFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!( f
+              , "Interrupt Frame: \
+                \n   instruction pointer: {:p} \
+                \n   code segment:        {} \
+                \n   eflags:              {:?}"
+             , self.eip
+             , self.cs
+             , self.eflags)
+    }
+}
+
+/// Thread execution context
+///
+/// This is saved by [`switch`](fn.switch.html) when a thread is switched
+/// away from, and loaded when it's switched back to. Only the callee-saved
+/// registers are saved, since `switch` is called like any other function.
+#[repr(C, packed)]
+pub struct Context { /// Value of the stack pointer (`esp`) register
+                     pub esp: *mut u8
+                   , /// Value of the general-purpose registers
+                     pub registers: Registers
+                   , /// Value of the instruction pointer (`eip`) register
+                     pub eip: *mut u8
+                   }
+
+impl Context {
+    /// Returns an empty `Context`.
+    ///
+    /// This is suitable for a thread which is already running, since its
+    /// context will be filled in when it's switched away from.
+    pub const fn empty() -> Self {
+        Context { esp: 0 as *mut u8
+                , registers: Registers::empty()
+                , eip: 0 as *mut u8
+                }
+    }
+
+    /// Returns a `Context` which will call `entry(arg)` on the stack ending
+    /// at `stack_top` when it's switched to.
+    pub fn new( stack_top: *mut u8
+              , entry: extern "C" fn(usize) -> !
+              , arg: usize)
+              -> Self {
+        let mut registers = Registers::empty();
+        registers.ebx = entry as u32;
+        registers.esi = arg as u32;
+        Context { esp: (stack_top as usize & !0xf) as *mut u8
+                , registers: registers
+                , eip: start as *mut u8
+                }
+    }
+}
+
+/// Entry point for a new `Context`.
+///
+/// `Context::new` leaves the entry point in `%ebx` and its argument in
+/// `%esi`. The frame pointer is zeroed so that backtraces stop here, and the
+/// stack is kept 16-byte aligned at the call.
+///
+/// THIS FUNCTION IS NAKED. DO NOT CALL IT NORMALLY.
+#[naked]
+unsafe extern "C" fn start() {
+    asm!( "xor ebp, ebp
+           sub esp, 12
+           push esi
+           call ebx
+           ud2"
+        :::: "intel", "volatile");
+}
+
+/// Save the current context into `from`, and switch to `to`.
+///
+/// When `from` is switched back to, this returns to its caller as though
+/// nothing happened. Offsets into `Context` are hardcoded, so its layout
+/// must not change without changing this too.
+///
+/// # Safety
+/// + `to` must be a context saved by `switch` or created by
+///   [`Context::new`](struct.Context.html#method.new), and its stack must
+///   still be mapped.
+/// + Interrupts should be disabled.
+#[naked]
+#[inline(never)]
+pub unsafe extern "C" fn switch(_from: *mut Context, _to: *const Context) {
+    // the arguments are on the stack, above the return address. the caller
+    // pops them, so the stack pointer after returning points at `from`.
+    asm!( "mov eax, [esp + 4]
+           mov edx, [esp + 8]
+           mov ecx, [esp]
+           mov [eax + 32], ecx
+           lea ecx, [esp + 4]
+           mov [eax], ecx
+           mov [eax + 4], edi
+           mov [eax + 8], esi
+           mov [eax + 12], ebp
+           mov [eax + 16], ebx
+           mov esp, [edx]
+           mov edi, [edx + 4]
+           mov esi, [edx + 8]
+           mov ebp, [edx + 12]
+           mov ebx, [edx + 16]
+           jmp dword ptr [edx + 32]"
+        :::: "intel", "volatile");
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn test_context_correct_size() {
+        use core::mem::size_of;
+        use super::Context;
+
+        // `switch` hardcodes these offsets
+        assert_eq!(size_of::<Context>(), 36);
+    }
+}
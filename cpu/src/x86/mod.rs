@@ -6,11 +6,17 @@
 //  Released under the terms of the MIT license. See `LICENSE` in the root
 //  directory of this repository for more information.
 //
-//! `x86` architecture-specific implementation.
+//! Code for interacting with the `x86` CPU.
 //!
-//! This module contains code for `x86` 32-bit protected-mode systems.
-pub mod cpu;
-pub mod memory;
-// pub mod keyboard;
+//! This module contains code for 32-bit protected-mode systems: interrupt
+//! and thread contexts and (single-CPU) per-CPU data, plus everything shared
+//! with `x86_64` (from `x86_all`).
 
-pub const ARCH_BITS: u8 = 32;
+// `percpu!` must be defined before anything in `x86_all` can use it
+#[macro_use] pub mod percpu;
+#[path = "../x86_all/mod.rs"] mod cpu_all;
+
+pub mod context;
+
+pub use self::context::Registers;
+pub use self::cpu_all::*;
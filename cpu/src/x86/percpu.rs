@@ -0,0 +1,123 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Per-CPU data.
+//!
+//! We only ever run on one CPU on `x86`, so a per-CPU variable is just a
+//! `static`. This has the same interface as the `x86_64` version, so code
+//! using per-CPU variables doesn't have to care which one it gets.
+#![warn(missing_docs)]
+use core::cell::UnsafeCell;
+
+use interrupts;
+
+/// Proof that the current thread can't migrate to another CPU.
+///
+/// This is unsafe to implement, since per-CPU variables can be accessed
+/// safely by anyone holding a `Pinned` value.
+pub unsafe trait Pinned { }
+
+unsafe impl Pinned for interrupts::Guard { }
+
+/// Set up the per-CPU data for CPU number `cpu`.
+///
+/// There's nothing to set up, so this always succeeds.
+#[inline]
+pub unsafe fn initialize(_cpu: usize) -> Result<(), &'static str> { Ok(()) }
+
+/// Returns true if this CPU's per-CPU data has been set up, which it always
+/// has.
+#[inline]
+pub fn is_initialized() -> bool { true }
+
+/// Returns the current CPU's number, which is always 0.
+#[inline]
+pub unsafe fn cpu() -> usize { 0 }
+
+/// A per-CPU variable.
+///
+/// These should be declared with the [`percpu!`](../macro.percpu.html)
+/// macro, rather than constructed directly.
+pub struct PerCpu<T> { value: UnsafeCell<T> }
+
+unsafe impl<T: Send> Sync for PerCpu<T> { }
+
+impl<T> PerCpu<T> {
+    #[doc(hidden)]
+    pub const fn new(initial: T) -> Self {
+        PerCpu { value: UnsafeCell::new(initial) }
+    }
+
+    /// Returns a pointer to the current CPU's copy of this variable.
+    #[inline]
+    pub unsafe fn as_ptr(&'static self) -> *mut T { self.value.get() }
+
+    /// Returns a reference to CPU number `cpu`'s copy of this variable.
+    ///
+    /// # Panics
+    /// + If `cpu` isn't 0.
+    pub fn on(&'static self, cpu: usize) -> &'static T
+    where T: Sync {
+        assert!(cpu == 0, "CPU {}'s per-CPU block isn't initialized", cpu);
+        unsafe { &*self.as_ptr() }
+    }
+
+    /// Returns a reference to the current CPU's copy of this variable.
+    #[inline]
+    pub fn get<'a, P: Pinned>(&'static self, _pinned: &'a P) -> &'a T {
+        unsafe { &*self.as_ptr() }
+    }
+
+    /// Call `f` with a mutable reference to the current CPU's copy of this
+    /// variable.
+    ///
+    /// `f` must not access this variable itself.
+    #[inline]
+    pub fn with_mut<P, F, R>(&'static self, _pinned: &P, f: F) -> R
+    where P: Pinned
+        , F: FnOnce(&mut T) -> R {
+        f(unsafe { &mut *self.as_ptr() })
+    }
+}
+
+impl<T: Copy> PerCpu<T> {
+    /// Returns the value of the current CPU's copy of this variable.
+    #[inline]
+    pub fn read<P: Pinned>(&'static self, pinned: &P) -> T {
+        *self.get(pinned)
+    }
+
+    /// Set the current CPU's copy of this variable to `value`.
+    #[inline]
+    pub fn write<P: Pinned>(&'static self, pinned: &P, value: T) {
+        self.with_mut(pinned, |v| *v = value)
+    }
+}
+
+/// Declare per-CPU variables.
+///
+/// Each declaration looks like a `static`, but declares a
+/// [`PerCpu`](percpu/struct.PerCpu.html) holding a value of the given type.
+#[macro_export]
+macro_rules! percpu {
+    ( $(#[$attr:meta])* static $name:ident: $ty:ty = $init:expr;
+      $($tail:tt)* ) => {
+        $(#[$attr])*
+        static $name: $crate::percpu::PerCpu<$ty>
+            = $crate::percpu::PerCpu::new($init);
+        percpu! { $($tail)* }
+    };
+    ( $(#[$attr:meta])* pub static $name:ident: $ty:ty = $init:expr;
+      $($tail:tt)* ) => {
+        $(#[$attr])*
+        pub static $name: $crate::percpu::PerCpu<$ty>
+            = $crate::percpu::PerCpu::new($init);
+        percpu! { $($tail)* }
+    };
+    () => {};
+}
@@ -0,0 +1,219 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Support for the local Advanced Programmable Interrupt Controller (APIC).
+//!
+//! Every core has its own local APIC, which receives interrupts from the
+//! I/O APIC and other cores, and which contains a high-resolution timer.
+//! We currently drive the local APIC in xAPIC mode, through its memory-mapped
+//! register page.
+//!
+//! Before calling [`initialize`](fn.initialize.html), the kernel must
+//! identity-map the APIC's register page (see [`base`](fn.base.html)) as
+//! uncacheable.
+//!
+//! See "Chapter 10: Advanced Programmable Interrupt Controller (APIC)" in
+//! the _Intel® 64 and IA-32 Architectures Software Developer’s Manual_.
+#![warn(missing_docs)]
+use memory::PAddr;
+use ::{cpuid, interrupts, msr};
+
+use core::ptr;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+pub mod timer;
+
+/// Interrupt vector for spurious interrupts from the local APIC.
+///
+/// The low four bits of this must be set on some older processors.
+pub const SPURIOUS_VECTOR: u8 = 0xef;
+
+/// Spurious-vector register bit that software-enables the local APIC.
+const APIC_SOFTWARE_ENABLE: u32 = 1 << 8;
+
+/// Interrupt command register bit set while an IPI is being sent.
+const ICR_DELIVERY_PENDING: u32 = 1 << 12;
+/// Interrupt command register bit asserting a level-triggered IPI.
+const ICR_LEVEL_ASSERT: u32 = 1 << 14;
+/// Interrupt command register bit selecting level-triggered delivery.
+const ICR_TRIGGER_LEVEL: u32 = 1 << 15;
+/// Interrupt command register delivery mode for INIT IPIs.
+const ICR_INIT: u32 = 0b101 << 8;
+/// Interrupt command register delivery mode for startup IPIs.
+const ICR_STARTUP: u32 = 0b110 << 8;
+
+/// Virtual address of the APIC register page, or 0 if uninitialized.
+static BASE: AtomicUsize = AtomicUsize::new(0);
+
+/// Offsets of local APIC registers from the APIC base address.
+#[repr(usize)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Register { Id                = 0x020
+              , Version           = 0x030
+              , TaskPriority      = 0x080
+              , EndOfInterrupt    = 0x0b0
+              , SpuriousVector    = 0x0f0
+              , ErrorStatus       = 0x280
+              , InterruptCommand  = 0x300
+              , InterruptDest     = 0x310
+              , LvtTimer          = 0x320
+              , TimerInitialCount = 0x380
+              , TimerCurrentCount = 0x390
+              , TimerDivide       = 0x3e0
+              }
+
+/// Returns true if this CPU has a local APIC.
+#[inline]
+pub fn is_available() -> bool {
+    cpuid::has(cpuid::APIC)
+}
+
+/// Returns the physical address of the local APIC's register page.
+#[inline]
+pub fn base() -> PAddr {
+    unsafe { msr::apic_base::address() }
+}
+
+/// Read a local APIC register.
+#[inline]
+unsafe fn read(reg: Register) -> u32 {
+    let addr = BASE.load(Ordering::Relaxed) + reg as usize;
+    ptr::read_volatile(addr as *const u32)
+}
+
+/// Write to a local APIC register.
+#[inline]
+unsafe fn write(reg: Register, value: u32) {
+    let addr = BASE.load(Ordering::Relaxed) + reg as usize;
+    ptr::write_volatile(addr as *mut u32, value)
+}
+
+/// Returns true if the local APIC has been initialized.
+#[inline]
+pub fn is_initialized() -> bool {
+    BASE.load(Ordering::Relaxed) != 0
+}
+
+/// Enable the local APIC on the current CPU.
+///
+/// # Safety
+/// + The APIC register page (see [`base`](fn.base.html)) must be
+///   identity-mapped and uncacheable.
+pub unsafe fn initialize() -> Result<(), &'static str> {
+    if !is_available() {
+        return Err("CPU does not have a local APIC")
+    }
+    msr::apic_base::insert(msr::apic_base::GLOBAL_ENABLE);
+    BASE.store(*base() as usize, Ordering::SeqCst);
+
+    // accept all interrupts, and software-enable the APIC
+    write(Register::TaskPriority, 0);
+    write( Register::SpuriousVector
+         , APIC_SOFTWARE_ENABLE | SPURIOUS_VECTOR as u32);
+    // the error status register must be written before it is read
+    write(Register::ErrorStatus, 0);
+
+    debug!( "Local APIC {} enabled at {:?}, version {:#x}"
+          , id(), base(), read(Register::Version) & 0xff);
+    Ok(())
+}
+
+/// Returns the ID of the current CPU's local APIC.
+#[inline]
+pub fn id() -> u8 {
+    (unsafe { read(Register::Id) } >> 24) as u8
+}
+
+/// Signal the end of an interrupt to the local APIC.
+///
+/// This must be called at the end of every handler for an interrupt that was
+/// delivered by the local APIC, except for spurious interrupts.
+#[inline]
+pub fn end_of_interrupt() {
+    unsafe { write(Register::EndOfInterrupt, 0) }
+}
+
+/// The destination of an inter-processor interrupt.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Destination { /// the CPU with the given local APIC ID
+                       Apic(u8)
+                     , /// the current CPU
+                       Current
+                     , /// every CPU, including the current one
+                       All
+                     , /// every CPU except the current one
+                       Others
+                     }
+
+impl Destination {
+    /// Returns the destination field and shorthand bits of the interrupt
+    /// command register for this destination.
+    fn command(&self) -> (u32, u32) {
+        match *self {
+            Destination::Apic(id) => ((id as u32) << 24, 0b00 << 18)
+          , Destination::Current => (0, 0b01 << 18)
+          , Destination::All => (0, 0b10 << 18)
+          , Destination::Others => (0, 0b11 << 18)
+        }
+    }
+}
+
+/// Write `command` to the interrupt command register, sending an IPI to
+/// `dest`.
+///
+/// This waits until the IPI has been delivered.
+unsafe fn send_command(dest: Destination, command: u32) {
+    // an interrupt handler sending an IPI between the two writes would
+    // clobber the destination
+    let _guard = interrupts::disable();
+    let (dest_field, shorthand) = dest.command();
+    write(Register::InterruptDest, dest_field);
+    write(Register::InterruptCommand, command | shorthand);
+    while read(Register::InterruptCommand) & ICR_DELIVERY_PENDING != 0 {
+        asm!("pause" :::: "volatile");
+    }
+}
+
+/// Send an inter-processor interrupt with the given `vector` to `dest`.
+///
+/// The handler for `vector` must signal the end of the interrupt with
+/// [`end_of_interrupt`](fn.end_of_interrupt.html).
+///
+/// # Safety
+/// + Every CPU in `dest` must have a handler for `vector`.
+pub unsafe fn send_ipi(dest: Destination, vector: u8) {
+    send_command(dest, ICR_LEVEL_ASSERT | vector as u32);
+}
+
+/// Send an INIT IPI to the CPU with local APIC ID `apic_id`.
+///
+/// This resets the target CPU into its wait-for-SIPI state. As the
+/// INIT-SIPI-SIPI sequence calls for, the level-triggered INIT is asserted
+/// and then deasserted.
+///
+/// # Safety
+/// + Whatever the target CPU was doing will be lost.
+pub unsafe fn send_init(apic_id: u8) {
+    let dest = Destination::Apic(apic_id);
+    send_command(dest, ICR_INIT | ICR_TRIGGER_LEVEL | ICR_LEVEL_ASSERT);
+    send_command(dest, ICR_INIT | ICR_TRIGGER_LEVEL);
+}
+
+/// Send a startup IPI to the CPU with local APIC ID `apic_id`.
+///
+/// The target CPU starts executing in real mode at physical address
+/// `page << 12`, so the startup code must be in the first megabyte of
+/// memory, and page-aligned.
+///
+/// # Safety
+/// + The target CPU must be waiting for a SIPI, and valid startup code must
+///   be at `page << 12`.
+pub unsafe fn send_startup(apic_id: u8, page: u8) {
+    send_command( Destination::Apic(apic_id)
+                , ICR_STARTUP | ICR_LEVEL_ASSERT | page as u32);
+}
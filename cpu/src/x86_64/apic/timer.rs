@@ -0,0 +1,304 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! The local APIC timer.
+//!
+//! Every local APIC contains a timer that can count down in one-shot or
+//! periodic mode at a (divided) bus clock frequency. On CPUs that advertise
+//! it, the timer can also fire when the timestamp counter passes a deadline
+//! written to `IA32_TSC_DEADLINE`, which is both more precise and cheaper to
+//! re-arm.
+//!
+//! Unlike the PIT, the APIC timer is per-CPU, and can be re-armed for
+//! arbitrary intervals, so it's what we'll want to use for a tickless
+//! scheduler.
+#![warn(missing_docs)]
+use super::{Register, read, write};
+use ::{cpuid, msr, pit};
+use ::timer::timestamp;
+
+use core::mem;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// Interrupt vector the APIC timer fires on.
+pub const VECTOR: u8 = 0x30;
+
+/// Number of nanoseconds in a second.
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+/// How long to sample the APIC timer for during calibration, in microseconds.
+const CALIBRATION_MICROS: u64 = 10_000;
+
+/// LVT timer entry bit that masks the timer interrupt.
+const LVT_MASKED: u32 = 1 << 16;
+/// Divide configuration value for dividing the bus clock by 16.
+const DIVIDE_BY_16: u32 = 0b0011;
+/// Value of `ARMED` when the timer was cancelled, or was never armed.
+const NOT_ARMED: usize = !0;
+
+/// Modes the APIC timer can operate in.
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Mode { /// Count down once from the initial count, then stop.
+                OneShot     = 0b00 << 17
+              , /// Count down repeatedly, reloading the initial count.
+                Periodic    = 0b01 << 17
+              , /// Fire when the timestamp counter passes `IA32_TSC_DEADLINE`.
+                TscDeadline = 0b10 << 17
+              }
+
+/// Frequency the APIC timer counts at (after dividing), in Hz.
+static APIC_HZ: AtomicUsize = AtomicUsize::new(0);
+/// True if we are using TSC-deadline mode.
+static USE_DEADLINE: AtomicBool = AtomicBool::new(false);
+/// Period to re-arm for in TSC-deadline mode, in TSC ticks (0 if one-shot).
+static DEADLINE_PERIOD: AtomicUsize = AtomicUsize::new(0);
+/// The mode a timer was last armed in, as a `Mode`'s bits, or `NOT_ARMED`.
+static ARMED: AtomicUsize = AtomicUsize::new(NOT_ARMED);
+/// Address of the function to call when the timer fires (0 if none).
+static HANDLER: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns true if the CPU supports TSC-deadline mode.
+#[inline]
+pub fn has_tsc_deadline() -> bool {
+    cpuid::has(cpuid::TSC_DEADLINE)
+}
+
+/// Returns true if timer events use TSC-deadline mode.
+#[inline]
+pub fn uses_tsc_deadline() -> bool {
+    USE_DEADLINE.load(Ordering::Relaxed)
+}
+
+/// Returns the mode the timer was last armed in, or `None` if it was
+/// cancelled since, or has never been armed.
+///
+/// Periodic events are armed in `TscDeadline` mode when
+/// [`uses_tsc_deadline`](fn.uses_tsc_deadline.html) is true, since they're
+/// re-armed each time they fire.
+pub fn mode() -> Option<Mode> {
+    match ARMED.load(Ordering::Relaxed) {
+        NOT_ARMED => None
+      , bits if bits == Mode::OneShot as usize => Some(Mode::OneShot)
+      , bits if bits == Mode::Periodic as usize => Some(Mode::Periodic)
+      , _ => Some(Mode::TscDeadline)
+    }
+}
+
+/// Returns the calibrated frequency of the APIC timer, in Hz, or `None` if
+/// it has not been calibrated.
+#[inline]
+pub fn frequency() -> Option<u64> {
+    match APIC_HZ.load(Ordering::Relaxed) {
+        0 => None
+      , hz => Some(hz as u64)
+    }
+}
+
+/// Convert a duration in nanoseconds to ticks of a clock running at `hz`.
+#[inline]
+fn nanos_to_ticks(nanos: u64, hz: u64) -> u64 {
+    // split the multiplication so we don't overflow for long intervals
+    (nanos / NANOS_PER_SEC) * hz + (nanos % NANOS_PER_SEC) * hz / NANOS_PER_SEC
+}
+
+/// Set the LVT timer entry to deliver `VECTOR` in the given mode.
+#[inline]
+unsafe fn set_lvt(mode: Mode, masked: bool) {
+    write( Register::LvtTimer
+         , VECTOR as u32 | mode as u32 | if masked { LVT_MASKED } else { 0 });
+}
+
+/// Calibrate the APIC timer, using the current CPU's.
+///
+/// The timer's count rate is calibrated against PIT channel 2. If the CPU
+/// supports TSC-deadline mode, and the TSC has already been calibrated, we
+/// will use TSC-deadline mode for timer events.
+///
+/// This only calibrates, and leaves the timer stopped. Every CPU's timer
+/// counts at the same rate, so this need only be called once, and the
+/// other CPUs can [arm](fn.periodic.html) their timers without it.
+///
+/// Returns the timer's calibrated frequency, in Hz.
+///
+/// # Safety
+/// + The local APIC must have been initialized.
+/// + The IDT must be loaded, since this [probes](../../msr/fn.probe.html)
+///   for the TSC deadline MSR.
+/// + This busy-waits on PIT channel 2 for ~10 milliseconds.
+pub unsafe fn initialize() -> Result<u64, &'static str> {
+    if !super::is_initialized() {
+        return Err("Local APIC has not been initialized")
+    }
+
+    // calibrate the countdown against the PIT, with the interrupt masked
+    write(Register::TimerDivide, DIVIDE_BY_16);
+    set_lvt(Mode::OneShot, true);
+    write(Register::TimerInitialCount, 0xffff_ffff);
+    let elapsed = pit::measure(CALIBRATION_MICROS, || {
+        0xffff_ffff - read(Register::TimerCurrentCount) as u64
+    });
+    write(Register::TimerInitialCount, 0);
+
+    let hz = elapsed * 1_000_000 / CALIBRATION_MICROS;
+    if hz == 0 {
+        return Err("APIC timer did not count during calibration")
+    }
+    APIC_HZ.store(hz as usize, Ordering::SeqCst);
+
+    // some hypervisors advertise TSC-deadline mode without implementing its
+    // MSR, so make sure it's really there
+    let deadline = has_tsc_deadline() && timestamp::frequency().is_some()
+                && msr::probe(msr::IA32_TSC_DEADLINE).is_some();
+    USE_DEADLINE.store(deadline, Ordering::SeqCst);
+
+    debug!( "APIC timer runs at {} Hz, TSC-deadline mode {}"
+          , hz, if deadline { "enabled" } else { "disabled" });
+    Ok(hz)
+}
+
+/// Set the function to call when a timer event fires.
+///
+/// The handler is called in interrupt context, so it should be brief.
+#[inline]
+pub fn set_handler(handler: fn()) {
+    HANDLER.store(handler as usize, Ordering::SeqCst);
+}
+
+/// Arm a one-shot timer event on the current CPU, to fire in `nanos`
+/// nanoseconds.
+///
+/// This replaces any previously armed event. The timer must have been
+/// [calibrated](fn.initialize.html), on any CPU.
+pub fn oneshot(nanos: u64) -> Result<(), &'static str> {
+    let hz = frequency().ok_or("APIC timer has not been calibrated")?;
+    DEADLINE_PERIOD.store(0, Ordering::SeqCst);
+    unsafe {
+        if USE_DEADLINE.load(Ordering::Relaxed) {
+            arm_deadline(nanos)
+        } else {
+            arm_count(Mode::OneShot, nanos_to_ticks(nanos, hz));
+            Ok(())
+        }
+    }
+}
+
+/// Arm a periodic timer event on the current CPU, to fire every `nanos`
+/// nanoseconds.
+///
+/// This replaces any previously armed event. The timer must have been
+/// [calibrated](fn.initialize.html), on any CPU.
+pub fn periodic(nanos: u64) -> Result<(), &'static str> {
+    let hz = frequency().ok_or("APIC timer has not been calibrated")?;
+    unsafe {
+        if USE_DEADLINE.load(Ordering::Relaxed) {
+            // TSC-deadline mode has no periodic mode, so we re-arm the
+            // deadline each time the interrupt fires
+            let tsc_hz = timestamp::frequency()
+                .ok_or("Timestamp counter has not been calibrated")?;
+            DEADLINE_PERIOD.store( nanos_to_ticks(nanos, tsc_hz) as usize
+                                 , Ordering::SeqCst);
+            arm_deadline(nanos)
+        } else {
+            arm_count(Mode::Periodic, nanos_to_ticks(nanos, hz));
+            Ok(())
+        }
+    }
+}
+
+/// Cancel any timer event armed on the current CPU.
+pub fn cancel() {
+    DEADLINE_PERIOD.store(0, Ordering::SeqCst);
+    ARMED.store(NOT_ARMED, Ordering::SeqCst);
+    unsafe {
+        if USE_DEADLINE.load(Ordering::Relaxed) {
+            msr::write(msr::IA32_TSC_DEADLINE, 0);
+        } else {
+            write(Register::TimerInitialCount, 0);
+        }
+    }
+}
+
+/// Clamp a tick count to the range of the 32-bit initial count register.
+///
+/// An initial count of 0 stops the timer, so we never write 0.
+#[inline]
+fn clamp(ticks: u64) -> u32 {
+    match ticks {
+        0 => 1
+      , t if t > 0xffff_ffff => 0xffff_ffff
+      , t => t as u32
+    }
+}
+
+/// Start the countdown in `mode`, from `ticks` ticks of the divided clock.
+///
+/// The divider is per-CPU, and INIT resets it, so it's set every time the
+/// timer is armed, rather than trusting it to still be the one `APIC_HZ`
+/// was calibrated with.
+unsafe fn arm_count(mode: Mode, ticks: u64) {
+    write(Register::TimerDivide, DIVIDE_BY_16);
+    set_lvt(mode, false);
+    write(Register::TimerInitialCount, clamp(ticks));
+    ARMED.store(mode as usize, Ordering::SeqCst);
+}
+
+/// Arm the TSC deadline `nanos` nanoseconds from now.
+unsafe fn arm_deadline(nanos: u64) -> Result<(), &'static str> {
+    let tsc_hz = timestamp::frequency()
+        .ok_or("Timestamp counter has not been calibrated")?;
+    set_lvt(Mode::TscDeadline, false);
+    let now = timestamp::rtdsc();
+    msr::write( msr::IA32_TSC_DEADLINE
+              , now.wrapping_add(nanos_to_ticks(nanos, tsc_hz)));
+    ARMED.store(Mode::TscDeadline as usize, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Handle an APIC timer interrupt.
+///
+/// This should be called from the interrupt handler for
+/// [`VECTOR`](constant.VECTOR.html). It re-arms periodic TSC-deadline events,
+/// calls the registered handler, and signals the end of the interrupt.
+pub fn handle_interrupt() {
+    let period = DEADLINE_PERIOD.load(Ordering::Relaxed) as u64;
+    if period != 0 {
+        unsafe {
+            msr::write( msr::IA32_TSC_DEADLINE
+                      , timestamp::rtdsc().wrapping_add(period));
+        }
+    }
+
+    match HANDLER.load(Ordering::Relaxed) {
+        0 => {}
+      , f => {
+            let handler: fn() = unsafe { mem::transmute(f) };
+            handler()
+        }
+    }
+    super::end_of_interrupt();
+}
+
+#[cfg(test)]
+mod test {
+    use super::{clamp, nanos_to_ticks};
+
+    #[test]
+    fn nanos_to_ticks_does_not_overflow() {
+        assert_eq!(nanos_to_ticks(1_000_000_000, 1_000), 1_000);
+        assert_eq!(nanos_to_ticks(1_500, 1_000_000_000), 1_500);
+        // an hour at 4 GHz
+        assert_eq!( nanos_to_ticks(3_600 * 1_000_000_000, 4_000_000_000)
+                  , 3_600 * 4_000_000_000);
+    }
+
+    #[test]
+    fn clamp_never_stops_timer() {
+        assert_eq!(clamp(0), 1);
+        assert_eq!(clamp(1 << 40), 0xffff_ffff);
+    }
+}
@@ -15,11 +15,21 @@ use core::mem;
 use core::fmt;
 use super::flags::{Flags as RFlags};
 use super::segment;
+use super::PrivilegeLevel;
 
 /// Registers pushed to the stack when handling an interrupt or context switch.
+///
+/// This contains all of the general-purpose registers except for `%rsp`, which
+/// is saved separately (in the `InterruptFrame` or the `Context`).
 #[repr(C, packed)]
 #[derive(Copy, Clone)]
-pub struct Registers { pub rsi: u64
+pub struct Registers { pub r15: u64
+                     , pub r14: u64
+                     , pub r13: u64
+                     , pub r12: u64
+                     , pub rbp: u64
+                     , pub rbx: u64
+                     , pub rsi: u64
                      , pub rdi: u64
                      , pub r11: u64
                      , pub r10: u64
@@ -35,8 +45,10 @@ pub struct Registers { pub rsi: u64
      /// (if you would ever want to do this)
      /// TODO: rewrite this to be a `convert::Into` implementation.
      //         - eliza, 03/09/2017
-     pub unsafe fn to_array(&self) -> [u64; 9] {
-        //  [ self.rsi, self.rdi, self.r11
+     pub unsafe fn to_array(&self) -> [u64; 15] {
+        //  [ self.r15, self.r14, self.r13
+        //  , self.r12, self.rbp, self.rbx
+        //  , self.rsi, self.rdi, self.r11
         //  , self.r10, self.r9, self.r8
         //  , self.rdx, self.rcx, self.rax
         //  ]
@@ -46,13 +58,18 @@ pub struct Registers { pub rsi: u64
 
      /// Create a new empty set of Registers
      pub const fn empty() -> Self {
-         Registers { rsi: 0, rdi: 0, r11: 0
+         Registers { r15: 0, r14: 0, r13: 0
+                   , r12: 0, rbp: 0, rbx: 0
+                   , rsi: 0, rdi: 0, r11: 0
                    , r10: 0, r9:  0, r8:  0
                    , rdx: 0, rcx: 0, rax: 0
                    }
      }
 
-     /// Push the caller-saved registers to the stack
+     /// Returns the saved frame pointer (`%rbp`).
+     #[inline] pub fn frame_pointer(&self) -> usize { self.rbp as usize }
+
+     /// Push the general-purpose registers to the stack
      /// (such as when handling a context switch or interrupt).
      ///
      /// THIS FUNCTION IS NAKED. DO NOT CALL IT NORMALLY.
@@ -67,19 +84,31 @@ pub struct Registers { pub rsi: u64
                 push r10
                 push r11
                 push rdi
-                push rsi"
+                push rsi
+                push rbx
+                push rbp
+                push r12
+                push r13
+                push r14
+                push r15"
              :::: "intel"
                 , "volatile");
      }
 
-     /// Push the caller-saved registers off the stack
+     /// Pop the general-purpose registers off the stack
      /// (such as when handling a context switch or interrupt).
      ///
      /// THIS FUNCTION IS NAKED. DO NOT CALL IT NORMALLY.
      #[naked]
      #[inline(always)]
      pub unsafe fn pop() {
-         asm!( "pop rsi
+         asm!( "pop r15
+                pop r14
+                pop r13
+                pop r12
+                pop rbp
+                pop rbx
+                pop rsi
                 pop rdi
                 pop r11
                 pop r10
@@ -96,12 +125,16 @@ pub struct Registers { pub rsi: u64
 impl fmt::Debug for Registers {
      fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
          write!( f
-               , "    RSI: {:#018x} RDI: {:#018x} R11: {:#018x}\n    \
-                      R10: {:#018x} R9:  {:#018x} R8:  {:#018x}\n    \
-                      RDX: {:#018x} RCX: {:#018x} RAX: {:#018x}"
-              , self.rsi, self.rdi, self.r11
-              , self.r10, self.r9,  self.r8
-              , self.rdx, self.rcx, self.rax)
+               , "    RAX: {:#018x} RBX: {:#018x} RCX: {:#018x}\n    \
+                      RDX: {:#018x} RSI: {:#018x} RDI: {:#018x}\n    \
+                      RBP: {:#018x} R8:  {:#018x} R9:  {:#018x}\n    \
+                      R10: {:#018x} R11: {:#018x} R12: {:#018x}\n    \
+                      R13: {:#018x} R14: {:#018x} R15: {:#018x}"
+              , self.rax, self.rbx, self.rcx
+              , self.rdx, self.rsi, self.rdi
+              , self.rbp, self.r8,  self.r9
+              , self.r10, self.r11, self.r12
+              , self.r13, self.r14, self.r15)
      }
 }
 
@@ -130,6 +163,16 @@ pub struct InterruptFrame {
   , __pad_4: u16
 }
 
+impl InterruptFrame {
+    /// Returns the instruction pointer at the time of the interrupt.
+    #[inline] pub fn instruction_pointer(&self) -> *const u8 { self.rip }
+
+    /// Returns true if the interrupt arrived while the CPU was in user mode.
+    #[inline] pub fn is_user(&self) -> bool {
+        self.cs.get_rpl() == PrivilegeLevel::UserMode
+    }
+}
+
 #[cfg(test)]
 mod test {
     #[test]
@@ -139,6 +182,15 @@ mod test {
 
         assert_eq!(size_of::<InterruptFrame>(), 32);
     }
+
+    #[test]
+    fn test_context_correct_size() {
+        use core::mem::size_of;
+        use super::Context;
+
+        // `switch` hardcodes these offsets
+        assert_eq!(size_of::<Context>(), 136);
+    }
 }
 
 impl fmt::Debug for InterruptFrame {
@@ -161,24 +213,96 @@ impl fmt::Debug for InterruptFrame {
 }
 
 /// Thread execution context
+///
+/// This is saved by [`switch`](fn.switch.html) when a thread is switched
+/// away from, and loaded when it's switched back to. Only the callee-saved
+/// registers are saved, since `switch` is called like any other function.
 #[repr(C, packed)]
 pub struct Context { /// Value of the stack pointer (`rsp`) register
                      pub rsp: *mut u8
-                   , /// Value of the caller-saved registers
+                   , /// Value of the general-purpose registers
                      pub registers: Registers
                    , /// Value of the instruction pointer (`rip`) register
                      pub rip: *mut u8
-                 //, pub stack: [u8] // TODO: should be box
                    }
 
 impl Context {
-    pub fn empty() -> Self {
-        unsafe {
-            Context { rsp: mem::transmute(0u64)
-                    , registers: Registers::empty()
-                    , rip: mem::transmute(0u64)
-                  //, stack: [0u8; 8]
-                    }
-        }
+    /// Returns an empty `Context`.
+    ///
+    /// This is suitable for a thread which is already running, since its
+    /// context will be filled in when it's switched away from.
+    pub const fn empty() -> Self {
+        Context { rsp: 0 as *mut u8
+                , registers: Registers::empty()
+                , rip: 0 as *mut u8
+                }
+    }
+
+    /// Returns a `Context` which will call `entry(arg)` on the stack ending
+    /// at `stack_top` when it's switched to.
+    pub fn new( stack_top: *mut u8
+              , entry: extern "C" fn(usize) -> !
+              , arg: usize)
+              -> Self {
+        let mut registers = Registers::empty();
+        registers.r12 = entry as u64;
+        registers.r13 = arg as u64;
+        Context { rsp: (stack_top as usize & !0xf) as *mut u8
+                , registers: registers
+                , rip: start as *mut u8
+                }
     }
 }
+
+/// Entry point for a new `Context`.
+///
+/// `Context::new` leaves the entry point in `%r12` and its argument in
+/// `%r13`. The frame pointer is zeroed so that backtraces stop here.
+///
+/// THIS FUNCTION IS NAKED. DO NOT CALL IT NORMALLY.
+#[naked]
+unsafe extern "C" fn start() {
+    asm!( "xor rbp, rbp
+           mov rdi, r13
+           call r12
+           ud2"
+        :::: "intel", "volatile");
+}
+
+/// Save the current context into `from`, and switch to `to`.
+///
+/// When `from` is switched back to, this returns to its caller as though
+/// nothing happened. Offsets into `Context` are hardcoded, so its layout
+/// must not change without changing this too.
+///
+/// # Safety
+/// + `to` must be a context saved by `switch` or created by
+///   [`Context::new`](struct.Context.html#method.new), and its stack must
+///   still be mapped.
+/// + Interrupts should be disabled.
+#[naked]
+#[inline(never)]
+pub unsafe extern "C" fn switch(_from: *mut Context, _to: *const Context) {
+    // `%rdi` is `from` and `%rsi` is `to`. we save the return address as
+    // `from`'s instruction pointer, and the stack pointer as it will be
+    // after returning, so switching back to `from` just returns.
+    asm!( "mov rax, [rsp]
+           mov [rdi + 128], rax
+           lea rax, [rsp + 8]
+           mov [rdi], rax
+           mov [rdi + 8], r15
+           mov [rdi + 16], r14
+           mov [rdi + 24], r13
+           mov [rdi + 32], r12
+           mov [rdi + 40], rbp
+           mov [rdi + 48], rbx
+           mov rsp, [rsi]
+           mov r15, [rsi + 8]
+           mov r14, [rsi + 16]
+           mov r13, [rsi + 24]
+           mov r12, [rsi + 32]
+           mov rbp, [rsi + 40]
+           mov rbx, [rsi + 48]
+           jmp qword ptr [rsi + 128]"
+        :::: "intel", "volatile");
+}
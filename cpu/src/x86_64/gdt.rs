@@ -0,0 +1,178 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Per-CPU Global Descriptor Tables.
+//!
+//! The GDT set up by the boot code only needs to get the bootstrap processor
+//! into long mode. Once we're running in Rust, each CPU loads its own GDT,
+//! which holds the kernel's and user mode's segments and a descriptor for
+//! that CPU's Task State Segment.
+//!
+//! The kernel code segment selector is the same as in the boot GDT, so
+//! interrupt gates created before a CPU switches GDTs stay valid. The user
+//! data segment comes right before the user code segment, since that's the
+//! order `sysret` expects them in.
+#![warn(missing_docs)]
+use dtable::DTable;
+use memory::VAddr;
+use segment::Selector;
+use smp::MAX_CPUS;
+use task::{self, StateSegment};
+
+/// Number of 8-byte entries in each GDT.
+///
+/// The TSS descriptor is 16 bytes long, so it takes up two entries.
+pub const GDT_ENTRIES: usize = 7;
+
+/// Selector for the kernel code segment.
+pub const KERNEL_CODE: Selector = Selector::from_raw(1 << 3);
+/// Selector for the kernel data segment.
+pub const KERNEL_DATA: Selector = Selector::from_raw(2 << 3);
+/// Selector for the user data segment, with a requested privilege level of
+/// 3.
+pub const USER_DATA: Selector = Selector::from_raw(3 << 3 | 3);
+/// Selector for the user code segment, with a requested privilege level of
+/// 3.
+pub const USER_CODE: Selector = Selector::from_raw(4 << 3 | 3);
+/// Selector for the current CPU's Task State Segment.
+pub const TSS: Selector = Selector::from_raw(5 << 3);
+
+/// Present, ring 0, execute/read, 64-bit code segment.
+const KERNEL_CODE_DESCRIPTOR: u64 = 0x00af_9a00_0000_ffff;
+/// Present, ring 0, read/write data segment.
+const KERNEL_DATA_DESCRIPTOR: u64 = 0x00cf_9200_0000_ffff;
+/// Present, ring 3, read/write data segment.
+const USER_DATA_DESCRIPTOR: u64 = 0x00cf_f200_0000_ffff;
+/// Present, ring 3, execute/read, 64-bit code segment.
+const USER_CODE_DESCRIPTOR: u64 = 0x00af_fa00_0000_ffff;
+/// Descriptor type for a present, available 64-bit TSS.
+const TSS_AVAILABLE: u64 = 0x89;
+
+/// A CPU's Global Descriptor Table.
+#[derive(Copy, Clone)]
+#[repr(C, align(16))]
+pub struct Gdt { entries: [u64; GDT_ENTRIES] }
+
+impl Gdt {
+    /// Returns a new GDT containing the kernel and user segments and no TSS.
+    pub const fn new() -> Self {
+        Gdt { entries: [ 0
+                       , KERNEL_CODE_DESCRIPTOR
+                       , KERNEL_DATA_DESCRIPTOR
+                       , USER_DATA_DESCRIPTOR
+                       , USER_CODE_DESCRIPTOR
+                       , 0, 0 ] }
+    }
+
+    /// Point this GDT's TSS descriptor at `tss`.
+    pub fn set_tss(&mut self, tss: &'static StateSegment) {
+        let base = tss as *const StateSegment as u64;
+        let limit = (task::SIZE - 1) as u64;
+        self.entries[5] = (limit & 0xffff)
+                        | (base & 0xff_ffff) << 16
+                        | TSS_AVAILABLE << 40
+                        | (limit >> 16 & 0xf) << 48
+                        | (base >> 24 & 0xff) << 56;
+        self.entries[6] = base >> 32;
+    }
+}
+
+impl DTable for Gdt {
+    type Entry = u64;
+
+    #[inline(always)] fn entry_count(&self) -> usize { GDT_ENTRIES }
+
+    /// Load the GDT with the `lgdt` instruction.
+    #[inline] fn load(&'static self) {
+        unsafe {
+            asm!(  "lgdt ($0)"
+                :: "r"(&self.get_ptr())
+                :  "memory" );
+        }
+    }
+}
+
+/// A CPU's descriptor tables.
+#[derive(Copy, Clone)]
+struct Tables { gdt: Gdt
+              , tss: StateSegment
+              }
+
+/// Descriptor tables for each CPU, indexed by CPU number.
+static mut TABLES: [Tables; MAX_CPUS]
+    = [ Tables { gdt: Gdt::new(), tss: StateSegment::new() }; MAX_CPUS ];
+
+/// Returns the Task State Segment of `cpu`.
+///
+/// # Safety
+/// + The TSS is shared with the CPU it belongs to, so it should only be
+///   modified by that CPU.
+pub unsafe fn tss(cpu: usize) -> &'static mut StateSegment {
+    &mut TABLES[cpu].tss
+}
+
+/// Set the stack `cpu` switches to when an interrupt or exception arrives
+/// in user mode.
+///
+/// # Safety
+/// + `top` must be the top of a mapped kernel stack, which nothing else is
+///   using while `cpu` runs in user mode.
+pub unsafe fn set_kernel_stack(cpu: usize, top: VAddr) {
+    TABLES[cpu].tss.rsp[0] = top;
+}
+
+/// Load `cpu`'s GDT and TSS on the current CPU.
+///
+/// This reloads all the segment registers. `%fs` and `%gs` are loaded with
+/// the null selector, which clears their base addresses.
+///
+/// # Safety
+/// + This should be called once on each CPU, with its own CPU number.
+pub unsafe fn load(cpu: usize) {
+    TABLES[cpu].gdt.set_tss(&TABLES[cpu].tss);
+    TABLES[cpu].gdt.load();
+
+    // we can't `mov` to `%cs`, so do a far return to the new code segment
+    asm!( "pushq $0
+           leaq 1f(%rip), %rax
+           pushq %rax
+           lretq
+           1:"
+        :: "r"(KERNEL_CODE.bits() as u64)
+        :  "rax", "memory"
+        :  "volatile");
+    KERNEL_DATA.load_ss();
+    KERNEL_DATA.load_ds();
+    KERNEL_DATA.load_es();
+    Selector::from_raw(0).load_fs();
+    Selector::from_raw(0).load_gs();
+
+    asm!(  "ltr $0"
+        :: "r"(TSS.bits())
+        :: "volatile");
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn user_segments_are_where_sysret_expects() {
+        // `sysret` loads `%ss` from 8 past the selector base in `IA32_STAR`,
+        // and `%cs` from 16 past it
+        assert_eq!(USER_DATA.bits() & !3, KERNEL_DATA.bits() + 8);
+        assert_eq!(USER_CODE.bits() & !3, KERNEL_DATA.bits() + 16);
+    }
+
+    #[test]
+    fn user_segments_are_ring_3() {
+        assert_eq!(USER_DATA_DESCRIPTOR >> 45 & 3, 3);
+        assert_eq!(USER_CODE_DESCRIPTOR >> 45 & 3, 3);
+        assert_eq!(KERNEL_CODE_DESCRIPTOR >> 45 & 3, 0);
+    }
+}
@@ -12,11 +12,18 @@
 //! CPU I/O (from `x86_all`), and reading/writing the x86 control registers.
 //!
 
+// `percpu!` must be defined before anything in `x86_all` can use it
+#[macro_use] pub mod percpu;
 #[path = "../x86_all/mod.rs"] mod cpu_all;
 
 pub mod context;
 pub mod task;
 pub mod msr;
+pub mod apic;
+pub mod gdt;
+pub mod smp;
+pub mod syscall;
+pub mod user;
 
 pub use self::context::Registers;
 pub use self::cpu_all::*;
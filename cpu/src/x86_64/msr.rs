@@ -7,10 +7,66 @@
 //  directory of this repository for more information.
 //
 //! Code for interacting with the Model-Specific Registers (MSRs).
+//!
+//! The raw [`read`](fn.read.html) and [`write`](fn.write.html) functions
+//! work on any MSR, while the submodules of this module provide typed access
+//! to the registers the kernel uses.
+//!
+//! Reading or writing an MSR the CPU doesn't implement causes a general
+//! protection fault. [`probe`](fn.probe.html) can be used to find out whether
+//! an MSR exists without crashing.
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::fmt;
+use spin::Mutex;
+
+use context::InterruptFrame;
 
 /// Extended Feature Enable Register (EFER) on IA-32
 pub const IA32_EFER: u32 = 0xc0000080;
 
+/// Local APIC base address and enable register
+pub const IA32_APIC_BASE: u32 = 0x1b;
+
+/// Local APIC timer TSC deadline
+pub const IA32_TSC_DEADLINE: u32 = 0x6e0;
+
+/// Segment selectors used by `syscall` and `sysret`
+pub const IA32_STAR: u32 = 0xc0000081;
+
+/// Long mode `syscall` target address
+pub const IA32_LSTAR: u32 = 0xc0000082;
+
+/// Compatibility mode `syscall` target address
+pub const IA32_CSTAR: u32 = 0xc0000083;
+
+/// `%rflags` bits to clear on `syscall`
+pub const IA32_FMASK: u32 = 0xc0000084;
+
+/// Base address of the `%fs` segment
+pub const IA32_FS_BASE: u32 = 0xc0000100;
+
+/// Base address of the `%gs` segment
+pub const IA32_GS_BASE: u32 = 0xc0000101;
+
+/// Value swapped into `IA32_GS_BASE` by `swapgs`
+pub const IA32_KERNEL_GS_BASE: u32 = 0xc0000102;
+
+/// Auxiliary value returned by `rdtscp`
+pub const IA32_TSC_AUX: u32 = 0xc0000103;
+
+/// Page Attribute Table
+pub const IA32_PAT: u32 = 0x277;
+
+/// MTRR capabilities
+pub const IA32_MTRRCAP: u32 = 0xfe;
+
+/// Default memory type and MTRR enables
+pub const IA32_MTRR_DEF_TYPE: u32 = 0x2ff;
+
+/// Base of the first variable-range MTRR. The base of range `n` is at
+/// `IA32_MTRR_PHYSBASE0 + 2 * n`, and its mask is the MSR after that.
+pub const IA32_MTRR_PHYSBASE0: u32 = 0x200;
+
 /// Write `value` to the specified `msr`
 ///
 /// # Arguments
@@ -37,12 +93,443 @@ pub unsafe fn read(msr: u32) -> u64 {
     ((high as u64) << 32) | (low as u64)
 }
 
-
 /// Enable the NXE (No Execute) in the IA-32 EFER register.
 ///
 /// This allows us to set the NXE bit on pages.
 pub unsafe fn enable_nxe() {
-    let nxe_bit = 1 << 11;
-    let efer = read(IA32_EFER) | nxe_bit;
-    write(IA32_EFER, efer);
+    efer::write(efer::read() | efer::NXE);
+}
+
+//==--------------------------------------------------------------------------==
+// Safe probing
+
+/// Serializes probes, since there's only one flag for whether they faulted.
+static PROBE_LOCK: Mutex<()> = Mutex::new(());
+/// Set by the fault handler if the probe's `rdmsr` faulted.
+static FAULTED: AtomicBool = AtomicBool::new(false);
+
+extern {
+    /// The probe's `rdmsr` instruction, defined in
+    /// [`probe_read`](fn.probe_read.html).
+    static msr_probe_rdmsr: u8;
+}
+
+/// Read the specified `msr`, if the CPU implements it.
+///
+/// Returns `None` if reading the MSR caused a general protection fault. This
+/// requires the IDT to be loaded, and the general protection fault handler to
+/// call [`fixup_probe`](fn.fixup_probe.html).
+pub fn probe(msr: u32) -> Option<u64> {
+    let _guard = PROBE_LOCK.lock();
+    FAULTED.store(false, Ordering::SeqCst);
+    let value = unsafe { probe_read(msr) };
+    if FAULTED.load(Ordering::SeqCst) { None } else { Some(value) }
+}
+
+/// Read `msr` with the one `rdmsr` instruction that
+/// [`fixup_probe`](fn.fixup_probe.html) will skip if it faults.
+///
+/// This mustn't be inlined, or the label would be defined more than once.
+#[inline(never)]
+unsafe fn probe_read(msr: u32) -> u64 {
+    let (low, high): (u32, u32);
+    asm!( ".global msr_probe_rdmsr
+           msr_probe_rdmsr:
+           rdmsr"
+        : "={eax}" (low), "={edx}" (high)
+        : "{ecx}" (msr)
+        : "memory"
+        : "volatile" );
+    ((high as u64) << 32) | (low as u64)
+}
+
+/// Recover from a general protection fault caused by [`probe`](fn.probe.html).
+///
+/// If `frame` points at the probe's own `rdmsr` instruction, this records
+/// that the probe failed and skips the instruction, and returns true. The
+/// fault handler should then return, rather than reporting the fault. Faults
+/// anywhere else, including other `rdmsr`s and `wrmsr`s, are left alone.
+pub fn fixup_probe(frame: &mut InterruptFrame) -> bool {
+    let rdmsr = unsafe { &msr_probe_rdmsr as *const u8 };
+    if frame.rip != rdmsr { return false }
+    FAULTED.store(true, Ordering::SeqCst);
+    // `rdmsr` is two bytes long
+    frame.rip = unsafe { frame.rip.offset(2) };
+    true
+}
+
+//==--------------------------------------------------------------------------==
+// Typed registers
+
+/// Generates a module for an MSR holding a plain 64-bit value.
+macro_rules! value_msr {
+    ($(#[$attr:meta])* mod $name:ident = $msr:expr) => {
+        $(#[$attr])*
+        pub mod $name {
+            /// Read this register.
+            #[inline]
+            pub unsafe fn read() -> u64 { super::read($msr) }
+
+            /// Write `value` to this register.
+            #[inline]
+            pub unsafe fn write(value: u64) { super::write($msr, value) }
+        }
+    }
+}
+
+/// Generates `read` and `write` functions for an MSR holding bitflags.
+macro_rules! flags_msr {
+    ($msr:expr) => {
+        /// Read this register.
+        ///
+        /// Any reserved bits are ignored.
+        #[inline]
+        pub unsafe fn read() -> Flags {
+            Flags::from_bits_truncate(super::read($msr))
+        }
+
+        /// Write `flags` to this register.
+        #[inline]
+        pub unsafe fn write(flags: Flags) { super::write($msr, flags.bits()) }
+    }
+}
+
+/// `IA32_EFER`, the Extended Feature Enable Register.
+pub mod efer {
+    use super::IA32_EFER;
+
+    bitflags! {
+        /// Flags in `IA32_EFER`.
+        pub flags Flags: u64 {
+            /// System Call Extensions: enables `syscall`/`sysret`
+            const SCE = 1 << 0
+          , /// Long Mode Enable
+            const LME = 1 << 8
+          , /// Long Mode Active (read only)
+            const LMA = 1 << 10
+          , /// No-Execute Enable
+            const NXE = 1 << 11
+          , /// Secure Virtual Machine Enable (AMD)
+            const SVME = 1 << 12
+          , /// Long Mode Segment Limit Enable (AMD)
+            const LMSLE = 1 << 13
+          , /// Fast `fxsave`/`fxrstor` (AMD)
+            const FFXSR = 1 << 14
+          , /// Translation Cache Extension (AMD)
+            const TCE = 1 << 15
+        }
+    }
+
+    flags_msr!(IA32_EFER);
+}
+
+/// `IA32_APIC_BASE`, the local APIC's base address and enable bits.
+pub mod apic_base {
+    use memory::PAddr;
+    use super::IA32_APIC_BASE;
+
+    /// Mask for the base address field.
+    pub const ADDR_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+    bitflags! {
+        /// Flags in `IA32_APIC_BASE`.
+        pub flags Flags: u64 {
+            /// This is the bootstrap processor
+            const BSP = 1 << 8
+          , /// x2APIC mode enable
+            const X2APIC_ENABLE = 1 << 10
+          , /// Local APIC global enable
+            const GLOBAL_ENABLE = 1 << 11
+        }
+    }
+
+    /// Read the flags in `IA32_APIC_BASE`.
+    #[inline]
+    pub unsafe fn flags() -> Flags {
+        Flags::from_bits_truncate(super::read(IA32_APIC_BASE))
+    }
+
+    /// Set `flags` in `IA32_APIC_BASE`, leaving the base address unchanged.
+    #[inline]
+    pub unsafe fn insert(flags: Flags) {
+        let value = super::read(IA32_APIC_BASE);
+        super::write(IA32_APIC_BASE, value | flags.bits())
+    }
+
+    /// Returns the physical address of the local APIC's register page.
+    #[inline]
+    pub unsafe fn address() -> PAddr {
+        PAddr::from(super::read(IA32_APIC_BASE) & ADDR_MASK)
+    }
+}
+
+/// `IA32_STAR`, the segment selectors loaded by `syscall` and `sysret`.
+pub mod star {
+    use segment::Selector;
+    use super::IA32_STAR;
+
+    /// Read the selectors in `IA32_STAR`.
+    ///
+    /// Returns `(syscall_cs, sysret_base)`. `syscall` loads `%cs` with
+    /// `syscall_cs` and `%ss` with `syscall_cs + 8`; `sysret` to 64-bit mode
+    /// loads `%cs` with `sysret_base + 16` and `%ss` with `sysret_base + 8`.
+    #[inline]
+    pub unsafe fn read() -> (Selector, Selector) {
+        let star = super::read(IA32_STAR);
+        ( Selector::from_raw((star >> 32) as u16)
+        , Selector::from_raw((star >> 48) as u16) )
+    }
+
+    /// Set the selectors used by `syscall` and `sysret`.
+    ///
+    /// See [`read`](fn.read.html) for how these are used.
+    #[inline]
+    pub unsafe fn write(syscall_cs: Selector, sysret_base: Selector) {
+        let star = ((sysret_base.bits() as u64) << 48)
+                 | ((syscall_cs.bits() as u64) << 32);
+        super::write(IA32_STAR, star)
+    }
+}
+
+value_msr! {
+    /// `IA32_LSTAR`, the entry point for `syscall` in long mode.
+    mod lstar = super::IA32_LSTAR
+}
+
+value_msr! {
+    /// `IA32_CSTAR`, the entry point for `syscall` in compatibility mode.
+    mod cstar = super::IA32_CSTAR
+}
+
+/// `IA32_FMASK`, the `%rflags` bits cleared by `syscall`.
+pub mod sfmask {
+    use flags::Flags;
+    use super::IA32_FMASK;
+
+    /// Read the `%rflags` bits cleared by `syscall`.
+    #[inline]
+    pub unsafe fn read() -> Flags {
+        Flags::from_bits_truncate(super::read(IA32_FMASK) as usize)
+    }
+
+    /// Set the `%rflags` bits cleared by `syscall`.
+    #[inline]
+    pub unsafe fn write(mask: Flags) {
+        super::write(IA32_FMASK, mask.bits() as u64)
+    }
+}
+
+value_msr! {
+    /// `IA32_FS_BASE`, the base address of the `%fs` segment.
+    mod fs_base = super::IA32_FS_BASE
+}
+
+value_msr! {
+    /// `IA32_GS_BASE`, the base address of the `%gs` segment.
+    mod gs_base = super::IA32_GS_BASE
+}
+
+value_msr! {
+    /// `IA32_KERNEL_GS_BASE`, which is exchanged with `IA32_GS_BASE` by
+    /// `swapgs`.
+    mod kernel_gs_base = super::IA32_KERNEL_GS_BASE
+}
+
+value_msr! {
+    /// `IA32_TSC_AUX`, the value returned in `%ecx` by `rdtscp`.
+    ///
+    /// We use this to store the current CPU's number.
+    mod tsc_aux = super::IA32_TSC_AUX
+}
+
+/// Memory types used by the PAT and the MTRRs.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(u8)]
+pub enum MemoryType { /// Uncacheable (UC)
+                      Uncacheable = 0
+                    , /// Write Combining (WC)
+                      WriteCombining = 1
+                    , /// Write Through (WT)
+                      WriteThrough = 4
+                    , /// Write Protected (WP)
+                      WriteProtected = 5
+                    , /// Write Back (WB)
+                      WriteBack = 6
+                    , /// Uncached (UC-), which may be overridden by the MTRRs
+                      ///
+                      /// This is only valid in the PAT.
+                      Uncached = 7
+                    }
+
+impl MemoryType {
+    /// Decode a memory type, returning `None` if it's reserved.
+    pub fn from_bits(bits: u8) -> Option<Self> {
+        match bits {
+            0 => Some(MemoryType::Uncacheable)
+          , 1 => Some(MemoryType::WriteCombining)
+          , 4 => Some(MemoryType::WriteThrough)
+          , 5 => Some(MemoryType::WriteProtected)
+          , 6 => Some(MemoryType::WriteBack)
+          , 7 => Some(MemoryType::Uncached)
+          , _ => None
+        }
+    }
+}
+
+impl fmt::Display for MemoryType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            MemoryType::Uncacheable => "UC"
+          , MemoryType::WriteCombining => "WC"
+          , MemoryType::WriteThrough => "WT"
+          , MemoryType::WriteProtected => "WP"
+          , MemoryType::WriteBack => "WB"
+          , MemoryType::Uncached => "UC-"
+        })
+    }
+}
+
+/// `IA32_PAT`, the Page Attribute Table.
+pub mod pat {
+    use super::{IA32_PAT, MemoryType};
+
+    /// The PAT's contents at reset: `WB, WT, UC-, UC` repeated twice.
+    pub const DEFAULT: [MemoryType; 8]
+        = [ MemoryType::WriteBack, MemoryType::WriteThrough
+          , MemoryType::Uncached, MemoryType::Uncacheable
+          , MemoryType::WriteBack, MemoryType::WriteThrough
+          , MemoryType::Uncached, MemoryType::Uncacheable ];
+
+    /// Encode eight PAT entries as the value of `IA32_PAT`.
+    pub fn encode(entries: &[MemoryType; 8]) -> u64 {
+        entries.iter().enumerate()
+               .fold(0, |pat, (i, &ty)| pat | ((ty as u64) << (i * 8)))
+    }
+
+    /// Decode the value of `IA32_PAT`.
+    ///
+    /// Reserved entries are decoded as `Uncacheable`.
+    pub fn decode(pat: u64) -> [MemoryType; 8] {
+        let mut entries = [MemoryType::Uncacheable; 8];
+        for (i, entry) in entries.iter_mut().enumerate() {
+            *entry = MemoryType::from_bits((pat >> (i * 8)) as u8 & 0x7)
+                        .unwrap_or(MemoryType::Uncacheable);
+        }
+        entries
+    }
+
+    /// Read the PAT's entries.
+    #[inline]
+    pub unsafe fn read() -> [MemoryType; 8] { decode(super::read(IA32_PAT)) }
+
+    /// Write the PAT's entries.
+    ///
+    /// # Safety
+    /// + The caches and TLBs must be flushed after changing the PAT.
+    #[inline]
+    pub unsafe fn write(entries: &[MemoryType; 8]) {
+        super::write(IA32_PAT, encode(entries))
+    }
+}
+
+/// Memory Type Range Registers.
+pub mod mtrr {
+    use memory::PAddr;
+    use super::{IA32_MTRRCAP, IA32_MTRR_DEF_TYPE, IA32_MTRR_PHYSBASE0};
+    use super::MemoryType;
+
+    /// Mask for the address fields of the variable-range MTRRs.
+    const ADDR_MASK: u64 = 0x000f_ffff_ffff_f000;
+    /// Valid bit in a variable-range MTRR's mask register.
+    const MASK_VALID: u64 = 1 << 11;
+
+    bitflags! {
+        /// Flags in `IA32_MTRRCAP`.
+        pub flags Capabilities: u64 {
+            /// Number of variable-range MTRRs
+            const VCNT = 0xff
+          , /// Fixed-range MTRRs are supported
+            const FIX = 1 << 8
+          , /// The write-combining memory type is supported
+            const WC = 1 << 10
+          , /// System-management range registers are supported
+            const SMRR = 1 << 11
+        }
+    }
+
+    bitflags! {
+        /// Flags in `IA32_MTRR_DEF_TYPE`.
+        pub flags DefaultFlags: u64 {
+            /// The default memory type
+            const TYPE = 0xff
+          , /// Fixed-range MTRRs enable
+            const FE = 1 << 10
+          , /// MTRRs enable
+            const E = 1 << 11
+        }
+    }
+
+    /// A variable-range MTRR.
+    #[derive(Copy, Clone, Debug)]
+    pub struct Range { /// The base address of the range
+                       pub base: PAddr
+                     , /// The address mask of the range
+                       pub mask: u64
+                     , /// The memory type of addresses in the range
+                       pub ty: Option<MemoryType>
+                     }
+
+    /// Read the MTRR capabilities.
+    #[inline]
+    pub unsafe fn capabilities() -> Capabilities {
+        Capabilities::from_bits_truncate(super::read(IA32_MTRRCAP))
+    }
+
+    /// Returns the number of variable-range MTRRs.
+    #[inline]
+    pub unsafe fn variable_count() -> usize {
+        (capabilities() & VCNT).bits() as usize
+    }
+
+    /// Read `IA32_MTRR_DEF_TYPE`.
+    #[inline]
+    pub unsafe fn default_flags() -> DefaultFlags {
+        DefaultFlags::from_bits_truncate(super::read(IA32_MTRR_DEF_TYPE))
+    }
+
+    /// Returns the memory type of addresses not covered by any MTRR.
+    #[inline]
+    pub unsafe fn default_type() -> Option<MemoryType> {
+        MemoryType::from_bits((default_flags() & TYPE).bits() as u8)
+    }
+
+    /// Read the `n`th variable-range MTRR.
+    ///
+    /// Returns `None` if there is no such range, or it isn't enabled.
+    pub unsafe fn variable(n: usize) -> Option<Range> {
+        if n >= variable_count() { return None }
+        let msr = IA32_MTRR_PHYSBASE0 + 2 * n as u32;
+        let (base, mask) = (super::read(msr), super::read(msr + 1));
+        if mask & MASK_VALID == 0 { return None }
+        Some(Range { base: PAddr::from(base & ADDR_MASK)
+                   , mask: mask & ADDR_MASK
+                   , ty: MemoryType::from_bits(base as u8)
+                   })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn pat_round_trip() {
+        let entries = [ MemoryType::WriteBack, MemoryType::WriteThrough
+                      , MemoryType::Uncached, MemoryType::Uncacheable
+                      , MemoryType::WriteBack, MemoryType::WriteCombining
+                      , MemoryType::Uncached, MemoryType::Uncacheable ];
+        assert_eq!(pat::decode(pat::encode(&entries)), entries);
+        // the power-on default value of IA32_PAT
+        assert_eq!(pat::encode(&pat::DEFAULT), 0x0007_0406_0007_0406);
+    }
 }
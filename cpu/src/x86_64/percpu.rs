@@ -0,0 +1,299 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Per-CPU data.
+//!
+//! Per-CPU variables are declared with the [`percpu!`](../macro.percpu.html)
+//! macro, which places their initial values in the `.percpu` linker
+//! section. Each CPU has its own copy of that section, in a per-CPU _block_,
+//! and `IA32_GS_BASE` points at the current CPU's block while we're in the
+//! kernel. A variable's address on the current CPU is therefore the block's
+//! address plus the variable's offset in the section.
+//!
+//! When we return to user mode, `swapgs` should be used to swap the kernel's
+//! GS base into `IA32_KERNEL_GS_BASE`, and again on entry to the kernel to
+//! swap it back.
+//!
+//! The start of each block also holds a couple of words for the
+//! [`syscall`](../syscall/index.html) entry stub, which has to switch
+//! stacks before it has any registers to spare, so it finds them at fixed
+//! offsets from `%gs`.
+//!
+//! A per-CPU variable may only be accessed while the current thread can't
+//! be moved to another CPU, which is proven by a [`Pinned`](trait.Pinned.html)
+//! value, such as an [interrupt guard](../interrupts/struct.Guard.html).
+//!
+//! ```ignore
+//! percpu! {
+//!     /// Number of interrupts handled by this CPU.
+//!     static INTERRUPTS: usize = 0;
+//! }
+//!
+//! let guard = cpu::interrupts::disable();
+//! INTERRUPTS.with_mut(&guard, |count| *count += 1);
+//! ```
+#![warn(missing_docs)]
+use core::cell::UnsafeCell;
+use core::{mem, ptr};
+
+use memory::VAddr;
+use util::irq_lock;
+
+use interrupts;
+use msr;
+use smp::MAX_CPUS;
+
+/// Maximum size of the `.percpu` section, in bytes.
+pub const AREA_SIZE: usize = 4096;
+
+/// Offset from `%gs` of the kernel stack that `syscall` switches to.
+pub const SYSCALL_STACK: usize = 16;
+
+/// Offset from `%gs` of a scratch word where the `syscall` entry stub
+/// saves the user's stack pointer.
+pub const USER_STACK: usize = 24;
+
+extern {
+    /// Start of the `.percpu` section (from the linker script).
+    static percpu_start: u8;
+    /// End of the `.percpu` section (from the linker script).
+    static percpu_end: u8;
+}
+
+/// The start of a CPU's per-CPU block.
+///
+/// This is padded to 64 bytes, so that per-CPU variables with alignments up
+/// to 64 are still aligned in each CPU's copy.
+#[repr(C, align(64))]
+#[derive(Copy, Clone)]
+struct Header { /// the address of this header, so we can find it through
+                /// `%gs` without reading an MSR. must be first.
+                this: usize
+              , /// the current CPU's number. must be second, at
+                /// `irq_lock::CPU_NUMBER`.
+                cpu: usize
+              , /// the top of the running thread's kernel stack, for
+                /// `syscall`. must be at `SYSCALL_STACK`.
+                syscall_stack: usize
+              , /// the user's stack pointer, while the `syscall` entry
+                /// stub switches stacks. must be at `USER_STACK`.
+                user_stack: usize
+              }
+
+/// A CPU's per-CPU block.
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct Block { header: Header
+             , data: [u8; AREA_SIZE]
+             }
+
+/// Per-CPU blocks, indexed by CPU number.
+static mut BLOCKS: [Block; MAX_CPUS]
+    = [ Block { header: Header { this: 0, cpu: 0
+                               , syscall_stack: 0, user_stack: 0 }
+              , data: [0; AREA_SIZE] }
+      ; MAX_CPUS ];
+
+/// Proof that the current thread can't migrate to another CPU.
+///
+/// This is unsafe to implement, since per-CPU variables can be accessed
+/// safely by anyone holding a `Pinned` value.
+pub unsafe trait Pinned { }
+
+unsafe impl Pinned for interrupts::Guard { }
+
+/// Set up the per-CPU block for CPU number `cpu`, and point `IA32_GS_BASE`
+/// at it.
+///
+/// # Safety
+/// + This should be called once on each CPU, with its own CPU number, after
+///   the segment registers have been loaded, and before it takes any
+///   `IrqSpinlock`s.
+pub unsafe fn initialize(cpu: usize) -> Result<(), &'static str> {
+    let start = &percpu_start as *const u8;
+    let len = &percpu_end as *const u8 as usize - start as usize;
+    if len > AREA_SIZE {
+        return Err("Per-CPU data doesn't fit in a per-CPU block")
+    }
+    let block = &mut BLOCKS[cpu];
+    ptr::copy_nonoverlapping(start, block.data.as_mut_ptr(), len);
+    block.header.this = &block.header as *const Header as usize;
+    block.header.cpu = cpu;
+
+    msr::gs_base::write(block.header.this as u64);
+    msr::kernel_gs_base::write(0);
+    irq_lock::use_per_cpu();
+    Ok(())
+}
+
+/// Returns true if this CPU's per-CPU block has been set up.
+#[inline]
+pub fn is_initialized() -> bool {
+    unsafe { msr::gs_base::read() != 0 }
+}
+
+/// Returns the current CPU's number.
+///
+/// # Safety
+/// + The current CPU's per-CPU block must have been
+///   [initialized](fn.initialize.html).
+#[inline]
+pub unsafe fn cpu() -> usize {
+    let cpu: usize;
+    asm!( "mov $0, gs:[8]"
+        : "=r"(cpu)
+        ::: "intel");
+    cpu
+}
+
+/// Set the kernel stack the current CPU switches to when user code makes a
+/// system call with `syscall`.
+///
+/// # Safety
+/// + The current CPU's per-CPU block must have been
+///   [initialized](fn.initialize.html).
+/// + `top` must be the top of a mapped kernel stack, which nothing else is
+///   using while the current CPU runs in user mode.
+#[inline]
+pub unsafe fn set_syscall_stack(top: VAddr) {
+    asm!( "mov gs:[16], $0"
+        :: "r"(top.as_usize())
+        :  "memory"
+        :  "intel", "volatile");
+}
+
+/// A per-CPU variable.
+///
+/// These should be declared with the [`percpu!`](../macro.percpu.html)
+/// macro, rather than constructed directly.
+pub struct PerCpu<T> { initial: UnsafeCell<T> }
+
+unsafe impl<T: Send> Sync for PerCpu<T> { }
+
+impl<T> PerCpu<T> {
+    #[doc(hidden)]
+    pub const fn new(initial: T) -> Self {
+        PerCpu { initial: UnsafeCell::new(initial) }
+    }
+
+    /// Returns a pointer to the current CPU's copy of this variable.
+    ///
+    /// # Safety
+    /// + The current CPU's per-CPU block must have been
+    ///   [initialized](fn.initialize.html), and the pointer must not be
+    ///   used after the current thread migrates to another CPU.
+    #[inline]
+    pub unsafe fn as_ptr(&'static self) -> *mut T {
+        let block: usize;
+        asm!( "mov $0, gs:[0]"
+            : "=r"(block)
+            ::: "intel");
+        (block + mem::size_of::<Header>() + self.offset()) as *mut T
+    }
+
+    /// Returns this variable's offset into a per-CPU block's data.
+    #[inline]
+    fn offset(&'static self) -> usize {
+        self.initial.get() as usize - unsafe {
+            &percpu_start as *const u8 as usize
+        }
+    }
+
+    /// Returns a reference to CPU number `cpu`'s copy of this variable.
+    ///
+    /// Since `cpu` may be using its copy at the same time, this is only
+    /// possible for `Sync` types.
+    ///
+    /// # Panics
+    /// + If `cpu`'s per-CPU block hasn't been
+    ///   [initialized](fn.initialize.html).
+    pub fn on(&'static self, cpu: usize) -> &'static T
+    where T: Sync {
+        let this = unsafe { BLOCKS[cpu].header.this };
+        assert!(this != 0, "CPU {}'s per-CPU block isn't initialized", cpu);
+        unsafe {
+            &*((this + mem::size_of::<Header>() + self.offset()) as *const T)
+        }
+    }
+
+    /// Returns a reference to the current CPU's copy of this variable.
+    #[inline]
+    pub fn get<'a, P: Pinned>(&'static self, _pinned: &'a P) -> &'a T {
+        unsafe { &*self.as_ptr() }
+    }
+
+    /// Call `f` with a mutable reference to the current CPU's copy of this
+    /// variable.
+    ///
+    /// `f` must not access this variable itself.
+    #[inline]
+    pub fn with_mut<P, F, R>(&'static self, _pinned: &P, f: F) -> R
+    where P: Pinned
+        , F: FnOnce(&mut T) -> R {
+        f(unsafe { &mut *self.as_ptr() })
+    }
+}
+
+impl<T: Copy> PerCpu<T> {
+    /// Returns the value of the current CPU's copy of this variable.
+    #[inline]
+    pub fn read<P: Pinned>(&'static self, pinned: &P) -> T {
+        *self.get(pinned)
+    }
+
+    /// Set the current CPU's copy of this variable to `value`.
+    #[inline]
+    pub fn write<P: Pinned>(&'static self, pinned: &P, value: T) {
+        self.with_mut(pinned, |v| *v = value)
+    }
+}
+
+/// Declare per-CPU variables.
+///
+/// Each declaration looks like a `static`, but declares a
+/// [`PerCpu`](percpu/struct.PerCpu.html) holding a value of the given type,
+/// which each CPU starts out with a copy of.
+#[macro_export]
+macro_rules! percpu {
+    ( $(#[$attr:meta])* static $name:ident: $ty:ty = $init:expr;
+      $($tail:tt)* ) => {
+        $(#[$attr])*
+        #[link_section = ".percpu"]
+        static $name: $crate::percpu::PerCpu<$ty>
+            = $crate::percpu::PerCpu::new($init);
+        percpu! { $($tail)* }
+    };
+    ( $(#[$attr:meta])* pub static $name:ident: $ty:ty = $init:expr;
+      $($tail:tt)* ) => {
+        $(#[$attr])*
+        #[link_section = ".percpu"]
+        pub static $name: $crate::percpu::PerCpu<$ty>
+            = $crate::percpu::PerCpu::new($init);
+        percpu! { $($tail)* }
+    };
+    () => {};
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn header_offsets() {
+        let header = Header { this: 0, cpu: 0
+                            , syscall_stack: 0, user_stack: 0 };
+        let base = &header as *const Header as usize;
+        assert_eq!(&header.cpu as *const usize as usize - base
+                  , irq_lock::CPU_NUMBER);
+        assert_eq!(&header.syscall_stack as *const usize as usize - base
+                  , SYSCALL_STACK);
+        assert_eq!(&header.user_stack as *const usize as usize - base
+                  , USER_STACK);
+        assert_eq!(mem::size_of::<Header>(), 64);
+    }
+}
@@ -0,0 +1,102 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Bookkeeping for symmetric multiprocessing.
+//!
+//! Each logical CPU is identified by a _CPU number_, counting up from 0 for
+//! the bootstrap processor (BSP) in the order the CPUs were brought up. CPU
+//! numbers are dense, so they can be used to index per-CPU arrays; local
+//! APIC IDs, which the hardware uses to address CPUs, need not be.
+#![warn(missing_docs)]
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use {apic, percpu};
+
+/// The maximum number of CPUs we'll bring up.
+pub const MAX_CPUS: usize = 16;
+
+/// The CPU number of the bootstrap processor.
+pub const BSP: usize = 0;
+
+/// Bitmap of the CPUs which are online.
+static ONLINE: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of CPU numbers which have been assigned.
+static ASSIGNED: AtomicUsize = AtomicUsize::new(1);
+
+/// Local APIC IDs of each CPU, indexed by CPU number.
+///
+/// Entries are only written by the BSP, before the CPU they describe is
+/// started.
+static mut APIC_IDS: [u8; MAX_CPUS] = [0; MAX_CPUS];
+
+/// Assign the next free CPU number to the CPU with the local APIC ID
+/// `apic_id`.
+///
+/// # Returns
+/// + `Some(cpu)` with the new CPU number
+/// + `None` if there are already `MAX_CPUS` CPUs.
+///
+/// # Safety
+/// + This should only be called by the BSP.
+pub unsafe fn assign(apic_id: u8) -> Option<usize> {
+    let cpu = ASSIGNED.load(Ordering::SeqCst);
+    if cpu >= MAX_CPUS { return None }
+    APIC_IDS[cpu] = apic_id;
+    ASSIGNED.store(cpu + 1, Ordering::SeqCst);
+    Some(cpu)
+}
+
+/// Record the local APIC ID of the bootstrap processor.
+///
+/// # Safety
+/// + This should only be called by the BSP, once its local APIC is enabled.
+pub unsafe fn initialize_bsp() {
+    APIC_IDS[BSP] = apic::id();
+    mark_online(BSP);
+}
+
+/// Mark `cpu` as online.
+#[inline]
+pub fn mark_online(cpu: usize) {
+    ONLINE.fetch_or(1 << cpu, Ordering::SeqCst);
+}
+
+/// Returns true if `cpu` is online.
+#[inline]
+pub fn is_online(cpu: usize) -> bool {
+    cpu < MAX_CPUS && ONLINE.load(Ordering::SeqCst) & (1 << cpu) != 0
+}
+
+/// Returns a bitmap of the CPUs which are online.
+#[inline]
+pub fn online_mask() -> usize { ONLINE.load(Ordering::SeqCst) }
+
+/// Returns the number of CPUs which are online.
+#[inline]
+pub fn online_count() -> usize { online_mask().count_ones() as usize }
+
+/// Returns the local APIC ID of `cpu`.
+#[inline]
+pub fn apic_id(cpu: usize) -> u8 {
+    unsafe { APIC_IDS[cpu] }
+}
+
+/// Returns the CPU number of the current CPU.
+///
+/// Once the current CPU's [per-CPU block](../percpu/index.html) is set up,
+/// this is read from there; before then, it's looked up by local APIC ID.
+/// Before the local APIC has been initialized, this is always the BSP.
+pub fn current() -> usize {
+    if percpu::is_initialized() { return unsafe { percpu::cpu() } }
+    if !apic::is_initialized() { return BSP }
+    let id = apic::id();
+    let assigned = ASSIGNED.load(Ordering::SeqCst);
+    (0..assigned).find(|&cpu| apic_id(cpu) == id)
+                 .unwrap_or(BSP)
+}
@@ -0,0 +1,79 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! The `syscall` and `sysret` instructions.
+//!
+//! `syscall` jumps to the address in `IA32_LSTAR` in ring 0, with the user's
+//! return address in `%rcx` and its `%rflags` in `%r11`, and clears the
+//! `%rflags` bits in `IA32_FMASK`. Unlike an interrupt, it doesn't switch
+//! stacks, so the entry stub has to:
+//!
+//! 1. `swapgs`, to find the current CPU's
+//!    [per-CPU block](../percpu/index.html)
+//! 2. save the user's `%rsp` at
+//!    [`percpu::USER_STACK`](../percpu/constant.USER_STACK.html)
+//! 3. load the kernel stack from
+//!    [`percpu::SYSCALL_STACK`](../percpu/constant.SYSCALL_STACK.html),
+//!    which is set with
+//!    [`percpu::set_syscall_stack`](../percpu/fn.set_syscall_stack.html)
+//!    whenever a thread is switched to
+//! 4. push a [`Frame`](struct.Frame.html).
+//!
+//! Interrupts are masked until it's done, so no interrupt handler can run
+//! on the user's stack, or see the user's GS base, in the meantime. (An NMI
+//! still could, until NMIs get a stack of their own.) To return, the stub
+//! pops the frame, loads the user's `%rsp`, and does `swapgs` and `sysretq`.
+//!
+//! On Intel CPUs, `sysretq` to a non-canonical return address raises a
+//! general protection fault in ring 0, but with the user's `%rsp` and GS
+//! base, so the stub must check the return address first, and return with
+//! `iretq` if it isn't a user address.
+use context::Registers;
+use flags;
+use gdt::{KERNEL_CODE, KERNEL_DATA};
+use msr::{efer, lstar, sfmask, star};
+
+/// The user's registers, saved on the kernel stack by a `syscall` entry
+/// stub.
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+pub struct Frame { /// the general-purpose registers, as they were when
+                   /// `syscall` was executed
+                   pub registers: Registers
+                 , /// where to return to (from `%rcx`)
+                   pub rip: u64
+                 , /// the user's `%rflags` (from `%r11`)
+                   pub rflags: u64
+                 , /// the user's stack pointer
+                   pub rsp: u64
+                 }
+
+/// Enable `syscall` and `sysret` on the current CPU, with `entry` as the
+/// entry point for `syscall`.
+///
+/// `syscall` runs `entry` with interrupts disabled, and with the direction,
+/// trap and alignment check flags cleared.
+///
+/// # Safety
+/// + This should be called on each CPU, once its GDT has been loaded.
+/// + `entry` must be a naked entry stub which works as described in the
+///   [module docs](index.html).
+pub unsafe fn initialize(entry: unsafe extern "C" fn()) {
+    // `sysret` loads the user segments from 8 and 16 past the kernel data
+    // segment (see `gdt`)
+    star::write(KERNEL_CODE, KERNEL_DATA);
+    lstar::write(entry as usize as u64);
+    sfmask::write(flags::IF | flags::TF | flags::DF | flags::AC);
+    efer::write(efer::read() | efer::SCE);
+}
+
+/// Returns true if `syscall` has been enabled on the current CPU.
+#[inline]
+pub fn is_enabled() -> bool {
+    unsafe { efer::read().contains(efer::SCE) }
+}
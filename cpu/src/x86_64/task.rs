@@ -17,15 +17,18 @@ pub struct StateDescriptor { pub upper: segment::Descriptor
                            }
 
 
+/// Size of a 64-bit [`StateSegment`](struct.StateSegment.html), in bytes.
+pub const SIZE: usize = 104;
+
 /// A 64-bit Task State Segment
 #[repr(C, packed)]
-#[derive(Debug)]
+#[derive(Copy, Clone, Debug)]
 pub struct StateSegment {
     _reserved_1: u32
   , /// 64-bit values of the stack pointers (`%rsp`) for privilege rings 0-2
     //  TODO: should this be an array or just three u64s?
     pub rsp: [VAddr; 3]
-  , _reserved_2: u32
+  , _reserved_2: u64
   , /// 64-bit values of the interrupt stack table registers
     pub ist: [VAddr; 7]
   , _reserved_3: u64
@@ -44,7 +47,9 @@ impl StateSegment {
                      , ist: [ VAddr::new(0); 7 ]
                      , _reserved_3: 0
                      , _reserved_4: 0
-                     , iomap_base_offset: 0
+                     // an I/O map base past the end of the segment means
+                     // there is no I/O permission bitmap
+                     , iomap_base_offset: SIZE as u16
                      }
     }
 }
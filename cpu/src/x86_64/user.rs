@@ -0,0 +1,119 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Entering user mode.
+//!
+//! We get to ring 3 the same way we return from an interrupt: by building
+//! an interrupt stack frame with the user segments and executing `iretq`.
+//! Before that, `swapgs` puts the user's GS base in place and parks the
+//! kernel's in `IA32_KERNEL_GS_BASE`, so the kernel has to `swapgs` again on
+//! every entry from user mode (see the [`percpu`](../percpu/index.html)
+//! module).
+//!
+//! When an interrupt or exception arrives in user mode, the CPU switches to
+//! the stack in the TSS's `rsp[0]`, which should be set to the current
+//! thread's kernel stack with
+//! [`gdt::set_kernel_stack`](../gdt/fn.set_kernel_stack.html). Interrupt
+//! handlers which may run in user mode should start by taking a
+//! [`KernelGs`](struct.KernelGs.html) guard, so that per-CPU data can be
+//! used.
+use core::marker::PhantomData;
+
+use memory::VAddr;
+
+use context::InterruptFrame;
+use flags;
+use gdt::{USER_CODE, USER_DATA};
+
+/// The `%rflags` bit which is always set.
+const RFLAGS_RESERVED: usize = 1 << 1;
+
+/// Drop to ring 3, and start running at `entry` with the stack pointer set
+/// to `stack`.
+///
+/// All the general-purpose registers are zeroed, so no kernel data leaks
+/// into user mode.
+///
+/// # Safety
+/// + `entry` and `stack` must be mapped user-accessible in the current
+///   address space.
+/// + The current CPU's TSS must have a kernel stack to come back to.
+/// + Interrupts are enabled on the way out, so anything the kernel was
+///   doing on this stack is abandoned.
+pub unsafe fn enter(entry: VAddr, stack: VAddr) -> ! {
+    // user code starts out with interrupts enabled, and nothing else
+    let rflags = flags::IF.bits() | RFLAGS_RESERVED;
+    asm!( "cli
+           push $0
+           push $1
+           push $2
+           push $3
+           push $4
+           xor rax, rax
+           xor rbx, rbx
+           xor rcx, rcx
+           xor rdx, rdx
+           xor rsi, rsi
+           xor rdi, rdi
+           xor rbp, rbp
+           xor r8, r8
+           xor r9, r9
+           xor r10, r10
+           xor r11, r11
+           xor r12, r12
+           xor r13, r13
+           xor r14, r14
+           xor r15, r15
+           swapgs
+           iretq"
+        :: "r"(USER_DATA.bits() as u64)
+         , "r"(stack.as_usize() as u64)
+         , "r"(rflags as u64)
+         , "r"(USER_CODE.bits() as u64)
+         , "r"(entry.as_usize() as u64)
+        :  "memory"
+        :  "intel", "volatile");
+    unreachable!()
+}
+
+/// Makes sure the kernel's GS base is loaded while an interrupt handler
+/// runs.
+///
+/// If the interrupt arrived in user mode, taking the guard swaps in the
+/// kernel's GS base, and dropping it swaps the user's back in.
+#[must_use]
+pub struct KernelGs { swapped: bool
+                    , _not_send: PhantomData<*const ()>
+                    }
+
+impl KernelGs {
+    /// Load the kernel's GS base, if the interrupt that `frame` belongs to
+    /// arrived in user mode.
+    ///
+    /// This should be the first thing an interrupt handler does, and the
+    /// guard must live until the handler returns.
+    #[inline(always)]
+    pub fn enter(frame: &InterruptFrame) -> Self {
+        let swapped = frame.is_user();
+        if swapped { unsafe { swapgs(); } }
+        KernelGs { swapped: swapped, _not_send: PhantomData }
+    }
+}
+
+impl Drop for KernelGs {
+    #[inline(always)]
+    fn drop(&mut self) {
+        if self.swapped { unsafe { swapgs(); } }
+    }
+}
+
+/// Swap the GS base with `IA32_KERNEL_GS_BASE`.
+#[inline(always)]
+unsafe fn swapgs() {
+    asm!("swapgs" ::: "memory" : "volatile");
+}
@@ -0,0 +1,179 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Frame-pointer stack backtraces.
+//!
+//! The kernel is built with frame pointers, so every stack frame begins with
+//! the caller's frame pointer, followed by the return address:
+//!
+//! ```text
+//!            +------------------+
+//!  bp + 8 -> |  return address  |
+//!            +------------------+
+//!  bp     -> | caller's bp      | ---> next frame
+//!            +------------------+
+//! ```
+//!
+//! Walking this chain gives us the return address of every frame on the
+//! stack. The boot code zeroes the frame pointer before calling into Rust, so
+//! the chain is terminated by a null frame pointer.
+#![warn(missing_docs)]
+use core::{fmt, mem};
+
+/// Maximum number of frames to walk, in case the chain is corrupted.
+pub const MAX_FRAMES: usize = 32;
+
+/// Size of a machine word, in bytes.
+const WORD: usize = mem::size_of::<usize>();
+
+/// An iterator over the return addresses of the frames on the stack.
+#[derive(Copy, Clone, Debug)]
+pub struct Frames { frame_pointer: usize
+                  , depth: usize
+                  }
+
+impl Frames {
+    /// Walk the stack starting from the frame pointed to by `frame_pointer`.
+    ///
+    /// # Safety
+    /// + `frame_pointer` must point to a valid stack frame, or be 0.
+    pub unsafe fn from_frame_pointer(frame_pointer: usize) -> Self {
+        Frames { frame_pointer: frame_pointer, depth: 0 }
+    }
+
+    /// Walk the stack starting from the caller's frame.
+    #[inline(always)]
+    pub fn current() -> Self {
+        unsafe { Frames::from_frame_pointer(frame_pointer()) }
+    }
+}
+
+impl Iterator for Frames {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        let bp = self.frame_pointer;
+        if bp == 0 || bp % WORD != 0 || self.depth >= MAX_FRAMES {
+            return None
+        }
+        let (next_bp, return_addr) = unsafe {
+            ( *(bp as *const usize)
+            , *((bp + WORD) as *const usize) )
+        };
+        // the stack grows down, so the caller's frame must be above ours;
+        // if it isn't, the chain is corrupted and we should stop here.
+        self.frame_pointer = if next_bp > bp { next_bp } else { 0 };
+        self.depth += 1;
+        if return_addr == 0 { None } else { Some(return_addr) }
+    }
+}
+
+/// Resolves an address to a symbol name and the offset into that symbol.
+pub type Resolver = fn(usize) -> Option<(&'static str, usize)>;
+
+/// Function used to resolve symbol names when formatting backtraces.
+static mut RESOLVER: Option<Resolver> = None;
+
+/// Set the function used to resolve addresses to symbol names.
+///
+/// # Safety
+/// + This should only be called once, during init, before any other CPUs
+///   are started.
+pub unsafe fn set_resolver(resolver: Resolver) {
+    RESOLVER = Some(resolver);
+}
+
+/// Resolve `addr` to a symbol name and offset, if a resolver has been set and
+/// it knows about `addr`.
+#[inline]
+pub fn resolve(addr: usize) -> Option<(&'static str, usize)> {
+    unsafe { RESOLVER }.and_then(|resolve| resolve(addr))
+}
+
+/// Formats a Rust symbol name, demangling it if it's mangled.
+///
+/// This understands just enough of the legacy Rust mangling scheme
+/// (`_ZN` + length-prefixed path segments + `E`) to print paths like
+/// `sos_kernel::kernel_init`. The trailing hash segment is omitted. Names
+/// that don't look mangled are printed unchanged.
+pub struct Demangle<'a>(pub &'a str);
+
+impl<'a> fmt::Display for Demangle<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = self.0;
+        if !name.starts_with("_ZN") || !name.ends_with('E') {
+            return f.write_str(name)
+        }
+        let mut rest = &name[3..name.len() - 1];
+        let mut first = true;
+        while !rest.is_empty() {
+            let digits = rest.bytes()
+                             .take_while(|&b| b'0' <= b && b <= b'9')
+                             .count();
+            let len: usize = match rest[..digits].parse() {
+                Ok(len) if digits + len <= rest.len() => len
+              , _ => return f.write_str(name) // not mangled after all
+            };
+            let segment = &rest[digits..digits + len];
+            rest = &rest[digits + len..];
+            // the last segment is a hash, like `h0123456789abcdef`
+            if rest.is_empty() && segment.len() == 17
+                && segment.starts_with('h') {
+                break;
+            }
+            if !first { f.write_str("::")?; }
+            f.write_str(segment)?;
+            first = false;
+        }
+        Ok(())
+    }
+}
+
+/// A formattable backtrace.
+#[derive(Copy, Clone, Debug)]
+pub struct Backtrace(pub Frames);
+
+impl Backtrace {
+    /// Capture a backtrace of the caller's stack.
+    #[inline(always)]
+    pub fn current() -> Self { Backtrace(Frames::current()) }
+}
+
+impl fmt::Display for Backtrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Backtrace:")?;
+        for (i, addr) in self.0.enumerate() {
+            match resolve(addr) {
+                Some((name, offset)) =>
+                    write!( f, "\n  {:>2}: {:#018x} {}+{:#x}"
+                          , i, addr, Demangle(name), offset)?
+              , None => write!(f, "\n  {:>2}: {:#018x} <unknown>", i, addr)?
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the current value of the frame pointer register.
+#[cfg(target_arch = "x86_64")]
+#[inline(always)]
+pub fn frame_pointer() -> usize {
+    let bp: usize;
+    unsafe { asm!("mov $0, rbp" : "=r"(bp) ::: "intel"); }
+    bp
+}
+
+/// Returns the current value of the frame pointer register.
+#[cfg(target_arch = "x86")]
+#[inline(always)]
+pub fn frame_pointer() -> usize {
+    let bp: usize;
+    unsafe { asm!("mov $0, ebp" : "=r"(bp) ::: "intel"); }
+    bp
+}
+
@@ -82,6 +82,11 @@ bitflags! {
         ///
         /// If set, enables unmasked SSE exceptions.
         const OSXMMEXCPT = 1 << 10
+      , /// User-Mode Instruction Prevention
+        ///
+        /// If set, the `SGDT`, `SIDT`, `SLDT`, `SMSW`, and `STR` instructions
+        /// can only be executed in Ring 0.
+        const UMIP = 1 << 11
       , /// Virtual Machine Extensions Enable
         const VMXE = 1 << 13
       , /// Safer Mode Extensions Enable
@@ -100,7 +105,7 @@ bitflags! {
         const SMEP = 1 << 20
       , /// Supervisor Mode Access Protection Enable
         ///
-        /// If set, access of data in a higher ring generates a faul
+        /// If set, access of data in a higher ring generates a fault
         const SMAP = 1 << 21
       , /// Protection Key Enable
         const PKE = 1 << 22
@@ -117,3 +122,22 @@ cpu_flag! {
     doc="If disabled, the `RTDSC` instruction can only be executed in Ring 0.",
     TSD, is_timestamp_disabled, disable_timestamp
 }
+cpu_flag! {
+    doc="If set, page translations marked global are not flushed when `%cr3` \
+        is written.",
+    PGE, is_global_pages_enabled, enable_global_pages
+}
+cpu_flag! {
+    doc="If set, executing code in user pages while in Ring 0 faults.",
+    SMEP, is_smep_enabled, enable_smep
+}
+cpu_flag! {
+    doc="If set, accessing user pages while in Ring 0 faults, unless the `AC` \
+        flag is set.",
+    SMAP, is_smap_enabled, enable_smap
+}
+cpu_flag! {
+    doc="If set, descriptor table instructions can only be executed in \
+        Ring 0.",
+    UMIP, is_umip_enabled, enable_umip
+}
@@ -121,7 +121,7 @@ pub mod cr3 {
         asm!(   "mov $0, cr3"
             :   "=r"(result)
             ::: "intel" );
-        PAddr::from(result)
+        PAddr::from(result as u64)
     }
 
     /// Write a value to `$cr3`.
@@ -145,10 +145,11 @@ pub mod cr3 {
     ///   operation.
     #[cfg(target_arch = "x86")]
     pub unsafe fn write(addr: PAddr) {
-        let value: u32 = addr.into();
+        let value = *addr as u32;
         asm!(  "mov cr3, $0"
             :: "r"(value)
-            :: "intel");
+            :  "memory"
+            :  "intel");
     }
 
     /// Returns the current Page Directory base frame.
@@ -0,0 +1,539 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! CPU identification and feature detection using the `cpuid` instruction.
+//!
+//! The information reported by `cpuid` doesn't change while the system is
+//! running, so each leaf is only queried once. [`info()`](fn.info.html)
+//! returns the cached results, and [`has()`](fn.has.html) is a shorthand for
+//! checking whether the CPU supports a particular feature.
+//!
+//! The one exception is `OSXSAVE`, which mirrors a bit in `%cr4` that the
+//! kernel sets after the CPU is first queried, so it's read from `%cr4`
+//! whenever it's asked for.
+#![warn(missing_docs)]
+use core::{fmt, str};
+use spin::Once;
+
+use control_regs::cr4;
+
+/// Leaf reporting the highest basic leaf and the vendor string.
+const LEAF_VENDOR: u32 = 0x0;
+/// Leaf reporting the processor signature and basic feature bits.
+const LEAF_FEATURES: u32 = 0x1;
+/// Leaf reporting deterministic cache parameters (Intel).
+const LEAF_CACHES: u32 = 0x4;
+/// Leaf reporting structured extended feature bits.
+const LEAF_EXT_FEATURES: u32 = 0x7;
+/// Leaf reporting the highest extended leaf.
+const LEAF_EXT_MAX: u32 = 0x8000_0000;
+/// Leaf reporting extended processor feature bits.
+const LEAF_EXT_PROC_FEATURES: u32 = 0x8000_0001;
+/// First of the three leaves containing the brand string.
+const LEAF_BRAND: u32 = 0x8000_0002;
+/// Leaf reporting advanced power management features.
+const LEAF_POWER: u32 = 0x8000_0007;
+/// Leaf reporting cache topology (AMD, requires `TOPOEXT`).
+const LEAF_AMD_CACHES: u32 = 0x8000_001d;
+
+/// The registers returned by the `cpuid` instruction.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Registers { /// `%eax`
+                       pub eax: u32
+                     , /// `%ebx`
+                       pub ebx: u32
+                     , /// `%ecx`
+                       pub ecx: u32
+                     , /// `%edx`
+                       pub edx: u32
+                     }
+
+/// Execute `cpuid` for the given `leaf` and `subleaf`.
+///
+/// Callers should check that `leaf` is supported (see
+/// [`Info::max_leaf`](struct.Info.html#structfield.max_leaf)); unsupported
+/// leaves return the data for the highest supported basic leaf.
+#[inline]
+pub fn cpuid(leaf: u32, subleaf: u32) -> Registers {
+    let (eax, ebx, ecx, edx): (u32, u32, u32, u32);
+    unsafe {
+        asm!( "cpuid"
+            : "={eax}" (eax), "={ebx}" (ebx), "={ecx}" (ecx), "={edx}" (edx)
+            : "{eax}" (leaf), "{ecx}" (subleaf)
+            :: "volatile");
+    }
+    Registers { eax: eax, ebx: ebx, ecx: ecx, edx: edx }
+}
+
+bitflags! {
+    /// CPU features detected by `cpuid`.
+    ///
+    /// These are collected from several different `cpuid` leaves, so the bit
+    /// positions don't correspond to any particular register.
+    pub flags Features: u64 {
+        /// x87 floating-point unit on-chip
+        const FPU =           1 << 0,
+        /// Timestamp counter (`rdtsc`)
+        const TSC =           1 << 1,
+        /// Model-specific registers (`rdmsr`/`wrmsr`)
+        const MSR =           1 << 2,
+        /// Physical address extension
+        const PAE =           1 << 3,
+        /// Local APIC on-chip
+        const APIC =          1 << 4,
+        /// Page global enable
+        const PGE =           1 << 5,
+        /// Page attribute table
+        const PAT =           1 << 6,
+        /// `clflush` instruction
+        const CLFLUSH =       1 << 7,
+        /// `fxsave`/`fxrstor` instructions
+        const FXSR =          1 << 8,
+        /// SSE extensions
+        const SSE =           1 << 9,
+        /// SSE2 extensions
+        const SSE2 =          1 << 10,
+        /// More than one logical processor per package
+        const HTT =           1 << 11,
+        /// SSE3 extensions
+        const SSE3 =          1 << 12,
+        /// Process-context identifiers
+        const PCID =          1 << 13,
+        /// x2APIC mode
+        const X2APIC =        1 << 14,
+        /// APIC timer TSC-deadline mode
+        const TSC_DEADLINE =  1 << 15,
+        /// `xsave`/`xrstor` and `XCR0`
+        const XSAVE =         1 << 16,
+        /// `XSAVE` has been enabled by the OS (`%cr4.OSXSAVE`)
+        const OSXSAVE =       1 << 17,
+        /// AVX extensions
+        const AVX =           1 << 18,
+        /// `rdrand` instruction
+        const RDRAND =        1 << 19,
+        /// `rdfsbase`/`wrfsbase`/`rdgsbase`/`wrgsbase` instructions
+        const FSGSBASE =      1 << 20,
+        /// Supervisor-mode execution prevention
+        const SMEP =          1 << 21,
+        /// `invpcid` instruction
+        const INVPCID =       1 << 22,
+        /// Supervisor-mode access prevention
+        const SMAP =          1 << 23,
+        /// User-mode instruction prevention
+        const UMIP =          1 << 24,
+        /// Execute-disable bit
+        const NX =            1 << 25,
+        /// 1 GiB pages
+        const PAGE_1GB =      1 << 26,
+        /// `rdtscp` instruction
+        const RDTSCP =        1 << 27,
+        /// Long mode (x86_64)
+        const LONG_MODE =     1 << 28,
+        /// The TSC runs at a constant rate in all power states
+        const INVARIANT_TSC = 1 << 29,
+        /// Extended cache topology leaf (AMD)
+        const TOPOEXT =       1 << 30
+    }
+}
+
+/// Table mapping `cpuid` register bits to `Features`.
+///
+/// Each entry is `(leaf, register, bit, feature)`, where `register` is 0-3 for
+/// `eax`, `ebx`, `ecx`, and `edx`, respectively.
+const FEATURE_BITS: [(u32, u8, u8, Features); 31]
+    = [ (LEAF_FEATURES, 3, 0, FPU)
+      , (LEAF_FEATURES, 3, 4, TSC)
+      , (LEAF_FEATURES, 3, 5, MSR)
+      , (LEAF_FEATURES, 3, 6, PAE)
+      , (LEAF_FEATURES, 3, 9, APIC)
+      , (LEAF_FEATURES, 3, 13, PGE)
+      , (LEAF_FEATURES, 3, 16, PAT)
+      , (LEAF_FEATURES, 3, 19, CLFLUSH)
+      , (LEAF_FEATURES, 3, 24, FXSR)
+      , (LEAF_FEATURES, 3, 25, SSE)
+      , (LEAF_FEATURES, 3, 26, SSE2)
+      , (LEAF_FEATURES, 3, 28, HTT)
+      , (LEAF_FEATURES, 2, 0, SSE3)
+      , (LEAF_FEATURES, 2, 17, PCID)
+      , (LEAF_FEATURES, 2, 21, X2APIC)
+      , (LEAF_FEATURES, 2, 24, TSC_DEADLINE)
+      , (LEAF_FEATURES, 2, 26, XSAVE)
+      , (LEAF_FEATURES, 2, 27, OSXSAVE)
+      , (LEAF_FEATURES, 2, 28, AVX)
+      , (LEAF_FEATURES, 2, 30, RDRAND)
+      , (LEAF_EXT_FEATURES, 1, 0, FSGSBASE)
+      , (LEAF_EXT_FEATURES, 1, 7, SMEP)
+      , (LEAF_EXT_FEATURES, 1, 10, INVPCID)
+      , (LEAF_EXT_FEATURES, 1, 20, SMAP)
+      , (LEAF_EXT_FEATURES, 2, 2, UMIP)
+      , (LEAF_EXT_PROC_FEATURES, 3, 20, NX)
+      , (LEAF_EXT_PROC_FEATURES, 3, 26, PAGE_1GB)
+      , (LEAF_EXT_PROC_FEATURES, 3, 27, RDTSCP)
+      , (LEAF_EXT_PROC_FEATURES, 3, 29, LONG_MODE)
+      , (LEAF_EXT_PROC_FEATURES, 2, 22, TOPOEXT)
+      , (LEAF_POWER, 3, 8, INVARIANT_TSC)
+      ];
+
+impl Features {
+    /// Decode the features reported in `regs`, the result of querying `leaf`.
+    pub fn from_leaf(leaf: u32, regs: &Registers) -> Self {
+        let words = [regs.eax, regs.ebx, regs.ecx, regs.edx];
+        FEATURE_BITS.iter()
+            .filter(|&&(l, reg, bit, _)|
+                l == leaf && words[reg as usize] & (1 << bit) != 0)
+            .fold(Features::empty(), |features, &(_, _, _, f)| features | f)
+    }
+}
+
+/// CPU manufacturers.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Vendor { /// `GenuineIntel`
+                  Intel
+                , /// `AuthenticAMD`
+                  Amd
+                , /// Any other vendor string
+                  Other([u8; 12])
+                }
+
+impl Vendor {
+    /// Decode the vendor string returned by leaf 0.
+    pub fn from_registers(regs: &Registers) -> Self {
+        let mut id = [0u8; 12];
+        // the vendor string is in ebx, edx, ecx (in that order)
+        for (i, word) in [regs.ebx, regs.edx, regs.ecx].iter().enumerate() {
+            for byte in 0..4 {
+                id[i * 4 + byte] = (word >> (byte * 8)) as u8;
+            }
+        }
+        match &id {
+            b"GenuineIntel" => Vendor::Intel
+          , b"AuthenticAMD" => Vendor::Amd
+          , _ => Vendor::Other(id)
+        }
+    }
+}
+
+impl fmt::Display for Vendor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Vendor::Intel => f.write_str("Intel")
+          , Vendor::Amd => f.write_str("AMD")
+          , Vendor::Other(ref id) =>
+                f.write_str(str::from_utf8(id).unwrap_or("unknown"))
+        }
+    }
+}
+
+/// The processor's family, model, and stepping.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Signature { /// The display family, including the extended family
+                       pub family: u16
+                     , /// The display model, including the extended model
+                       pub model: u8
+                     , /// The stepping ID
+                       pub stepping: u8
+                     }
+
+impl Signature {
+    /// Decode the processor signature in `%eax` from leaf 1.
+    pub fn from_eax(eax: u32) -> Self {
+        let family = ((eax >> 8) & 0xf) as u16;
+        let model = ((eax >> 4) & 0xf) as u8;
+        Signature {
+            family: if family == 0xf { family + ((eax >> 20) & 0xff) as u16 }
+                    else { family }
+          , model: if family == 0x6 || family == 0xf {
+                        model + (((eax >> 16) & 0xf) << 4) as u8
+                    } else { model }
+          , stepping: (eax & 0xf) as u8
+        }
+    }
+}
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!( f, "family {:#x} model {:#x} stepping {}"
+              , self.family, self.model, self.stepping)
+    }
+}
+
+/// The type of a CPU cache.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum CacheType { /// Data cache
+                     Data
+                   , /// Instruction cache
+                     Instruction
+                   , /// Unified cache
+                     Unified
+                   }
+
+/// A description of a CPU cache.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Cache { /// The cache level (L1, L2, ...)
+                   pub level: u8
+                 , /// The type of data the cache holds
+                   pub ty: CacheType
+                 , /// Size of a cache line, in bytes
+                   pub line_size: usize
+                 , /// Number of ways of associativity
+                   pub ways: usize
+                 , /// Number of sets
+                   pub sets: usize
+                 , /// Maximum number of logical processors sharing the cache
+                   pub shared_by: usize
+                 }
+
+impl Cache {
+    /// Decode a deterministic cache parameters leaf.
+    ///
+    /// Intel's leaf 4 and AMD's leaf `0x8000_001d` use the same format.
+    /// Returns `None` if the leaf describes no cache.
+    pub fn from_registers(regs: &Registers) -> Option<Self> {
+        let ty = match regs.eax & 0x1f {
+            1 => CacheType::Data
+          , 2 => CacheType::Instruction
+          , 3 => CacheType::Unified
+          , _ => return None
+        };
+        Some(Cache { level: ((regs.eax >> 5) & 0x7) as u8
+                   , ty: ty
+                   , line_size: (regs.ebx & 0xfff) as usize + 1
+                   , ways: (regs.ebx >> 22) as usize + 1
+                   , sets: regs.ecx as usize + 1
+                   , shared_by: ((regs.eax >> 14) & 0xfff) as usize + 1
+                   })
+    }
+
+    /// Returns the size of this cache, in bytes.
+    #[inline]
+    pub fn size(&self) -> usize {
+        self.line_size * self.ways * self.sets
+    }
+}
+
+impl fmt::Display for Cache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!( f, "L{} {:?}: {} KiB, {}-way, {} byte lines"
+              , self.level, self.ty, self.size() / 1024
+              , self.ways, self.line_size)
+    }
+}
+
+/// An iterator over the CPU's caches.
+pub struct Caches { leaf: u32
+                  , subleaf: u32
+                  }
+
+impl Iterator for Caches {
+    type Item = Cache;
+
+    fn next(&mut self) -> Option<Cache> {
+        if self.leaf == 0 { return None }
+        let cache = Cache::from_registers(&cpuid(self.leaf, self.subleaf));
+        self.subleaf += 1;
+        cache
+    }
+}
+
+/// Processor topology information.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Topology { /// The initial APIC ID of the CPU that ran `cpuid`
+                      pub apic_id: u8
+                    , /// Maximum number of addressable logical processor IDs
+                      /// in this physical package
+                      pub max_logical: u8
+                    , /// Size of the line flushed by `clflush`, in bytes
+                      pub clflush_size: usize
+                    }
+
+impl Topology {
+    /// Decode the topology information in `%ebx` from leaf 1.
+    pub fn from_ebx(ebx: u32) -> Self {
+        Topology { apic_id: (ebx >> 24) as u8
+                 , max_logical: (ebx >> 16) as u8
+                 , clflush_size: ((ebx >> 8) & 0xff) as usize * 8
+                 }
+    }
+}
+
+/// Information about the CPU.
+pub struct Info { /// The CPU's manufacturer
+                  pub vendor: Vendor
+                , /// The highest supported basic leaf
+                  pub max_leaf: u32
+                , /// The highest supported extended leaf
+                  pub max_ext_leaf: u32
+                , /// Family, model, and stepping
+                  pub signature: Signature
+                , /// Supported features, except `OSXSAVE`, which may have
+                  /// changed since the CPU was queried
+                  pub features: Features
+                , /// Topology information
+                  pub topology: Topology
+                , brand: [u8; 48]
+                }
+
+impl Info {
+    /// Query the CPU.
+    pub fn detect() -> Self {
+        let regs = cpuid(LEAF_VENDOR, 0);
+        let max_leaf = regs.eax;
+        let vendor = Vendor::from_registers(&regs);
+        let max_ext_leaf = cpuid(LEAF_EXT_MAX, 0).eax;
+
+        let basic = cpuid(LEAF_FEATURES, 0);
+        let mut features = Features::from_leaf(LEAF_FEATURES, &basic);
+        features.remove(OSXSAVE);
+        if LEAF_EXT_FEATURES <= max_leaf {
+            features |= Features::from_leaf( LEAF_EXT_FEATURES
+                                           , &cpuid(LEAF_EXT_FEATURES, 0));
+        }
+        for &leaf in &[LEAF_EXT_PROC_FEATURES, LEAF_POWER] {
+            if leaf <= max_ext_leaf {
+                features |= Features::from_leaf(leaf, &cpuid(leaf, 0));
+            }
+        }
+
+        let mut brand = [0u8; 48];
+        if LEAF_BRAND + 2 <= max_ext_leaf {
+            for (i, chunk) in brand.chunks_mut(16).enumerate() {
+                let regs = cpuid(LEAF_BRAND + i as u32, 0);
+                let words = [regs.eax, regs.ebx, regs.ecx, regs.edx];
+                for (j, byte) in chunk.iter_mut().enumerate() {
+                    *byte = (words[j / 4] >> ((j % 4) * 8)) as u8;
+                }
+            }
+        }
+
+        Info { vendor: vendor
+             , max_leaf: max_leaf
+             , max_ext_leaf: max_ext_leaf
+             , signature: Signature::from_eax(basic.eax)
+             , features: features
+             , topology: Topology::from_ebx(basic.ebx)
+             , brand: brand
+             }
+    }
+
+    /// Returns the processor's brand string, if it has one.
+    pub fn brand(&self) -> Option<&str> {
+        let len = self.brand.iter().position(|&b| b == 0)
+                      .unwrap_or(self.brand.len());
+        match str::from_utf8(&self.brand[..len]).map(|s| s.trim()) {
+            Ok("") | Err(_) => None
+          , Ok(brand) => Some(brand)
+        }
+    }
+
+    /// Returns an iterator over the CPU's caches.
+    ///
+    /// This will be empty if the CPU doesn't support enumerating its caches.
+    pub fn caches(&self) -> Caches {
+        let leaf = match self.vendor {
+            Vendor::Amd if self.features.contains(TOPOEXT)
+                        && LEAF_AMD_CACHES <= self.max_ext_leaf =>
+                LEAF_AMD_CACHES
+          , Vendor::Amd => 0
+          , _ if LEAF_CACHES <= self.max_leaf => LEAF_CACHES
+          , _ => 0
+        };
+        Caches { leaf: leaf, subleaf: 0 }
+    }
+}
+
+impl fmt::Display for Info {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!( f, "{} {} ({})"
+              , self.vendor, self.brand().unwrap_or("unknown CPU")
+              , self.signature)
+    }
+}
+
+static INFO: Once<Info> = Once::new();
+
+/// Returns information about the CPU.
+///
+/// The CPU is only queried the first time this is called.
+#[inline]
+pub fn info() -> &'static Info {
+    INFO.call_once(Info::detect)
+}
+
+/// Returns the features supported by the CPU.
+#[inline]
+pub fn features() -> Features {
+    let mut features = info().features;
+    if os_xsave() { features.insert(OSXSAVE); }
+    features
+}
+
+/// Returns true if the CPU supports all of `features`.
+#[inline]
+pub fn has(features: Features) -> bool {
+    let cached = features - OSXSAVE;
+    info().features.contains(cached)
+        && (cached == features || os_xsave())
+}
+
+/// Returns true if `%cr4.OSXSAVE` is set.
+#[inline]
+fn os_xsave() -> bool {
+    // `cpuid` is only ever run in kernel mode, where `%cr4` is readable
+    unsafe { cr4::read().contains(cr4::OSXSAVE) }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn vendor_string() {
+        // "GenuineIntel"
+        let regs = Registers { eax: 0xd
+                             , ebx: 0x756e_6547
+                             , ecx: 0x6c65_746e
+                             , edx: 0x4965_6e69 };
+        assert_eq!(Vendor::from_registers(&regs), Vendor::Intel);
+    }
+
+    #[test]
+    fn extended_family_and_model() {
+        // Skylake: family 6, extended model 5, model 0xe, stepping 3
+        assert_eq!( Signature::from_eax(0x0005_06e3)
+                  , Signature { family: 0x6, model: 0x5e, stepping: 3 });
+        // Zen: family 0xf + extended family 8
+        assert_eq!( Signature::from_eax(0x0080_0f11)
+                  , Signature { family: 0x17, model: 0x1, stepping: 1 });
+    }
+
+    #[test]
+    fn features_from_leaves() {
+        let regs = Registers { edx: (1 << 20) | (1 << 26), ..Default::default() };
+        assert_eq!( Features::from_leaf(LEAF_EXT_PROC_FEATURES, &regs)
+                  , NX | PAGE_1GB);
+        // the same bits mean different things in other leaves
+        assert_eq!(Features::from_leaf(LEAF_FEATURES, &regs), SSE2);
+
+        let regs = Registers { ebx: (1 << 7) | (1 << 20), ..Default::default() };
+        assert_eq!(Features::from_leaf(LEAF_EXT_FEATURES, &regs), SMEP | SMAP);
+    }
+
+    #[test]
+    fn deterministic_cache_params() {
+        // 32 KiB L1 data cache, 8-way, 64 sets, 64 byte lines
+        let regs = Registers { eax: 0x0000_0121
+                             , ebx: 0x01c0_003f
+                             , ecx: 0x0000_003f
+                             , edx: 0 };
+        let cache = Cache::from_registers(&regs).unwrap();
+        assert_eq!(cache.level, 1);
+        assert_eq!(cache.ty, CacheType::Data);
+        assert_eq!(cache.size(), 32 * 1024);
+        assert!(Cache::from_registers(&Registers::default()).is_none());
+    }
+}
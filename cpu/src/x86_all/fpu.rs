@@ -0,0 +1,317 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! x87 FPU, SSE, and AVX state management.
+//!
+//! The kernel itself is compiled without floating-point or SIMD instructions,
+//! so the FPU's register state only ever belongs to a task. Since saving and
+//! restoring it is expensive, we switch it lazily: when switching tasks, the
+//! scheduler calls [`switch_to`](fn.switch_to.html), which sets the `TS` flag
+//! in `%cr0` unless the next task already owns the FPU. The first FPU or SSE
+//! instruction the task executes then raises a Device Not Available (`#NM`)
+//! exception, whose handler calls
+//! [`handle_device_not_available`](fn.handle_device_not_available.html) to
+//! save the previous owner's state and load the new task's.
+//!
+//! Code that would rather switch eagerly can call
+//! [`State::save`](struct.State.html#method.save) and
+//! [`State::restore`](struct.State.html#method.restore) directly.
+//!
+//! Each CPU has its own FPU, so which task's state is loaded is tracked
+//! per-CPU.
+#![warn(missing_docs)]
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use control_regs::{cr0, cr4};
+use {cpuid, percpu};
+
+/// Size of a [`State`](struct.State.html) save area, in bytes.
+///
+/// This is enough for the x87, SSE, and AVX state components, which are the
+/// only ones we enable in `XCR0`.
+pub const STATE_SIZE: usize = 1024;
+
+/// Default x87 FPU control word: all exceptions masked, 64-bit precision.
+const DEFAULT_FCW: u16 = 0x037f;
+/// Default `MXCSR`: all SSE exceptions masked.
+const DEFAULT_MXCSR: u32 = 0x1f80;
+
+/// `XCR0` bit enabling the x87 state component.
+const XCR0_X87: u64 = 1 << 0;
+/// `XCR0` bit enabling the SSE state component.
+const XCR0_SSE: u64 = 1 << 1;
+/// `XCR0` bit enabling the AVX state component.
+const XCR0_AVX: u64 = 1 << 2;
+
+/// Whether the FPU has been initialized.
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+/// Whether to use `xsave`/`xrstor` rather than `fxsave`/`fxrstor`.
+static USE_XSAVE: AtomicBool = AtomicBool::new(false);
+/// The state components enabled in `XCR0`.
+static XCR0: AtomicUsize = AtomicUsize::new(0);
+
+percpu! {
+    /// Address of the `State` belonging to the task running on this CPU, or
+    /// 0 if no task is running.
+    static CURRENT: usize = 0;
+    /// Address of the `State` whose contents are loaded in this CPU's FPU,
+    /// or 0 if its contents don't belong to anyone.
+    static OWNER: usize = 0;
+}
+
+/// A save area for the FPU, SSE, and AVX register state.
+///
+/// This is the layout used by both `fxsave` (the first 512 bytes) and
+/// `xsave`.
+#[repr(C, align(64))]
+pub struct State { fcw: u16
+                 , _legacy_1: [u8; 22]
+                 , mxcsr: u32
+                 , mxcsr_mask: u32
+                 , _legacy_2: [u8; 480]
+                 , /// `XSAVE` header and extended state
+                   _extended: [u8; STATE_SIZE - 512]
+                 }
+
+impl State {
+    /// Returns a new save area, holding the initial FPU state.
+    pub const fn new() -> Self {
+        State { fcw: DEFAULT_FCW
+              , _legacy_1: [0; 22]
+              , mxcsr: DEFAULT_MXCSR
+              , mxcsr_mask: 0
+              , _legacy_2: [0; 480]
+              // a zeroed XSAVE header tells `xrstor` to put every state
+              // component in its initial configuration
+              , _extended: [0; STATE_SIZE - 512]
+              }
+    }
+
+    /// Save the current FPU state into this save area.
+    ///
+    /// # Safety
+    /// + The FPU must have been [initialized](fn.initialize.html), and `TS`
+    ///   must be clear.
+    #[inline]
+    pub unsafe fn save(&mut self) {
+        if USE_XSAVE.load(Ordering::Relaxed) {
+            let mask = XCR0.load(Ordering::Relaxed) as u64;
+            asm!( "xsave [$0]"
+                :: "r"(self as *mut State)
+                 , "{eax}"(mask as u32), "{edx}"((mask >> 32) as u32)
+                : "memory"
+                : "intel", "volatile");
+        } else {
+            asm!( "fxsave [$0]"
+                :: "r"(self as *mut State)
+                : "memory"
+                : "intel", "volatile");
+        }
+    }
+
+    /// Load the FPU state from this save area.
+    ///
+    /// # Safety
+    /// + The FPU must have been [initialized](fn.initialize.html), and `TS`
+    ///   must be clear.
+    #[inline]
+    pub unsafe fn restore(&self) {
+        if USE_XSAVE.load(Ordering::Relaxed) {
+            let mask = XCR0.load(Ordering::Relaxed) as u64;
+            asm!( "xrstor [$0]"
+                :: "r"(self as *const State)
+                 , "{eax}"(mask as u32), "{edx}"((mask >> 32) as u32)
+                : "memory"
+                : "intel", "volatile");
+        } else {
+            asm!( "fxrstor [$0]"
+                :: "r"(self as *const State)
+                : "memory"
+                : "intel", "volatile");
+        }
+    }
+}
+
+impl fmt::Debug for State {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!( f, "FPU State {{ fcw: {:#06x}, mxcsr: {:#010x} }}"
+              , self.fcw, self.mxcsr)
+    }
+}
+
+/// Read an extended control register.
+#[inline]
+unsafe fn xgetbv(xcr: u32) -> u64 {
+    let (high, low): (u32, u32);
+    asm!( "xgetbv"
+        : "={eax}"(low), "={edx}"(high)
+        : "{ecx}"(xcr)
+        :: "volatile");
+    ((high as u64) << 32) | low as u64
+}
+
+/// Write an extended control register.
+#[inline]
+unsafe fn xsetbv(xcr: u32, value: u64) {
+    asm!( "xsetbv"
+        :: "{ecx}"(xcr), "{eax}"(value as u32), "{edx}"((value >> 32) as u32)
+        :: "volatile");
+}
+
+/// Clear the `TS` flag in `%cr0`, permitting FPU instructions.
+#[inline]
+pub unsafe fn clts() {
+    asm!("clts" :::: "volatile");
+}
+
+/// Set the `TS` flag in `%cr0`, so the next FPU instruction raises `#NM`.
+#[inline]
+pub unsafe fn set_task_switched() {
+    cr0::write(cr0::read() | cr0::TS);
+}
+
+/// Returns true if the FPU state is saved with `xsave`.
+#[inline]
+pub fn uses_xsave() -> bool { USE_XSAVE.load(Ordering::Relaxed) }
+
+/// Initialize the x87 FPU, SSE, and (if supported) AVX.
+///
+/// Afterwards, `TS` is set so that the first FPU instruction executed will
+/// trap. No task owns the FPU until one is [switched to](fn.switch_to.html).
+///
+/// # Safety
+/// + This should only be called once, on the bootstrap processor. Other
+///   CPUs should call [`enable`](fn.enable.html).
+pub unsafe fn initialize() -> Result<(), &'static str> {
+    enable()?;
+    INITIALIZED.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Enable the x87 FPU, SSE, and (if supported) AVX on the current CPU.
+///
+/// Afterwards, `TS` is set so that the first FPU instruction executed will
+/// trap.
+///
+/// # Safety
+/// + This should only be called once per CPU.
+pub unsafe fn enable() -> Result<(), &'static str> {
+    if !cpuid::has(cpuid::FPU) {
+        return Err("CPU has no x87 FPU")
+    }
+    if !cpuid::has(cpuid::FXSR | cpuid::SSE) {
+        return Err("CPU does not support SSE and `fxsave`")
+    }
+
+    // use the on-chip FPU, trap `wait` when `TS` is set, and report x87
+    // errors with exceptions rather than IRQ 13
+    let mut flags = cr0::read();
+    flags.remove(cr0::EM);
+    flags.insert(cr0::MP | cr0::NE);
+    cr0::write(flags);
+
+    let mut flags = cr4::read();
+    flags.insert(cr4::OSFXSR | cr4::OSXMMEXCPT);
+    if cpuid::has(cpuid::XSAVE) { flags.insert(cr4::OSXSAVE); }
+    cr4::write(flags);
+
+    if cpuid::has(cpuid::XSAVE) {
+        let mut xcr0 = XCR0_X87 | XCR0_SSE;
+        if cpuid::has(cpuid::AVX) { xcr0 |= XCR0_AVX; }
+        xsetbv(0, xcr0);
+        // leaf 0xd reports the save area size for the enabled components
+        if cpuid::cpuid(0xd, 0).ebx as usize > STATE_SIZE {
+            return Err("XSAVE area is larger than the FPU save area")
+        }
+        XCR0.store(xgetbv(0) as usize, Ordering::SeqCst);
+        USE_XSAVE.store(true, Ordering::SeqCst);
+    }
+
+    asm!("fninit" :::: "volatile");
+    set_task_switched();
+    Ok(())
+}
+
+/// Switch to the FPU state of the next task to run.
+///
+/// This should be called by the scheduler on every context switch. If the
+/// next task already owns the FPU, `TS` is cleared; otherwise it is set, so
+/// the task's state will be loaded when it first uses the FPU.
+///
+/// # Safety
+/// + `next` must remain valid until it is [released](fn.release.html).
+/// + The current CPU's [per-CPU data](../percpu/index.html) must be set up,
+///   and interrupts must be disabled.
+#[inline]
+pub unsafe fn switch_to(next: *mut State) {
+    *CURRENT.as_ptr() = next as usize;
+    if !INITIALIZED.load(Ordering::Relaxed) { return }
+    if *OWNER.as_ptr() == next as usize {
+        clts();
+    } else {
+        set_task_switched();
+    }
+}
+
+/// Forget about a save area which is about to be freed.
+///
+/// This should be called when a task exits, on the CPU it ran on, so that
+/// its state isn't saved into freed memory when another task next uses the
+/// FPU.
+///
+/// # Safety
+/// + The current CPU's [per-CPU data](../percpu/index.html) must be set up,
+///   and interrupts must be disabled.
+#[inline]
+pub unsafe fn release(state: *mut State) {
+    let owner = OWNER.as_ptr();
+    if *owner == state as usize { *owner = 0; }
+}
+
+/// Handle a Device Not Available (`#NM`) exception.
+///
+/// This saves the FPU state of the previous owner (if any), and loads the
+/// state of the current task.
+///
+/// # Safety
+/// + This should only be called from the `#NM` exception handler.
+pub unsafe fn handle_device_not_available() -> Result<(), &'static str> {
+    if !INITIALIZED.load(Ordering::Relaxed) {
+        return Err("FPU used before it was initialized")
+    }
+    if !percpu::is_initialized() {
+        return Err("FPU used before per-CPU data was set up")
+    }
+    let current = *CURRENT.as_ptr();
+    if current == 0 {
+        return Err("FPU used by a context with no FPU state")
+    }
+    clts();
+    let owner = OWNER.as_ptr();
+    if *owner != current {
+        if *owner != 0 {
+            (*(*owner as *mut State)).save();
+        }
+        (*(current as *const State)).restore();
+        *owner = current;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use core::mem;
+
+    #[test]
+    fn state_layout() {
+        assert_eq!(mem::size_of::<State>(), STATE_SIZE);
+        assert_eq!(mem::align_of::<State>(), 64);
+    }
+}
@@ -0,0 +1,213 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Structured reports for CPU exceptions.
+//!
+//! A [`Fault`](struct.Fault.html) bundles together everything we know about
+//! an exception: the interrupt frame, the general-purpose registers saved by
+//! the exception entry stub, the control registers, the decoded error code,
+//! and a backtrace of the faulting stack.
+use core::fmt;
+use core::fmt::Write;
+
+use vga::{CONSOLE, Color};
+
+use backtrace::{Backtrace, Frames};
+use context::{InterruptFrame, Registers};
+use control_regs::{self, CrState};
+use super::PageFaultErrorCode;
+
+/// Vector number of the double fault exception.
+const DOUBLE_FAULT: u8 = 8;
+/// Vector number of the invalid TSS exception.
+const INVALID_TSS: u8 = 10;
+/// Vector number of the segment not present exception.
+const SEGMENT_NOT_PRESENT: u8 = 11;
+/// Vector number of the stack-segment fault exception.
+const STACK_SEGMENT_FAULT: u8 = 12;
+/// Vector number of the general protection fault exception.
+const GENERAL_PROTECTION_FAULT: u8 = 13;
+/// Vector number of the page fault exception.
+const PAGE_FAULT: u8 = 14;
+/// Vector number of the alignment check exception.
+const ALIGNMENT_CHECK: u8 = 17;
+/// Vector number of the security exception.
+const SECURITY_EXCEPTION: u8 = 30;
+
+/// Descriptor tables that a selector error code may refer to.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum DescriptorTable { /// The Global Descriptor Table
+                           Gdt
+                         , /// The Interrupt Descriptor Table
+                           Idt
+                         , /// The Local Descriptor Table
+                           Ldt
+                         }
+
+/// A selector error code, as pushed by segment-related exceptions.
+///
+/// ```ignore
+///  15                             3   2   1   0
+/// +---------------------------------+---+---+---+
+/// |          Selector index         |TI |IDT|EXT|
+/// +---------------------------------+---+---+---+
+/// ```
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct SelectorErrorCode(pub u32);
+
+impl SelectorErrorCode {
+    /// Returns true if the exception originated externally to the processor.
+    #[inline] pub fn is_external(&self) -> bool { self.0 & 0b001 != 0 }
+
+    /// Returns the descriptor table the selector index refers to.
+    #[inline]
+    pub fn table(&self) -> DescriptorTable {
+        match self.0 & 0b110 {
+            0b000 => DescriptorTable::Gdt
+          , 0b100 => DescriptorTable::Ldt
+          , _     => DescriptorTable::Idt
+        }
+    }
+
+    /// Returns the index of the selector into its descriptor table.
+    #[inline] pub fn index(&self) -> u16 { ((self.0 & 0xffff) >> 3) as u16 }
+}
+
+impl fmt::Display for SelectorErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!( f, "{:?} entry {:#x}{}"
+              , self.table(), self.index()
+              , if self.is_external() { " (external event)" } else { "" })
+    }
+}
+
+/// A decoded exception error code.
+#[derive(Copy, Clone, Debug)]
+pub enum ErrorCode { /// The exception does not push an error code
+                     None
+                   , /// The error code refers to a segment selector
+                     Selector(SelectorErrorCode)
+                   , /// A page fault error code, with the faulting address
+                     PageFault(PageFaultErrorCode, usize)
+                   , /// An error code with no further structure
+                     Raw(usize)
+                   }
+
+impl ErrorCode {
+    /// Decode the error code pushed by the exception with the given vector.
+    pub fn decode(vector: u8, code: usize, crs: &CrState) -> Self {
+        match vector {
+            INVALID_TSS | SEGMENT_NOT_PRESENT | STACK_SEGMENT_FAULT
+          | GENERAL_PROTECTION_FAULT | SECURITY_EXCEPTION if code != 0 =>
+                ErrorCode::Selector(SelectorErrorCode(code as u32))
+          , PAGE_FAULT =>
+                ErrorCode::PageFault( PageFaultErrorCode::from_bits_truncate(
+                                        code as u32)
+                                    , crs.cr2 )
+          , DOUBLE_FAULT | INVALID_TSS | SEGMENT_NOT_PRESENT
+          | STACK_SEGMENT_FAULT | GENERAL_PROTECTION_FAULT | ALIGNMENT_CHECK
+          | SECURITY_EXCEPTION => ErrorCode::Raw(code)
+          , _ => ErrorCode::None
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ErrorCode::None => Ok(())
+          , ErrorCode::Selector(ref sel) =>
+                write!(f, "Error code: {:#x} ({})", sel.0, sel)
+          , ErrorCode::PageFault(ref code, addr) =>
+                write!( f, "Error code: {:#x} [{}{}{}{}]\nFaulting address: \
+                            {:#x}\n{}"
+                      , code.bits()
+                      , if code.contains(super::PRESENT) { "P" } else { "-" }
+                      , if code.contains(super::READ_WRITE) { "W" } else { "R" }
+                      , if code.contains(super::USER_MODE) { "U" } else { "S" }
+                      , if code.contains(super::INST_FETCH) { "I" } else { "-" }
+                      , addr, code)
+          , ErrorCode::Raw(code) => write!(f, "Error code: {:#x}", code)
+        }
+    }
+}
+
+/// A report describing a CPU exception.
+pub struct Fault<'a> { /// The exception's vector number
+                       pub vector: u8
+                     , /// The name of the exception
+                       pub name: &'static str
+                     , /// The kind of exception (fault, trap, or abort)
+                       pub kind: &'static str
+                     , /// The source triggering the exception
+                       pub source: &'static str
+                     , /// The interrupt stack frame pushed by the CPU
+                       pub frame: &'a InterruptFrame
+                     , /// The general-purpose registers at the time of the
+                       /// exception
+                       pub registers: &'a Registers
+                     , /// The decoded error code
+                       pub error_code: ErrorCode
+                     , /// The control registers at the time of the exception
+                       pub control_regs: CrState
+                     , /// Backtrace of the faulting stack
+                       pub backtrace: Backtrace
+                     }
+
+impl<'a> Fault<'a> {
+    /// Construct a report for an exception, capturing the control registers
+    /// and walking the stack from the saved frame pointer.
+    pub fn new( vector: u8
+              , name: &'static str
+              , kind: &'static str
+              , source: &'static str
+              , frame: &'a InterruptFrame
+              , registers: &'a Registers
+              , error_code: usize)
+              -> Self {
+        let crs = control_regs::dump();
+        let frames = unsafe {
+            Frames::from_frame_pointer(registers.frame_pointer())
+        };
+        Fault { vector: vector
+              , name: name
+              , kind: kind
+              , source: source
+              , frame: frame
+              , registers: registers
+              , error_code: ErrorCode::decode(vector, error_code, &crs)
+              , control_regs: crs
+              , backtrace: Backtrace(frames)
+              }
+    }
+
+    /// Print this report to the VGA console and the serial log.
+    pub fn report(&self) {
+        let _ = write!( CONSOLE.lock()
+                               .set_colors(Color::White, Color::Blue)
+                      , "{}", self);
+        error!("{}", self);
+    }
+}
+
+impl<'a> fmt::Display for Fault<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!( f, "EVERYTHING IS FINE: {} {} (#{}) at {:p}"
+                , self.name, self.kind, self.vector
+                , self.frame.instruction_pointer())?;
+        writeln!(f, "Source: {}. This is fine.", self.source)?;
+        match self.error_code {
+            ErrorCode::None => {}
+          , ref code => writeln!(f, "{}", code)?
+        }
+        writeln!(f, "{:?}", self.frame)?;
+        writeln!(f, "Registers:\n{:?}", self.registers)?;
+        writeln!(f, "    {}", self.control_regs)?;
+        write!(f, "{}", self.backtrace)
+    }
+}
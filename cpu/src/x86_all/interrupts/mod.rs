@@ -14,13 +14,13 @@
 #![warn(missing_docs)]
 pub mod idt;
 pub mod pics;
-
-use vga::{CONSOLE, Color};
+pub mod fault;
 
 use core::fmt;
-use core::fmt::Write;
+use core::marker::PhantomData;
 
 use context::InterruptFrame;
+use flags;
 
 /// Number of interrupt vectors corresponding to CPU exceptions.
 ///
@@ -85,33 +85,46 @@ impl fmt::Display for PageFaultErrorCode {
    }
 }
 
-/// Handler for the system timer interrupt
-pub extern "x86-interrupt" fn timer(_frame: &InterruptFrame) {
-    // do nothing, just signal the pics to end the IRQ
-    // println!("timer!");
-    unsafe { pics::end_pic_interrupt(0x20); }
+/// Disable interrupts on the current CPU until the returned guard is
+/// dropped.
+///
+/// When the guard is dropped, interrupts are re-enabled only if they were
+/// enabled when it was created, so guards may be nested.
+#[inline]
+pub fn disable() -> Guard {
+    let were_enabled = flags::read().contains(flags::IF);
+    unsafe { asm!("cli" :::: "volatile"); }
+    Guard { were_enabled: were_enabled, _not_send: PhantomData }
 }
 
+/// A guard which keeps interrupts disabled on the current CPU while it's
+/// alive.
+///
+/// Guards can't be sent to other threads, since they only keep interrupts
+/// disabled on the CPU which created them. See [`disable`](fn.disable.html).
+#[must_use]
+pub struct Guard { were_enabled: bool
+                 , _not_send: PhantomData<*const ()>
+                 }
 
+impl Drop for Guard {
+    #[inline]
+    fn drop(&mut self) {
+        if self.were_enabled {
+            unsafe { asm!("sti" :::: "volatile"); }
+        }
+    }
+}
 
-/// Handles page fault exceptions
-#[no_mangle] #[inline(never)]
-pub extern "x86-interrupt" fn page_fault( frame: &InterruptFrame, error_code: usize) {
-   let _ = write!( CONSOLE.lock()
-                      .set_colors(Color::White, Color::Blue)
-                   //   .clear()
-             , "IT'S NOT MY FAULT: Page Fault at {:p} \
-                \nError code: {:#x}\n\n{}\n{:?}"
-             , (*frame).rip
-             , error_code
-             , PageFaultErrorCode::from_bits_truncate(error_code as u32)
-             , *frame
-             );
-   // TODO: stack dumps please
-
-   loop { }
+/// Handler for the system timer interrupt
+pub extern "x86-interrupt" fn timer(_frame: &InterruptFrame) {
+    // count the tick, and signal the pics to end the IRQ
+    ::pit::tick();
+    unsafe { pics::end_pic_interrupt(pics::IRQ::Timer as u8); }
 }
 
+
+
 /// Test interrupt handler for ensuring that the IDT is configured correctly.
 #[no_mangle] #[inline(never)]
 pub extern "x86-interrupt" fn test(_frame: &InterruptFrame) {
@@ -21,7 +21,7 @@
 //! PIC1 starts at 32 and PIC2 at 40.
 
 use Port;
-use spin::Mutex;
+use util::irq_lock::IrqSpinlock;
 
 use core::mem::transmute;
 
@@ -239,8 +239,8 @@ impl IRQHandler for BothPICs {
 }
 
 /// Global PIC instance and mutex
-static PICS: Mutex<BothPICs>
-    = Mutex::new(BothPICs::new());
+static PICS: IrqSpinlock<BothPICs>
+    = IrqSpinlock::new(BothPICs::new());
 
 /// Initialize the system's Programmable Interrupt Controller
 ///
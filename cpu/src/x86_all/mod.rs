@@ -39,11 +39,18 @@ macro_rules! cpu_flag {
 }
 
 pub mod control_regs;
+pub mod cpuid;
 pub mod segment;
+pub mod smap;
 pub mod dtable;
 pub mod flags;
+pub mod fpu;
 pub mod timer;
+pub mod pit;
 pub mod interrupts;
+pub mod backtrace;
+
+pub use util::irq_lock::{IrqSpinlock, IrqSpinlockGuard};
 
 /// Represents an x86 privilege level.
 #[derive(Copy, Clone, Debug, PartialEq, PartialOrd, Ord, Eq)]
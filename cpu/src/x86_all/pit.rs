@@ -0,0 +1,202 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Support for the 8253/8254 Programmable Interval Timer.
+//!
+//! The PIT has three channels, all driven by the same ~1.193182 MHz
+//! oscillator. Channel 0 is wired to `IRQ0` on the leader PIC, so we use it
+//! as the kernel's periodic tick source. Channel 2 is wired to the PC speaker
+//! gate, which we abuse as a polled one-shot timer for calibrating other
+//! clocks (such as the TSC) before interrupts are enabled.
+//!
+//! See [the OS Dev Wiki](http://wiki.osdev.org/Programmable_Interval_Timer)
+//! for more information.
+#![warn(missing_docs)]
+use Port;
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Frequency of the PIT's oscillator, in Hz.
+pub const BASE_FREQUENCY: u32 = 1_193_182;
+
+/// Default frequency of the kernel tick, in Hz.
+pub const DEFAULT_FREQUENCY: u32 = 1000;
+
+/// Number of nanoseconds in a second.
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// Data port for channel 0 (wired to `IRQ0`).
+const CHANNEL_0: Port<u8> = Port::<u8>::new(0x40);
+/// Data port for channel 2 (wired to the PC speaker).
+const CHANNEL_2: Port<u8> = Port::<u8>::new(0x42);
+/// Mode/command register (write-only).
+const COMMAND: Port<u8> = Port::<u8>::new(0x43);
+/// Keyboard controller port B, which controls the channel 2 gate.
+const PORT_B: Port<u8> = Port::<u8>::new(0x61);
+
+/// Command byte selecting channel 0, lobyte/hibyte access, mode 2
+/// (rate generator).
+const CMD_CHANNEL_0_RATE: u8 = 0b0011_0100;
+/// Command byte selecting channel 2, lobyte/hibyte access, mode 0
+/// (interrupt on terminal count).
+const CMD_CHANNEL_2_ONESHOT: u8 = 0b1011_0000;
+
+/// Port B bit that enables the channel 2 gate.
+const GATE_2: u8 = 1 << 0;
+/// Port B bit that connects channel 2 to the speaker.
+const SPEAKER: u8 = 1 << 1;
+/// Port B bit that reflects the channel 2 output.
+const OUT_2: u8 = 1 << 5;
+
+/// Number of ticks since the PIT was initialized.
+static TICKS: AtomicUsize = AtomicUsize::new(0);
+/// Frequency that channel 0 was programmed at, in Hz (0 if uninitialized).
+static FREQUENCY: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns the channel reload value for the requested frequency.
+#[inline]
+fn divisor_for(hz: u32) -> u16 {
+    match BASE_FREQUENCY / hz {
+        // a reload value of 0 means 65536 to the PIT
+        d if d > 0xFFFF => 0
+      , 0 => 1
+      , d => d as u16
+    }
+}
+
+/// Initialize channel 0 to fire `IRQ0` at (approximately) `hz` Hz.
+///
+/// The actual frequency is limited by the resolution of the divisor, and is
+/// clamped to the range 19 Hz ... 1.19 MHz.
+///
+/// # Safety
+/// + This reprograms the PIT, and should only be called during init, before
+///   the timer interrupt has been unmasked.
+pub unsafe fn initialize(hz: u32) {
+    let divisor = divisor_for(hz);
+    COMMAND.write(CMD_CHANNEL_0_RATE);
+    CHANNEL_0.write(divisor as u8);
+    CHANNEL_0.write((divisor >> 8) as u8);
+
+    // compute the frequency we *actually* got out of that divisor
+    let actual = match divisor { 0 => BASE_FREQUENCY / 0x10000
+                               , d => BASE_FREQUENCY / d as u32 };
+    FREQUENCY.store(actual as usize, Ordering::SeqCst);
+    TICKS.store(0, Ordering::SeqCst);
+    debug!("PIT channel 0: divisor {}, {} Hz", divisor, actual);
+}
+
+/// Records that a timer tick has occurred.
+///
+/// This should be called by the `IRQ0` handler, and nowhere else.
+#[inline]
+pub fn tick() -> usize {
+    TICKS.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+/// Returns the number of ticks since the PIT was initialized.
+#[inline]
+pub fn ticks() -> usize {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Returns the frequency of the tick, in Hz, or `None` if the PIT has not
+/// been initialized.
+#[inline]
+pub fn frequency() -> Option<u32> {
+    match FREQUENCY.load(Ordering::Relaxed) {
+        0 => None
+      , hz => Some(hz as u32)
+    }
+}
+
+/// Returns the time elapsed since the PIT was initialized, in nanoseconds.
+///
+/// The resolution of this clock is one tick. Returns 0 if the PIT has not
+/// been initialized.
+#[inline]
+pub fn uptime_nanos() -> u64 {
+    frequency().map(|hz| ticks() as u64 * NANOS_PER_SEC / hz as u64)
+               .unwrap_or(0)
+}
+
+/// Returns the time elapsed since the PIT was initialized, in milliseconds.
+#[inline]
+pub fn uptime_millis() -> u64 {
+    uptime_nanos() / 1_000_000
+}
+
+/// Busy-wait for (at least) `micros` microseconds, using channel 2.
+///
+/// This polls the channel 2 output and does not require interrupts, so it is
+/// safe to use for calibrating other timers during early init. Waits longer
+/// than ~54 milliseconds (the longest period the PIT can count) are split into
+/// multiple countdowns.
+///
+/// # Safety
+/// + This clobbers channel 2 and the PC speaker gate.
+/// + Nothing else may be using channel 2 concurrently.
+pub unsafe fn spin_wait_micros(mut micros: u64) {
+    /// Longest countdown we can do in a single go, in microseconds.
+    const MAX_MICROS: u64 = 0xFFFF * 1_000_000 / BASE_FREQUENCY as u64;
+
+    while micros > 0 {
+        let chunk = if micros > MAX_MICROS { MAX_MICROS } else { micros };
+        let count = (chunk * BASE_FREQUENCY as u64 / 1_000_000) as u16;
+
+        // enable the channel 2 gate, but keep the speaker quiet
+        let port_b = PORT_B.read();
+        PORT_B.write((port_b & !SPEAKER) | GATE_2);
+
+        COMMAND.write(CMD_CHANNEL_2_ONESHOT);
+        CHANNEL_2.write(count as u8);
+        CHANNEL_2.write((count >> 8) as u8);
+
+        // restart the countdown by toggling the gate
+        let port_b = PORT_B.read();
+        PORT_B.write(port_b & !GATE_2);
+        PORT_B.write(port_b | GATE_2);
+
+        // OUT2 goes high when the count reaches zero
+        while PORT_B.read() & OUT_2 == 0 { }
+
+        micros -= chunk;
+    }
+}
+
+/// Measure how far `counter` advances over `micros` microseconds, using
+/// channel 2 as the reference clock.
+///
+/// This is the building block for calibrating other clocks (e.g. the TSC or
+/// the local APIC timer) against the PIT.
+///
+/// # Safety
+/// + See [`spin_wait_micros`](fn.spin_wait_micros.html).
+pub unsafe fn measure<F>(micros: u64, counter: F) -> u64
+where F: Fn() -> u64 {
+    let start = counter();
+    spin_wait_micros(micros);
+    let end = counter();
+    end.wrapping_sub(start)
+}
+
+#[cfg(test)]
+mod test {
+    use super::divisor_for;
+
+    #[test]
+    fn divisor_1000_hz() {
+        assert_eq!(divisor_for(1000), 1193);
+    }
+
+    #[test]
+    fn divisor_is_clamped() {
+        assert_eq!(divisor_for(1), 0);
+        assert_eq!(divisor_for(super::BASE_FREQUENCY * 2), 1);
+    }
+}
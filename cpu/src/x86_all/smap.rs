@@ -0,0 +1,140 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Supervisor Mode Access Prevention, and helpers for accessing user memory.
+//!
+//! When SMAP is enabled, the kernel faults if it touches a page mapped as
+//! user-accessible, unless the `AC` flag is set. This catches kernel bugs
+//! where a pointer from userspace is dereferenced by accident. Code that
+//! _means_ to access user memory must do so inside a
+//! [`UserAccess`](struct.UserAccess.html) guard, which sets `AC` with `stac`
+//! and clears it again with `clac` when dropped.
+//!
+//! If SMAP is unsupported or has not been enabled, the guards do nothing.
+#![warn(missing_docs)]
+use core::ptr;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use control_regs::cr4;
+use cpuid;
+
+/// The first address above the user half of the address space.
+#[cfg(target_arch = "x86_64")]
+pub const USER_TOP: usize = 0x0000_8000_0000_0000;
+/// The first address above the user half of the address space.
+#[cfg(target_arch = "x86")]
+pub const USER_TOP: usize = 0xc000_0000;
+
+/// Whether SMAP has been enabled.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Enable SMAP, if the CPU supports it.
+///
+/// # Safety
+/// + After this is called, any access to user pages outside of a
+///   [`UserAccess`](struct.UserAccess.html) guard will fault.
+pub unsafe fn enable() -> Result<(), &'static str> {
+    if !cpuid::has(cpuid::SMAP) {
+        return Err("CPU does not support SMAP")
+    }
+    cr4::enable_smap(true);
+    ENABLED.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Returns true if SMAP has been enabled.
+#[inline]
+pub fn is_enabled() -> bool { ENABLED.load(Ordering::Relaxed) }
+
+/// Set the `AC` flag, permitting access to user pages.
+///
+/// # Safety
+/// + This disables SMAP's protection until `clac` is called.
+#[inline(always)]
+pub unsafe fn stac() {
+    if is_enabled() { asm!("stac" ::: "memory" : "volatile"); }
+}
+
+/// Clear the `AC` flag, forbidding access to user pages.
+#[inline(always)]
+pub unsafe fn clac() {
+    if is_enabled() { asm!("clac" ::: "memory" : "volatile"); }
+}
+
+/// A guard permitting the kernel to access user pages while it's alive.
+///
+/// Guards should be kept as short-lived as possible, and must not be held
+/// across anything that might switch tasks.
+pub struct UserAccess { _private: () }
+
+impl UserAccess {
+    /// Permit access to user pages until the returned guard is dropped.
+    #[inline]
+    pub fn new() -> Self {
+        unsafe { stac(); }
+        UserAccess { _private: () }
+    }
+}
+
+impl Drop for UserAccess {
+    #[inline]
+    fn drop(&mut self) { unsafe { clac(); } }
+}
+
+/// Returns true if the `len` bytes starting at `addr` are all in the user
+/// half of the address space.
+#[inline]
+pub fn is_user_range(addr: usize, len: usize) -> bool {
+    match addr.checked_add(len) {
+        Some(end) => addr != 0 && end <= USER_TOP
+      , None => false
+    }
+}
+
+/// Copy `dst.len()` bytes from the user address `src` into `dst`.
+///
+/// # Safety
+/// + The user pages containing `src` must be mapped, or this will fault.
+pub unsafe fn copy_from_user(dst: &mut [u8], src: *const u8)
+                             -> Result<(), &'static str> {
+    if !is_user_range(src as usize, dst.len()) {
+        return Err("Source is not in user memory")
+    }
+    let _access = UserAccess::new();
+    ptr::copy_nonoverlapping(src, dst.as_mut_ptr(), dst.len());
+    Ok(())
+}
+
+/// Copy `src` to the user address `dst`.
+///
+/// # Safety
+/// + The user pages containing `dst` must be mapped and writable, or this
+///   will fault.
+pub unsafe fn copy_to_user(dst: *mut u8, src: &[u8])
+                           -> Result<(), &'static str> {
+    if !is_user_range(dst as usize, src.len()) {
+        return Err("Destination is not in user memory")
+    }
+    let _access = UserAccess::new();
+    ptr::copy_nonoverlapping(src.as_ptr(), dst, src.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn user_ranges() {
+        assert!(is_user_range(0x1000, 0x1000));
+        assert!(is_user_range(USER_TOP - 0x10, 0x10));
+        assert!(!is_user_range(USER_TOP - 0x10, 0x11));
+        assert!(!is_user_range(0, 0x10));
+        assert!(!is_user_range(usize::max_value() - 1, 4));
+    }
+}
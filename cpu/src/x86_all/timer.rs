@@ -11,6 +11,14 @@
 pub mod timestamp {
     //! x86 Timestamp register
     use core::mem;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Frequency of the timestamp counter in Hz, or 0 if it has not yet been
+    /// calibrated.
+    static TSC_HZ: AtomicUsize = AtomicUsize::new(0);
+
+    /// How long to sample the TSC for during calibration, in microseconds.
+    const CALIBRATION_MICROS: u64 = 50_000;
 
 
     /// Read the current value of the timestamp counter.
@@ -42,9 +50,11 @@ pub mod timestamp {
     #[inline]
     pub fn is_available() -> Result<(), &'static str> {
         use ::control_regs::cr4;
-        use ::PrivilegeLevel;
+        use ::{cpuid, PrivilegeLevel};
 
-        if PrivilegeLevel::current_iopl() != PrivilegeLevel::KernelMode {
+        if !cpuid::has(cpuid::TSC) {
+            Err("This CPU has no timestamp counter.")
+        } else if PrivilegeLevel::current_iopl() != PrivilegeLevel::KernelMode {
             Err("Reading timestamp register requires kernel mode.")
         } else if
             // it's safe to do this since we already know we are in kernel mode.
@@ -63,6 +73,58 @@ pub mod timestamp {
     /// been executed.
     #[inline]
     pub fn wait_get_timestamp() -> Result<u64, &'static str> {
+        use ::cpuid;
+        if !cpuid::has(cpuid::RDTSCP) {
+            return Err("This CPU does not support `rdtscp`.")
+        }
         is_available().map(|_| unsafe { rtdscp() })
     }
+
+    /// Calibrate the timestamp counter's frequency against the PIT.
+    ///
+    /// This must be called before timestamps can be converted to wall-clock
+    /// time with [`to_nanos`](fn.to_nanos.html). Returns the measured
+    /// frequency in Hz.
+    ///
+    /// # Safety
+    /// + This uses PIT channel 2, and busy-waits for ~50 milliseconds.
+    pub unsafe fn calibrate() -> Result<u64, &'static str> {
+        use ::pit;
+        is_available()?;
+        let elapsed = pit::measure(CALIBRATION_MICROS, || rtdsc());
+        let hz = elapsed * 1_000_000 / CALIBRATION_MICROS;
+        if hz == 0 {
+            return Err("Timestamp counter did not advance during calibration")
+        }
+        TSC_HZ.store(hz as usize, Ordering::SeqCst);
+        Ok(hz)
+    }
+
+    /// Returns the calibrated frequency of the timestamp counter in Hz, or
+    /// `None` if it has not been calibrated.
+    #[inline]
+    pub fn frequency() -> Option<u64> {
+        match TSC_HZ.load(Ordering::Relaxed) {
+            0 => None
+          , hz => Some(hz as u64)
+        }
+    }
+
+    /// Convert a timestamp counter value to nanoseconds.
+    ///
+    /// Returns `None` if the timestamp counter has not been calibrated.
+    #[inline]
+    pub fn to_nanos(timestamp: u64) -> Option<u64> {
+        // split the multiplication so we don't overflow after a few seconds
+        frequency().map(|hz| (timestamp / hz) * 1_000_000_000
+                           + (timestamp % hz) * 1_000_000_000 / hz)
+    }
+
+    /// Returns the current timestamp in nanoseconds, or an error if the
+    /// timestamp counter is unavailable or uncalibrated.
+    #[inline]
+    pub fn get_nanos() -> Result<u64, &'static str> {
+        get_timestamp().and_then(|ts|
+            to_nanos(ts).ok_or("Timestamp counter has not been calibrated"))
+    }
 }
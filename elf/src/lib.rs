@@ -80,6 +80,8 @@ macro_rules! impl_getters {
 pub mod section;
 pub mod file;
 pub mod program;
+pub mod symbol;
+pub mod loader;
 
 /// An ELF section header.
 pub type Section<W> = section::Header<Word = W>;
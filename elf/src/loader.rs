@@ -0,0 +1,506 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Loading ELF64 executables.
+//!
+//! This crate doesn't know about page tables, so nothing here maps any
+//! memory. Instead, [`Executable::parse`] checks that a binary is an
+//! executable we know how to load, and [`Executable::segments`] says what
+//! should be mapped where, with what contents and permissions.
+//! [`build_stack`] lays out the new program's initial stack, with its
+//! arguments, environment and auxiliary vector.
+//!
+//! Only statically-linked x86_64 executables are supported, since there's
+//! no dynamic linker to hand shared objects to.
+//!
+//! [`Executable::parse`]: struct.Executable.html#method.parse
+//! [`Executable::segments`]: struct.Executable.html#method.segments
+//! [`build_stack`]: fn.build_stack.html
+use core::mem;
+
+use memory::PAGE_SIZE;
+
+use file::{self, Header as FileHeader};
+use program;
+use ElfResult;
+
+/// End of the auxiliary vector.
+pub const AT_NULL: usize = 0;
+/// Address of the program headers in memory.
+pub const AT_PHDR: usize = 3;
+/// Size of one program header.
+pub const AT_PHENT: usize = 4;
+/// Number of program headers.
+pub const AT_PHNUM: usize = 5;
+/// The system's page size.
+pub const AT_PAGESZ: usize = 6;
+/// The program's entry point.
+pub const AT_ENTRY: usize = 9;
+
+/// Size of a 64-bit ELF file header.
+const FILE_HEADER_SIZE: usize = 64;
+/// Size of a 64-bit ELF program header.
+const PROGRAM_HEADER_SIZE: usize = 56;
+
+/// `e_machine` for x86_64.
+const EM_X86_64: u16 = 0x3e;
+/// `p_type` of a loadable segment.
+const PT_LOAD: u32 = 1;
+
+/// A segment of an executable, which should be mapped into memory.
+#[derive(Clone, Debug)]
+pub struct Segment<'a> {
+    /// the virtual address of the segment's first byte
+    pub vaddr: usize
+  , /// the number of bytes the segment takes up in memory
+    pub mem_size: usize
+  , /// the contents of the start of the segment. the other
+    /// `mem_size - data.len()` bytes should be zeroed.
+    pub data: &'a [u8]
+  , /// whether the segment should be writable or executable
+    pub flags: program::Flags
+  , /// where `data` starts in the file
+    offset: usize
+}
+
+impl<'a> Segment<'a> {
+    /// Returns the address just past the end of the segment.
+    #[inline] pub fn end(&self) -> usize { self.vaddr + self.mem_size }
+
+    /// Returns true if `addr` is in this segment.
+    #[inline] pub fn contains(&self, addr: usize) -> bool {
+        addr >= self.vaddr && addr < self.end()
+    }
+}
+
+/// One program header, read field by field.
+///
+/// Program headers aren't cast to
+/// [`HeaderRepr64`](../program/struct.HeaderRepr64.html), since the binary
+/// may not be aligned, and its segments may have types that
+/// [`program::Type`](../program/enum.Type.html) doesn't know about.
+struct RawHeader { ty: u32
+                 , flags: u32
+                 , offset: usize
+                 , vaddr: usize
+                 , file_size: usize
+                 , mem_size: usize
+                 }
+
+impl RawHeader {
+    fn read(bytes: &[u8]) -> ElfResult<Self> {
+        Ok(RawHeader { ty: read_u32(bytes, 0)
+                     , flags: read_u32(bytes, 4)
+                     , offset: to_usize(read_u64(bytes, 8))?
+                     , vaddr: to_usize(read_u64(bytes, 16))?
+                     , file_size: to_usize(read_u64(bytes, 32))?
+                     , mem_size: to_usize(read_u64(bytes, 40))?
+                     })
+    }
+}
+
+/// An ELF64 executable which has been checked, and can be loaded.
+#[derive(Copy, Clone, Debug)]
+pub struct Executable<'a> { binary: &'a [u8]
+                          , header: &'a file::HeaderRepr<u64>
+                          }
+
+impl<'a> Executable<'a> {
+    /// Check that `binary` is a statically-linked x86_64 executable, whose
+    /// segments are all within the file, and don't overlap in memory.
+    pub fn parse(binary: &'a [u8]) -> ElfResult<Self> {
+        // check the fields which are enums in `HeaderRepr` before it's read
+        check_ident(binary)?;
+        let header = <file::HeaderRepr<u64> as FileHeader>
+                        ::from_slice(binary)?;
+        if header.get_type() != file::Type::Executable {
+            return Err("Only executables can be loaded")
+        }
+        if header.ph_entry_size() != PROGRAM_HEADER_SIZE {
+            return Err("Program headers are the wrong size")
+        }
+        if header.ph_count() == 0 {
+            return Err("Executable has no program headers")
+        }
+        let end = header.ph_offset()
+                        .checked_add(header.ph_count() * PROGRAM_HEADER_SIZE)
+                        .ok_or("Program headers are past the end of the file")?;
+        if end > binary.len() {
+            return Err("Program headers are past the end of the file")
+        }
+
+        let exe = Executable { binary: binary, header: header };
+        // the spec says loadable segments are sorted by address, so we only
+        // have to compare each one with the last
+        let entry = header.entry_point();
+        let mut last_page = None;
+        let mut entry_ok = false;
+        for i in 0..header.ph_count() {
+            let ph = exe.program_header(i)?;
+            if ph.ty != PT_LOAD || ph.mem_size == 0 { continue }
+            let file_end = ph.offset.checked_add(ph.file_size)
+                             .ok_or("Segment is past the end of the file")?;
+            if file_end > binary.len() {
+                return Err("Segment is past the end of the file")
+            }
+            if ph.file_size > ph.mem_size {
+                return Err("Segment is bigger in the file than in memory")
+            }
+            let end = ph.vaddr.checked_add(ph.mem_size)
+                        .ok_or("Segment wraps around the address space")?;
+            let first = ph.vaddr / PAGE_SIZE as usize;
+            if let Some(last) = last_page {
+                if first <= last {
+                    return Err("Loadable segments share pages or are out \
+                                of order")
+                }
+            }
+            last_page = Some((end - 1) / PAGE_SIZE as usize);
+
+            let flags = program::Flags::from_bits_truncate(ph.flags);
+            if flags.contains(program::EXECUTABLE)
+                && entry >= ph.vaddr && entry < end {
+                entry_ok = true;
+            }
+        }
+        if !entry_ok {
+            return Err("Entry point isn't in an executable segment")
+        }
+        Ok(exe)
+    }
+
+    /// Returns the address execution should start at.
+    #[inline]
+    pub fn entry_point(&self) -> usize { self.header.entry_point() }
+
+    /// Returns the loadable segments, in order of address.
+    #[inline]
+    pub fn segments(&self) -> Segments<'a> {
+        Segments { exe: *self, next: 0 }
+    }
+
+    /// Returns the address the program headers end up at, if a loadable
+    /// segment contains them.
+    pub fn program_headers_addr(&self) -> Option<usize> {
+        let offset = self.header.ph_offset();
+        let end = offset + self.header.ph_count() * PROGRAM_HEADER_SIZE;
+        for segment in self.segments() {
+            if offset >= segment.offset
+                && end <= segment.offset + segment.data.len() {
+                return Some(segment.vaddr + (offset - segment.offset))
+            }
+        }
+        None
+    }
+
+    /// Returns the auxiliary vector to pass to the program, without the
+    /// terminating `AT_NULL` entry.
+    pub fn aux_vector(&self) -> AuxVector {
+        let mut auxv = AuxVector { entries: [(AT_NULL, 0); AUXV_LEN]
+                                 , len: 0 };
+        if let Some(addr) = self.program_headers_addr() {
+            auxv.push(AT_PHDR, addr);
+        }
+        auxv.push(AT_PHENT, PROGRAM_HEADER_SIZE);
+        auxv.push(AT_PHNUM, self.header.ph_count());
+        auxv.push(AT_PAGESZ, PAGE_SIZE as usize);
+        auxv.push(AT_ENTRY, self.entry_point());
+        auxv
+    }
+
+    /// Returns program header number `i`.
+    fn program_header(&self, i: usize) -> ElfResult<RawHeader> {
+        let start = self.header.ph_offset() + i * PROGRAM_HEADER_SIZE;
+        RawHeader::read(&self.binary[start .. start + PROGRAM_HEADER_SIZE])
+    }
+}
+
+/// An iterator over the loadable segments of an
+/// [`Executable`](struct.Executable.html).
+#[derive(Clone, Debug)]
+pub struct Segments<'a> { exe: Executable<'a>
+                        , next: usize
+                        }
+
+impl<'a> Iterator for Segments<'a> {
+    type Item = Segment<'a>;
+
+    fn next(&mut self) -> Option<Segment<'a>> {
+        while self.next < self.exe.header.ph_count() {
+            let i = self.next;
+            self.next += 1;
+            // `parse` already checked every header
+            let ph = match self.exe.program_header(i) {
+                Ok(ph) => ph
+              , Err(_) => continue
+            };
+            if ph.ty != PT_LOAD || ph.mem_size == 0 { continue }
+            return Some(Segment {
+                vaddr: ph.vaddr
+              , mem_size: ph.mem_size
+              , data: &self.exe.binary[ph.offset .. ph.offset + ph.file_size]
+              , flags: program::Flags::from_bits_truncate(ph.flags)
+              , offset: ph.offset
+            })
+        }
+        None
+    }
+}
+
+/// Maximum number of entries in an [`AuxVector`](struct.AuxVector.html).
+const AUXV_LEN: usize = 5;
+
+/// The auxiliary vector for an executable, which tells it about itself.
+#[derive(Copy, Clone, Debug)]
+pub struct AuxVector { entries: [(usize, usize); AUXV_LEN]
+                     , len: usize
+                     }
+
+impl AuxVector {
+    /// Returns the `(type, value)` pairs in this vector.
+    #[inline]
+    pub fn as_slice(&self) -> &[(usize, usize)] { &self.entries[..self.len] }
+
+    fn push(&mut self, ty: usize, value: usize) {
+        self.entries[self.len] = (ty, value);
+        self.len += 1;
+    }
+}
+
+/// Lay out a new program's initial stack, as the System V ABI for x86_64
+/// describes it, and return the initial stack pointer.
+///
+/// `stack` is the memory just below `top`, which is where the stack
+/// starts. From `top` down, it ends up looking like this:
+///
+/// ```text
+///         argument and environment strings
+///         padding, so that the stack pointer is 16-byte aligned
+///         AT_NULL, 0
+///         auxiliary vector entries
+///         0
+///         pointers to environment strings
+///         0
+///         pointers to argument strings
+///  sp ->  argument count
+/// ```
+///
+/// `auxv` shouldn't include the terminating `AT_NULL` entry.
+pub fn build_stack( stack: &mut [u8], top: usize
+                  , args: &[&[u8]], env: &[&[u8]], auxv: &[(usize, usize)])
+                  -> ElfResult<usize> {
+    const TOO_BIG: &'static str = "Arguments don't fit on the stack";
+    let bottom = top.checked_sub(stack.len()).ok_or(TOO_BIG)?;
+    let strings_len = args.iter().chain(env.iter())
+                          .fold(0, |len, s| len + s.len() + 1);
+    let words = 1 + args.len() + 1 + env.len() + 1 + 2 * (auxv.len() + 1);
+    let strings = top.checked_sub(strings_len).ok_or(TOO_BIG)?;
+    let sp = strings.checked_sub(words * mem::size_of::<u64>())
+                    .ok_or(TOO_BIG)? & !0xf;
+    if sp < bottom { return Err(TOO_BIG) }
+
+    {
+        let mut word_addr = sp;
+        let mut push = |value: usize| {
+            write_u64(stack, word_addr - bottom, value as u64);
+            word_addr += mem::size_of::<u64>();
+        };
+        let mut string_addr = strings;
+        push(args.len());
+        for list in &[args, env] {
+            for s in list.iter() {
+                push(string_addr);
+                string_addr += s.len() + 1;
+            }
+            push(0);
+        }
+        for &(ty, value) in auxv.iter().chain(&[(AT_NULL, 0)]) {
+            push(ty);
+            push(value);
+        }
+    }
+
+    let mut offset = strings - bottom;
+    for s in args.iter().chain(env.iter()) {
+        stack[offset .. offset + s.len()].copy_from_slice(s);
+        stack[offset + s.len()] = 0;
+        offset += s.len() + 1;
+    }
+    Ok(sp)
+}
+
+/// Check the identifier and machine of a 64-bit ELF file header.
+fn check_ident(bytes: &[u8]) -> ElfResult<()> {
+    if bytes.len() < FILE_HEADER_SIZE {
+        return Err("File is too short to be an ELF64 executable")
+    }
+    if bytes[0..4] != file::MAGIC {
+        return Err("Not an ELF file")
+    }
+    if bytes[4] != file::Class::Elf64 as u8 {
+        return Err("Not a 64-bit ELF file")
+    }
+    if bytes[5] != file::DataEncoding::LittleEndian as u8 {
+        return Err("Not a little-endian ELF file")
+    }
+    if bytes[6] != file::Version::Current as u8 {
+        return Err("Unknown ELF version")
+    }
+    if bytes[7] != file::OsAbi::SystemV as u8
+        && bytes[7] != file::OsAbi::Linux as u8 {
+        return Err("Unsupported OS ABI")
+    }
+    if read_u16(bytes, 18) != EM_X86_64 {
+        return Err("Not an x86_64 executable")
+    }
+    Ok(())
+}
+
+fn to_usize(value: u64) -> ElfResult<usize> {
+    if value > usize::max_value() as u64 {
+        Err("Value is too big for this machine")
+    } else {
+        Ok(value as usize)
+    }
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    bytes[offset] as u16 | (bytes[offset + 1] as u16) << 8
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    read_u16(bytes, offset) as u32 | (read_u16(bytes, offset + 2) as u32) << 16
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    read_u32(bytes, offset) as u64 | (read_u32(bytes, offset + 4) as u64) << 32
+}
+
+fn write_u64(bytes: &mut [u8], offset: usize, value: u64) {
+    for i in 0..8 {
+        bytes[offset + i] = (value >> (i * 8)) as u8;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use program::{EXECUTABLE, READABLE, WRITABLE};
+
+    /// A tiny user program (see `test_data/hello.s`).
+    const HELLO: &'static [u8; 8648] = include_bytes!("../test_data/hello");
+
+    #[test]
+    fn parse_segments() {
+        let exe = Executable::parse(&HELLO[..]).unwrap();
+        assert_eq!(exe.entry_point(), 0x80_0000_1000);
+
+        assert_eq!(exe.segments().count(), 4);
+
+        let text = exe.segments().nth(1).unwrap();
+        assert_eq!(text.vaddr, 0x80_0000_1000);
+        assert_eq!(text.flags, READABLE | EXECUTABLE);
+
+        // .data and .bss: the .bss part is zero-filled
+        let data = exe.segments().nth(3).unwrap();
+        assert_eq!(data.vaddr, 0x80_0000_3016);
+        assert_eq!(data.mem_size, 0x100a);
+        assert_eq!(data.data, &[22, 0, 0, 0, 0, 0, 0, 0][..]);
+        assert_eq!(data.flags, READABLE | WRITABLE);
+    }
+
+    #[test]
+    fn aux_vector() {
+        let exe = Executable::parse(&HELLO[..]).unwrap();
+        assert_eq!( exe.aux_vector().as_slice()
+                  , &[ (AT_PHDR, 0x80_0000_0040), (AT_PHENT, 56)
+                     , (AT_PHNUM, 5), (AT_PAGESZ, 4096)
+                     , (AT_ENTRY, 0x80_0000_1000) ][..]);
+    }
+
+    #[test]
+    fn reject_bad_headers() {
+        let mut bytes = *HELLO;
+        bytes[4] = 1;
+        assert_eq!( Executable::parse(&bytes[..]).unwrap_err()
+                  , "Not a 64-bit ELF file");
+
+        let mut bytes = *HELLO;
+        bytes[18] = 0x03;
+        assert_eq!( Executable::parse(&bytes[..]).unwrap_err()
+                  , "Not an x86_64 executable");
+
+        // ET_DYN
+        let mut bytes = *HELLO;
+        bytes[16] = 3;
+        assert_eq!( Executable::parse(&bytes[..]).unwrap_err()
+                  , "Only executables can be loaded");
+
+        assert!(Executable::parse(&HELLO[..32]).is_err());
+    }
+
+    #[test]
+    fn reject_truncated_segments() {
+        // the read-only data segment starts at 0x2000
+        assert_eq!( Executable::parse(&HELLO[..0x2010]).unwrap_err()
+                  , "Segment is past the end of the file");
+    }
+
+    #[test]
+    fn reject_overlapping_segments() {
+        // move .rodata onto the same page as .text
+        let mut bytes = *HELLO;
+        let vaddr = 64 + 2 * 56 + 16;
+        bytes[vaddr + 1] = 0x10;
+        assert_eq!( Executable::parse(&bytes[..]).unwrap_err()
+                  , "Loadable segments share pages or are out of order");
+    }
+
+    /// Where the test stacks start.
+    const TOP: usize = 0x1000;
+
+    /// Read the word at `addr` on a test stack.
+    fn word(stack: &[u8], addr: usize) -> usize {
+        read_u64(stack, addr - (TOP - stack.len())) as usize
+    }
+
+    /// Read the string at `addr` on a test stack.
+    fn string(stack: &[u8], addr: usize) -> &[u8] {
+        let start = addr - (TOP - stack.len());
+        let len = stack[start..].iter().position(|&b| b == 0).unwrap();
+        &stack[start .. start + len]
+    }
+
+    #[test]
+    fn stack_layout() {
+        let mut stack = [0u8; 256];
+        let sp = build_stack( &mut stack, TOP
+                            , &[&b"hello"[..]], &[&b"A=b"[..]]
+                            , &[(AT_PAGESZ, 4096)])
+                    .unwrap();
+        assert_eq!(sp % 16, 0);
+        let stack = &stack[..];
+
+        assert_eq!(word(stack, sp), 1);
+        assert_eq!(string(stack, word(stack, sp + 8)), b"hello");
+        assert_eq!(word(stack, sp + 16), 0);
+        assert_eq!(string(stack, word(stack, sp + 24)), b"A=b");
+        assert_eq!(word(stack, sp + 32), 0);
+        assert_eq!(word(stack, sp + 40), AT_PAGESZ);
+        assert_eq!(word(stack, sp + 48), 4096);
+        assert_eq!(word(stack, sp + 56), AT_NULL);
+        assert_eq!(word(stack, sp + 64), 0);
+    }
+
+    #[test]
+    fn stack_too_small() {
+        let mut stack = [0u8; 32];
+        assert!(build_stack(&mut stack, TOP, &[&b"hello"[..]], &[], &[])
+                    .is_err());
+    }
+}
@@ -85,7 +85,8 @@ pub trait Header: fmt::Debug {
 
     /// Look up the name of this section in the passed string table.
     #[inline] fn get_name<'a>(&self, strtab: StrTable<'a>) -> &'a str {
-        unimplemented!()
+        strtab.at_index(self.name_offset() as usize)
+              .unwrap_or("<unnamed>")
     }
 
     // Field accessors -------------------------------------------------
@@ -447,10 +448,10 @@ impl<'a> StrTable<'a> {
     // TODO: can this be replaced with an ops::Index implementation?
     //       but then we can't implement Deref to a slice any more?
     //          - eliza, 03/07/2017
-    pub fn at_index(&'a self, i: usize) -> Option<&'a str> {
+    pub fn at_index(&self, i: usize) -> Option<&'a str> {
         use core::str::from_utf8_unchecked;
-        if i <= self.len() {
-            read_to_null(&self[i..])
+        if i < self.0.len() {
+            read_to_null(&self.0[i..])
                 .map(|bytes| unsafe {
                     // TODO: should this be checked, or do we assume the ELF
                     //       binary has only well-formed strings? this could be
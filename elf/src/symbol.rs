@@ -0,0 +1,273 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! ELF symbol tables.
+//!
+//! Refer to the Symbol Table [entry] in Section 4 of the ELF standard for more
+//! information.
+//!
+//! [entry]: http://www.sco.com/developers/gabi/latest/ch4.symtab.html
+use super::{ElfResult, ElfWord, extract_from_slice};
+use super::section::StrTable;
+
+use core::{fmt, mem};
+
+/// Trait representing an ELF symbol table entry.
+///
+/// This allows [`SymbolRepr32`] and [`SymbolRepr64`] to provide a consistent
+/// API, since the fields of 32- and 64-bit symbols are laid out differently.
+///
+/// [`SymbolRepr32`]: struct.SymbolRepr32.html
+/// [`SymbolRepr64`]: struct.SymbolRepr64.html
+pub trait Symbol: Sized {
+    type Word: ElfWord;
+
+    /// Returns the offset of this symbol's name in the string table.
+    fn name_offset(&self) -> u32;
+
+    /// Returns the value (typically the address) of this symbol.
+    fn value(&self) -> usize;
+
+    /// Returns the size of the object this symbol refers to.
+    ///
+    /// This may be zero if the symbol has no size, or an unknown size.
+    fn size(&self) -> usize;
+
+    /// Returns the raw `st_info` field, containing the type and binding.
+    fn info(&self) -> u8;
+
+    /// Returns the index of the section this symbol is defined relative to.
+    fn section_index(&self) -> u16;
+
+    /// Returns the [type](enum.Type.html) of this symbol.
+    #[inline] fn ty(&self) -> Type { Type::from(self.info() & 0xf) }
+
+    /// Returns the [binding](enum.Binding.html) of this symbol.
+    #[inline] fn binding(&self) -> Binding { Binding::from(self.info() >> 4) }
+
+    /// Returns true if `addr` lies within the object this symbol refers to.
+    #[inline] fn contains(&self, addr: usize) -> bool {
+        let start = self.value();
+        addr >= start && addr < start + self.size()
+    }
+
+    /// Look up the name of this symbol in the passed string table.
+    #[inline] fn get_name<'a>(&self, strtab: &StrTable<'a>) -> Option<&'a str> {
+        strtab.at_index(self.name_offset() as usize)
+    }
+}
+
+/// The type of an ELF symbol (the low four bits of `st_info`).
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Type { /// `STT_NOTYPE`: The symbol's type is not specified.
+                NoType
+              , /// `STT_OBJECT`: The symbol is a data object.
+                Object
+              , /// `STT_FUNC`: The symbol is a function or other executable
+                /// code.
+                Function
+              , /// `STT_SECTION`: The symbol is associated with a section.
+                Section
+              , /// `STT_FILE`: The symbol names a source file.
+                File
+              , /// `STT_COMMON`: The symbol labels an uninitialized common
+                /// block.
+                Common
+              , /// `STT_TLS`: The symbol specifies a thread-local storage
+                /// entity.
+                ThreadLocal
+              , /// An OS- or processor-specific symbol type.
+                Other(u8)
+              }
+
+impl From<u8> for Type {
+    #[inline]
+    fn from(ty: u8) -> Self {
+        match ty {
+            0 => Type::NoType
+          , 1 => Type::Object
+          , 2 => Type::Function
+          , 3 => Type::Section
+          , 4 => Type::File
+          , 5 => Type::Common
+          , 6 => Type::ThreadLocal
+          , x => Type::Other(x)
+        }
+    }
+}
+
+/// The binding of an ELF symbol (the high four bits of `st_info`).
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Binding { /// `STB_LOCAL`: not visible outside its object file.
+                   Local
+                 , /// `STB_GLOBAL`: visible to all object files.
+                   Global
+                 , /// `STB_WEAK`: like global symbols, but with lower
+                   /// precedence.
+                   Weak
+                 , /// An OS- or processor-specific binding.
+                   Other(u8)
+                 }
+
+impl From<u8> for Binding {
+    #[inline]
+    fn from(binding: u8) -> Self {
+        match binding {
+            0 => Binding::Local
+          , 1 => Binding::Global
+          , 2 => Binding::Weak
+          , x => Binding::Other(x)
+        }
+    }
+}
+
+/// Raw representation of a 64-bit ELF symbol (`Elf64_Sym`).
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+pub struct SymbolRepr64 { name_offset: u32
+                        , info: u8
+                        , other: u8
+                        , section_index: u16
+                        , value: u64
+                        , size: u64
+                        }
+
+/// Raw representation of a 32-bit ELF symbol (`Elf32_Sym`).
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+pub struct SymbolRepr32 { name_offset: u32
+                        , value: u32
+                        , size: u32
+                        , info: u8
+                        , other: u8
+                        , section_index: u16
+                        }
+
+impl Symbol for SymbolRepr64 {
+    type Word = u64;
+    impl_getters! {
+        fn name_offset(&self) -> u32;
+        fn value(&self) -> usize;
+        fn size(&self) -> usize;
+        fn info(&self) -> u8;
+        fn section_index(&self) -> u16;
+    }
+}
+
+impl Symbol for SymbolRepr32 {
+    type Word = u32;
+    impl_getters! {
+        fn name_offset(&self) -> u32;
+        fn value(&self) -> usize;
+        fn size(&self) -> usize;
+        fn info(&self) -> u8;
+        fn section_index(&self) -> u16;
+    }
+}
+
+/// A symbol table, with the string table containing its symbols' names.
+#[derive(Clone)]
+pub struct SymbolTable<'a, S: 'a> { symbols: &'a [S]
+                                   , strings: StrTable<'a>
+                                   }
+
+impl<'a, S> SymbolTable<'a, S>
+where S: Symbol + 'a {
+
+    /// Construct a symbol table from the raw contents of a symbol table
+    /// section and its linked string table section.
+    ///
+    /// # Safety
+    /// + `symbols` must contain valid symbols of type `S`.
+    pub unsafe fn new(symbols: &'a [u8], strings: &'a [u8])
+                      -> ElfResult<Self> {
+        let n = symbols.len() / mem::size_of::<S>();
+        Ok(SymbolTable { symbols: extract_from_slice::<S>(symbols, 0, n)?
+                       , strings: StrTable::from(strings)
+                       })
+    }
+
+    /// Returns the number of symbols in the table.
+    #[inline] pub fn len(&self) -> usize { self.symbols.len() }
+
+    /// Returns an iterator over the symbols in this table.
+    #[inline]
+    pub fn symbols(&self) -> ::core::slice::Iter<'a, S> { self.symbols.iter() }
+
+    /// Returns the name of a symbol in this table.
+    #[inline]
+    pub fn name_of(&self, symbol: &S) -> Option<&'a str> {
+        symbol.get_name(&self.strings)
+    }
+
+    /// Find the function containing `addr`.
+    ///
+    /// Returns the function's name, and the offset of `addr` from the start
+    /// of the function. If no function symbol's extent contains `addr`, the
+    /// closest function symbol starting before `addr` is used instead.
+    pub fn resolve(&self, addr: usize) -> Option<(&'a str, usize)> {
+        let mut best: Option<&'a S> = None;
+        for sym in self.symbols.iter()
+                       .filter(|s| s.ty() == Type::Function
+                                && s.value() != 0
+                                && s.value() <= addr) {
+            if sym.contains(addr) {
+                best = Some(sym);
+                break;
+            }
+            best = match best {
+                Some(b) if b.value() >= sym.value() => Some(b)
+              , _ => Some(sym)
+            };
+        }
+        best.and_then(|sym| self.name_of(sym)
+                                .map(|name| (name, addr - sym.value())))
+    }
+}
+
+impl<'a, S> fmt::Debug for SymbolTable<'a, S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SymbolTable {{ {} symbols }}", self.symbols.len())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn func(name_offset: u32, value: u64, size: u64) -> SymbolRepr64 {
+        SymbolRepr64 { name_offset: name_offset
+                     , info: (1 << 4) | 2
+                     , other: 0
+                     , section_index: 1
+                     , value: value
+                     , size: size
+                     }
+    }
+
+    #[test]
+    fn resolve_function_and_offset() {
+        let strings = b"\0foo\0bar\0";
+        let symbols = [ func(1, 0x1000, 0x100), func(5, 0x1100, 0x80) ];
+        let table = SymbolTable { symbols: &symbols[..]
+                                , strings: StrTable::from(&strings[..]) };
+
+        assert_eq!(table.resolve(0x1010), Some(("foo", 0x10)));
+        assert_eq!(table.resolve(0x1100), Some(("bar", 0)));
+        // past the end of `bar`, so fall back to the closest preceding symbol
+        assert_eq!(table.resolve(0x2000), Some(("bar", 0xf00)));
+        assert_eq!(table.resolve(0x10), None);
+    }
+
+    #[test]
+    fn type_and_binding() {
+        let sym = func(0, 0, 0);
+        assert_eq!(sym.ty(), Type::Function);
+        assert_eq!(sym.binding(), Binding::Global);
+    }
+}
@@ -0,0 +1,37 @@
+# A tiny SOS user program, for testing the ELF loader.
+#
+# Build with:
+#   as -o hello.o hello.s
+#   ld -static -nostdlib -s --build-id=none -z max-page-size=0x1000 \
+#      -z noexecstack -Ttext-segment=0x8000000000 -o hello hello.o
+
+        .section .text
+        .globl _start
+_start:
+        # write(message, length)
+        movq    $1, %rax
+        leaq    message(%rip), %rdi
+        movq    length(%rip), %rsi
+        syscall
+        # count the zeroed bytes in .bss, which should be all of them
+        leaq    buffer(%rip), %rdi
+        movq    $4096, %rcx
+        xorl    %eax, %eax
+        repe scasb
+        # exit(bytes left unchecked)
+        movq    %rcx, %rdi
+        movq    $0, %rax
+        syscall
+        ud2
+
+        .section .rodata
+message:
+        .ascii  "Hello from user mode!\n"
+
+        .section .data
+length:
+        .quad   22
+
+        .section .bss
+buffer:
+        .skip   4096
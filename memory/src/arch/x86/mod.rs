@@ -6,29 +6,97 @@
 //  Released under the terms of the MIT license. See `LICENSE` in the root
 //  directory of this repository for more information.
 //
-use ::memory::VAddr;
+//! Architecture-specific memory management.
+//!
+//! Virtual addresses are 32 bits wide on `x86`, but physical addresses are
+//! still 64 bits wide: the CPU may have more than 4 GiB of memory (which PAE
+//! can address), and the memory map the bootloader gives us uses 64-bit
+//! addresses either way.
+use ::{Addr, Page};
 
-/// A physical (linear) memory address is a 32-bit unsigned integer
-#[derive(Copy, Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
-pub struct PAddr(u32);
+use core::{fmt, ops};
 
-impl Addr<u32> for PAddr { }
-impl_addr! { PAddr, u32 }
+pub const PAGE_SHIFT: u8 = 12;
+/// The size of a page (4KiB), in bytes
+pub const PAGE_SIZE: u64 = 1 << PAGE_SHIFT; // 4k
+/// The size of a large page (4MiB) in bytes
+pub const LARGE_PAGE_SIZE: u64 = 1024 * 1024 * 4;
 
-impl convert::Into<u32> for PAddr {
-    #[inline] fn into(self) -> u32 { self.as_u32() }
+
+macro_attr! {
+    /// A physical (linear) memory address is a 64-bit unsigned integer
+    #[derive(Copy, Clone, Eq, Ord, PartialEq, PartialOrd, Addr!(u64, 'P'))]
+    #[repr(C)]
+    pub struct PAddr(u64);
+}
+
+macro_attr! {
+    /// A frame (physical page)
+    #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Page!(PAddr) )]
+    pub struct PhysicalPage { pub number: u64 }
+}
+impl fmt::Debug for PhysicalPage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "frame #{} at {:#p}", self.number, self.base_addr())
+    }
 }
 
-impl convert::From<u32> for PAddr {
-    #[inline] fn from(u: u32) -> Self { PAddr::from_u32(u) }
+impl ops::Add<usize> for PhysicalPage {
+    type Output = Self;
+
+    #[inline] fn add(self, rhs: usize) -> Self {
+        PhysicalPage { number: self.number +  rhs as u64 }
+    }
+}
+
+impl ops::Sub<usize> for PhysicalPage {
+    type Output = Self;
+
+    #[inline] fn sub(self, rhs: usize) -> Self {
+        PhysicalPage { number: self.number -  rhs as u64 }
+    }
 }
 
-impl convert::From<*mut u8> for PAddr {
-    #[inline] fn from(ptr: *mut u8) -> Self { PAddr::from_ptr(ptr) }
+impl ops::AddAssign<usize> for PhysicalPage {
+    #[inline] fn add_assign(&mut self, rhs: usize) {
+        self.number += rhs as u64;
+    }
 }
 
-impl PAddr {
-    #[inline] pub fn from_ptr(ptr: *mut u8) -> Self { PAddr(ptr as u32) }
-    #[inline] pub const fn from_u32(u: u32) -> Self { PAddr(u) }
-    #[inline] pub const fn as_u32(&self) -> u32 { self.0 }
+impl ops::SubAssign<usize> for PhysicalPage {
+    #[inline] fn sub_assign(&mut self, rhs: usize) {
+        self.number -= rhs as u64;
+    }
+}
+
+impl PhysicalPage {
+
+    /// Returns the physical address where this frame starts.
+    #[inline]
+    pub const fn base_addr(&self) -> PAddr {
+        PAddr(self.number << PAGE_SHIFT)
+    }
+
+    /// Returns a new frame containing `addr`
+    #[inline]
+    pub const fn containing_addr(addr: PAddr) -> PhysicalPage {
+        PhysicalPage { number: addr.0 >> PAGE_SHIFT }
+    }
+
+    /// Convert the frame into a raw pointer to the frame's base address
+    ///
+    /// Frames above 4 GiB can't be addressed directly on `x86`.
+    #[inline]
+    pub unsafe fn as_ptr<T>(&self) -> *const T {
+        *self.base_addr() as usize as *const u8 as *const T
+    }
+
+    /// Convert the frame into a raw mutable pointer to the frame's base address
+    ///
+    /// Frames above 4 GiB can't be addressed directly on `x86`.
+    #[inline]
+    pub unsafe fn as_mut_ptr<T>(&self) -> *mut T {
+        *self.base_addr() as usize as *mut u8 as *mut T
+    }
+
 }
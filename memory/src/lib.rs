@@ -69,25 +69,53 @@ impl VAddr {
     /// Convert this virtual address to a `usize`.
     #[inline] pub const fn as_usize(&self) -> usize { self.0 }
 
+    /// Returns true if this address is canonical (i.e., its upper bits are
+    /// all equal to the highest implemented bit).
+    #[cfg(target_arch = "x86_64")]
+    #[inline] pub fn is_canonical(&self) -> bool {
+        (self.0 < 0x0000_8000_0000_0000) || (self.0 >= 0xffff_8000_0000_0000)
+    }
+
+    /// Returns true if this address is canonical. Every 32-bit address is.
+    #[cfg(target_arch = "x86")]
+    #[inline] pub fn is_canonical(&self) -> bool { true }
+
     /// Calculate the index in the PML4 table corresponding to this address.
+    #[cfg(target_arch = "x86_64")]
     #[inline] pub fn pml4_index(&self) -> usize {
         *((self >> 39) & 0b111111111 as usize)
     }
 
     /// Calculate the index in the PDPT table corresponding to this address.
+    #[cfg(target_arch = "x86_64")]
     #[inline] pub fn pdpt_index(&self) -> usize {
         *((self >> 30) & 0b111111111)
     }
 
     /// Calculate the index in the PD table corresponding to this address.
+    #[cfg(target_arch = "x86_64")]
     #[inline] pub fn pd_index(&self) -> usize {
         *((self >> 21) & 0b111111111)
     }
 
     /// Calculate the index in the PT table corresponding to this address.
+    #[cfg(target_arch = "x86_64")]
     #[inline] pub fn pt_index(&self) -> usize {
         *((self >> 12) & 0b111111111)
     }
+
+    /// Calculate the index in the page directory corresponding to this
+    /// address.
+    #[cfg(target_arch = "x86")]
+    #[inline] pub fn pd_index(&self) -> usize {
+        *((self >> 22) & 0b1111111111)
+    }
+
+    /// Calculate the index in the page table corresponding to this address.
+    #[cfg(target_arch = "x86")]
+    #[inline] pub fn pt_index(&self) -> usize {
+        *((self >> 12) & 0b1111111111)
+    }
 }
 
 use core::ops::Range;
@@ -164,8 +192,7 @@ macro_attr!{
 impl VirtualPage {
     fn containing_addr( addr: VAddr) -> Self {
         use ::PAGE_SHIFT;
-        assert!( (addr < 0x0000_8000_0000_0000) || (addr >= 0xffff_8000_0000_0000)
-               , "invalid address : 0x{:x}", addr );
+        assert!(addr.is_canonical(), "invalid address : 0x{:x}", addr );
         Self { number: addr.0 >> PAGE_SHIFT }
     }
 }
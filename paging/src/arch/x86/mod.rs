@@ -0,0 +1,570 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Paging
+//!
+//! Without PAE, the `x86` architecture uses a two-level page table structure:
+//! a Page Directory (PD), whose entries point to Page Tables (PT). Each table
+//! has 1024 32-bit entries, so page tables can only refer to the first 4 GiB
+//! of physical memory.
+//!
+//! As on `x86_64`, the last entry in the page directory maps the page
+//! directory itself, so the active page tables can be reached through the
+//! top 4 MiB of the address space.
+use core::{fmt, ops};
+use core::ptr::Unique;
+
+use alloc::FrameAllocator;
+use cpu::control_regs::cr3;
+use memory::{Addr, PAGE_SIZE, PAddr, Page, PhysicalPage, VAddr, VirtualPage};
+use params::InitParams;
+use ::{CacheType, Mapper, MapResult, MapErr};
+
+use self::table::*;
+use self::temp::TempPage;
+
+pub mod table;
+pub mod tlb;
+#[path = "../x86_all/temp.rs"] pub mod temp;
+
+/// Index of the recursive entry in the page directory.
+const RECURSIVE_ENTRY: usize = N_ENTRIES - 1;
+
+#[derive(Debug)]
+pub struct ActivePageTable { pd: ActivePageDirectory }
+
+impl ops::Deref for ActivePageTable {
+    type Target = ActivePageDirectory;
+
+    fn deref(&self) -> &ActivePageDirectory {
+        &self.pd
+    }
+}
+
+impl ops::DerefMut for ActivePageTable {
+    fn deref_mut(&mut self) -> &mut ActivePageDirectory {
+        &mut self.pd
+    }
+}
+
+impl ActivePageTable {
+    pub unsafe fn new() -> ActivePageTable {
+        ActivePageTable { pd: ActivePageDirectory::new() }
+    }
+
+    /// Execute a closure with the recursive mapping temporarily changed to a
+    /// new page table
+    pub fn using<F>( &mut self
+                   , table: &mut InactivePageTable
+                   , temp_page: &mut temp::TempPage
+                   , f: F)
+                   -> MapResult
+    where F: FnOnce(&mut ActivePageDirectory) -> MapResult {
+        let result: MapResult;
+        use self::tlb::flush_all;
+        {
+            // back up the current page directory frame
+            let prev_pd_frame = unsafe {
+                // this is safe to execute; we are in kernel mode
+                cr3::current_pagetable_frame()
+            };
+
+            // map temporary_page to the current page directory
+            let pd = temp_page.map_to_table(prev_pd_frame.clone(), self)?;
+
+            // remap the recursive entry to map to the frame containing the
+            // new page directory.
+            self.pd_mut()[RECURSIVE_ENTRY]
+                .set(table.pd_frame, PRESENT | WRITABLE);
+            unsafe {
+                // this is safe to execute; we are in kernel mode
+                flush_all();
+            }
+
+            // execute the closure
+            result = f(self);
+
+            // remap the recursive entry to point back to the original frame
+            pd[RECURSIVE_ENTRY].set(prev_pd_frame, PRESENT | WRITABLE);
+
+            unsafe {
+                // this is safe to execute; we are in kernel mode
+                flush_all();
+            }
+        }
+        let _ = temp_page.unmap(self)?;
+        return result
+
+    }
+
+    /// Replace the current `ActivePageTable` with the given `InactivePageTable`
+    ///
+    /// # Arguments
+    /// + `new_table`: the `InactivePageTable` that will replace the current
+    ///                `ActivePageTable`.
+    ///
+    /// # Returns
+    /// + the old active page table as an `InactivePageTable`.
+    pub fn replace_with(&mut self, new_table: InactivePageTable)
+                       -> InactivePageTable {
+        unsafe {
+            trace!("replacing {:?} with {:?}", self, new_table);
+            // this is safe to execute; we are in kernel mode
+            let old_pd_frame = cr3::current_pagetable_frame();
+            trace!("current page directory frame is {:?}", old_pd_frame);
+
+            cr3::write(new_table.pd_frame.base_addr());
+            trace!("set new page directory frame to {:?}", new_table.pd_frame);
+
+            InactivePageTable { pd_frame: old_pd_frame }
+        }
+    }
+
+    /// Unmap everything in the user half of the active table.
+    ///
+    /// User mode isn't supported on `x86` yet, so nothing is ever mapped
+    /// there, and this does nothing.
+    ///
+    /// # Safety
+    /// + The frames mapped in the user half must belong to this table
+    ///   alone, and no other CPU may have it loaded.
+    pub unsafe fn free_user<A>(&mut self, _alloc: &mut A)
+    where A: FrameAllocator { }
+
+}
+
+/// Returns the page table entry flags for the memory type `cache`.
+///
+/// We don't program the PAT on `x86`, so only the default PAT entries are
+/// available. Write-combining isn't one of them, so it falls back to
+/// uncacheable.
+fn cache_flags(cache: CacheType) -> EntryFlags {
+    match cache {
+        CacheType::WriteBack => EntryFlags::empty()
+      , CacheType::WriteThrough => WRITE_THROUGH
+      , CacheType::WriteCombining | CacheType::Uncacheable =>
+            NO_CACHE | WRITE_THROUGH
+    }
+}
+
+/// Struct representing the currently active page directory.
+///
+/// The `ActivePageDirectory` is a `Unique` reference to a PD-level page
+/// table. It's unique because there can only be one active page directory at
+/// a given time.
+pub struct ActivePageDirectory(Unique<Table<PDLevel>>);
+impl fmt::Debug for ActivePageDirectory {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Active {:?}", unsafe { self.0.as_ref() })
+    }
+}
+/// The active page directory is the single point of entry for page mapping.
+impl Mapper for ActivePageDirectory {
+    type Flags = EntryFlags;
+
+    fn translate(&self, vaddr: VAddr) -> Option<PAddr> {
+        let offset = *vaddr % PAGE_SIZE as usize;
+        self.translate_page(Page::containing(vaddr))
+            .map(|frame| frame.base_addr() + offset as u64)
+    }
+
+    fn translate_page(&self, page: VirtualPage) -> Option<PhysicalPage> {
+        let pd = self.pd();
+        pd.next_table(page)
+          .and_then(|pt| pt[page].get_frame())
+          .or_else(|| pd[page].do_huge(PTLevel::index_of(page)))
+    }
+
+    /// Modifies the page tables so that `page` maps to `frame`.
+    ///
+    /// # Arguments
+    /// + `page`: the virtual `Page` to map
+    /// + `frame`: the physical `Frame` that `Page` should map to.
+    /// + `flags`: the page table entry flags.
+    /// + `alloc`: a memory allocator
+    fn map<A>( &mut self, page: VirtualPage, frame: PhysicalPage
+             , flags: EntryFlags, alloc: &mut A)
+             -> MapResult<()>
+    where A: FrameAllocator {
+        // get or create the page table at the page's PD index
+        let mut page_table = self.pd_mut().create_next(page, alloc)?;
+        trace!(" . . Map: Got page table");
+        // check if the page at that index is not currently in use, as we
+        // cannot map a page which is currently in use.
+        if page_table[page].is_unused() {
+            // set the page table entry at that index
+            page_table[page].set(frame, flags | table::PRESENT);
+            Ok(())
+        } else {
+            Err(MapErr::AlreadyInUse {
+                message: "map frame"
+              , page: page
+              , frame: frame
+            })
+        }
+    }
+
+    fn map_with_cache<A>( &mut self, page: VirtualPage, frame: PhysicalPage
+                        , flags: EntryFlags, cache: CacheType, alloc: &mut A)
+                        -> MapResult<()>
+    where A: FrameAllocator {
+        let flags = (flags - (NO_CACHE | WRITE_THROUGH)) | cache_flags(cache);
+        self.map(page, frame, flags, alloc)
+    }
+
+    fn identity_map<A>(&mut self, frame: PhysicalPage, flags: EntryFlags
+                      , alloc: &mut A)
+                      -> MapResult<()>
+    where A: FrameAllocator {
+        self.map( Page::containing(VAddr::from(*frame.base_addr() as usize))
+                , frame
+                , flags
+                , alloc )
+    }
+
+    fn map_to_any<A>( &mut self
+                    , page: VirtualPage
+                    , flags: EntryFlags
+                    , alloc: &mut A)
+                    -> MapResult<()>
+    where A: FrameAllocator {
+        let frame = unsafe { alloc.allocate() }
+            .map_err(|err| MapErr::Alloc {
+                message: "map to any"
+              , page: page
+              , cause: err
+          })?;
+        self.map(page, frame, flags, alloc)
+    }
+
+    fn update_flags(&mut self, page: VirtualPage, flags: EntryFlags)
+                    -> MapResult<()> {
+        let page_table = self.pd_mut()
+                             .next_table_mut(page)
+                             .ok_or(MapErr::Other {
+                                message: "update flags"
+                              , page: page
+                              , cause: "huge pages not supported"
+                            })?;
+        let entry = &mut page_table[page];
+        let frame = entry.get_frame()
+                         .ok_or(MapErr::Other {
+                           message: "update flags"
+                         , page: page
+                         , cause: "it was not mapped"
+                       })?;
+        entry.set(frame, flags | table::PRESENT);
+        tlb::shootdown(page);
+        Ok(())
+    }
+
+    /// Unmap the given `VirtualPage`.
+    ///
+    /// All freed frames are returned to the given `FrameAllocator`.
+    fn unmap<A>(&mut self, page: VirtualPage, alloc: &mut A) -> MapResult<()>
+    where A: FrameAllocator {
+        // get the page table entry corresponding to the page.
+        let page_table = self.pd_mut()
+                             .next_table_mut(page)
+                             .ok_or(MapErr::Other {
+                                message: "unmap"
+                              , page: page
+                              , cause: "huge pages not supported"
+                            })?;
+        // index the entry from the table
+        let entry = &mut page_table[page];
+        trace!("got page table entry for {:?}", page);
+        // get the pointed frame for the page table entry.
+        let frame = entry.get_frame()
+                         .ok_or(MapErr::Other {
+                           message: "unmap"
+                         , page: page
+                         , cause: "it was not mapped"
+                       })?;
+        trace!("page table entry for {:?} points to {:?}", page, frame);
+        // mark the page table entry as unused
+        entry.set_unused();
+        trace!("set page table entry for {:?} as unused", page);
+        tlb::shootdown(page);
+        trace!("flushed TLB");
+        unsafe {
+            // this is hopefully safe because nobody else should be using an
+            // allocated page frame
+            alloc.deallocate(frame);
+            trace!("deallocated page {:?}", frame);
+        }
+        Ok(())
+    }
+
+}
+
+impl ActivePageDirectory {
+
+    pub unsafe fn new() -> Self {
+        ActivePageDirectory(Unique::new(PD_PTR))
+    }
+
+    fn pd(&self) -> &Table<PDLevel> {
+        unsafe { self.0.as_ref() }
+    }
+
+    fn pd_mut(&mut self) -> &mut Table<PDLevel> {
+        unsafe { self.0.as_mut() }
+    }
+
+    /// Returns true if the given page is mapped.
+    #[inline]
+    pub fn is_mapped(&self, page: &VirtualPage) -> bool {
+         self.translate_page(*page).is_some()
+    }
+
+}
+
+/// An inactive page table that the CPU is not currently using
+#[derive(Debug)]
+pub struct InactivePageTable {
+    pd_frame: PhysicalPage
+}
+
+impl InactivePageTable {
+    pub fn new( frame: PhysicalPage
+              , active_table: &mut ActivePageTable
+              , temp: &mut TempPage)
+              -> MapResult<Self> {
+        {
+            trace!("Mapping page {} to frame {}", temp.number, frame.number);
+            let table = temp.map_to_table(frame.clone(), active_table)?;
+            trace!( " . . . Mapped temp page to table frame .");
+            table.zero();
+            trace!( " . . . Zeroed inactive table frame.");
+            table[RECURSIVE_ENTRY].set( frame.clone(), PRESENT | WRITABLE);
+            trace!(" . . . Set active table to point to new inactive table.")
+        }
+        let _ = temp.unmap(active_table)?;
+        trace!(" . . Unmapped temp page.");
+
+        Ok(InactivePageTable { pd_frame: frame })
+    }
+
+    /// Create a page table for a user address space.
+    ///
+    /// User mode isn't supported on `x86` yet, so this always fails.
+    pub fn new_user( _frame: PhysicalPage
+                   , _active_table: &mut ActivePageTable
+                   , temp: &mut TempPage)
+                   -> MapResult<Self> {
+        Err(MapErr::Other { message: "create a user page table"
+                          , page: **temp
+                          , cause: "user mode isn't supported on x86"
+                          })
+    }
+
+    /// Returns a handle on the page tables loaded on the current CPU.
+    ///
+    /// # Safety
+    /// + The tables are still in use, so they must not be freed through
+    ///   the returned handle.
+    pub unsafe fn current() -> Self {
+        InactivePageTable { pd_frame: cr3::current_pagetable_frame() }
+    }
+
+    /// Returns the frame holding this table's page directory.
+    #[inline] pub fn frame(&self) -> PhysicalPage { self.pd_frame }
+
+    /// Deallocate this table's page directory, once its user half has been
+    /// freed with [`ActivePageTable::free_user`][free_user].
+    ///
+    /// # Safety
+    /// + This table must not be loaded on any CPU, and must never be
+    ///   used again.
+    ///
+    /// [free_user]: struct.ActivePageTable.html#method.free_user
+    pub unsafe fn free<A>(&self, alloc: &mut A)
+    where A: FrameAllocator {
+        alloc.deallocate(self.pd_frame);
+    }
+
+    /// Load this table on the current CPU, without giving up the table
+    /// that was loaded before.
+    ///
+    /// # Safety
+    /// + This table must map the kernel, including the running code and
+    ///   stack.
+    pub unsafe fn activate(&self) {
+        if cr3::current_pagetable_frame() == self.pd_frame { return }
+        cr3::write(self.pd_frame.base_addr());
+    }
+}
+
+/// The lowest address in the user half of an address space.
+pub const USER_BASE: usize = 0x0040_0000;
+
+/// Returns true if `addr` is in the user half of an address space.
+#[inline]
+pub fn is_user_address(addr: usize) -> bool {
+    addr >= USER_BASE && addr < 0xc000_0000
+}
+
+pub fn test_paging<A>(alloc: &mut A) -> MapResult<()>
+where A: FrameAllocator {
+    info!("testing paging");
+    let mut pd = unsafe { ActivePageDirectory::new() };
+
+    // address 0 is mapped
+    trace!("Some = {:?}", pd.translate(VAddr::from(0)));
+     // second PT entry
+    trace!("Some = {:?}", pd.translate(VAddr::from(4096)));
+    // last mapped byte of the first page table
+    trace!("Some = {:?}", pd.translate(VAddr::from(1024 * 4096 - 1)));
+
+    let addr = VAddr::from(0x4200_0000); // 264th PD entry
+    let page = VirtualPage::containing(addr);
+    let frame = unsafe { alloc.allocate().expect("no more frames") };
+    trace!("None = {:?}, map to {:?}",
+             pd.translate(addr),
+             frame);
+    let _ = pd.map(page, frame, EntryFlags::empty(), alloc)?;
+    trace!("Some = {:?}", pd.translate(addr));
+    trace!( "next free frame: {:?}"
+            , unsafe { alloc.allocate() });
+
+    let _ = pd.unmap(Page::containing(addr), alloc)?;
+    trace!("None = {:?}", pd.translate(addr));
+    Ok(())
+
+}
+
+/// Remaps the kernel using 4KiB pages.
+pub fn kernel_remap<A>(params: &InitParams, alloc: &mut A)
+                       -> MapResult<ActivePageTable>
+where A: FrameAllocator {
+    use elf::section;
+    // create a  temporary page for switching page tables
+    // page number chosen fairly arbitrarily.
+    const TEMP_PAGE_NUMBER: usize = 0xcafe0;
+    let mut temp_page = TempPage::new(TEMP_PAGE_NUMBER, alloc);
+    trace!("Created temporary page.");
+
+    // old and new page tables
+    let mut current_table = unsafe { ActivePageTable::new() };
+    trace!("Got current page table.");
+
+    let mut new_table = unsafe {
+        InactivePageTable::new(
+             alloc.allocate()
+                  .map_err(|err| MapErr::Alloc {
+                      message: "create the new page table"
+                     , page: *temp_page
+                     , cause: err
+                 })?
+          , &mut current_table
+          , &mut temp_page
+      )?
+    };
+    kinfoln!(dots: " . . ", "Created new {:?}", new_table);
+
+    // actually remap the kernel --------------------------------------------
+    current_table.using(&mut new_table, &mut temp_page, |pd| {
+        // extract allocated ELF sections
+        let sections
+            = params.elf_sections()
+                    .filter(|s| s.is_allocated());
+
+        kinfoln!(dots: " . . ", "Remapping kernel ELF sections.");
+
+        for section in sections { // remap ELF sections
+            attempt!(
+                if section.address().is_page_aligned() {
+                    let flags = EntryFlags::from(section);
+
+                    let start_frame = PhysicalPage::from(section.address());
+                    let end_frame = PhysicalPage::from(section.end_address());
+
+                    for frame in start_frame .. end_frame {
+                        let _ = pd.identity_map(frame, flags, alloc)?;
+                    }
+                    Ok(())
+                } else {
+                    Err(MapErr::NoPage::<VirtualPage> {
+                        message: "identity map section"
+                      , cause: "the start address was not page aligned"
+                    })
+                } =>
+                      dots: " . . . ",
+                      "Identity mapping {}", section );
+        }
+
+        // identity map the symbol and string tables. these aren't allocated
+        // sections, but we want them so that we can symbolize backtraces.
+        kinfoln!(dots: " . . ", "Identity mapping kernel symbol tables.");
+        let tables = params.elf_sections()
+                           .filter(|s| !s.is_allocated())
+                           .filter(|s| match s.get_type() {
+                                Ok(section::Type::SymbolTable) |
+                                Ok(section::Type::StringTable) => true
+                              , _ => false
+                            });
+        for table in tables {
+            // symbol tables need not be page aligned, and may share frames
+            // with each other.
+            let start_frame = PhysicalPage::containing(table.address());
+            let mut end_frame = PhysicalPage::containing(table.end_address());
+            if !table.end_address().is_page_aligned() { end_frame += 1; }
+
+            for frame in start_frame .. end_frame {
+                match pd.identity_map(frame, PRESENT, alloc) {
+                    Ok(()) | Err(MapErr::AlreadyInUse { .. }) => {}
+                  , Err(why) => return Err(why)
+                }
+            }
+        }
+
+        // remap VGA buffer
+        let vga_buffer_frame = PhysicalPage::containing(PAddr::from(0xb8000));
+        attempt!( pd.identity_map(vga_buffer_frame, WRITABLE, alloc) =>
+                  dots: " . . ", "Identity mapping VGA buffer" );
+
+
+        // remap Multiboot info
+        kinfoln!( dots: " . . ", "Identity mapping multiboot info" );
+        let multiboot_start = PhysicalPage::from(params.multiboot_start());
+        let multiboot_end = PhysicalPage::from(params.multiboot_end());
+
+        for frame in multiboot_start .. multiboot_end {
+            let _ = pd.identity_map(frame, PRESENT, alloc)?;
+        }
+
+        // identity map the boot modules, so they can be read until they're
+        // loaded. they may share frames with each other, or with the
+        // multiboot info.
+        for module in params.modules() {
+            kinfoln!( dots: " . . ", "Identity mapping module {:?}"
+                    , module.command_line.unwrap_or(""));
+            for frame in module.frames() {
+                match pd.identity_map(frame, PRESENT, alloc) {
+                    Ok(()) | Err(MapErr::AlreadyInUse { .. }) => {}
+                  , Err(why) => return Err(why)
+                }
+            }
+        }
+        Ok(())
+    })?;
+
+    trace!("replacing old page table with new page table");
+    // switch page tables ---------------------------------------------------
+    let old_table = current_table.replace_with(new_table);
+    kinfoln!(dots: " . . ", "Successfully switched to remapped page table!");
+
+    // create guard page at the location of the old page directory
+    let old_pd_vaddr = VAddr::from(*(old_table.pd_frame.base()) as usize);
+    let old_pd_page  = VirtualPage::containing(old_pd_vaddr);
+    let _ = current_table.unmap(old_pd_page, alloc)?;
+    trace!("Unmapped guard page at {:?}", old_pd_page.base());
+    Ok(current_table)
+}
@@ -0,0 +1,356 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+use alloc::FrameAllocator;
+use ::elf;
+use memory::{Addr, PAGE_SIZE, PAddr, PhysicalPage, VAddr, VirtualPage};
+
+use core::marker::PhantomData;
+use core::ops::{Index, IndexMut};
+use core::{convert, fmt, intrinsics};
+
+use ::{ MapResult, MapErr};
+
+/// The number of entries in a page table.
+pub const N_ENTRIES: usize = 1024;
+/// Size of a page table (in bytes)
+pub const PAGE_TABLE_SIZE: usize = N_ENTRIES * PAGE_SIZE as usize;
+
+/// Base virtual address of the page directory
+pub const PD_VADDR: u32 = 0xffff_f000;
+
+/// A pointer to the page directory
+pub const PD_PTR: *mut Table<PDLevel> = PD_VADDR as *mut _;
+
+/// Mask to apply to a page table entry to isolate the flags
+pub const ENTRY_FLAGS_MASK: u32 = (PAGE_SIZE as u32 - 1) as u32;
+
+/// A page table
+#[repr(C)]
+pub struct Table<L>
+where L: TableLevel { /// The entries in the page table.
+                      entries: [Entry; N_ENTRIES]
+                    , _level_marker: PhantomData<L>
+                    }
+
+impl<L:TableLevel> fmt::Debug for Table<L> {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at {:#p}"
+              , unsafe { intrinsics::type_name::<L>() }
+              , self)
+    }
+}
+
+pub trait TableLevel {
+    /// How much to shift an address by to find its index in this table.
+    const ADDR_SHIFT_AMOUNT: usize;
+    /// How much to shift a page number by to find its index in this level table
+    const PAGE_SHIFT_AMOUNT: usize;
+    /// Mask for indices
+    const INDEX_MASK: usize = 0x3ff;
+
+}
+
+pub trait IndexOf<I> {
+    fn index_of(i: I) -> usize;
+}
+
+impl<T> IndexOf<VAddr> for T
+where T: TableLevel {
+
+    /// Returns the index in this table for the given virtual address
+    #[inline]
+    fn index_of(addr: VAddr) -> usize {
+        (addr.as_usize() >> Self::ADDR_SHIFT_AMOUNT) & Self::INDEX_MASK
+    }
+
+}
+
+impl<T> IndexOf<VirtualPage> for T
+where T: TableLevel {
+    /// Returns the index in this table for the given virtual page
+    #[inline]
+    fn index_of(page: VirtualPage) -> usize {
+        (page.number >> Self::PAGE_SHIFT_AMOUNT) & Self::INDEX_MASK
+    }
+}
+
+impl<T> IndexOf<usize> for T
+where T: TableLevel {
+    #[inline(always)]
+    fn index_of(i: usize) -> usize { i }
+
+}
+
+pub enum PDLevel   {}
+pub enum PTLevel   {}
+
+impl TableLevel for PDLevel   {
+    const ADDR_SHIFT_AMOUNT: usize = 22;
+    const PAGE_SHIFT_AMOUNT: usize = 10;
+}
+impl TableLevel for PTLevel   {
+    const ADDR_SHIFT_AMOUNT: usize = 12;
+    const PAGE_SHIFT_AMOUNT: usize = 0;
+}
+
+pub trait Sublevel: TableLevel {
+    type Next: TableLevel;
+}
+impl Sublevel for PDLevel {
+    type Next = PTLevel;
+}
+
+impl<L, I> Index<I> for Table<L>
+where L: TableLevel
+    , L: IndexOf<I> {
+    type Output = Entry;
+
+    #[inline] fn index(&self, i: I) -> &Entry {
+        &self.entries[L::index_of(i)]
+    }
+}
+
+impl<L, I> IndexMut<I> for Table<L>
+where L: TableLevel
+    , L: IndexOf<I> {
+    #[inline] fn index_mut(&mut self, i: I) -> &mut Entry {
+        &mut self.entries[L::index_of(i)]
+    }
+}
+
+
+impl<L: TableLevel> Table<L>  {
+
+    /// Zeroes out the page table by setting all entries "unused"
+    pub fn zero(&mut self) -> &mut Self {
+        trace!("zeroing {:?}", self);
+        for entry in self.entries.iter_mut() {
+            entry.set_unused();
+        }
+        trace!("zeroed {:?} successfully", self);
+        self
+    }
+
+    /// Return the start physical address of this `Table`
+    #[inline]
+    pub fn start_paddr(&self) -> PAddr {
+        PAddr::from(self as *const Self as usize as u64)
+    }
+
+    /// Return the `PhysicalPage` containing this table.
+    #[inline]
+    pub fn frame(&self) -> PhysicalPage {
+        PhysicalPage::containing(self.start_paddr())
+    }
+
+}
+
+impl<L: Sublevel> Table<L> {
+
+
+    /// Returns the address of the next table, or None if none exists.
+    #[inline]
+    fn next_table_addr(&self, i: usize) -> Option<VAddr> {
+        let flags = self[i].flags();
+        if flags.contains(PRESENT) && !flags.contains(HUGE_PAGE) {
+            let table_addr = self as *const _ as usize;
+            Some(VAddr::from(table_addr << 10) | (i << 12))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the next table, or `None` if none exists
+    #[inline]
+    pub fn next_table<I>(&self, i: I) -> Option<&Table<L::Next>>
+    where L: IndexOf<I> {
+        self.next_table_addr(L::index_of(i))
+            .map(|table_addr| unsafe { &*(table_addr.as_ptr()) })
+    }
+
+    /// Mutably borrows the next table.
+    #[inline]
+    pub fn next_table_mut<I>(&self, i: I) -> Option<& mut Table<L::Next>>
+    where L: IndexOf<I>
+        , I: fmt::Debug {
+        trace!("{:?}, {:?}", self, i);
+        self.next_table_addr(L::index_of(i))
+            .map(|table_addr| unsafe { &mut *(table_addr.as_mut_ptr()) })
+    }
+
+
+    /// Returns the next table, creating it if it does not exist.
+    pub fn create_next<A>(&mut self, i: VirtualPage, alloc: &mut A)
+                         -> MapResult<&mut Table<L::Next>>
+    where A: FrameAllocator {
+        if self.next_table(i).is_none() {
+            if self[i].is_huge() {
+                return Err(MapErr::Other {
+                    message: "create next table"
+                  , page: i
+                  , cause: "huge pages not supported"
+                })
+            }
+            let frame = unsafe { alloc.allocate() }
+                .map_err(|err| MapErr::Alloc {
+                    message: "create next table"
+                  , page: i
+                  , cause: err
+              })?;
+
+            self[i].set(frame, PRESENT | WRITABLE);
+            self.next_table_mut(i).map(Table::zero)
+        } else {
+            self.next_table_mut(i)
+        }.ok_or(MapErr::TableNotFound {
+            message: "create next table"
+          , page: i
+          , what: unsafe { intrinsics::type_name::<L::Next>() }
+        })
+
+    }
+}
+
+
+
+bitflags! {
+    pub flags EntryFlags: u32 {
+        /// Present flag.
+        /// Must be 1 to map a 4-MByte page or reference a page table.
+        const PRESENT =         1 << 0,
+        /// Writable flag.
+        /// If 0, writes may not be allowed to the 4-MB region controlled
+        /// by this entry
+        const WRITABLE =        1 << 1
+      , const USER_ACCESSIBLE = 1 << 2
+      , const WRITE_THROUGH =   1 << 3
+      , const NO_CACHE =        1 << 4
+      , const ACCESSED =        1 << 5
+      , const DIRTY =           1 << 6
+      , /// Page size flag (only in page directory entries, when 4 MiB pages
+        /// are enabled)
+        const HUGE_PAGE =       1 << 7
+      , const GLOBAL =          1 << 8
+    }
+}
+
+impl EntryFlags {
+    /// Returns true if this page is huge
+    #[inline]
+    pub fn is_huge(&self) -> bool {
+        self.contains(HUGE_PAGE)
+    }
+
+    /// Returns true if this page is present
+    #[inline]
+    pub fn is_present(&self) -> bool {
+        self.contains(PRESENT)
+    }
+
+    #[inline]
+    pub fn set_present(&mut self, present: bool) -> &mut Self {
+        if present { self.insert(PRESENT) }
+        else { self.remove(PRESENT) }
+        self
+    }
+
+    #[inline]
+    pub fn set_writable(&mut self, writable: bool) -> &mut Self {
+        if writable { self.insert(WRITABLE) }
+        else { self.remove(WRITABLE) }
+        self
+    }
+}
+
+#[derive(Debug)]
+pub struct Entry(u32);
+
+impl Entry {
+
+    pub fn new(addr: PAddr) -> Self {
+        assert!(addr.is_page_aligned());
+        assert!( *addr <= u32::max_value() as u64
+               , "32-bit page tables can't address memory above 4 GiB");
+        Entry(*addr as u32)
+    }
+
+    /// Returns the frame at `offset` into the huge page this entry maps, if
+    /// it is a huge page.
+    #[inline]
+    pub fn do_huge(&self, offset: usize) -> Option<PhysicalPage> {
+        if self.is_huge() {
+            self.get_frame()
+                .map(|start_frame| {
+                    assert!( start_frame.number as usize % N_ENTRIES == 0
+                           , "Start frame must be aligned on a 4MB boundary!");
+                    start_frame + offset
+                })
+        } else {
+            None
+        }
+    }
+
+    /// Returns true if this is an unused entry
+    #[inline]
+    pub fn is_unused(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Sets this entry to be unused
+    #[inline(never)]
+    pub fn set_unused(&mut self) {
+        self.0 = 0;
+    }
+
+    /// Returns true if this page is huge
+    #[inline]
+    pub fn is_huge(&self) -> bool {
+        self.flags().is_huge()
+    }
+
+    /// Access the entry's bitflags.
+    #[inline]
+    pub fn flags(&self) -> EntryFlags {
+        EntryFlags::from_bits_truncate(self.0)
+    }
+
+    /// Returns the physical address pointed to by this page table entry
+    #[inline]
+    pub fn get_addr(&self) -> PAddr {
+        PAddr::from((self.0 & !ENTRY_FLAGS_MASK) as u64)
+    }
+
+    /// Returns the frame in memory pointed to by this page table entry.
+    pub fn get_frame(&self) -> Option<PhysicalPage> {
+        if self.flags().is_present() {
+            Some(PhysicalPage::containing(self.get_addr()))
+        } else {
+            None
+        }
+    }
+
+    pub fn set(&mut self, frame: PhysicalPage, flags: EntryFlags) {
+        let addr: u64 = frame.base_addr().into();
+        assert!( addr <= u32::max_value() as u64
+               , "32-bit page tables can't address memory above 4 GiB");
+        self.0 = addr as u32 | flags.bits();
+    }
+
+}
+
+impl<'a> convert::From<&'a elf::Section<u32>> for EntryFlags {
+    /// There's no no-execute bit without PAE, so every present section is
+    /// executable.
+    fn from(section: &'a elf::Section<u32>) -> Self {
+        *EntryFlags::empty()
+            .set_present(section.is_allocated())
+            .set_writable(section.is_writable())
+    }
+}
@@ -0,0 +1,81 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Translation lookaside buffer management.
+//!
+//! We only run on one CPU on `x86`, so shooting down a page just means
+//! flushing it from our own TLB.
+use cpu::control_regs::{cr3, cr4};
+use memory::VAddr;
+
+use super::{Page, VirtualPage};
+
+/// Invalidate the TLB completely, including global entries.
+///
+/// Reloading `%cr3` leaves global entries alone, so if global pages are
+/// enabled, this toggles `%cr4.PGE` instead, which flushes everything.
+///
+/// # Safety
+/// + Causes a general protection fault if not executed in kernel mode.
+pub unsafe fn flush_all() {
+    let flags = cr4::read();
+    if flags.contains(cr4::PGE) {
+        cr4::write(flags - cr4::PGE);
+        cr4::write(flags);
+    } else {
+        cr3::write(cr3::read());
+    }
+}
+
+/// Something which may be flushed from the TLB
+pub trait Flush {
+    /// Invalidate this object in the TLB using the `invlpg` instruction.
+    ///
+    /// # Safety
+    /// + Causes a general protection fault if not executed in kernel mode.
+    unsafe fn invlpg(self);
+}
+
+impl Flush for VAddr {
+    #[inline]
+    unsafe fn invlpg(self) {
+         asm!( "invlpg [$0]"
+             :
+             : "r" (*self)
+             : "memory"
+             : "intel", "volatile" );
+    }
+}
+
+impl Flush for VirtualPage {
+    #[inline]
+    unsafe fn invlpg(self) {
+        self.base().invlpg()
+    }
+}
+
+/// Record the page tables loaded on the current CPU.
+///
+/// This does nothing, since there are no other CPUs to shoot down.
+#[inline]
+pub fn set_active() { }
+
+/// Invalidate `page` in the TLB.
+///
+/// This should be called after a mapping is removed or its permissions are
+/// reduced.
+#[inline]
+pub fn shootdown(page: VirtualPage) {
+    unsafe { page.invlpg() }
+}
+
+/// Flush the entire TLB.
+#[inline]
+pub fn shootdown_all() {
+    unsafe { flush_all() }
+}
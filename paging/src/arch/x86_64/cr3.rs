@@ -1,5 +1,5 @@
 use super::table::{Table, PML4Level};
-use cpu::control_regs::cr3::{read, write};
+use cpu::control_regs::cr3::{current_pagetable_frame, write};
 pub use cpu::control_regs::cr3::*;
 
 /// Returns the current Page Meta-Level 4 table
@@ -11,7 +11,8 @@ pub use cpu::control_regs::cr3::*;
 #[cfg(target_arch = "x86_64")]
 #[inline]
 pub unsafe fn current_pml4() -> *mut Table<PML4Level> {
-    read().as_mut_ptr::<Table<PML4Level>>()
+    // the low bits of `%cr3` may hold flags or a PCID
+    current_pagetable_frame().base_addr().as_mut_ptr::<Table<PML4Level>>()
 }
 
 /// Sets the current Page Meta-Level 4 Table
@@ -18,14 +18,16 @@ use core::ptr::Unique;
 use alloc::FrameAllocator;
 use memory::{Addr, PAGE_SIZE, PAddr, Page, PhysicalPage, VAddr, VirtualPage};
 use params::InitParams;
-use ::{Mapper, MapResult, MapErr};
+use ::{CacheType, Mapper, MapResult, MapErr};
 
 use self::table::*;
 use self::temp::TempPage;
 
 pub mod table;
 pub mod tlb;
-pub mod temp;
+pub mod pcid;
+pub mod pat;
+#[path = "../x86_all/temp.rs"] pub mod temp;
 pub mod cr3;
 #[derive(Debug)]
 pub struct ActivePageTable { pml4: ActivePML4 }
@@ -85,6 +87,9 @@ impl ActivePageTable {
             unsafe {
                 // this is safe to execute; we are in kernel mode
                 flush_all();
+                // the closure may have changed mappings which are still
+                // cached under the table's PCID
+                pcid::invalidate_context(table.pcid);
             }
         }
         let _ = temp_page.unmap(self)?;
@@ -106,17 +111,66 @@ impl ActivePageTable {
             trace!("replacing {:?} with {:?}", self, new_table);
             // this is safe to execute; we are in kernel mode
             let old_pml4_frame = cr3::current_pagetable_frame();
+            let old_pcid = pcid::current();
             trace!("current pml4 frame is {:?}", old_pml4_frame);
 
-            cr3::set_pagetable_frame(new_table.pml4_frame);
+            pcid::switch(new_table.pml4_frame, new_table.pcid);
             trace!("set new pml4 frame to {:?}", new_table.pml4_frame);
+            tlb::set_active();
 
             InactivePageTable {
                 pml4_frame: old_pml4_frame
+              , pcid: old_pcid
             }
         }
     }
 
+    /// Unmap everything in the user half of the active table, deallocating
+    /// the frames mapped there and the page tables which mapped them.
+    ///
+    /// Nothing is flushed from the TLB, so this is only for tables which
+    /// are about to be [freed](struct.InactivePageTable.html#method.free).
+    ///
+    /// # Safety
+    /// + The frames mapped in the user half must belong to this table
+    ///   alone, and no other CPU may have it loaded.
+    pub unsafe fn free_user<A>(&mut self, alloc: &mut A)
+    where A: FrameAllocator {
+        let pml4 = self.pml4_mut();
+        for i in USER_PML4_START..USER_PML4_END {
+            if let Some(pdpt) = pml4.next_table_mut(i) {
+                for j in 0..N_ENTRIES {
+                    if let Some(pd) = pdpt.next_table_mut(j) {
+                        for k in 0..N_ENTRIES {
+                            if let Some(pt) = pd.next_table_mut(k) {
+                                free_entries(pt, alloc);
+                            }
+                        }
+                        free_entries(pd, alloc);
+                    }
+                }
+                free_entries(pdpt, alloc);
+            }
+            if let Some(frame) = pml4[i].get_frame() {
+                alloc.deallocate(frame);
+            }
+            pml4[i].set_unused();
+        }
+    }
+
+}
+
+/// Deallocate the frame each present entry in `table` points to, and clear
+/// the entries.
+unsafe fn free_entries<L, A>(table: &mut Table<L>, alloc: &mut A)
+where L: TableLevel
+    , A: FrameAllocator {
+    for i in 0..N_ENTRIES {
+        if let Some(frame) = table[i].get_frame() {
+            alloc.deallocate(frame);
+        }
+        table[i].set_unused();
+    }
 }
 
 /// Struct representing the currently active PML4 instance.
@@ -177,15 +231,17 @@ impl Mapper for ActivePML4 {
         // base virtual address of page being mapped
         // let addr = page.base();
 
+        // user pages have to be user-accessible at every level
+        let user = flags.contains(USER_ACCESSIBLE);
         // access or create all the lower-level page tables.
         let mut page_table // get the PML4
             = self.pml4_mut()
                   // get or create the PDPT table at the page's PML4 index
-                  .create_next(page, alloc)
+                  .create_next(page, user, alloc)
                   // get or create the PD table at the page's PDPT index
-                  .and_then(|pdpt| pdpt.create_next(page, alloc))
+                  .and_then(|pdpt| pdpt.create_next(page, user, alloc))
                   // get or create the page table at the  page's PD table index
-                  .and_then(|pd| pd.create_next(page, alloc))?;
+                  .and_then(|pd| pd.create_next(page, user, alloc))?;
         trace!(" . . Map: Got page table");
         // check if the page at that index is not currently in use, as we
         // cannot map a page which is currently in use.
@@ -202,6 +258,15 @@ impl Mapper for ActivePML4 {
         }
     }
 
+    fn map_with_cache<A>( &mut self, page: VirtualPage, frame: PhysicalPage
+                        , flags: EntryFlags, cache: CacheType, alloc: &mut A)
+                        -> MapResult<()>
+    where A: FrameAllocator {
+        let flags = (flags - (PAT | NO_CACHE | WRITE_THROUGH))
+                  | pat::flags_for(cache);
+        self.map(page, frame, flags, alloc)
+    }
+
     fn identity_map<A>(&mut self, frame: PhysicalPage, flags: EntryFlags
                       , alloc: &mut A)
                       -> MapResult<()>
@@ -227,13 +292,34 @@ impl Mapper for ActivePML4 {
         self.map(page, frame, flags, alloc)
     }
 
+    fn update_flags(&mut self, page: VirtualPage, flags: EntryFlags)
+                    -> MapResult<()> {
+        let page_table = self.pml4_mut()
+                             .next_table_mut(page)
+                             .and_then(|pdpt| pdpt.next_table_mut(page))
+                             .and_then(|pd| pd.next_table_mut(page))
+                             .ok_or(MapErr::Other {
+                                message: "update flags"
+                              , page: page
+                              , cause: "huge pages not supported"
+                            })?;
+        let entry = &mut page_table[page];
+        let frame = entry.get_frame()
+                         .ok_or(MapErr::Other {
+                           message: "update flags"
+                         , page: page
+                         , cause: "it was not mapped"
+                       })?;
+        entry.set(frame, flags | table::PRESENT);
+        tlb::shootdown(page);
+        Ok(())
+    }
+
     /// Unmap the given `VirtualPage`.
     ///
     /// All freed frames are returned to the given `FrameAllocator`.
     fn unmap<A>(&mut self, page: VirtualPage, alloc: &mut A) -> MapResult<()>
     where A: FrameAllocator {
-        use self::tlb::Flush;
-
         // get the page table entry corresponding to the page.
         let page_table = self.pml4_mut()
                              .next_table_mut(page)
@@ -258,9 +344,9 @@ impl Mapper for ActivePML4 {
         // mark the page table entry as unused
         entry.set_unused();
         trace!("set page table entry for {:?} as unused", page);
-        // deallocate the frame and flush the translation lookaside buffer
-        // this is safe because we're in kernel mode
-        unsafe { page.invlpg() };
+        // flush the page from every CPU's TLB before freeing the frame, so
+        // that no CPU can still write to it
+        tlb::shootdown(page);
         trace!("flushed TLB");
         unsafe {
             // this is hopefully safe because nobody else should be using an
@@ -299,9 +385,13 @@ impl ActivePML4 {
 }
 
 /// An inactive page table that the CPU is not currently using
+///
+/// If [PCIDs](pcid/index.html) are enabled, each new table is given its own
+/// PCID, so that switching to it doesn't flush the TLB.
 #[derive(Debug)]
 pub struct InactivePageTable {
     pml4_frame: PhysicalPage
+  , pcid: pcid::Pcid
 }
 
 impl InactivePageTable {
@@ -321,10 +411,102 @@ impl InactivePageTable {
         let _ = temp.unmap(active_table)?;
         trace!(" . . Unmapped temp page.");
 
-        Ok(InactivePageTable { pml4_frame: frame })
+        Ok(InactivePageTable { pml4_frame: frame, pcid: pcid::allocate() })
+    }
+
+    /// Create a page table for a user address space in `frame`.
+    ///
+    /// The user half of the new table is empty, and every other PML4 entry
+    /// is copied from the active table, so the kernel stays mapped while
+    /// the new table is loaded. Only the kernel's existing PML4 entries are
+    /// shared, so the kernel shouldn't create new ones once there are user
+    /// address spaces.
+    pub fn new_user( frame: PhysicalPage
+                   , active_table: &mut ActivePageTable
+                   , temp: &mut TempPage)
+                   -> MapResult<Self> {
+        {
+            let table = temp.map_to_table(frame.clone(), active_table)?;
+            table.zero();
+            let active = active_table.pml4();
+            for i in (0..USER_PML4_START).chain(USER_PML4_END..511) {
+                if let Some(kernel_frame) = active[i].get_frame() {
+                    table[i].set(kernel_frame, active[i].flags());
+                }
+            }
+            table[511].set(frame.clone(), PRESENT | WRITABLE);
+        }
+        let _ = temp.unmap(active_table)?;
+        trace!("Created user page table in {:?}", frame);
+
+        Ok(InactivePageTable { pml4_frame: frame, pcid: pcid::allocate() })
+    }
+
+    /// Returns a handle on the page tables loaded on the current CPU.
+    ///
+    /// # Safety
+    /// + The tables are still in use, so they must not be freed through
+    ///   the returned handle.
+    pub unsafe fn current() -> Self {
+        InactivePageTable { pml4_frame: cr3::current_pagetable_frame()
+                          , pcid: pcid::current()
+                          }
+    }
+
+    /// Returns the frame holding this table's PML4.
+    #[inline] pub fn frame(&self) -> PhysicalPage { self.pml4_frame }
+
+    /// Deallocate this table's PML4, once its user half has been freed with
+    /// [`ActivePageTable::free_user`][free_user].
+    ///
+    /// This also [frees](pcid/fn.free.html) the table's PCID, flushing any
+    /// TLB entries tagged with it on every CPU.
+    ///
+    /// # Safety
+    /// + This table must not be loaded on any CPU, and must never be
+    ///   used again.
+    ///
+    /// [free_user]: struct.ActivePageTable.html#method.free_user
+    pub unsafe fn free<A>(&self, alloc: &mut A)
+    where A: FrameAllocator {
+        pcid::free(self.pcid);
+        alloc.deallocate(self.pml4_frame);
+    }
+
+    /// Load this table on the current CPU, without giving up the table
+    /// that was loaded before.
+    ///
+    /// # Safety
+    /// + This table must map the kernel, including the running code and
+    ///   stack.
+    pub unsafe fn activate(&self) {
+        if cr3::current_pagetable_frame() == self.pml4_frame { return }
+        pcid::switch(self.pml4_frame, self.pcid);
+        tlb::set_active();
     }
 }
 
+/// The lowest address in the user half of an address space.
+///
+/// The first PML4 entry belongs to the kernel, which is identity mapped
+/// there, so user address spaces start at the second.
+pub const USER_BASE: usize = USER_PML4_START << 39;
+
+/// The first PML4 entry in the user half of an address space.
+const USER_PML4_START: usize = 1;
+/// The first PML4 entry above the user half of an address space.
+const USER_PML4_END: usize = 256;
+
+/// Returns true if `addr` is in the user half of an address space.
+///
+/// The last page of the user half is left out, so that user code can't
+/// put a `syscall` instruction at its very end. `sysret` would then return
+/// to the non-canonical address just past it, which faults in ring 0.
+#[inline]
+pub fn is_user_address(addr: usize) -> bool {
+    addr >= USER_BASE && addr < (USER_PML4_END << 39) - PAGE_SIZE as usize
+}
+
 pub fn test_paging<A>(alloc: &mut A) -> MapResult<()>
 where A: FrameAllocator {
     info!("testing paging");
@@ -368,7 +550,7 @@ where A: FrameAllocator {
 pub fn kernel_remap<A>(params: &InitParams, alloc: &mut A)
                        -> MapResult<ActivePageTable>
 where A: FrameAllocator {
-    use elf::Section;
+    use elf::section;
     // create a  temporary page for switching page tables
     // page number chosen fairly arbitrarily.
     const TEMP_PAGE_NUMBER: usize = 0xfacade;
@@ -405,7 +587,9 @@ where A: FrameAllocator {
         for section in sections { // remap ELF sections
             attempt!(
                 if section.address().is_page_aligned() {
-                    let flags = EntryFlags::from(section);
+                    // the kernel is mapped into every address space, so
+                    // its mappings needn't be flushed when switching tables
+                    let flags = EntryFlags::from(section) | GLOBAL;
 
                     let start_frame = PhysicalPage::from(section.address());
                     let end_frame = PhysicalPage::from(section.end_address());
@@ -424,6 +608,31 @@ where A: FrameAllocator {
                       "Identity mapping {}", section );
         }
 
+        // identity map the symbol and string tables. these aren't allocated
+        // sections, but we want them so that we can symbolize backtraces.
+        kinfoln!(dots: " . . ", "Identity mapping kernel symbol tables.");
+        let tables = params.elf_sections()
+                           .filter(|s| !s.is_allocated())
+                           .filter(|s| match s.get_type() {
+                                Ok(section::Type::SymbolTable) |
+                                Ok(section::Type::StringTable) => true
+                              , _ => false
+                            });
+        for table in tables {
+            // symbol tables need not be page aligned, and may share frames
+            // with each other.
+            let start_frame = PhysicalPage::containing(table.address());
+            let mut end_frame = PhysicalPage::containing(table.end_address());
+            if !table.end_address().is_page_aligned() { end_frame += 1; }
+
+            for frame in start_frame .. end_frame {
+                match pml4.identity_map(frame, NO_EXECUTE, alloc) {
+                    Ok(()) | Err(MapErr::AlreadyInUse { .. }) => {}
+                  , Err(why) => return Err(why)
+                }
+            }
+        }
+
         // remap VGA buffer
         let vga_buffer_frame = PhysicalPage::containing(PAddr::from(0xb8000));
         attempt!( pml4.identity_map(vga_buffer_frame, WRITABLE, alloc) =>
@@ -439,6 +648,20 @@ where A: FrameAllocator {
             let _ = pml4.identity_map(frame, PRESENT, alloc)?;
                 // .expect("couldn't identity map Multiboot {:?}", frame);
         }
+
+        // identity map the boot modules, so they can be read until they're
+        // loaded. they may share frames with each other, or with the
+        // multiboot info.
+        for module in params.modules() {
+            kinfoln!( dots: " . . ", "Identity mapping module {:?}"
+                    , module.command_line.unwrap_or(""));
+            for frame in module.frames() {
+                match pml4.identity_map(frame, NO_EXECUTE, alloc) {
+                    Ok(()) | Err(MapErr::AlreadyInUse { .. }) => {}
+                  , Err(why) => return Err(why)
+                }
+            }
+        }
         Ok(())
     })?;
 
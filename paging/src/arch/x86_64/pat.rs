@@ -0,0 +1,102 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Page Attribute Table.
+//!
+//! The memory type of a 4 KiB page is selected by an index into the PAT,
+//! formed from the `PAT`, `NO_CACHE` (`PCD`), and `WRITE_THROUGH` (`PWT`)
+//! bits of its page table entry:
+//!
+//! ```text
+//!  index | PAT PCD PWT | type
+//! -------+-------------+------
+//!    0   |  0   0   0  | WB
+//!    1   |  0   0   1  | WT
+//!    2   |  0   1   0  | UC-
+//!    3   |  0   1   1  | UC
+//!    4   |  1   0   0  | WC
+//!    5   |  1   0   1  | WP
+//!    6   |  1   1   0  | UC-
+//!    7   |  1   1   1  | UC
+//! ```
+//!
+//! The first four entries are the same as the PAT's power-on defaults, so
+//! write-back, write-through, and uncacheable mappings work the same whether
+//! or not we've programmed the PAT. Write-combining is only available once
+//! [`initialize`](fn.initialize.html) has been called.
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use cpu::control_regs::cr0;
+use cpu::cpuid;
+use cpu::msr::{self, MemoryType};
+
+use ::CacheType;
+use super::table::{EntryFlags, NO_CACHE, PAT, WRITE_THROUGH};
+use super::tlb;
+
+/// The PAT layout programmed by [`initialize`](fn.initialize.html).
+pub const LAYOUT: [MemoryType; 8]
+    = [ MemoryType::WriteBack, MemoryType::WriteThrough
+      , MemoryType::Uncached, MemoryType::Uncacheable
+      , MemoryType::WriteCombining, MemoryType::WriteProtected
+      , MemoryType::Uncached, MemoryType::Uncacheable ];
+
+/// Whether the PAT has been programmed with `LAYOUT`.
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+/// Program the PAT with [`LAYOUT`](constant.LAYOUT.html).
+///
+/// # Safety
+/// + This should be called once on each CPU, with interrupts disabled, and
+///   before anything is mapped with `CacheType::WriteCombining`.
+pub unsafe fn initialize() -> Result<(), &'static str> {
+    if !cpuid::has(cpuid::PAT) {
+        return Err("CPU does not support the PAT")
+    }
+    // the SDM's procedure for changing memory types: disable caching, flush
+    // the caches and TLBs, change the PAT, then flush everything again.
+    let flags = cr0::read();
+    cr0::write((flags | cr0::CD) - cr0::NW);
+    asm!("wbinvd" :::: "volatile");
+    tlb::flush_all();
+
+    msr::pat::write(&LAYOUT);
+
+    asm!("wbinvd" :::: "volatile");
+    tlb::flush_all();
+    cr0::write(flags);
+
+    INITIALIZED.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Returns true if the PAT has been programmed.
+#[inline]
+pub fn is_initialized() -> bool { INITIALIZED.load(Ordering::Relaxed) }
+
+/// Returns the page table entry flags that select `cache` for a 4 KiB page.
+///
+/// If the PAT hasn't been programmed, write-combining falls back to
+/// uncacheable.
+pub fn flags_for(cache: CacheType) -> EntryFlags {
+    match cache {
+        CacheType::WriteBack => EntryFlags::empty()
+      , CacheType::WriteThrough => WRITE_THROUGH
+      , CacheType::Uncacheable => NO_CACHE | WRITE_THROUGH
+      , CacheType::WriteCombining if is_initialized() => PAT
+      , CacheType::WriteCombining => NO_CACHE | WRITE_THROUGH
+    }
+}
+
+/// Returns the cache type selected by a 4 KiB page's entry `flags`.
+pub fn cache_type(flags: EntryFlags) -> MemoryType {
+    let index = if flags.contains(PAT) { 4 } else { 0 }
+              | if flags.contains(NO_CACHE) { 2 } else { 0 }
+              | if flags.contains(WRITE_THROUGH) { 1 } else { 0 };
+    if is_initialized() { LAYOUT[index] } else { msr::pat::DEFAULT[index] }
+}
@@ -0,0 +1,198 @@
+//! Process-context identifiers.
+//!
+//! When PCIDs are enabled, each TLB entry is tagged with the 12-bit PCID in
+//! `%cr3` when it was created, and only entries tagged with the current PCID
+//! are used. This lets us switch address spaces without flushing the TLB:
+//! every [`InactivePageTable`](../struct.InactivePageTable.html) is given
+//! its own PCID, and switching to it sets the "no flush" bit in `%cr3`.
+//!
+//! The catch is that `invlpg` and reloading `%cr3` only flush entries for
+//! the current PCID (and global entries), so invalidating anything else has
+//! to go through the functions in this module, which use `invpcid` if the
+//! CPU supports it.
+//!
+//! When a table is freed, its PCID is flushed on every CPU and handed out
+//! again. If the CPU doesn't support PCIDs, or every PCID is in use, page
+//! tables are untagged, and switching to them flushes the TLB as usual.
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use cpu::control_regs::{cr3, cr4};
+use cpu::cpuid;
+use memory::{PAddr, PhysicalPage, VAddr};
+use spin::Mutex;
+
+use super::tlb::{self, Flush};
+
+/// The largest PCID.
+pub const MAX_PCID: u16 = 0xfff;
+
+/// The PCID used for untagged page tables.
+pub const UNTAGGED: Pcid = Pcid(0);
+
+/// `%cr3` bit which preserves the new PCID's TLB entries when it's written.
+const NO_FLUSH: u64 = 1 << 63;
+
+/// `invpcid` type invalidating one address in one PCID.
+const INVPCID_ADDRESS: u64 = 0;
+/// `invpcid` type invalidating every non-global entry for one PCID.
+const INVPCID_CONTEXT: u64 = 1;
+/// `invpcid` type invalidating every entry, including global entries.
+const INVPCID_EVERYTHING: u64 = 2;
+
+/// Whether PCIDs have been enabled.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+/// Whether the CPU supports `invpcid`.
+static HAS_INVPCID: AtomicBool = AtomicBool::new(false);
+/// Number of words in the bitmap of PCIDs in use.
+const WORDS: usize = (MAX_PCID as usize + 1) / 64;
+
+/// Bitmap of the PCIDs which are in use.
+static IN_USE: Mutex<[u64; WORDS]> = Mutex::new([0; WORDS]);
+
+/// A process-context identifier.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Pcid(u16);
+
+impl Pcid {
+    /// Returns the PCID with the given number.
+    ///
+    /// Only the low 12 bits of `number` are used.
+    #[inline]
+    pub fn from_number(number: u16) -> Pcid { Pcid(number & MAX_PCID) }
+
+    /// Returns this PCID's number.
+    #[inline] pub fn number(&self) -> u16 { self.0 }
+}
+
+/// Enable PCIDs on the current CPU, if it supports them.
+///
+/// # Safety
+/// + This should be called once on each CPU, while the low 12 bits of
+///   `%cr3` are clear.
+pub unsafe fn initialize() -> Result<(), &'static str> {
+    if !cpuid::has(cpuid::PCID) {
+        return Err("CPU does not support PCIDs")
+    }
+    if *cr3::read() & MAX_PCID as u64 != 0 {
+        return Err("PCIDs can't be enabled while %cr3 has flags set")
+    }
+    cr4::write(cr4::read() | cr4::PCIDE);
+    HAS_INVPCID.store(cpuid::has(cpuid::INVPCID), Ordering::SeqCst);
+    ENABLED.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Returns true if PCIDs are enabled.
+#[inline]
+pub fn is_enabled() -> bool { ENABLED.load(Ordering::Relaxed) }
+
+/// Returns a free PCID for a new address space.
+///
+/// If PCIDs aren't enabled, or every PCID is in use, this returns
+/// [`UNTAGGED`](constant.UNTAGGED.html).
+pub fn allocate() -> Pcid {
+    if !is_enabled() { return UNTAGGED }
+    let mut in_use = IN_USE.lock();
+    // PCID 0 is `UNTAGGED`, so it's never handed out
+    in_use[0] |= 1;
+    match in_use.iter().position(|&word| word != !0) {
+        Some(i) => {
+            let bit = (!in_use[i]).trailing_zeros() as usize;
+            in_use[i] |= 1 << bit;
+            Pcid((i * 64 + bit) as u16)
+        }
+      , None => {
+            warn!("Out of PCIDs; address space switches will flush the TLB");
+            UNTAGGED
+        }
+    }
+}
+
+/// Give back `pcid`, so that it can be handed out again.
+///
+/// Its TLB entries are flushed on every CPU first, so that the next address
+/// space tagged with it won't see them.
+///
+/// # Safety
+/// + No page tables tagged with `pcid` may be loaded on any CPU, or ever be
+///   loaded again.
+pub unsafe fn free(pcid: Pcid) {
+    if pcid == UNTAGGED { return }
+    tlb::shootdown_pcid(pcid);
+    let number = pcid.0 as usize;
+    IN_USE.lock()[number / 64] &= !(1 << (number % 64));
+}
+
+/// Returns the current PCID.
+#[inline]
+pub fn current() -> Pcid {
+    if !is_enabled() { return UNTAGGED }
+    Pcid((unsafe { *cr3::read() } & MAX_PCID as u64) as u16)
+}
+
+/// Switch to the page tables in `frame`, tagged with `pcid`.
+///
+/// If `pcid` isn't [`UNTAGGED`](constant.UNTAGGED.html), any TLB entries
+/// left from the last time it was current are kept.
+///
+/// # Safety
+/// + `frame` must contain a valid PML4 which maps the kernel.
+/// + Any TLB entries tagged with `pcid` must still be valid.
+pub unsafe fn switch(frame: PhysicalPage, pcid: Pcid) {
+    let mut value = *frame.base_addr();
+    if is_enabled() && pcid != UNTAGGED {
+        value |= pcid.0 as u64 | NO_FLUSH;
+    }
+    cr3::write(PAddr::from(value));
+}
+
+/// Execute `invpcid` with the given type and descriptor.
+#[inline]
+unsafe fn invpcid(ty: u64, pcid: Pcid, addr: usize) {
+    let descriptor: [u64; 2] = [pcid.0 as u64, addr as u64];
+    asm!( "invpcid $0, [$1]"
+        :: "r"(ty), "r"(&descriptor)
+        :  "memory"
+        :  "intel", "volatile");
+}
+
+/// Invalidate every TLB entry, for every PCID, including global entries.
+///
+/// # Safety
+/// + Causes a general protection fault if not executed in kernel mode.
+pub unsafe fn flush_everything() {
+    if HAS_INVPCID.load(Ordering::Relaxed) {
+        invpcid(INVPCID_EVERYTHING, UNTAGGED, 0);
+    } else {
+        // any change to `PGE` flushes the entire TLB
+        let flags = cr4::read();
+        cr4::write(flags ^ cr4::PGE);
+        cr4::write(flags);
+    }
+}
+
+/// Invalidate the TLB entries for `addr` tagged with `pcid`.
+///
+/// # Safety
+/// + Causes a general protection fault if not executed in kernel mode.
+pub unsafe fn invalidate_page(pcid: Pcid, addr: VAddr) {
+    if HAS_INVPCID.load(Ordering::Relaxed) {
+        invpcid(INVPCID_ADDRESS, pcid, *addr);
+    } else if pcid == current() {
+        addr.invlpg();
+    } else {
+        flush_everything();
+    }
+}
+
+/// Invalidate every non-global TLB entry tagged with `pcid`.
+///
+/// # Safety
+/// + Causes a general protection fault if not executed in kernel mode.
+pub unsafe fn invalidate_context(pcid: Pcid) {
+    if HAS_INVPCID.load(Ordering::Relaxed) {
+        invpcid(INVPCID_CONTEXT, pcid, 0);
+    } else {
+        flush_everything();
+    }
+}
@@ -8,6 +8,7 @@
 //
 use alloc::FrameAllocator;
 use ::elf;
+use cpu::cpuid;
 use memory::{Addr, PAGE_SIZE, PAddr, Page, PhysicalPage, VAddr, VirtualPage};
 
 use core::marker::PhantomData;
@@ -228,10 +229,16 @@ impl<L: Sublevel> Table<L> {
 
 
     /// Returns the next table, creating it if it does not exist.
-    pub fn create_next<A>(&mut self, i: VirtualPage, alloc: &mut A)
+    ///
+    /// If `user` is true, the entry pointing at the next table is made
+    /// user-accessible, so that user pages can be mapped below it.
+    pub fn create_next<A>( &mut self, i: VirtualPage, user: bool
+                         , alloc: &mut A)
                          -> MapResult<&mut Table<L::Next>>
     where A: FrameAllocator {
         //println!("in create_next");
+        let table_flags = if user { PRESENT | WRITABLE | USER_ACCESSIBLE }
+                          else { PRESENT | WRITABLE };
         if self.next_table(i).is_none() {
             if self[i].is_huge() {
                 return Err(MapErr::Other {
@@ -249,10 +256,15 @@ impl<L: Sublevel> Table<L> {
               })?;
             //println!("done.");
 
-            self[i].set(frame, PRESENT | WRITABLE);
+            self[i].set(frame, table_flags);
             //println!("setted.");
             self.next_table_mut(i).map(Table::zero)
         } else {
+            if user && !self[i].flags().contains(USER_ACCESSIBLE) {
+                let frame = self[i].get_frame().unwrap();
+                let flags = self[i].flags() | USER_ACCESSIBLE;
+                self[i].set(frame, flags);
+            }
             self.next_table_mut(i)
         }.ok_or(MapErr::TableNotFound {
             message: "create next table"
@@ -280,6 +292,9 @@ bitflags! {
       , const ACCESSED =        1 << 5
       , const DIRTY =           1 << 6
       , const HUGE_PAGE =       1 << 7
+      , /// Page Attribute Table index bit (only in 4 KiB page entries, where
+        /// it shares a bit with `HUGE_PAGE`)
+        const PAT =             1 << 7
       , const GLOBAL =          1 << 8
       , const NO_EXECUTE =      1 << 63
     }
@@ -385,9 +400,12 @@ impl Entry {
         }
     }
 
-    pub fn set(&mut self, frame: PhysicalPage, flags: EntryFlags) {
+    pub fn set(&mut self, frame: PhysicalPage, mut flags: EntryFlags) {
         let addr: u64 = frame.base_addr().into();
         assert!(addr & !0x000fffff_fffff000 == 0);
+        // setting the no-execute bit is a reserved bit violation if the CPU
+        // doesn't support it, so quietly drop it.
+        if !cpuid::has(cpuid::NX) { flags.remove(NO_EXECUTE); }
         self.0 = addr | flags.bits();
     }
 
@@ -401,3 +419,12 @@ impl<'a> convert::From<&'a elf::Section<u64>> for EntryFlags {
             .set_executable(section.is_executable())
     }
 }
+
+impl convert::From<elf::program::Flags> for EntryFlags {
+    fn from(flags: elf::program::Flags) -> Self {
+        *EntryFlags::empty()
+            .set_present(true)
+            .set_writable(flags.contains(elf::program::WRITABLE))
+            .set_executable(flags.contains(elf::program::EXECUTABLE))
+    }
+}
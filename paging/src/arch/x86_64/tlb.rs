@@ -1,13 +1,69 @@
+//! Translation lookaside buffer management.
+//!
+//! Each CPU caches translations in its own TLB, and `invlpg` or reloading
+//! `%cr3` only affects the CPU that executes it. When a mapping is removed or
+//! its permissions are reduced, other CPUs that might have cached it must be
+//! told to flush it too. This is a _TLB shootdown_: the CPU that changed the
+//! mapping sends the others an IPI on
+//! [`SHOOTDOWN_VECTOR`](constant.SHOOTDOWN_VECTOR.html), and waits for them
+//! all to acknowledge that they've flushed it.
+//!
+//! Mappings outside the [user half](../constant.USER_BASE.html) of the
+//! address space belong to the kernel and are shared by every address space,
+//! so they're shot down on every online CPU. Other mappings are only shot
+//! down on CPUs with the same page tables loaded, unless
+//! [PCIDs](../pcid/index.html) are enabled: then any CPU may still have them
+//! cached under the address space's PCID, so they're shot down everywhere.
+use core::ptr;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use cpu::apic::{self, Destination};
+use cpu::control_regs::cr3;
+use cpu::smp;
 use memory::VAddr;
-use super::{Page, VirtualPage};
+use spin::Mutex;
+
+use super::{Page, VirtualPage, is_user_address};
+use super::pcid::{self, Pcid};
+
+/// Interrupt vector for TLB shootdown IPIs.
+pub const SHOOTDOWN_VECTOR: u8 = 0xfd;
+
+/// Shootdown target meaning "flush the whole TLB".
+///
+/// The zero page is never mapped, so it's never shot down on its own.
+const FLUSH_ALL: usize = 0;
+/// Shootdown target meaning "flush everything tagged with the target PCID".
+///
+/// This isn't page-aligned, so it's never a page being shot down.
+const FLUSH_PCID: usize = 1;
+
+/// Held by the CPU which is currently shooting down a page.
+static SHOOTDOWN: Mutex<()> = Mutex::new(());
+/// Address of the page being shot down, or `FLUSH_ALL`.
+static TARGET: AtomicUsize = AtomicUsize::new(FLUSH_ALL);
+/// The PCID of the address space the current shootdown is for.
+static TARGET_PCID: AtomicUsize = AtomicUsize::new(0);
+/// Bitmap of the CPUs which have yet to acknowledge the current shootdown.
+static PENDING: AtomicUsize = AtomicUsize::new(0);
 
-/// Invalidate the TLB completely by reloading the CR3 register.
+/// The PML4 frame address each CPU has loaded, indexed by CPU number.
+///
+/// Each CPU only writes its own entry.
+static mut ACTIVE: [u64; smp::MAX_CPUS] = [0; smp::MAX_CPUS];
+
+/// Invalidate the TLB completely, including global entries.
+///
+/// Reloading `%cr3` would leave global entries, and with PCIDs enabled,
+/// other PCIDs' entries, so this uses
+/// [`pcid::flush_everything`](../pcid/fn.flush_everything.html) whether or
+/// not PCIDs are enabled.
 ///
 /// # Safety
 /// + Causes a general protection fault if not executed in kernel mode.
+#[inline]
 pub unsafe fn flush_all() {
-    use cpu::control_regs::cr3;
-    cr3::write(cr3::read());
+    pcid::flush_everything();
 }
 
 /// Something which may be flushed from the TLB
@@ -54,3 +110,113 @@ impl Flush for VirtualPage {
         self.base().invlpg()
     }
 }
+
+/// Record the page tables loaded on the current CPU.
+///
+/// This must be called whenever a CPU loads `%cr3`, so that shootdowns for
+/// that address space reach it.
+pub fn set_active() {
+    unsafe {
+        let cpu = smp::current();
+        ptr::write_volatile(&mut ACTIVE[cpu], *cr3::read());
+    }
+}
+
+/// Returns the bitmap of other CPUs which might have `target` cached.
+fn targets(target: usize) -> usize {
+    let current = smp::current();
+    let others = smp::online_mask() & !(1 << current);
+    if target == FLUSH_ALL || target == FLUSH_PCID
+        || !is_user_address(target) || pcid::is_enabled() {
+        return others
+    }
+    let pml4 = unsafe { *cr3::read() };
+    (0..smp::MAX_CPUS)
+        .filter(|&cpu| others & (1 << cpu) != 0)
+        .filter(|&cpu| unsafe { ptr::read_volatile(&ACTIVE[cpu]) } == pml4)
+        .fold(0, |mask, cpu| mask | 1 << cpu)
+}
+
+/// Flush `target`, in the address space tagged with `tag`, on this CPU.
+unsafe fn invalidate(target: usize, tag: Pcid) {
+    match target {
+        FLUSH_ALL => flush_all()
+      , FLUSH_PCID => pcid::invalidate_context(tag)
+        // kernel mappings which aren't global may be cached under any PCID
+      , addr if !is_user_address(addr) && pcid::is_enabled() => flush_all()
+      , addr => pcid::invalidate_page(tag, VAddr::from(addr))
+    }
+}
+
+/// Flush the current shootdown's target on this CPU, if this CPU hasn't
+/// acknowledged it yet.
+fn acknowledge() {
+    let bit = 1 << smp::current();
+    if PENDING.load(Ordering::SeqCst) & bit == 0 { return }
+    let tag = Pcid::from_number(TARGET_PCID.load(Ordering::SeqCst) as u16);
+    unsafe { invalidate(TARGET.load(Ordering::SeqCst), tag) }
+    PENDING.fetch_and(!bit, Ordering::SeqCst);
+}
+
+/// Flush `target`, in the address space tagged with `tag`, on this CPU and
+/// every other CPU which might have it cached, and wait until they've all
+/// done so.
+fn shootdown_target(target: usize, tag: Pcid) {
+    unsafe { invalidate(target, tag) }
+    let cpus = targets(target);
+    if cpus == 0 { return }
+
+    // while we wait for the lock, another CPU may be waiting for us to
+    // acknowledge its shootdown. we might have interrupts disabled, so
+    // acknowledge it here instead of waiting for the IPI.
+    let _lock = loop {
+        if let Some(lock) = SHOOTDOWN.try_lock() { break lock }
+        acknowledge();
+        unsafe { asm!("pause" :::: "volatile"); }
+    };
+    TARGET.store(target, Ordering::SeqCst);
+    TARGET_PCID.store(tag.number() as usize, Ordering::SeqCst);
+    PENDING.store(cpus, Ordering::SeqCst);
+    for cpu in (0..smp::MAX_CPUS).filter(|&cpu| cpus & (1 << cpu) != 0) {
+        unsafe {
+            apic::send_ipi( Destination::Apic(smp::apic_id(cpu))
+                          , SHOOTDOWN_VECTOR);
+        }
+    }
+    while PENDING.load(Ordering::SeqCst) != 0 {
+        unsafe { asm!("pause" :::: "volatile"); }
+    }
+}
+
+/// Invalidate `page` on every CPU which might have it cached.
+///
+/// This should be called after a mapping is removed or its permissions are
+/// reduced.
+#[inline]
+pub fn shootdown(page: VirtualPage) {
+    shootdown_target(*page.base(), pcid::current())
+}
+
+/// Flush the entire TLB on every CPU which might share the current address
+/// space.
+#[inline]
+pub fn shootdown_all() {
+    shootdown_target(FLUSH_ALL, pcid::current())
+}
+
+/// Flush every entry tagged with `pcid` on every CPU.
+///
+/// This should be called before a PCID is reused.
+#[inline]
+pub fn shootdown_pcid(pcid: Pcid) {
+    shootdown_target(FLUSH_PCID, pcid)
+}
+
+/// Handle a TLB shootdown IPI.
+///
+/// This should be called by the handler for
+/// [`SHOOTDOWN_VECTOR`](constant.SHOOTDOWN_VECTOR.html).
+pub fn handle_shootdown() {
+    acknowledge();
+    apic::end_of_interrupt();
+}
@@ -72,7 +72,9 @@ impl TempPage {
         trace!("unmapping temp page {:?}", self);
         // assert!( table.is_mapped(self)
         //         , "Cannot unmap {:?}, as it is not mapped", self);
-        table.unmap(self.page, &mut self.frames)
+        // the frame the temp page was mapped to still belongs to whoever
+        // asked us to map it, so it mustn't be freed
+        table.unmap(self.page, &mut KeepFrame)
              .map(|_| { trace!("temp page unmapped") })
 
     }
@@ -124,3 +126,34 @@ impl FrameAllocator for FrameCache {
     }
 
 }
+
+/// A frame allocator which has no frames, and which leaves frames that are
+/// deallocated into it alone.
+///
+/// Unmapping a page deallocates the frame it was mapped to, so this is used
+/// to unmap pages whose frames are owned by someone else.
+#[derive(Debug)]
+struct KeepFrame;
+
+impl FrameAllocator for KeepFrame {
+
+    unsafe fn allocate(&mut self) -> AllocResult<PhysicalPage> {
+        Err(AllocErr::Exhausted {
+            request: Layout::from_size_align( PAGE_SIZE as usize
+                                            , PAGE_SIZE as usize)
+        })
+    }
+
+    unsafe fn deallocate(&mut self, _frame: PhysicalPage) { }
+
+    unsafe fn allocate_range(&mut self, num: usize)
+                            -> AllocResult<FrameRange> {
+        Err(AllocErr::Exhausted {
+            request: Layout::from_size_align( num * PAGE_SIZE as usize
+                                            , PAGE_SIZE as usize)
+        })
+    }
+
+    unsafe fn deallocate_range(&mut self, _range: FrameRange) { }
+
+}
@@ -54,6 +54,24 @@ impl<P> fmt::Debug for MapErr<P> where P: Page + fmt::Debug {
     }
 }
 
+/// Memory types that a mapping may use.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum CacheType { /// Write-back: normal, fully cached memory
+                     WriteBack
+                   , /// Write-combining: uncached, but writes may be
+                     /// buffered and combined. Good for framebuffers.
+                     WriteCombining
+                   , /// Write-through: reads are cached, writes go straight
+                     /// to memory
+                     WriteThrough
+                   , /// Uncacheable: for memory-mapped I/O registers
+                     Uncacheable
+                   }
+
+impl Default for CacheType {
+    #[inline] fn default() -> Self { CacheType::WriteBack }
+}
+
 pub trait Mapper {
     type Flags;
 
@@ -80,6 +98,25 @@ pub trait Mapper {
              -> MapResult<()>
     where A: FrameAllocator;
 
+    /// Modifies the page tables so that `page` maps to `frame`, using the
+    /// memory type `cache`.
+    ///
+    /// Any cache control bits in `flags` are replaced by those for `cache`.
+    fn map_with_cache<A>( &mut self, page: VirtualPage, frame: PhysicalPage
+                        , flags: Self::Flags, cache: CacheType, alloc: &mut A )
+                        -> MapResult<()>
+    where A: FrameAllocator;
+
+    /// Identity map a given `frame` using the memory type `cache`.
+    fn identity_map_with_cache<A>( &mut self, frame: PhysicalPage
+                                 , flags: Self::Flags, cache: CacheType
+                                 , alloc: &mut A )
+                                 -> MapResult<()>
+    where A: FrameAllocator {
+        let page = Page::containing(VAddr::from(*frame.base_addr() as usize));
+        self.map_with_cache(page, frame, flags, cache, alloc)
+    }
+
     /// Identity map a given `frame`.
     ///
     /// # Arguments
@@ -106,6 +143,12 @@ pub trait Mapper {
                     -> MapResult<()>
     where A: FrameAllocator;
 
+    /// Replace the flags of the existing mapping for `page` with `flags`.
+    ///
+    /// Stale translations of `page` are flushed from every CPU's TLB.
+    fn update_flags(&mut self, page: VirtualPage, flags: Self::Flags)
+                    -> MapResult<()>;
+
     /// Unmap the given `VirtualPage`.
     ///
     /// All freed frames are returned to the given `FrameAllocator`.
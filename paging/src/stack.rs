@@ -7,6 +7,10 @@
 //  directory of this repository for more information.
 //
 //! Stack allocator
+//!
+//! Stacks are allocated from a range of virtual pages. The page below each
+//! stack is left unmapped as a guard page, so that a stack overflow causes a
+//! page fault rather than silently corrupting whatever is below it.
 use alloc::{AllocResult, AllocErr, FrameAllocator, Layout};
 use memory::{PageRange, VAddr};
 use ::Mapper;
@@ -15,9 +19,12 @@ use arch::ActivePageTable;
 
 use core::ops::Range;
 
+/// A stack's address range, from its lowest address to its top.
 pub type Stack = Range<VAddr>;
 
 pub trait StackAllocator {
+    /// Allocate a stack of `num_pages` pages, mapping it with frames from
+    /// `frames`.
     fn allocate<A>( &mut self
                       , page_table: &mut ActivePageTable
                       , frames: &mut A
@@ -62,12 +69,12 @@ impl StackAllocator for PageRange {
             // successfully allocated! write back the working page range
             *self = working_pages;
 
-            for page in start_page .. end_page {
-                page_table.map_to_any(page, WRITABLE, frames);
+            for page in start_page .. end_page + 1 {
+                page_table.map_to_any(page, WRITABLE, frames)
+                          .map_err(|_| exhausted())?;
             }
 
-            let stack_top = end_page.end_address();
-            Ok(stack_top .. start_page.base())
+            Ok(start_page.base() .. end_page.end_address())
         }
     }
 }
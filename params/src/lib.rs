@@ -22,7 +22,7 @@ extern crate memory;
 extern crate elf;
 extern crate arrayvec;
 
-use memory::{ PAddr, Page, PhysicalPage, FrameRange };
+use memory::{ PAddr, Page, PhysicalPage, FrameRange, PAGE_SIZE };
 use core::default::Default;
 use core::iter::Step;
 use core::slice::Iter as SliceIter;
@@ -31,6 +31,7 @@ use arrayvec::{ArrayVec};
 pub mod mem;
 
 const MAX_MEM_AREAS: usize = 32;
+const MAX_MODULES: usize = 8;
 
 /// If we are on x86_64 or armv7 this uses the 64-bit ELF word
 #[cfg(target_pointer_width = "64")]
@@ -40,6 +41,32 @@ pub type ElfSections = elf::section::Sections<'static, u64>;
 #[cfg(target_pointer_width = "32")]
 pub type ElfSections = elf::section::Sections<'static, u32>;
 
+/// A module the bootloader loaded into memory along with the kernel.
+#[derive(Copy, Clone, Debug)]
+pub struct Module {
+    /// The address of the module's first byte
+    pub start: PAddr
+  , /// The address just past the module's last byte
+    pub end: PAddr
+  , /// The string the bootloader passed with the module, if there was one.
+    ///
+    /// This is usually a command line.
+    pub command_line: Option<&'static str>
+}
+
+impl Module {
+    /// Returns the length of the module, in bytes.
+    #[inline]
+    pub fn len(&self) -> usize { (*self.end - *self.start) as usize }
+
+    /// Returns the range of frames containing the module.
+    #[inline]
+    pub fn frames(&self) -> FrameRange {
+        PhysicalPage::containing(self.start) ..
+        PhysicalPage::containing(self.end + (PAGE_SIZE - 1))
+    }
+}
+
 /// Parameters used during the init process
 #[derive(Clone, Debug)]
 pub struct InitParams {
@@ -65,6 +92,13 @@ pub struct InitParams {
     /// N.B. that this is currently never `None`, as we only support multiboot.
     /// However, this may change at a later date.
     pub multiboot_end: Option<PAddr>
+  , /// The physical address of the ACPI Root System Description Pointer,
+    /// if the bootloader gave us one.
+    pub acpi_rsdp: Option<PAddr>
+  , /// The kernel command line the bootloader passed us, if there was one.
+    pub command_line: Option<&'static str>
+  , /// The modules the bootloader loaded along with the kernel.
+    pub modules: ArrayVec<[Module; MAX_MODULES]>
   , /// Map of memory areas
     pub mem_map: ArrayVec<[mem::Area; MAX_MEM_AREAS]>
     , /// Map of elf sections
@@ -88,6 +122,9 @@ impl Default for InitParams {
                    , stack_top: PAddr::from(0x0)
                    , multiboot_start: None
                    , multiboot_end: None
+                   , acpi_rsdp: None
+                   , command_line: None
+                   , modules: ArrayVec::<[Module; MAX_MODULES]>::new()
                    , mem_map: ArrayVec::<[mem::Area; MAX_MEM_AREAS]>::new()
                    , elf_sections: None
                    }
@@ -166,6 +203,33 @@ impl InitParams {
         unimplemented!()
     }
 
+    /// Returns the value of the option `key` on the kernel command line.
+    ///
+    /// Options are written as `key=value`, separated by whitespace.
+    ///
+    /// # Returns
+    /// + `Some(value)` if the command line set the option
+    /// + `None` if there's no command line, or it didn't set the option.
+    pub fn boot_option(&self, key: &str) -> Option<&'static str> {
+        self.command_line
+            .and_then(|line| line.split_whitespace()
+                                 .filter_map(|opt| {
+                                     let mut parts = opt.splitn(2, '=');
+                                     match (parts.next(), parts.next()) {
+                                         (Some(k), Some(v)) if k == key =>
+                                             Some(v)
+                                       , _ => None
+                                     }
+                                 })
+                                 .last())
+    }
+
+    /// Returns an iterator over the modules the bootloader loaded.
+    #[inline]
+    pub fn modules(&self) -> SliceIter<Module> {
+        self.modules.iter()
+    }
+
     /// returns an iterator over the memory map
     #[inline]
     pub fn mem_map(&self) -> mem::Map {
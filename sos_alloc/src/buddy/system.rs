@@ -1,15 +1,19 @@
 //! This module integrates the buddy heap allocator into the Rust runtime.
-use spin::Mutex;
 use core::ptr;
 
+use util::irq_lock::IrqSpinlock;
+
 use ::{Allocator, Layout};
 use super::{Heap, FreeList};
 
 /// The number of free lists for the kernel heap
 pub const NUM_FREE_LISTS: usize = 19;
 
-static ALLOC: Mutex<Option<Heap<'static>>>
-    = Mutex::new(None);
+/// The kernel heap.
+///
+/// Interrupt handlers may allocate, so this is an `IrqSpinlock`.
+static ALLOC: IrqSpinlock<Option<Heap<'static>>>
+    = IrqSpinlock::new(None);
 
 static mut KERNEL_FREE_LISTS: [FreeList; NUM_FREE_LISTS]
     // TODO: I really wish there was a less awful way to do this...
@@ -12,11 +12,18 @@
 //! it doesn't support deallocating frames.
 use super::{Frame, FrameRange, Allocator};
 use ::{AllocResult, AllocErr, Layout};
-use params::{InitParams, mem};
+use params::{InitParams, Module, mem};
 use memory::{Page, PAGE_SIZE, PAddr};
 
 use core::iter::Step;
 use core::convert::From;
+
+/// Frames below this address are never handed out.
+///
+/// The real-mode interrupt vector table and BIOS data area are down here,
+/// and the kernel copies its startup trampoline for the other CPUs here.
+pub const RESERVED_BELOW: u64 = 0x12000;
+
 /// A simple area allocator.
 ///
 /// This is based on the memory area allocation scheme described
@@ -31,6 +38,7 @@ pub struct MemMapAllocator<'a> { next_free: Frame
                                , areas: mem::Map<'a>
                                , kernel_frames: FrameRange
                                , mb_frames: FrameRange
+                               , modules: &'a [Module]
                                }
 impl<'a> MemMapAllocator<'a> {
     fn next_area(&mut self) {
@@ -41,24 +49,36 @@ impl<'a> MemMapAllocator<'a> {
                       Frame::containing(a.end_addr) >= self.next_free)
                   .min_by_key(|a| a.start_addr)
                   .map(|area| {
+                      // skip any hole before the area, but never go back
+                      // to frames we've already handed out
                       let start = Frame::containing(area.start_addr);
-                      if self.next_free > start { self.next_free = start };
+                      if self.next_free < start { self.next_free = start };
                       area
                   })
     }
 
+    /// Returns the frame just past the boot module containing `frame`, if
+    /// a boot module contains it.
+    fn module_end(&self, frame: Frame) -> Option<Frame> {
+        self.modules.iter()
+            .map(Module::frames)
+            .find(|frames| frame >= frames.start && frame < frames.end)
+            .map(|frames| frames.end)
+    }
+
 }
 
 impl<'a> From<&'a InitParams> for MemMapAllocator<'a> {
     fn from(params: &'a InitParams) -> Self {
         let mut new_allocator = MemMapAllocator {
-              next_free: Frame::containing(PAddr::new(0x12000))
+              next_free: Frame::containing(PAddr::new(RESERVED_BELOW))
             , current_area: None
             , areas: params.mem_map()
             , kernel_frames: params.kernel_frames()
             // TODO: handle non-multiboot case
             , mb_frames: Frame::containing(params.multiboot_start()) ..
                          Frame::containing(params.multiboot_end()).add_one()
+            , modules: &params.modules[..]
             };
         trace!("creating mem map allocator");
         trace!("kernel frames: {:?}", new_allocator.kernel_frames);
@@ -98,6 +118,11 @@ impl<'a> Allocator for MemMapAllocator<'a> {
                     self.next_free = self.mb_frames.end.add_one();
                     // println!("...and returning None");
                 }
+              , // this frame is part of a boot module.
+                f if self.module_end(f).is_some() => {
+                    // skip ahead to the end of the module.
+                    self.next_free = self.module_end(f).unwrap();
+                }
               , // this frame is free.
                 frame => {
                     // advance the next free frame and return this frame.
@@ -0,0 +1,102 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! # SOS Handle Tables
+//!
+//! A process refers to the things it may use, such as the processes it has
+//! spawned, by [`Handle`](struct.Handle.html)s, which are indices into its
+//! [`Handles`](struct.Handles.html) table. The table doesn't care what it
+//! holds, so that it can be tested on the host.
+#![crate_name = "sos_handles"]
+#![crate_type = "lib"]
+#![feature(alloc)]
+#![no_std]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+
+/// A process's name for something in its handle table.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Handle(usize);
+
+impl Handle {
+    /// Returns the handle with the given number.
+    #[inline] pub fn from_number(number: usize) -> Self { Handle(number) }
+
+    /// Returns this handle's number.
+    #[inline] pub fn number(&self) -> usize { self.0 }
+}
+
+/// A table of handles to `T`s.
+///
+/// Handles are numbered from zero, and the lowest free number is handed
+/// out first.
+#[derive(Debug)]
+pub struct Handles<T> { slots: Vec<Option<T>> }
+
+impl<T> Handles<T> {
+    /// Returns an empty table.
+    #[inline] pub fn new() -> Self { Handles { slots: Vec::new() } }
+
+    /// Add `object` to the table, and return its handle.
+    pub fn insert(&mut self, object: T) -> Handle {
+        match self.slots.iter().position(Option::is_none) {
+            Some(i) => { self.slots[i] = Some(object); Handle(i) }
+          , None => { self.slots.push(Some(object))
+                    ; Handle(self.slots.len() - 1) }
+        }
+    }
+
+    /// Returns the object `handle` refers to, if it's in the table.
+    #[inline]
+    pub fn get(&self, handle: Handle) -> Option<&T> {
+        self.slots.get(handle.0).and_then(Option::as_ref)
+    }
+
+    /// Take the object `handle` refers to out of the table.
+    pub fn remove(&mut self, handle: Handle) -> Option<T> {
+        let object = self.slots.get_mut(handle.0).and_then(Option::take);
+        while let Some(&None) = self.slots.last() { self.slots.pop(); }
+        object
+    }
+
+    /// Returns the number of handles in the table.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.slots.iter().filter(|slot| slot.is_some()).count()
+    }
+
+    /// Returns true if the table is empty.
+    #[inline] pub fn is_empty(&self) -> bool { self.slots.is_empty() }
+}
+
+impl<T> Default for Handles<T> {
+    #[inline] fn default() -> Self { Handles::new() }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reuse_handles() {
+        let mut handles = Handles::new();
+        let a = handles.insert('a');
+        let b = handles.insert('b');
+        assert_eq!((a.number(), b.number()), (0, 1));
+        assert_eq!(handles.remove(a), Some('a'));
+        assert!(handles.get(a).is_none());
+        assert_eq!(handles.get(b), Some(&'b'));
+        assert_eq!(handles.insert('c'), a);
+        assert_eq!(handles.len(), 2);
+        assert_eq!(handles.remove(b), Some('b'));
+        assert_eq!(handles.remove(a), Some('c'));
+        assert!(handles.is_empty());
+    }
+}
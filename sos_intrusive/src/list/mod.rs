@@ -397,6 +397,7 @@ where T: OwnedRef<N>
                                 *c.next_mut() = RawLink::some(n);
                             }
                         }
+                        self.list.length -= 1;
                         T::from_raw(p)
                     })
             }
@@ -198,7 +198,29 @@ mod boxed {
         assert_eq!(list.pop_back(), None);
     }
 
+    #[test]
+    fn test_find_and_remove() {
+        let mut list = TestList::new();
+        for i in 0..4 {
+            list.push_back(Box::new(NumberedNode::new(i)));
+        }
+
+        let removed = list.cursor_mut().find_and_remove(|n| n.number == 2);
+        assert_eq!(removed.unwrap().number, 2);
+        assert_eq!(list.len(), 3);
+
+        let removed = list.cursor_mut().find_and_remove(|n| n.number == 3);
+        assert_eq!(removed.unwrap().number, 3);
+        assert_eq!(list.len(), 2);
+        assert_eq!(list.back().unwrap().number, 1);
 
+        assert!(list.cursor_mut().find_and_remove(|n| n.number == 3).is_none());
+        assert_eq!(list.len(), 2);
+
+        assert_eq!(list.pop_front().unwrap().number, 0);
+        assert_eq!(list.pop_front().unwrap().number, 1);
+        assert!(list.is_empty());
+    }
 }
 
 // mod mut_ptr {
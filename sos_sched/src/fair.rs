@@ -0,0 +1,152 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Weighted fair scheduling.
+//!
+//! This works like Linux's Completely Fair Scheduler. Each thread has a
+//! _virtual run time_: the time it has spent running, scaled by the inverse
+//! of its [weight](../constant.WEIGHTS.html), so that heavier threads' virtual
+//! run time grows more slowly. The thread with the least virtual run time
+//! always runs next, so over time each thread gets a share of the CPU
+//! proportional to its weight.
+//!
+//! Every waiting thread should get to run once per _latency_ period, so the
+//! running thread's slice is its share of the latency, but never less than
+//! the _minimum granularity_, so that threads don't switch too often.
+//!
+//! The policy keeps track of the least virtual run time of any thread on the
+//! CPU. Threads which have been blocked for a while are given at least that,
+//! less half a latency period, when they're woken, so that they can't make
+//! up all of the time they spent blocked by hogging the CPU.
+use core::cmp;
+
+use sos_intrusive::List;
+use sos_intrusive::list::OwnedRef;
+
+use super::{DEFAULT_WEIGHT, Link, Policy, Schedulable};
+
+/// A weighted fair scheduling policy.
+pub struct Fair<T: Schedulable> {
+    queue: List<Link<T>, T>
+  , /// how often every waiting thread should run, in nanoseconds
+    latency: u64
+  , /// the shortest time a thread runs before it's preempted, in nanoseconds
+    min_granularity: u64
+  , /// the least virtual run time of any thread on this CPU
+    min_vruntime: u64
+  , /// the total weight of the waiting threads
+    queued_weight: u64
+}
+
+impl<T: Schedulable> Fair<T> {
+    /// Returns a new fair policy, which tries to run every waiting thread
+    /// once every `latency` nanoseconds, but lets each thread run for at
+    /// least `min_granularity` nanoseconds.
+    pub fn new(latency: u64, min_granularity: u64) -> Self {
+        Fair { queue: List::new()
+             , latency: latency
+             , min_granularity: min_granularity
+             , min_vruntime: 0
+             , queued_weight: 0
+             }
+    }
+
+    /// Returns the least virtual run time of any thread on this CPU.
+    #[inline] pub fn min_vruntime(&self) -> u64 { self.min_vruntime }
+
+    /// Returns the least virtual run time of any waiting thread.
+    fn min_queued(&mut self) -> Option<u64> {
+        let len = self.queue.len();
+        let mut cursor = self.queue.cursor_mut();
+        (0..len).filter_map(|_| cursor.next().map(|t| t.entity().vruntime))
+                .min()
+    }
+
+    /// Advance the minimum virtual run time, now that the running thread's
+    /// virtual run time is `current`.
+    fn update_min(&mut self, current: u64) {
+        let least = self.min_queued()
+                        .map_or(current, |queued| cmp::min(queued, current));
+        self.min_vruntime = cmp::max(self.min_vruntime, least);
+    }
+}
+
+impl<T: Schedulable> Policy<T> for Fair<T> {
+    #[inline] fn name(&self) -> &'static str { "fair" }
+
+    #[inline] fn len(&self) -> usize { self.queue.len() }
+
+    fn enqueue(&mut self, mut thread: Link<T>, _now: u64) {
+        {
+            let entity = thread.get_mut().entity_mut();
+            let floor = self.min_vruntime.saturating_sub(self.latency / 2);
+            entity.vruntime = cmp::max(entity.vruntime, floor);
+            self.queued_weight += entity.weight();
+        }
+        self.queue.push_back(thread);
+    }
+
+    fn remove(&mut self, thread: &T) -> Option<Link<T>> {
+        let weight = thread.entity().weight();
+        let thread = thread as *const T;
+        let removed = self.queue.cursor_mut()
+                          .find_and_remove(|t| t as *const T == thread);
+        if removed.is_some() { self.queued_weight -= weight; }
+        removed
+    }
+
+    fn pick_next(&mut self, now: u64) -> Option<Link<T>> {
+        let least = match self.min_queued() {
+            Some(least) => least
+          , None => return None
+        };
+        let mut thread = self.queue.cursor_mut()
+                             .find_and_remove(|t| t.entity().vruntime == least)
+                             .unwrap();
+        self.min_vruntime = cmp::max(self.min_vruntime, least);
+        let entity = thread.get_mut().entity_mut();
+        self.queued_weight -= entity.weight();
+        entity.since = now;
+        entity.updated = now;
+        Some(thread)
+    }
+
+    fn put_prev(&mut self, thread: &mut T, now: u64) {
+        account(thread, now);
+        self.update_min(thread.entity().vruntime);
+    }
+
+    fn tick(&mut self, current: &mut T, now: u64) -> bool {
+        account(current, now);
+        let vruntime = current.entity().vruntime;
+        self.update_min(vruntime);
+        let least = match self.min_queued() {
+            Some(least) => least
+          , None => return false
+        };
+
+        let weight = current.entity().weight();
+        let slice = cmp::max( self.latency * weight
+                                / (self.queued_weight + weight)
+                            , self.min_granularity);
+        let ran = now.saturating_sub(current.entity().since);
+        // a waiting thread which is more than a slice behind runs now, even
+        // if the slice isn't up
+        ran >= slice || (ran >= self.min_granularity
+                         && least + slice < vruntime)
+    }
+}
+
+/// Add the time `thread` has run since it was last accounted for to its
+/// virtual run time.
+fn account<T: Schedulable>(thread: &mut T, now: u64) {
+    let entity = thread.entity_mut();
+    let delta = now.saturating_sub(entity.updated);
+    entity.vruntime += delta * DEFAULT_WEIGHT / entity.weight();
+    entity.updated = now;
+}
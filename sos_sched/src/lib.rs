@@ -0,0 +1,229 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! # SOS Scheduling Policies
+//!
+//! A scheduling _policy_ decides which of a CPU's runnable threads should
+//! run next, and when the running thread should be preempted. The kernel's
+//! scheduler does the actual switching; a policy only orders the threads
+//! which are waiting to run.
+//!
+//! Policies never read a clock themselves. Every method which cares about
+//! time is passed the current time, in nanoseconds, so that policies can be
+//! tested on the host against a simulated clock.
+//!
+//! This crate provides three policies:
+//!
+//! + [`RoundRobin`](round_robin/struct.RoundRobin.html): every thread gets
+//!   the same time slice, in turn.
+//! + [`FixedPriority`](priority/struct.FixedPriority.html): threads with a
+//!   higher priority always run first, and threads which have waited too
+//!   long are _aged_ up a priority level, so that they can't starve.
+//! + [`Fair`](fair/struct.Fair.html): threads get a share of the CPU
+//!   proportional to their weight, by always running the thread which has
+//!   had the least weighted ("virtual") run time.
+#![crate_name = "sos_sched"]
+#![crate_type = "lib"]
+#![feature(ptr_internals)]
+#![feature(unique)]
+#![no_std]
+
+extern crate sos_intrusive;
+
+#[cfg(test)] extern crate std;
+
+use core::fmt;
+use core::ptr::Unique;
+
+use sos_intrusive::list::Node;
+
+pub mod round_robin;
+pub mod priority;
+pub mod fair;
+
+pub use round_robin::RoundRobin;
+pub use priority::FixedPriority;
+pub use fair::Fair;
+
+#[cfg(test)] mod test;
+
+/// The number of priority levels.
+pub const PRIORITY_LEVELS: usize = 8;
+
+/// The priority new threads get. Priority 0 is the highest.
+pub const DEFAULT_PRIORITY: usize = 4;
+
+/// The weight of a thread with the default priority.
+pub const DEFAULT_WEIGHT: u64 = 1024;
+
+/// The weight given to each priority level by the [fair](fair/index.html)
+/// policy.
+///
+/// Each level gets about 1.5 times the CPU time of the level below it,
+/// like every second Linux nice value.
+pub const WEIGHTS: [u64; PRIORITY_LEVELS]
+    = [6100, 3906, 2501, 1586, 1024, 655, 423, 272];
+
+/// A pointer to a thread owned by a policy's run queue.
+pub type Link<T> = Unique<T>;
+
+/// The scheduling state of a thread, which each policy uses for its own
+/// bookkeeping.
+#[derive(Clone, Debug)]
+pub struct Entity { /// the priority the thread was given
+                    priority: usize
+                  , /// the priority the thread is queued at, after aging
+                    effective: usize
+                  , /// weighted run time, in nanoseconds
+                    vruntime: u64
+                  , /// when the thread was queued, or started running
+                    since: u64
+                  , /// when the thread's run time was last accounted for
+                    updated: u64
+                  }
+
+impl Entity {
+    /// Returns the scheduling state for a new thread with the given
+    /// priority.
+    ///
+    /// # Panics
+    /// + If `priority` isn't less than `PRIORITY_LEVELS`.
+    pub fn new(priority: usize) -> Self {
+        assert!( priority < PRIORITY_LEVELS
+               , "Priority {} is out of range", priority);
+        Entity { priority: priority
+               , effective: priority
+               , vruntime: 0
+               , since: 0
+               , updated: 0
+               }
+    }
+
+    /// Returns the thread's priority.
+    #[inline] pub fn priority(&self) -> usize { self.priority }
+
+    /// Change the thread's priority.
+    ///
+    /// This must not be called while the thread is waiting on a run queue;
+    /// [`remove`](trait.Policy.html#tymethod.remove) it first.
+    ///
+    /// # Panics
+    /// + If `priority` isn't less than `PRIORITY_LEVELS`.
+    pub fn set_priority(&mut self, priority: usize) {
+        assert!( priority < PRIORITY_LEVELS
+               , "Priority {} is out of range", priority);
+        self.priority = priority;
+    }
+
+    /// Returns the thread's weight, for fair scheduling.
+    #[inline] pub fn weight(&self) -> u64 { WEIGHTS[self.priority] }
+
+    /// Returns the thread's weighted run time, in nanoseconds.
+    #[inline] pub fn vruntime(&self) -> u64 { self.vruntime }
+}
+
+impl Default for Entity {
+    #[inline] fn default() -> Self { Entity::new(DEFAULT_PRIORITY) }
+}
+
+/// A thread which can be scheduled by a [`Policy`](trait.Policy.html).
+pub trait Schedulable: Node {
+    /// Borrows this thread's scheduling state.
+    fn entity(&self) -> &Entity;
+
+    /// Mutably borrows this thread's scheduling state.
+    fn entity_mut(&mut self) -> &mut Entity;
+}
+
+/// A scheduling policy, which orders a CPU's runnable threads.
+///
+/// The running thread is never on the policy's run queue. When it stops
+/// running, the scheduler calls [`put_prev`](#tymethod.put_prev), and then
+/// [`enqueue`](#tymethod.enqueue) if it's still runnable.
+pub trait Policy<T: Schedulable> {
+    /// Returns the name of this policy.
+    fn name(&self) -> &'static str;
+
+    /// Returns the number of threads waiting to run.
+    fn len(&self) -> usize;
+
+    /// Returns true if no threads are waiting to run.
+    #[inline] fn is_empty(&self) -> bool { self.len() == 0 }
+
+    /// Add a runnable thread to the run queue.
+    fn enqueue(&mut self, thread: Link<T>, now: u64);
+
+    /// Take `thread` off the run queue, so that its scheduling state can be
+    /// changed.
+    ///
+    /// # Returns
+    /// + `Some(thread)` if the thread was waiting to run
+    /// + `None` if it wasn't on the run queue.
+    fn remove(&mut self, thread: &T) -> Option<Link<T>>;
+
+    /// Remove the thread which should run next from the run queue.
+    ///
+    /// # Returns
+    /// + `Some(thread)` with the thread to run
+    /// + `None` if the run queue is empty.
+    fn pick_next(&mut self, now: u64) -> Option<Link<T>>;
+
+    /// Account for the time `thread` has run, now that it's being
+    /// switched away from.
+    fn put_prev(&mut self, thread: &mut T, now: u64);
+
+    /// Handle a timer tick while `current` is running.
+    ///
+    /// # Returns
+    /// + `true` if `current` should be preempted
+    /// + `false` if it should keep running.
+    fn tick(&mut self, current: &mut T, now: u64) -> bool;
+}
+
+/// The policies which may be chosen at boot.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Kind { /// [`RoundRobin`](round_robin/struct.RoundRobin.html)
+                RoundRobin
+              , /// [`FixedPriority`](priority/struct.FixedPriority.html)
+                Priority
+              , /// [`Fair`](fair/struct.Fair.html)
+                Fair
+              }
+
+impl Kind {
+    /// Returns the kind of policy called `name`, as it would be written on
+    /// the kernel command line.
+    pub fn from_name(name: &str) -> Option<Kind> {
+        match name {
+            "round-robin" | "rr" => Some(Kind::RoundRobin)
+          , "priority" => Some(Kind::Priority)
+          , "fair" | "cfs" => Some(Kind::Fair)
+          , _ => None
+        }
+    }
+
+    /// Returns the name of this kind of policy.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Kind::RoundRobin => "round-robin"
+          , Kind::Priority => "priority"
+          , Kind::Fair => "fair"
+        }
+    }
+}
+
+impl Default for Kind {
+    #[inline] fn default() -> Self { Kind::Fair }
+}
+
+impl fmt::Display for Kind {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
@@ -0,0 +1,135 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Fixed-priority scheduling with aging.
+//!
+//! There's a FIFO run queue for each priority level, and the thread at the
+//! front of the highest-priority non-empty queue always runs next. A running
+//! thread is preempted as soon as a higher-priority thread is waiting, or
+//! when its time slice is up and another thread of the same priority is
+//! waiting.
+//!
+//! To keep low-priority threads from starving, a thread which has waited
+//! for the _aging interval_ is moved up to the next priority level, and so
+//! on until it runs. Once it runs, it goes back to its own priority.
+//!
+//! Since threads are queued with the time they were queued at, and time
+//! only goes forwards, each queue is sorted from longest to shortest wait,
+//! so aging only has to look at the front of each queue.
+use sos_intrusive::List;
+use sos_intrusive::list::OwnedRef;
+
+use super::{Link, Policy, PRIORITY_LEVELS, Schedulable};
+
+/// A fixed-priority scheduling policy.
+pub struct FixedPriority<T: Schedulable> {
+    queues: [List<Link<T>, T>; PRIORITY_LEVELS]
+  , /// in nanoseconds
+    slice: u64
+  , /// how long a thread may wait before it's aged, in nanoseconds
+    aging_interval: u64
+  , len: usize
+}
+
+impl<T: Schedulable> FixedPriority<T> {
+    /// Returns a new fixed-priority policy, which lets each thread run for
+    /// `slice` nanoseconds, and raises the priority of threads which have
+    /// waited for `aging_interval` nanoseconds.
+    pub fn new(slice: u64, aging_interval: u64) -> Self {
+        FixedPriority { queues: [ List::new(), List::new()
+                                , List::new(), List::new()
+                                , List::new(), List::new()
+                                , List::new(), List::new() ]
+                      , slice: slice
+                      , aging_interval: aging_interval
+                      , len: 0
+                      }
+    }
+
+    /// Move threads which have waited for the aging interval up a level.
+    fn age(&mut self, now: u64) {
+        // going from the highest level down means a thread moves up at most
+        // one level each time, since it's re-queued with the current time
+        for level in 1..PRIORITY_LEVELS {
+            while self.queues[level].front()
+                      .map(|t| now.saturating_sub(t.entity().since)
+                                >= self.aging_interval)
+                      .unwrap_or(false) {
+                let mut thread = self.queues[level].pop_front().unwrap();
+                {
+                    let entity = thread.get_mut().entity_mut();
+                    entity.effective = level - 1;
+                    entity.since = now;
+                }
+                self.queues[level - 1].push_back(thread);
+            }
+        }
+    }
+
+    /// Returns the highest priority of any waiting thread.
+    fn highest(&self) -> Option<usize> {
+        self.queues.iter().position(|q| !q.is_empty())
+    }
+}
+
+impl<T: Schedulable> Policy<T> for FixedPriority<T> {
+    #[inline] fn name(&self) -> &'static str { "priority" }
+
+    #[inline] fn len(&self) -> usize { self.len }
+
+    fn enqueue(&mut self, mut thread: Link<T>, now: u64) {
+        let level = {
+            let entity = thread.get_mut().entity_mut();
+            entity.effective = entity.priority;
+            entity.since = now;
+            entity.priority
+        };
+        self.queues[level].push_back(thread);
+        self.len += 1;
+    }
+
+    fn remove(&mut self, thread: &T) -> Option<Link<T>> {
+        let level = thread.entity().effective;
+        let thread = thread as *const T;
+        let removed = self.queues[level].cursor_mut()
+                          .find_and_remove(|t| t as *const T == thread);
+        if removed.is_some() { self.len -= 1; }
+        removed
+    }
+
+    fn pick_next(&mut self, now: u64) -> Option<Link<T>> {
+        self.age(now);
+        let level = match self.highest() {
+            Some(level) => level
+          , None => return None
+        };
+        let mut thread = self.queues[level].pop_front().unwrap();
+        self.len -= 1;
+        {
+            let entity = thread.get_mut().entity_mut();
+            entity.effective = entity.priority;
+            entity.since = now;
+        }
+        Some(thread)
+    }
+
+    #[inline] fn put_prev(&mut self, _thread: &mut T, _now: u64) { }
+
+    fn tick(&mut self, current: &mut T, now: u64) -> bool {
+        self.age(now);
+        let entity = current.entity();
+        match self.highest() {
+            Some(level) if level < entity.priority => true
+          , Some(level) if level == entity.priority =>
+                now.saturating_sub(entity.since) >= self.slice
+          , // a lower-priority thread waiting is still aging, so it'll get
+            // its turn
+            _ => false
+        }
+    }
+}
@@ -0,0 +1,61 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Round-robin scheduling.
+//!
+//! Threads run in the order they became runnable, and each may run for one
+//! time slice before it's preempted. Priorities are ignored.
+use sos_intrusive::List;
+use sos_intrusive::list::OwnedRef;
+
+use super::{Link, Policy, Schedulable};
+
+/// A round-robin scheduling policy.
+pub struct RoundRobin<T: Schedulable> { queue: List<Link<T>, T>
+                                      , /// in nanoseconds
+                                        slice: u64
+                                      }
+
+impl<T: Schedulable> RoundRobin<T> {
+    /// Returns a new round-robin policy, which lets each thread run for
+    /// `slice` nanoseconds.
+    pub fn new(slice: u64) -> Self {
+        RoundRobin { queue: List::new(), slice: slice }
+    }
+}
+
+impl<T: Schedulable> Policy<T> for RoundRobin<T> {
+    #[inline] fn name(&self) -> &'static str { "round-robin" }
+
+    #[inline] fn len(&self) -> usize { self.queue.len() }
+
+    fn enqueue(&mut self, thread: Link<T>, _now: u64) {
+        self.queue.push_back(thread)
+    }
+
+    fn remove(&mut self, thread: &T) -> Option<Link<T>> {
+        let thread = thread as *const T;
+        self.queue.cursor_mut()
+            .find_and_remove(|t| t as *const T == thread)
+    }
+
+    fn pick_next(&mut self, now: u64) -> Option<Link<T>> {
+        self.queue.pop_front()
+            .map(|mut thread| {
+                thread.get_mut().entity_mut().since = now;
+                thread
+            })
+    }
+
+    #[inline] fn put_prev(&mut self, _thread: &mut T, _now: u64) { }
+
+    fn tick(&mut self, current: &mut T, now: u64) -> bool {
+        !self.queue.is_empty()
+            && now.saturating_sub(current.entity().since) >= self.slice
+    }
+}
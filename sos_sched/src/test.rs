@@ -0,0 +1,353 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Tests for the scheduling policies, which run threads on a simulated CPU
+//! with a simulated clock.
+use std::boxed::Box;
+use std::mem;
+use std::vec::Vec;
+
+use sos_intrusive::RawLink;
+use sos_intrusive::list::{Node, OwnedRef};
+
+use super::*;
+
+/// One millisecond, in nanoseconds.
+const MS: u64 = 1_000_000;
+
+struct Task { id: usize
+            , entity: Entity
+            , next: RawLink<Task>
+            , prev: RawLink<Task>
+            }
+
+impl Node for Task {
+    fn next(&self) -> &RawLink<Task> { &self.next }
+    fn prev(&self) -> &RawLink<Task> { &self.prev }
+    fn next_mut(&mut self) -> &mut RawLink<Task> { &mut self.next }
+    fn prev_mut(&mut self) -> &mut RawLink<Task> { &mut self.prev }
+}
+
+impl Schedulable for Task {
+    fn entity(&self) -> &Entity { &self.entity }
+    fn entity_mut(&mut self) -> &mut Entity { &mut self.entity }
+}
+
+/// A CPU which ticks every millisecond.
+struct Sim<P: Policy<Task>> { policy: P
+                            , now: u64
+                            , tasks: Vec<*mut Task>
+                            , current: Option<Link<Task>>
+                            , /// how long each task has run, in nanoseconds
+                              ran: Vec<u64>
+                            }
+
+impl<P: Policy<Task>> Sim<P> {
+    fn new(policy: P) -> Self {
+        Sim { policy: policy
+            , now: 0
+            , tasks: Vec::new()
+            , current: None
+            , ran: Vec::new()
+            }
+    }
+
+    /// Spawn a task with the given priority, returning its ID.
+    fn spawn(&mut self, priority: usize) -> usize {
+        let id = self.tasks.len();
+        let task = Box::into_raw(Box::new(Task { id: id
+                                               , entity: Entity::new(priority)
+                                               , next: RawLink::none()
+                                               , prev: RawLink::none()
+                                               }));
+        self.tasks.push(task);
+        self.ran.push(0);
+        self.policy.enqueue(unsafe { OwnedRef::from_raw(task) }, self.now);
+        id
+    }
+
+    /// Returns the ID of the running task.
+    fn current(&self) -> Option<usize> {
+        self.current.as_ref().map(|t| t.get().id)
+    }
+
+    /// Switch to the next task, putting the running one back on the queue.
+    fn switch(&mut self) {
+        let now = self.now;
+        if let Some(mut prev) = self.current.take() {
+            self.policy.put_prev(prev.get_mut(), now);
+            self.policy.enqueue(prev, now);
+        }
+        self.current = self.policy.pick_next(now);
+    }
+
+    /// Run for `ms` milliseconds.
+    fn run(&mut self, ms: u64) {
+        if self.current.is_none() { self.switch() }
+        for _ in 0..ms {
+            self.now += MS;
+            let preempt = match self.current {
+                Some(ref mut current) => {
+                    self.ran[current.get().id] += MS;
+                    self.policy.tick(current.get_mut(), self.now)
+                }
+              , None => false
+            };
+            if preempt { self.switch() }
+        }
+    }
+
+    /// Block the running task, returning its ID.
+    fn block(&mut self) -> Link<Task> {
+        let mut task = self.current.take().expect("nothing is running");
+        self.policy.put_prev(task.get_mut(), self.now);
+        self.current = self.policy.pick_next(self.now);
+        task
+    }
+
+    /// Change the priority of a task which is waiting to run.
+    fn set_priority(&mut self, id: usize, priority: usize) {
+        let task = self.tasks[id];
+        let mut link = self.policy.remove(unsafe { &*task })
+                           .expect("task wasn't queued");
+        link.get_mut().entity.set_priority(priority);
+        self.policy.enqueue(link, self.now);
+    }
+
+    /// Make a blocked task runnable.
+    fn wake(&mut self, task: Link<Task>) {
+        self.policy.enqueue(task, self.now);
+    }
+}
+
+impl<P: Policy<Task>> Drop for Sim<P> {
+    fn drop(&mut self) {
+        for &task in &self.tasks {
+            mem::drop(unsafe { Box::from_raw(task) });
+        }
+    }
+}
+
+#[test]
+fn test_kind_from_name() {
+    assert_eq!(Kind::from_name("rr"), Some(Kind::RoundRobin));
+    assert_eq!(Kind::from_name("round-robin"), Some(Kind::RoundRobin));
+    assert_eq!(Kind::from_name("priority"), Some(Kind::Priority));
+    assert_eq!(Kind::from_name("fair"), Some(Kind::Fair));
+    assert_eq!(Kind::from_name("lottery"), None);
+}
+
+mod round_robin {
+    use super::*;
+
+    #[test]
+    fn test_takes_turns() {
+        let mut sim = Sim::new(RoundRobin::new(10 * MS));
+        sim.spawn(DEFAULT_PRIORITY);
+        sim.spawn(DEFAULT_PRIORITY);
+        sim.run(9);
+        assert_eq!(sim.current(), Some(0));
+        sim.run(1);
+        assert_eq!(sim.current(), Some(1));
+        sim.run(10);
+        assert_eq!(sim.current(), Some(0));
+    }
+
+    #[test]
+    fn test_ignores_priority() {
+        let mut sim = Sim::new(RoundRobin::new(10 * MS));
+        sim.spawn(PRIORITY_LEVELS - 1);
+        sim.spawn(0);
+        sim.run(1000);
+        assert_eq!(sim.ran[0], sim.ran[1]);
+    }
+
+    #[test]
+    fn test_not_preempted_alone() {
+        let mut sim = Sim::new(RoundRobin::new(10 * MS));
+        sim.spawn(DEFAULT_PRIORITY);
+        sim.run(100);
+        assert_eq!(sim.current(), Some(0));
+        assert_eq!(sim.policy.len(), 0);
+    }
+}
+
+mod priority {
+    use super::*;
+
+    #[test]
+    fn test_highest_runs_first() {
+        let mut sim = Sim::new(FixedPriority::new(10 * MS, 1000 * MS));
+        sim.spawn(5);
+        sim.spawn(1);
+        sim.spawn(3);
+        sim.run(0);
+        assert_eq!(sim.current(), Some(1));
+        sim.block();
+        assert_eq!(sim.current(), Some(2));
+        sim.block();
+        assert_eq!(sim.current(), Some(0));
+    }
+
+    #[test]
+    fn test_higher_priority_preempts() {
+        let mut sim = Sim::new(FixedPriority::new(10 * MS, 1000 * MS));
+        sim.spawn(5);
+        sim.run(2);
+        let high = sim.spawn(1);
+        sim.run(1);
+        assert_eq!(sim.current(), Some(high));
+    }
+
+    #[test]
+    fn test_same_priority_takes_turns() {
+        let mut sim = Sim::new(FixedPriority::new(10 * MS, 1000 * MS));
+        sim.spawn(2);
+        sim.spawn(2);
+        sim.spawn(6);
+        sim.run(100);
+        assert_eq!(sim.ran[0], sim.ran[1]);
+        assert_eq!(sim.ran[2], 0);
+    }
+
+    #[test]
+    fn test_raised_priority_preempts() {
+        let mut sim = Sim::new(FixedPriority::new(10 * MS, 1000 * MS));
+        sim.spawn(3);
+        let low = sim.spawn(6);
+        sim.spawn(6);
+        sim.run(1);
+        sim.set_priority(low, 1);
+        assert_eq!(sim.policy.len(), 2);
+        sim.run(1);
+        assert_eq!(sim.current(), Some(low));
+    }
+
+    #[test]
+    fn test_aging_prevents_starvation() {
+        let mut sim = Sim::new(FixedPriority::new(10 * MS, 50 * MS));
+        sim.spawn(0);
+        let low = sim.spawn(PRIORITY_LEVELS - 1);
+        // the low-priority task ages one level every 50ms, until it reaches
+        // the high-priority task's level and they take turns
+        sim.run(50 * (PRIORITY_LEVELS as u64 - 1) - 1);
+        assert_eq!(sim.ran[low], 0);
+        sim.run(20);
+        assert!(sim.ran[low] > 0);
+    }
+
+    #[test]
+    fn test_aging_is_undone_when_run() {
+        let mut sim = Sim::new(FixedPriority::new(10 * MS, 50 * MS));
+        sim.spawn(0);
+        let low = sim.spawn(PRIORITY_LEVELS - 1);
+        while sim.current() != Some(low) { sim.run(1) }
+        let task = sim.block();
+        assert_eq!(task.get().entity.effective, PRIORITY_LEVELS - 1);
+        sim.wake(task);
+    }
+}
+
+mod fair {
+    use super::*;
+
+    #[test]
+    fn test_equal_weights_share_equally() {
+        let mut sim = Sim::new(Fair::new(20 * MS, 2 * MS));
+        sim.spawn(DEFAULT_PRIORITY);
+        sim.spawn(DEFAULT_PRIORITY);
+        sim.spawn(DEFAULT_PRIORITY);
+        sim.run(3000);
+        for &ran in &sim.ran {
+            assert!(ran >= 990 * MS && ran <= 1010 * MS, "ran {}", ran);
+        }
+    }
+
+    #[test]
+    fn test_shares_by_weight() {
+        let mut sim = Sim::new(Fair::new(20 * MS, 2 * MS));
+        let heavy = sim.spawn(2);
+        let light = sim.spawn(DEFAULT_PRIORITY);
+        sim.run(10_000);
+        // a priority 2 thread has 2501 / 1024 times the weight
+        let ratio = sim.ran[heavy] as f64 / sim.ran[light] as f64;
+        assert!(ratio > 2.3 && ratio < 2.6, "ratio {}", ratio);
+    }
+
+    #[test]
+    fn test_slice_shrinks_with_more_threads() {
+        let mut sim = Sim::new(Fair::new(20 * MS, 2 * MS));
+        sim.spawn(DEFAULT_PRIORITY);
+        sim.spawn(DEFAULT_PRIORITY);
+        sim.run(9);
+        assert_eq!(sim.current(), Some(0));
+        sim.run(1);
+        assert_eq!(sim.current(), Some(1));
+
+        for _ in 0..8 { sim.spawn(DEFAULT_PRIORITY); }
+        // the 10 threads now share the 20ms latency, so the running thread
+        // only gets 2ms
+        sim.run(1);
+        assert_eq!(sim.current(), Some(1));
+        sim.run(1);
+        assert!(sim.current() != Some(1));
+    }
+
+    #[test]
+    fn test_woken_thread_cant_hog() {
+        let mut sim = Sim::new(Fair::new(20 * MS, 2 * MS));
+        let sleeper = sim.spawn(DEFAULT_PRIORITY);
+        sim.spawn(DEFAULT_PRIORITY);
+        sim.run(0);
+        let task = sim.block();
+        assert_eq!(task.get().id, sleeper);
+        sim.run(1000);
+        sim.wake(task);
+
+        // the sleeper is only let half a latency period ahead
+        let before = sim.ran[sleeper];
+        sim.run(100);
+        let ran = sim.ran[sleeper] - before;
+        assert!(ran >= 50 * MS && ran <= 65 * MS, "ran {}", ran);
+    }
+
+    #[test]
+    fn test_remove_updates_weight() {
+        let mut sim = Sim::new(Fair::new(20 * MS, 2 * MS));
+        sim.spawn(DEFAULT_PRIORITY);
+        let other = sim.spawn(DEFAULT_PRIORITY);
+        sim.run(1);
+        sim.set_priority(other, 0);
+        // the heavier thread is now waiting, so the running thread's share
+        // of the latency shrinks below what it has run
+        sim.run(3);
+        assert_eq!(sim.current(), Some(other));
+    }
+
+    #[test]
+    fn test_len_after_picking() {
+        let mut sim = Sim::new(Fair::new(20 * MS, 2 * MS));
+        sim.spawn(DEFAULT_PRIORITY);
+        sim.spawn(DEFAULT_PRIORITY);
+        sim.spawn(DEFAULT_PRIORITY);
+        sim.run(100);
+        assert_eq!(sim.policy.len(), 2);
+        sim.block();
+        sim.block();
+        assert_eq!(sim.policy.len(), 0);
+        assert!(sim.policy.is_empty());
+    }
+
+    #[test]
+    fn test_min_vruntime_advances_alone() {
+        let mut sim = Sim::new(Fair::new(20 * MS, 2 * MS));
+        sim.spawn(DEFAULT_PRIORITY);
+        sim.run(100);
+        assert_eq!(sim.policy.min_vruntime(), 100 * MS);
+    }
+}
@@ -0,0 +1,214 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! # SOS Tar Archives
+//!
+//! A reader for `ustar` archives, which the kernel uses for its initial
+//! ramdisk. Archives are read in place, without copying or allocating, so
+//! the files in an archive borrow from the bytes it was read from.
+#![crate_name = "sos_tar"]
+#![crate_type = "lib"]
+#![no_std]
+
+use core::{cmp, str};
+
+/// Size of a `ustar` header, and of the blocks file contents are padded to.
+pub const BLOCK_SIZE: usize = 512;
+
+/// A `ustar` archive.
+#[derive(Copy, Clone, Debug)]
+pub struct Archive<'a> { bytes: &'a [u8] }
+
+impl<'a> Archive<'a> {
+    /// Returns the archive in `bytes`.
+    ///
+    /// # Returns
+    /// + `Err` if `bytes` doesn't start with a valid `ustar` header.
+    pub fn new(bytes: &'a [u8]) -> Result<Self, &'static str> {
+        if bytes.len() < BLOCK_SIZE || &bytes[257..262] != b"ustar" {
+            return Err("Not a ustar archive")
+        }
+        if !checksum_ok(&bytes[..BLOCK_SIZE]) {
+            return Err("The first header's checksum is wrong")
+        }
+        Ok(Archive { bytes: bytes })
+    }
+
+    /// Returns an iterator over the regular files in the archive.
+    ///
+    /// Iteration stops early if any of the archive is corrupt.
+    #[inline]
+    pub fn files(&self) -> Files<'a> { Files { rest: self.bytes } }
+
+    /// Returns the contents of the file at `path`, if there is one.
+    pub fn get(&self, path: &str) -> Option<&'a [u8]> {
+        self.files()
+            .find(|file| file.is(path))
+            .map(|file| file.data)
+    }
+}
+
+/// A regular file in an [`Archive`](struct.Archive.html).
+#[derive(Copy, Clone, Debug)]
+pub struct File<'a> { /// the directory the file is in, which is only set
+                      /// if the path didn't fit in `name`
+                      pub prefix: &'a str
+                    , /// the file's name
+                      pub name: &'a str
+                    , /// the file's contents
+                      pub data: &'a [u8]
+                    }
+
+impl<'a> File<'a> {
+    /// Returns true if this file's path is `path`.
+    pub fn is(&self, path: &str) -> bool {
+        if self.prefix.is_empty() { return path == self.name }
+        path.len() == self.prefix.len() + 1 + self.name.len()
+            && path.starts_with(self.prefix)
+            && path[self.prefix.len()..].starts_with('/')
+            && path.ends_with(self.name)
+    }
+}
+
+/// An iterator over the regular files in an
+/// [`Archive`](struct.Archive.html).
+#[derive(Clone, Debug)]
+pub struct Files<'a> { rest: &'a [u8] }
+
+impl<'a> Iterator for Files<'a> {
+    type Item = File<'a>;
+
+    fn next(&mut self) -> Option<File<'a>> {
+        loop {
+            if self.rest.len() < BLOCK_SIZE { return None }
+            let (header, rest) = self.rest.split_at(BLOCK_SIZE);
+            // the archive ends with zeroed blocks
+            if header[0] == 0 || !checksum_ok(header) {
+                self.rest = &[];
+                return None
+            }
+            let size = match octal(&header[124..136]) {
+                Some(size) if size <= rest.len() => size
+              , _ => { self.rest = &[]; return None }
+            };
+            let padded = (size + BLOCK_SIZE - 1) / BLOCK_SIZE * BLOCK_SIZE;
+            self.rest = &rest[cmp::min(padded, rest.len())..];
+
+            // skip directories, links, and the like
+            if header[156] != b'0' && header[156] != 0 { continue }
+            match (string(&header[345..500]), string(&header[0..100])) {
+                (Some(prefix), Some(name)) => return Some(File {
+                    prefix: prefix
+                  , name: name
+                  , data: &rest[..size]
+                })
+              , _ => { self.rest = &[]; return None }
+            }
+        }
+    }
+}
+
+/// Returns true if the checksum of the `ustar` header `header` is right.
+///
+/// The checksum is the sum of the header's bytes, with the checksum field
+/// itself taken to be spaces.
+fn checksum_ok(header: &[u8]) -> bool {
+    let sum = header.iter().enumerate()
+                    .map(|(i, &b)| if i >= 148 && i < 156 { b' ' } else { b })
+                    .fold(0, |sum, b| sum + b as usize);
+    octal(&header[148..156]) == Some(sum)
+}
+
+/// Parse a zero- or space-terminated octal number.
+fn octal(field: &[u8]) -> Option<usize> {
+    let digits = field.iter()
+                      .skip_while(|&&b| b == b' ')
+                      .take_while(|&&b| b != 0 && b != b' ');
+    let mut value = 0usize;
+    for &digit in digits {
+        if digit < b'0' || digit > b'7' { return None }
+        value = value.checked_mul(8)?
+                     .checked_add((digit - b'0') as usize)?;
+    }
+    Some(value)
+}
+
+/// Returns the zero-terminated string in `field`, if it's UTF-8.
+fn string(field: &[u8]) -> Option<&str> {
+    let len = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    str::from_utf8(&field[..len]).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Write a `ustar` header for a file called `name`, `size` bytes long.
+    fn header(block: &mut [u8], name: &str, size: usize, ty: u8) {
+        use core::fmt::Write;
+        struct Field<'a>(&'a mut [u8], usize);
+        impl<'a> Write for Field<'a> {
+            fn write_str(&mut self, s: &str) -> ::core::fmt::Result {
+                self.0[self.1 .. self.1 + s.len()]
+                    .copy_from_slice(s.as_bytes());
+                self.1 += s.len();
+                Ok(())
+            }
+        }
+        block[..name.len()].copy_from_slice(name.as_bytes());
+        write!(Field(&mut block[124..136], 0), "{:011o}", size).unwrap();
+        block[156] = ty;
+        block[257..263].copy_from_slice(b"ustar\0");
+        block[263..265].copy_from_slice(b"00");
+        let sum = block.iter().enumerate()
+                       .map(|(i, &b)| if i >= 148 && i < 156 { b' ' }
+                                      else { b })
+                       .fold(0, |sum, b| sum + b as usize);
+        write!(Field(&mut block[148..156], 0), "{:06o}\0 ", sum).unwrap();
+    }
+
+    /// An archive with a directory `bin`, and a file `bin/hello`.
+    fn archive() -> [u8; 4 * BLOCK_SIZE] {
+        let mut bytes = [0u8; 4 * BLOCK_SIZE];
+        header(&mut bytes[..BLOCK_SIZE], "bin/", 0, b'5');
+        header( &mut bytes[BLOCK_SIZE .. 2 * BLOCK_SIZE]
+              , "bin/hello", 5, b'0');
+        bytes[2 * BLOCK_SIZE .. 2 * BLOCK_SIZE + 5]
+            .copy_from_slice(b"hello");
+        bytes
+    }
+
+    #[test]
+    fn find_files() {
+        let bytes = archive();
+        let archive = Archive::new(&bytes).unwrap();
+        assert_eq!(archive.files().count(), 1);
+        assert_eq!(archive.get("bin/hello"), Some(&b"hello"[..]));
+        assert_eq!(archive.get("bin"), None);
+        assert_eq!(archive.get("hello"), None);
+    }
+
+    #[test]
+    fn reject_corrupt_archives() {
+        let mut bytes = archive();
+        assert!(Archive::new(&bytes[..100]).is_err());
+        // a bad checksum in the second header ends the archive early
+        bytes[BLOCK_SIZE + 1] ^= 1;
+        assert_eq!(Archive::new(&bytes).unwrap().files().count(), 0);
+        bytes[0] ^= 1;
+        assert!(Archive::new(&bytes).is_err());
+    }
+
+    #[test]
+    fn prefixed_paths() {
+        let file = File { prefix: "usr/bin", name: "hello", data: &[] };
+        assert!(file.is("usr/bin/hello"));
+        assert!(!file.is("usr/binxhello"));
+        assert!(!file.is("hello"));
+    }
+}
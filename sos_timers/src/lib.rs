@@ -0,0 +1,217 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! # SOS Timer Queue
+//!
+//! A fixed-capacity queue of timers, each of which calls a function when
+//! its deadline passes.
+//!
+//! Timers are kept in a binary min-heap ordered by deadline, so finding the
+//! next timer to expire is O(1), and adding, expiring, or cancelling a timer is
+//! O(log n) (plus an O(n) search to find a timer being cancelled).
+//!
+//! Since the queue must be usable before the heap is available (and from
+//! interrupt context), it never allocates.
+//!
+//! The queue never reads a clock itself. Deadlines, and the current time
+//! passed to [`pop_expired`](struct.Queue.html#method.pop_expired), are in
+//! nanoseconds on whatever clock the kernel uses, so that the queue can be
+//! tested on the host.
+#![crate_name = "sos_timers"]
+#![crate_type = "lib"]
+#![feature(const_fn)]
+#![no_std]
+
+/// Maximum number of timers that may be pending at once.
+pub const CAPACITY: usize = 128;
+
+/// A function called when a timer expires, with the timer's data word.
+pub type Callback = fn(usize);
+
+/// A handle identifying a pending timer, used to cancel it.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct TimerId(u64);
+
+/// A pending timer.
+#[derive(Copy, Clone)]
+pub struct Timer { /// When the timer should fire, in nanoseconds
+                   pub deadline: u64
+                 , /// Handle for cancelling the timer
+                   pub id: TimerId
+                 , /// The function to call when the timer fires
+                   pub callback: Callback
+                 , /// Data passed to the callback
+                   pub data: usize
+                 }
+
+impl Timer {
+    /// Returns true if `self` should fire before `other`.
+    ///
+    /// Timers with the same deadline fire in the order they were added.
+    #[inline]
+    fn before(&self, other: &Timer) -> bool {
+        (self.deadline, self.id) < (other.deadline, other.id)
+    }
+
+    /// Call this timer's callback.
+    #[inline]
+    pub fn fire(self) { (self.callback)(self.data) }
+}
+
+/// A queue of pending timers, ordered by deadline.
+pub struct Queue { timers: [Option<Timer>; CAPACITY]
+                 , len: usize
+                 , next_id: u64
+                 }
+
+impl Queue {
+    /// Returns a new, empty timer queue.
+    pub const fn new() -> Self {
+        Queue { timers: [None; CAPACITY], len: 0, next_id: 0 }
+    }
+
+    /// Returns the number of pending timers.
+    #[inline] pub fn len(&self) -> usize { self.len }
+
+    /// Returns true if there are no pending timers.
+    #[inline] pub fn is_empty(&self) -> bool { self.len == 0 }
+
+    /// Returns the deadline of the next timer to fire, if any.
+    #[inline]
+    pub fn next_deadline(&self) -> Option<u64> {
+        self.timers[0].map(|t| t.deadline)
+    }
+
+    /// Add a timer that fires at `deadline`.
+    pub fn add( &mut self, deadline: u64
+              , callback: Callback, data: usize)
+              -> Result<TimerId, &'static str> {
+        if self.len == CAPACITY {
+            return Err("Timer queue is full")
+        }
+        let id = TimerId(self.next_id);
+        self.next_id += 1;
+
+        let idx = self.len;
+        self.timers[idx] = Some(Timer { deadline: deadline
+                                      , id: id
+                                      , callback: callback
+                                      , data: data });
+        self.len += 1;
+        self.sift_up(idx);
+        Ok(id)
+    }
+
+    /// Cancel the timer with the given `id`.
+    ///
+    /// Returns the timer if it was still pending, or `None` if it had already
+    /// fired or been cancelled.
+    pub fn cancel(&mut self, id: TimerId) -> Option<Timer> {
+        let idx = (0..self.len).find(|&i| self.get(i).id == id)?;
+        Some(self.remove(idx))
+    }
+
+    /// Remove and return the next timer whose deadline is at or before `now`.
+    pub fn pop_expired(&mut self, now: u64) -> Option<Timer> {
+        match self.timers[0] {
+            Some(ref t) if t.deadline <= now => {}
+          , _ => return None
+        }
+        Some(self.remove(0))
+    }
+
+    #[inline]
+    fn get(&self, idx: usize) -> &Timer {
+        self.timers[idx].as_ref().expect("timer queue slot was empty")
+    }
+
+    /// Remove the timer at `idx`, restoring the heap invariant.
+    fn remove(&mut self, idx: usize) -> Timer {
+        self.len -= 1;
+        let last = self.len;
+        self.timers.swap(idx, last);
+        let timer = self.timers[last].take()
+                        .expect("timer queue slot was empty");
+        if idx < self.len {
+            self.sift_down(idx);
+            self.sift_up(idx);
+        }
+        timer
+    }
+
+    fn sift_up(&mut self, mut idx: usize) {
+        while idx > 0 {
+            let parent = (idx - 1) / 2;
+            if !self.get(idx).before(self.get(parent)) { break; }
+            self.timers.swap(idx, parent);
+            idx = parent;
+        }
+    }
+
+    fn sift_down(&mut self, mut idx: usize) {
+        loop {
+            let (left, right) = (2 * idx + 1, 2 * idx + 2);
+            let mut first = idx;
+            if left < self.len && self.get(left).before(self.get(first)) {
+                first = left;
+            }
+            if right < self.len && self.get(right).before(self.get(first)) {
+                first = right;
+            }
+            if first == idx { break; }
+            self.timers.swap(idx, first);
+            idx = first;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn nop(_: usize) { }
+
+    #[test]
+    fn expires_in_deadline_order() {
+        let mut q = Queue::new();
+        for &d in &[50, 10, 40, 20, 30] {
+            q.add(d, nop, d as usize).unwrap();
+        }
+        assert_eq!(q.next_deadline(), Some(10));
+        assert!(q.pop_expired(5).is_none());
+
+        let now = 35;
+        let mut fired = [0; 3];
+        for slot in fired.iter_mut() {
+            *slot = q.pop_expired(now).expect("timer should have expired").data;
+        }
+        assert_eq!(fired, [10, 20, 30]);
+        assert!(q.pop_expired(now).is_none());
+        assert_eq!(q.len(), 2);
+    }
+
+    #[test]
+    fn cancel_removes_timer() {
+        let mut q = Queue::new();
+        let a = q.add(10, nop, 1).unwrap();
+        let b = q.add(20, nop, 2).unwrap();
+        assert_eq!(q.cancel(a).map(|t| t.data), Some(1));
+        assert!(q.cancel(a).is_none());
+        assert_eq!(q.pop_expired(100).map(|t| t.id), Some(b));
+        assert!(q.is_empty());
+    }
+
+    #[test]
+    fn full_queue_is_an_error() {
+        let mut q = Queue::new();
+        for i in 0..CAPACITY {
+            q.add(i as u64, nop, i).unwrap();
+        }
+        assert!(q.add(0, nop, 0).is_err());
+    }
+}
@@ -18,16 +18,15 @@
 //! specific items. If these are not defined, the platform-independant kernel
 //! implementation cannot function properly.
 //!
-//! Please note that currently only the architecture-specific implementation
-//! for `x86_64` (long mode) is implemented. The `armv7` and `x86` (protected
-//! mode) modules are currently much less complete.
+//! Please note that currently only the `x86_64` (long mode) and `x86`
+//! (protected mode) implementations are usable, and that the `x86` one only
+//! uses a single CPU. The `armv7` module is currently much less complete.
 
 // 64-bit x86_64 (long mode)
 #[cfg(target_arch="x86_64")] mod x86_64;
 #[cfg(target_arch="x86_64")] pub use self::x86_64::*;
 
 // 32-bit x86 (protected mode)
-// TODO: NYI
 #[cfg(target_arch = "x86")] mod x86;
 #[cfg(target_arch = "x86")] pub use self::x86::*;
 
@@ -0,0 +1,267 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+
+use cpu::interrupts::pics;
+use cpu::interrupts::idt::{Gate, Idt};
+use cpu::{fpu, pit};
+use cpu::timer::timestamp;
+
+use cpu::context::{InterruptFrame, Registers};
+use cpu::dtable::DTable;
+
+
+//==--------------------------------------------------------------------------==
+// Top-level interrupt handling
+
+/// Frequency of the kernel's periodic timer tick, in Hz.
+pub const TIMER_FREQUENCY: u32 = pit::DEFAULT_FREQUENCY;
+
+/// Initialize interrupt handling.
+///
+/// This function initializes the PICs, populates the IDT with interrupt
+/// handlers, loads the IDT pointer, programs the system timer, and enables
+/// interrupts.
+///
+/// This is called from the kernel during the init process.
+#[inline]
+pub unsafe fn initialize() -> Result<(), ()>{
+
+    pics::initialize();
+
+    // calibrate the TSC against the PIT before the tick starts, since the
+    // calibration busy-waits on PIT channel 2.
+    match timestamp::calibrate() {
+        Ok(hz) => kinfoln!( dots: " . . "
+                          , "Timestamp counter runs at {} MHz", hz / 1_000_000)
+      , Err(why) => warn!("Could not calibrate timestamp counter: {}", why)
+    }
+    kinfoln!(dots: " . . ", "Kernel clock is {:?}", ::time::initialize());
+    pit::initialize(TIMER_FREQUENCY);
+    kinfoln!( dots: " . . ", "System timer ticking at {} Hz"
+            , pit::frequency().unwrap_or(0));
+
+    IDT.load();         // Load the IDT pointer
+
+    Idt::enable_interrupts(); // enable interrupts
+    Ok(())
+
+}
+
+/// Load the IDT on the current CPU.
+#[inline]
+pub unsafe fn load() {
+    IDT.load();
+}
+
+/// The Rust half of an exception handler, called by its entry stub.
+type FaultHandler = extern "C" fn(&mut Registers, usize, &mut InterruptFrame);
+
+/// Generates the naked entry stub for an exception handler.
+///
+/// This works the same way as on `x86_64`, except that the handler's
+/// arguments are passed on the stack.
+macro_rules! exception_entry {
+    (no_code: $handler:ident) => {
+        /// Entry stub for this exception.
+        ///
+        /// THIS FUNCTION IS NAKED. DO NOT CALL IT NORMALLY.
+        #[naked]
+        pub unsafe extern "C" fn entry() {
+            asm!("push 0" :::: "intel", "volatile");
+            exception_entry!(@body $handler);
+        }
+    };
+    (code: $handler:ident) => {
+        /// Entry stub for this exception.
+        ///
+        /// THIS FUNCTION IS NAKED. DO NOT CALL IT NORMALLY.
+        #[naked]
+        pub unsafe extern "C" fn entry() {
+            exception_entry!(@body $handler);
+        }
+    };
+    (@body $handler:ident) => {
+        Registers::push();
+        // 7 saved registers, then the error code, then the interrupt frame.
+        asm!( "mov eax, esp
+               lea ecx, [esp + 8 * 4]
+               push ecx
+               push dword ptr [eax + 7 * 4]
+               push eax
+               call $0
+               add esp, 3 * 4"
+            :: "i"($handler as FaultHandler)
+            : "eax", "ecx", "memory"
+            : "intel", "volatile");
+        Registers::pop();
+        // pop the error code and return
+        asm!( "add esp, 4
+               iretd"
+            :::: "intel", "volatile");
+    };
+}
+
+macro_rules! exceptions {
+    ( $kind:ident ($code:ident): $name:ident, $vector:expr, $title:expr
+    , $source:expr, $($tail:tt)* ) => {
+        #[doc=$title]
+        pub mod $name {
+            use cpu::context::{InterruptFrame, Registers};
+            use cpu::interrupts::fault::Fault;
+            use super::FaultHandler;
+
+            /// Vector number of this exception.
+            pub const VECTOR: u8 = $vector;
+
+            #[doc=$title]
+            extern "C" fn handler( registers: &mut Registers
+                                 , error_code: usize
+                                 , frame: &mut InterruptFrame) {
+                Fault::new( VECTOR, $title, exceptions!(@kind $kind)
+                          , $source, frame, registers, error_code)
+                    .report();
+                exceptions!(@after $kind);
+            }
+
+            exception_entry!($code: handler);
+        }
+
+        exceptions! { $($tail)* }
+    };
+    (@kind fault) => { "Fault" };
+    (@kind trap) => { "Trap" };
+    (@kind abort) => { "Abort" };
+    (@after trap) => { };
+    (@after $kind:ident) => { loop { } };
+    ( ) => {};
+}
+
+exceptions! {
+    fault (no_code): divide_by_zero, 0, "Divide by Zero Error",
+           "DIV or IDIV instruction",
+    fault (no_code): nmi, 2, "Non-Maskable Interrupt",
+          "Non-maskable external interrupt",
+    trap (no_code): overflow, 4, "Overflow", "INTO instruction",
+    fault (no_code): bound_exceeded, 5, "BOUND range exceeded",
+          "BOUND instruction",
+    fault (no_code): undefined_opcode, 6, "Undefined Opcode",
+           "UD2 instruction or reserved opcode",
+    abort (code): double_fault, 8, "Double Fault"
+         , "Any instruction that can generate an exception, a NMI, or \
+            an INTR",
+    fault (code): invalid_tss, 10, "Invalid TSS"
+         , "Task switch or TSS access",
+    fault (code): segment_not_present, 11, "Segment Not Present"
+         , "Loading segment registers or accessing \
+            system segments",
+    fault (code): stack_segment_fault, 12, "Stack Segment Fault"
+         , "Stack operations and SS register loads",
+    fault (code): general_protection_fault, 13, "General Protection Fault"
+         , "Any memory reference or other protection checks",
+    fault (code): page_fault, 14, "Page Fault"
+         , "Any memory reference",
+    fault (no_code): floating_point_error, 16
+         , "x87 FPU Floating-Point Error (Math Fault)"
+         , "x87 FPU floating-point or WAIT/FWAIT instruction",
+    fault (code): alignment_check, 17, "Alignment Check"
+         , "Any data reference in memory",
+    abort (no_code): machine_check, 18, "Machine Check"
+         , "Model-dependent (probably hardware!)",
+    fault (no_code): simd_fp_exception, 19, "SIMD Floating-Point Exception"
+         , "SSE/SSE2/SSE3 floating-point instructions",
+}
+
+/// Point the IDT gate for an exception at that exception's entry stub.
+macro_rules! set_exception {
+    ($idt:expr, $($name:ident),+) => { $(
+        $idt[$name::VECTOR as usize] = Gate::from($name::entry as *const u8);
+    )+ }
+}
+
+lazy_static! {
+    static ref IDT: Idt = {
+        let mut idt = Idt::new();
+        use cpu::interrupts::*;
+
+        set_exception!( idt
+                      , divide_by_zero, nmi, overflow, bound_exceeded
+                      , undefined_opcode, double_fault
+                      , invalid_tss, segment_not_present, stack_segment_fault
+                      , general_protection_fault, page_fault
+                      , floating_point_error, alignment_check, machine_check
+                      , simd_fp_exception );
+        idt.overflow.set_trap();
+
+        idt.breakpoint = Gate::from(breakpoint as InterruptHandler);
+        idt.device_not_available
+            = Gate::from(device_not_available as InterruptHandler);
+
+        idt.interrupts[0x20 - 32] = Gate::from(timer_tick as InterruptHandler);
+        idt.interrupts[0x21 - 32] = Gate::from(keyboard as InterruptHandler);
+        idt.interrupts[0xff - 32] = Gate::from(test as InterruptHandler);
+
+        kinfoln!( dots: " . . ", target: "Adding interrupt handlers to IDT"
+                , "[ OKAY ]");
+        idt
+    };
+}
+
+
+#[no_mangle] #[inline(never)]
+pub extern "x86-interrupt" fn keyboard(_frame: &InterruptFrame) {
+    use io::keyboard;
+
+    if let Some(input) = keyboard::read_char() {
+        if input == '\r' {
+            println!("");
+        } else {
+            print!("{}", input);
+        }
+    }
+   // send the PICs the end interrupt signal
+   unsafe {
+       pics::end_pic_interrupt(0x21);
+   }
+}
+
+/// Handler for the system timer tick.
+///
+/// This advances the tick count, fires any expired kernel timers, and
+/// preempts the running thread if its time slice is up.
+#[no_mangle] #[inline(never)]
+pub extern "x86-interrupt" fn timer_tick(_frame: &InterruptFrame) {
+    pit::tick();
+    ::time::tick();
+    // send the PICs the end interrupt signal
+    unsafe {
+        pics::end_pic_interrupt(pics::IRQ::Timer as u8);
+    }
+    // this may switch threads, so the interrupt must be over
+    ::thread::tick();
+}
+
+/// Handler for the Device Not Available (`#NM`) exception.
+///
+/// This is raised when a task uses the FPU while `TS` is set, and loads that
+/// task's FPU state.
+#[no_mangle] #[inline(never)]
+pub extern "x86-interrupt" fn device_not_available(frame: &InterruptFrame) {
+    if let Err(why) = unsafe { fpu::handle_device_not_available() } {
+        panic!("Device Not Available: {}\n{:?}", why, frame);
+    }
+}
+
+#[no_mangle] #[inline(never)]
+pub extern "x86-interrupt" fn breakpoint(frame: &InterruptFrame) {
+    println!("Breakpoint! Frame: {:#?}", frame);
+   // send the PICs the end interrupt signal
+   unsafe {
+       pics::end_pic_interrupt(0x03);
+   }
+}
@@ -0,0 +1,281 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! `x86` (32-bit protected mode) architecture-specific implementation.
+//!
+//! GRUB leaves us in protected mode already, so unlike on `x86_64` there's no
+//! separate bootstrap library: `_start` just loads a GDT, turns on paging
+//! with a temporary page directory, and calls [`arch_init`].
+//!
+//! Only one CPU is used, and the local APIC, PAT and PCIDs aren't used.
+//!
+//! [`arch_init`]: fn.arch_init.html
+#[path = "../x86_all/drivers/mod.rs"] pub mod drivers;
+pub mod interrupts;
+pub mod smp;
+pub mod syscall;
+pub mod timer;
+pub mod user;
+
+#[path = "../x86_all/bda.rs"] pub mod bda;
+#[path = "../x86_all/multiboot2.rs"] pub mod multiboot2;
+
+pub const ARCH_BITS: u8 = 32;
+
+/// Start of the virtual address range which kernel thread stacks are mapped
+/// into. This is above the temporary page and below the recursive mapping.
+pub const THREAD_STACKS: usize = 0xe000_0000;
+
+extern {
+    #[link_name = "heap_base_addr"]
+    #[linkage = "external"]
+    pub static HEAP_BASE: *mut u8;
+    #[link_name = "heap_top_addr"]
+    #[linkage = "external"]
+    pub static HEAP_TOP: *mut u8;
+    #[link_name = "stack_base"]
+    pub static STACK_BASE: *mut u8;
+    #[link_name = "stack_top"]
+    pub static STACK_TOP: *mut u8;
+}
+
+use memory::PAddr;
+
+// The kernel's entry point, called by GRUB in protected mode with the
+// multiboot magic number in `%eax` and the multiboot info in `%ebx`.
+//
+// Until the kernel is remapped, we use a page directory which identity maps
+// the first GiB of memory with 4 MiB pages, and maps itself in its last
+// entry so that the recursive page table code works.
+global_asm!(r#"
+    .pushsection .text.boot, "ax"
+    .global _start
+    .code32
+_start:
+    cli
+    cld
+    movl    $stack_top, %esp
+    cmpl    $0x36d76289, %eax
+    jne     boot_error
+
+    lgdt    boot_gdt_ptr
+    ljmp    $0x08, $1f
+1:  movw    $0x10, %ax
+    movw    %ax, %ds
+    movw    %ax, %es
+    movw    %ax, %fs
+    movw    %ax, %gs
+    movw    %ax, %ss
+
+    xorl    %ecx, %ecx
+2:  movl    %ecx, %eax
+    shll    $22, %eax
+    # present + writable + huge
+    orl     $0x83, %eax
+    movl    %eax, boot_page_directory(, %ecx, 4)
+    incl    %ecx
+    cmpl    $256, %ecx
+    jne     2b
+    movl    $boot_page_directory, %eax
+    orl     $0x3, %eax
+    movl    %eax, (boot_page_directory + 1023 * 4)
+
+    movl    $boot_page_directory, %eax
+    movl    %eax, %cr3
+    # enable 4 MiB pages
+    movl    %cr4, %eax
+    orl     $(1 << 4), %eax
+    movl    %eax, %cr4
+    # enable paging and write protection
+    movl    %cr0, %eax
+    orl     $((1 << 31) | (1 << 16)), %eax
+    movl    %eax, %cr0
+
+    # `arch_init` takes a 64-bit `PAddr`
+    xorl    %ebp, %ebp
+    pushl   $0
+    pushl   %ebx
+    call    arch_init
+3:  hlt
+    jmp     3b
+
+boot_error:
+    movl    $0x4f524f45, 0xb8000
+4:  hlt
+    jmp     4b
+
+    .align 8
+boot_gdt:
+    .quad   0
+    .quad   0x00cf9a000000ffff
+    .quad   0x00cf92000000ffff
+boot_gdt_ptr:
+    .word   boot_gdt_ptr - boot_gdt - 1
+    .long   boot_gdt
+    .popsection
+"#);
+
+/// Enable the CPU's memory protection features, if they're supported.
+///
+/// # Safety
+/// + This must be called before any user pages are mapped, since
+///   afterwards the kernel can only access them through
+///   [`cpu::smap`](../../../cpu/smap/index.html).
+unsafe fn enable_protection() {
+    use cpu::control_regs::cr4;
+    use cpu::{cpuid, smap};
+
+    // `_start` already enabled write protection
+    kinfoln!(dots: " . ", "Page write protect ENABLED");
+
+    if cpuid::has(cpuid::PGE) {
+        cr4::enable_global_pages(true);
+        kinfoln!(dots: " . ", "Global pages ENABLED");
+    }
+    if cpuid::has(cpuid::SMEP) {
+        cr4::enable_smep(true);
+        kinfoln!(dots: " . ", "Supervisor mode execution prevention ENABLED");
+    }
+    if smap::enable().is_ok() {
+        kinfoln!(dots: " . ", "Supervisor mode access prevention ENABLED");
+    }
+    if cpuid::has(cpuid::UMIP) {
+        cr4::enable_umip(true);
+        kinfoln!(dots: " . ", "User mode instruction prevention ENABLED");
+    }
+}
+
+/// Entry point for architecture-specific kernel init
+///
+/// This expects to be passed the address of a valid Multiboot 2 info struct
+/// on the stack, as `_start` does.
+#[no_mangle]
+pub extern "C" fn arch_init(multiboot_addr: PAddr) {
+    use cpu::cpuid;
+    use params::{InitParams, Module, mem};
+    use spin::Once;
+
+    kinfoln!(dots: " . ", "Beginning `arch_init()` for x86");
+
+    ::io::term::CONSOLE.lock().clear();
+    ::logger::initialize()
+        .expect("Could not initialize logger!");
+
+
+    // -- Identify the CPU ----------------------------------------------------
+    let cpu = cpuid::info();
+    kinfoln!(dots: " . ", "Detected CPU: {}", cpu);
+    kinfoln!( dots: " . . ", "features: [ {:?} ]", cpu.features);
+    for cache in cpu.caches() {
+        kinfoln!(dots: " . . ", "{}", cache);
+    }
+
+    // -- Unpack multiboot tag ------------------------------------------------
+    kinfoln!( dots: " . "
+            , "trying to unpack multiboot info at {:?}"
+            , multiboot_addr);
+
+    let boot_info
+        = unsafe { multiboot2::Info::from(multiboot_addr)
+                    .expect("Could not unpack multiboot2 information!") };
+
+    // Extract ELF sections tag from the multiboot info
+    let elf_sections_tag
+        = boot_info.elf_sections()
+                   .expect("ELF sections tag required!");
+
+    // Load the kernel's symbol table, if the bootloader gave it to us
+    match elf_sections_tag.symbol_table() {
+        Some(table) => ::symbols::initialize(table)
+      , None => warn!("No kernel symbol table; backtraces won't have names.")
+    }
+
+    kinfoln!(dots: " . ", "Detecting kernel ELF sections:");
+
+    let mut n_elf_sections = 0;
+
+    let kernel_begin
+        = elf_sections_tag.sections()
+            .map(|s| {
+                kinfoln!( dots: " . . ", "{}", s );
+                kinfoln!( dots: " . . . ", "flags: [ {:?} ]", s.flags());
+                s.address() })
+            .min()
+            .expect("Could not find kernel start section!\
+                    \nSomething is deeply wrong.");
+
+    let kernel_end
+        = elf_sections_tag.sections()
+            .map(|s| { n_elf_sections += 1; s.end_address() })
+            .max()
+            .expect("Could not find kernel end section!\
+                    \nSomething is deeply wrong.");
+
+    kinfoln!( dots: " . ", "Detected {} kernel ELF sections.", n_elf_sections);
+    kinfoln!( dots: " . . ", "Kernel begins at {:#p} and ends at {:#p}."
+            , kernel_begin, kernel_end );
+
+    let multiboot_end = multiboot_addr + boot_info.length as u64;
+
+    kinfoln!( dots: " . . ", "Multiboot info begins at {:#x} and ends at {:#x}."
+            , multiboot_addr, multiboot_end);
+
+    let mut params = InitParams { kernel_base: kernel_begin
+                            , kernel_top: kernel_end
+                            , multiboot_start: Some(multiboot_addr)
+                            , multiboot_end: Some(multiboot_end)
+                            , acpi_rsdp: boot_info.rsdp()
+                            , command_line: boot_info.command_line()
+                            , heap_base: unsafe { PAddr::from(HEAP_BASE) }
+                            , heap_top: unsafe { PAddr::from(HEAP_TOP) }
+                            , stack_base: unsafe { PAddr::from(STACK_BASE) }
+                            , stack_top: unsafe { PAddr::from(STACK_TOP) }
+                            , elf_sections: Some(elf_sections_tag.sections())
+                            , ..Default::default()
+                        };
+
+    // Extract the memory map tag from the multiboot info
+    let mem_map = boot_info.mem_map()
+                           .expect("Memory map tag required!");
+
+    kinfoln!(dots: " . ", "Detected memory areas:");
+    for area in mem_map {
+        kinfoln!( dots: " . . ", "{}", area);
+        let a: mem::Area = area.into();
+        if a.is_usable == true { params.mem_map.push(a); }
+    }
+
+    // Extract the modules the bootloader loaded along with the kernel
+    for tag in boot_info.modules() {
+        let module: Module = tag.into();
+        kinfoln!( dots: " . ", "Found module {:?} from {:#x} to {:#x}."
+                , module.command_line.unwrap_or(""), module.start, module.end);
+        if params.modules.push(module).is_some() {
+            warn!("Too many boot modules, ignoring {:?}", module);
+        }
+    }
+
+     //-- enable flags needed for paging ------------------------------------
+     unsafe { enable_protection(); }
+
+     match unsafe { cpu::fpu::initialize() } {
+         Ok(()) => kinfoln!( dots: " . ", "FPU and SSE ENABLED ({})"
+                           , if cpu::fpu::uses_xsave() { "xsave" }
+                             else { "fxsave" })
+       , Err(why) => warn!("Could not initialize FPU: {}", why)
+     }
+
+     warn!("The no execute bit needs PAE, so all pages will be executable.");
+
+    // the kernel keeps using the parameters for as long as it runs, so
+    // they have to outlive `arch_init`'s stack frame.
+    static PARAMS: Once<InitParams> = Once::new();
+
+    kinfoln!(dots: " . ", "Transferring to `kernel_init()`.");
+    ::kernel_init(PARAMS.call_once(|| params));
+}
@@ -0,0 +1,25 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Multiprocessor support.
+//!
+//! Starting the application processors needs the local APIC, which we only
+//! use on `x86_64`, so on `x86` only the bootstrap processor runs.
+use paging::arch::ActivePageTable;
+use sos_alloc::FrameAllocator;
+use params::InitParams;
+
+/// Bring up the application processors.
+///
+/// This isn't supported on `x86` yet, so it always fails.
+pub fn initialize<A>( _params: &InitParams, _page_table: &mut ActivePageTable
+                    , _alloc: &mut A)
+                    -> Result<usize, &'static str>
+where A: FrameAllocator {
+    Err("SMP isn't supported on x86 yet")
+}
@@ -0,0 +1,19 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Entry points for system calls.
+//!
+//! Nothing runs in user mode on `x86` yet, so there's nothing to make
+//! system calls.
+
+/// Enable system calls on the current CPU.
+///
+/// This isn't supported on `x86` yet, so it always fails.
+pub unsafe fn initialize() -> Result<(), &'static str> {
+    Err("System calls aren't supported on x86 yet")
+}
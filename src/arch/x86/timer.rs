@@ -0,0 +1,24 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! High-resolution timer events.
+//!
+//! We don't use the local APIC on `x86`, so the PIT's periodic tick is the
+//! only timer, and kernel timers fire on the next tick after they expire.
+use paging::arch::ActivePageTable;
+use sos_alloc::FrameAllocator;
+
+/// Initialize the high-resolution timer.
+///
+/// There's nothing to do here, since the PIT is programmed when interrupts
+/// are initialized.
+pub fn initialize<A>(_page_table: &mut ActivePageTable, _alloc: &mut A)
+                    -> Result<(), &'static str>
+where A: FrameAllocator {
+    Ok(())
+}
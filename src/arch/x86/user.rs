@@ -0,0 +1,27 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Running threads in user mode.
+//!
+//! We don't have a TSS or user segments on `x86`, so threads can't run in
+//! user mode yet.
+use memory::VAddr;
+
+/// Drop to user mode.
+///
+/// This isn't supported on `x86` yet, so it always panics.
+pub unsafe fn enter(_entry: VAddr, _stack: VAddr) -> ! {
+    panic!("User mode isn't supported on x86 yet")
+}
+
+/// Set the stack the current CPU switches to when an interrupt or
+/// exception arrives in user mode.
+///
+/// Nothing runs in user mode on `x86`, so this does nothing.
+#[inline]
+pub unsafe fn set_kernel_stack(_top: VAddr) { }
@@ -9,18 +9,33 @@
 
 use cpu::interrupts::pics;
 use cpu::interrupts::idt::{Gate, Idt};
+use cpu::{apic, fpu, pit};
+use cpu::timer::timestamp;
 
-use cpu::context::InterruptFrame;
+use cpu::context::{InterruptFrame, Registers};
+use cpu::user::KernelGs;
 use cpu::dtable::DTable;
+use cpu::PrivilegeLevel;
+use paging::arch::tlb;
+
+use super::syscall;
 
 
 //==--------------------------------------------------------------------------==
 // Top-level interrupt handling
+//
+// Any interrupt may arrive while a thread is in user mode, so every handler
+// takes a `KernelGs` guard before doing anything else, and the exception
+// entry stubs `swapgs` themselves.
+
+/// Frequency of the kernel's periodic timer tick, in Hz.
+pub const TIMER_FREQUENCY: u32 = pit::DEFAULT_FREQUENCY;
 
 /// Initialize interrupt handling.
 ///
 /// This function initializes the PICs, populates the IDT with interrupt
-/// handlers, loads the IDT pointer, and enables interrupts.
+/// handlers, loads the IDT pointer, programs the system timer, and enables
+/// interrupts.
 ///
 /// This is called from the kernel during the init process.
 // TODO: make the result returned by this meaningful?
@@ -28,6 +43,19 @@ use cpu::dtable::DTable;
 pub unsafe fn initialize() -> Result<(), ()>{
 
     pics::initialize();
+
+    // calibrate the TSC against the PIT before the tick starts, since the
+    // calibration busy-waits on PIT channel 2.
+    match timestamp::calibrate() {
+        Ok(hz) => kinfoln!( dots: " . . "
+                          , "Timestamp counter runs at {} MHz", hz / 1_000_000)
+      , Err(why) => warn!("Could not calibrate timestamp counter: {}", why)
+    }
+    kinfoln!(dots: " . . ", "Kernel clock is {:?}", ::time::initialize());
+    pit::initialize(TIMER_FREQUENCY);
+    kinfoln!( dots: " . . ", "System timer ticking at {} Hz"
+            , pit::frequency().unwrap_or(0));
+
    // TODO: consider loading double-fault handler before anything else in case
    //       a double fault occurs during init?
     IDT.load();         // Load the IDT pointer
@@ -40,103 +68,197 @@ pub unsafe fn initialize() -> Result<(), ()>{
 
 }
 
-macro_rules! exception_inner {
-    ($title:expr, $kind:expr, $source:expr, $f:expr) => {
-        use vga::{CONSOLE, Color};
-        use core::fmt::Write;
-        let _ = write!( CONSOLE.lock()
-                               .set_colors(Color::White, Color::Blue)
-                      , "EVERYTHING IS FINE: {}{} at {:p}\n\
-                         Source: {}.\nThis is fine.\n\n\
-                         {:?}"
-                         , $title, $kind
-                         , (*$f).rip
-                         , $source
-                         , *$f);
+/// Load the IDT on the current CPU.
+///
+/// Every CPU shares the same IDT, but each has to load it for itself.
+#[inline]
+pub unsafe fn load() {
+    IDT.load();
+}
+
+/// The Rust half of an exception handler, called by its entry stub.
+type FaultHandler = extern "C" fn(&mut Registers, usize, &mut InterruptFrame);
+
+/// Try to recover from an exception the kernel caused deliberately.
+///
+/// Returns true if the exception was handled, in which case the handler
+/// should return rather than reporting it.
+fn fixup(vector: u8, frame: &mut InterruptFrame) -> bool {
+    use cpu::msr;
+    match vector {
+        general_protection_fault::VECTOR => msr::fixup_probe(frame)
+      , _ => false
+    }
+}
+
+/// Returns true if an exception was caused by user code, in which case the
+/// thread running it should be killed, rather than the kernel.
+fn caused_by_user(vector: u8, frame: &InterruptFrame) -> bool {
+    match vector {
+        nmi::VECTOR | double_fault::VECTOR | machine_check::VECTOR => false
+      , _ => frame.is_user()
+    }
+}
+
+/// Kill the thread whose user code caused an exception.
+///
+/// If it's the last thread in its process, the process exits with the
+/// status [`process::KILLED`](../../process/constant.KILLED.html).
+fn kill_user(title: &'static str, error_code: usize, frame: &InterruptFrame)
+            -> ! {
+    if let Some(id) = ::thread::current() {
+        warn!( "Thread {} killed by {} at {:p} (error code {:#x})"
+             , id, title, frame.instruction_pointer(), error_code);
+    }
+    // the exception came from user mode, so nothing in the kernel is
+    // holding a lock, and freeing the address space needs interrupts
+    unsafe { Idt::enable_interrupts(); }
+    ::process::exit(::process::KILLED)
+}
+
+/// Generates the naked entry stub for an exception handler.
+///
+/// The stub pushes a dummy error code for exceptions that don't push one (so
+/// that every handler sees the same stack layout), saves the general-purpose
+/// registers, and calls the handler with pointers to the saved registers and
+/// the interrupt frame. If the handler returns, the stub restores the
+/// registers and returns from the interrupt.
+///
+/// If the exception arrived in user mode, the stub swaps in the kernel's GS
+/// base on the way in, and the user's on the way out.
+macro_rules! exception_entry {
+    (no_code: $handler:ident) => {
+        /// Entry stub for this exception.
+        ///
+        /// THIS FUNCTION IS NAKED. DO NOT CALL IT NORMALLY.
+        #[naked]
+        pub unsafe extern "C" fn entry() {
+            asm!("push 0" :::: "intel", "volatile");
+            exception_entry!(@body $handler);
+        }
+    };
+    (code: $handler:ident) => {
+        /// Entry stub for this exception.
+        ///
+        /// THIS FUNCTION IS NAKED. DO NOT CALL IT NORMALLY.
+        #[naked]
+        pub unsafe extern "C" fn entry() {
+            exception_entry!(@body $handler);
+        }
     };
-    ($title:expr, $kind:expr, $source:expr, $f:expr, $e:expr) => {
-        use vga::{CONSOLE, Color};
-        use core::fmt::Write;
-        let _ = write!( CONSOLE.lock()
-                               .set_colors(Color::White, Color::Blue)
-                      , "EVERYTHING IS FINE: {}{} at {:p}\n\
-                         Source: {}.\n
-                         Error code: {:x}\nThis is fine.\n\n\
-                         {:?}"
-                         , $title, $kind
-                         , (*$f).rip
-                         , $source
-                         , $e
-                         , *$f);
+    (@body $handler:ident) => {
+        // the code segment is above the error code and the return address
+        asm!( "testb $$3, 16(%rsp)
+               jz 1f
+               swapgs
+               1:"
+            :::: "volatile");
+        Registers::push();
+        // 15 saved registers, then the error code, then the interrupt frame.
+        // the CPU aligns the stack before pushing the frame, so we have to
+        // realign it by one word before calling the handler.
+        asm!( "mov rdi, rsp
+               mov rsi, [rsp + 15 * 8]
+               lea rdx, [rsp + 16 * 8]
+               sub rsp, 8
+               call $0
+               add rsp, 8"
+            :: "i"($handler as FaultHandler)
+            : "rdi", "rsi", "rdx", "memory"
+            : "intel", "volatile");
+        Registers::pop();
+        // pop the error code and return
+        asm!( "testb $$3, 16(%rsp)
+               jz 2f
+               swapgs
+               2:
+               addq $$8, %rsp
+               iretq"
+            :::: "volatile");
     };
 }
 
 macro_rules! exceptions {
-    ( fault: $name:ident, $title:expr, $source:expr, $($tail:tt)* ) => {
+    ( $kind:ident ($code:ident): $name:ident, $vector:expr, $title:expr
+    , $source:expr, $($tail:tt)* ) => {
         #[doc=$title]
-        extern "x86-interrupt" fn $name(frame: &InterruptFrame) {
-            exception_inner! ($title, "Fault", $source, frame);
-            loop {}
+        pub mod $name {
+            use cpu::context::{InterruptFrame, Registers};
+            use cpu::interrupts::fault::Fault;
+            use super::{FaultHandler, caused_by_user, fixup, kill_user};
+
+            /// Vector number of this exception.
+            pub const VECTOR: u8 = $vector;
+
+            #[doc=$title]
+            extern "C" fn handler( registers: &mut Registers
+                                 , error_code: usize
+                                 , frame: &mut InterruptFrame) {
+                if fixup(VECTOR, frame) { return }
+                if caused_by_user(VECTOR, frame) {
+                    kill_user($title, error_code, frame)
+                }
+                Fault::new( VECTOR, $title, exceptions!(@kind $kind)
+                          , $source, frame, registers, error_code)
+                    .report();
+                exceptions!(@after $kind);
+            }
+
+            exception_entry!($code: handler);
         }
 
-        exceptions! {  $($tail)* }
+        exceptions! { $($tail)* }
     };
-     ( fault (code): $name:ident, $title:expr, $source:expr, $($tail:tt)* ) => {
-        #[doc=$title]
-        extern "x86-interrupt" fn $name( frame: &InterruptFrame
-                                       , error_code: usize) {
-           exception_inner! ($title, "Fault", $source, frame, error_code);
-           loop {}
-       }
-       exceptions! { $($tail)* }
-   };
-     ( trap: $name:ident, $title:expr, $source:expr, $($tail:tt)* ) => {
-         #[doc=$title]
-         extern "x86-interrupt" fn $name(frame: &InterruptFrame) {
-             exception_inner! ($title, "Trap", $source, frame);
-         }
-
-         exceptions! { $($tail)* }
-     };
-      ( ) => {};
-
+    (@kind fault) => { "Fault" };
+    (@kind trap) => { "Trap" };
+    (@kind abort) => { "Abort" };
+    (@after trap) => { };
+    (@after $kind:ident) => { loop { } };
+    ( ) => {};
 }
+
 exceptions! {
-    fault: divide_by_zero, "Divide by Zero Error",
+    fault (no_code): divide_by_zero, 0, "Divide by Zero Error",
            "DIV or IDIV instruction",
-    fault: nmi, "Non-Maskable Interrupt",
+    fault (no_code): nmi, 2, "Non-Maskable Interrupt",
           "Non-maskable external interrupt",
-    trap: overflow, "Overflow", "INTO instruction",
-    fault: bound_exceeded, "BOUND range exceeded",
+    trap (no_code): overflow, 4, "Overflow", "INTO instruction",
+    fault (no_code): bound_exceeded, 5, "BOUND range exceeded",
           "BOUND instruction",
-    fault: undefined_opcode, "Undefined Opcode",
+    fault (no_code): undefined_opcode, 6, "Undefined Opcode",
            "UD2 instruction or reserved opcode",
-    fault: device_not_available, "Device Not Available"
-         , "Floating-point or WAIT/FWAIT instruction \
-            (no math coprocessor)",
-    fault (code): double_fault, "Double Fault"
+    abort (code): double_fault, 8, "Double Fault"
          , "Any instruction that can generate an exception, a NMI, or \
             an INTR",
-    fault (code): invalid_tss, "Invalid TSS"
+    fault (code): invalid_tss, 10, "Invalid TSS"
          , "Task switch or TSS access",
-    fault (code): segment_not_present, "Segment Not Present"
+    fault (code): segment_not_present, 11, "Segment Not Present"
          , "Loading segment registers or accessing \
             system segments",
-    fault (code): general_protection_fault, "General Protection Fault"
-         , "Any memory reference or other protection checks",
-    fault (code): stack_segment_fault, "Stack Segment Fault"
+    fault (code): stack_segment_fault, 12, "Stack Segment Fault"
          , "Stack operations and SS register loads",
-    fault: floating_point_error
+    fault (code): general_protection_fault, 13, "General Protection Fault"
+         , "Any memory reference or other protection checks",
+    fault (code): page_fault, 14, "Page Fault"
+         , "Any memory reference",
+    fault (no_code): floating_point_error, 16
          , "x87 FPU Floating-Point Error (Math Fault)"
          , "x87 FPU floating-point or WAIT/FWAIT instruction",
-    fault: machine_check, "Machine Check"
-         , "Model-dependent (probably hardware!)",
-    fault (code): alignment_check, "Alignment Check"
+    fault (code): alignment_check, 17, "Alignment Check"
          , "Any data reference in memory",
-    fault: simd_fp_exception, "SIMD Floating-Point Exception"
+    abort (no_code): machine_check, 18, "Machine Check"
+         , "Model-dependent (probably hardware!)",
+    fault (no_code): simd_fp_exception, 19, "SIMD Floating-Point Exception"
          , "SSE/SSE2/SSE3 floating-point instructions",
 }
 
+/// Point the IDT gate for an exception at that exception's entry stub.
+macro_rules! set_exception {
+    ($idt:expr, $($name:ident),+) => { $(
+        $idt[$name::VECTOR as usize] = Gate::from($name::entry as *const u8);
+    )+ }
+}
+
 lazy_static! {
     static ref IDT: Idt = {
         let mut idt = Idt::new();
@@ -145,32 +267,34 @@ lazy_static! {
         // TODO: log each handler as it's added to the IDT? that way we can
         //       trace faults occurring during IDT population (if any)
         //          - eliza, 5/22/2017
-        idt.divide_by_zero = Gate::from(divide_by_zero as InterruptHandler);
-        idt.nmi = Gate::from(nmi as InterruptHandler);
-        idt.overflow = Gate::from(overflow as InterruptHandler);
+        set_exception!( idt
+                      , divide_by_zero, nmi, overflow, bound_exceeded
+                      , undefined_opcode, double_fault
+                      , invalid_tss, segment_not_present, stack_segment_fault
+                      , general_protection_fault, page_fault
+                      , floating_point_error, alignment_check, machine_check
+                      , simd_fp_exception );
         idt.overflow.set_trap();
-        idt.bound_exceeded = Gate::from(bound_exceeded as InterruptHandler);
-        idt.undefined_opcode = Gate::from(undefined_opcode as InterruptHandler);
-        idt.device_not_available = Gate::from(device_not_available as InterruptHandler);
-        idt.double_fault = Gate::from(double_fault as ErrorCodeHandler);
-        idt.invalid_tss = Gate::from(invalid_tss as ErrorCodeHandler);
-        idt.segment_not_present = Gate::from(segment_not_present as ErrorCodeHandler);
-        idt.stack_segment_fault = Gate::from(stack_segment_fault as ErrorCodeHandler);
-        idt.general_protection_fault = Gate::from(general_protection_fault as ErrorCodeHandler);
-        idt.page_fault = Gate::from(page_fault as ErrorCodeHandler);
-
-        idt.floating_point_error = Gate::from(floating_point_error as InterruptHandler);
-        idt.alignment_check = Gate::from(alignment_check as ErrorCodeHandler);
-        idt.machine_check = Gate::from(machine_check as InterruptHandler);
-        idt.simd_fp_exception = Gate::from(simd_fp_exception as InterruptHandler);
 
         idt.breakpoint = Gate::from(breakpoint as InterruptHandler);
-        idt.page_fault = Gate::from(page_fault as ErrorCodeHandler);
+        idt.device_not_available
+            = Gate::from(device_not_available as InterruptHandler);
 
-        idt.interrupts[0x20 - 32] = Gate::from(timer as InterruptHandler);
+        idt.interrupts[0x20 - 32] = Gate::from(timer_tick as InterruptHandler);
         idt.interrupts[0x21 - 32] = Gate::from(keyboard as InterruptHandler);
+        idt.interrupts[apic::timer::VECTOR as usize - 32]
+            = Gate::from(apic_timer as InterruptHandler);
+        idt.interrupts[apic::SPURIOUS_VECTOR as usize - 32]
+            = Gate::from(apic_spurious as InterruptHandler);
+        idt.interrupts[tlb::SHOOTDOWN_VECTOR as usize - 32]
+            = Gate::from(tlb_shootdown as InterruptHandler);
         idt.interrupts[0xff - 32] = Gate::from(test as InterruptHandler);
 
+        // user code may use `int 0x80` to make system calls
+        let syscall_gate = &mut idt.interrupts[syscall::VECTOR as usize - 32];
+        *syscall_gate = Gate::from(syscall::interrupt_entry as *const u8);
+        syscall_gate.set_dpl(PrivilegeLevel::UserMode);
+
         kinfoln!( dots: " . . ", target: "Adding interrupt handlers to IDT"
                 , "[ OKAY ]");
         idt
@@ -179,8 +303,9 @@ lazy_static! {
 
 
 #[no_mangle] #[inline(never)]
-pub extern "x86-interrupt" fn keyboard(_frame: &InterruptFrame) {
+pub extern "x86-interrupt" fn keyboard(frame: &InterruptFrame) {
     use io::keyboard;
+    let _gs = KernelGs::enter(frame);
 
     // println!("keyboard happened");
     if let Some(input) = keyboard::read_char() {
@@ -196,8 +321,65 @@ pub extern "x86-interrupt" fn keyboard(_frame: &InterruptFrame) {
    }
 }
 
+/// Handler for the system timer tick.
+///
+/// This advances the tick count, fires any expired kernel timers, and
+/// preempts the running thread if its time slice is up.
+#[no_mangle] #[inline(never)]
+pub extern "x86-interrupt" fn timer_tick(frame: &InterruptFrame) {
+    let _gs = KernelGs::enter(frame);
+    pit::tick();
+    ::time::tick();
+    // send the PICs the end interrupt signal
+    unsafe {
+        pics::end_pic_interrupt(pics::IRQ::Timer as u8);
+    }
+    // this may switch threads, so the interrupt must be over
+    ::thread::tick();
+}
+
+/// Handler for local APIC timer events.
+///
+/// The application processors' time slices are driven by their APIC timers,
+/// so this may preempt the running thread.
+#[no_mangle] #[inline(never)]
+pub extern "x86-interrupt" fn apic_timer(frame: &InterruptFrame) {
+    let _gs = KernelGs::enter(frame);
+    apic::timer::handle_interrupt();
+    ::thread::tick();
+}
+
+/// Handler for spurious interrupts from the local APIC.
+///
+/// Spurious interrupts must *not* be acknowledged with an EOI.
+#[no_mangle] #[inline(never)]
+pub extern "x86-interrupt" fn apic_spurious(frame: &InterruptFrame) {
+    let _gs = KernelGs::enter(frame);
+    trace!("spurious APIC interrupt");
+}
+
+/// Handler for TLB shootdown IPIs from other CPUs.
+#[no_mangle] #[inline(never)]
+pub extern "x86-interrupt" fn tlb_shootdown(frame: &InterruptFrame) {
+    let _gs = KernelGs::enter(frame);
+    tlb::handle_shootdown();
+}
+
+/// Handler for the Device Not Available (`#NM`) exception.
+///
+/// This is raised when a task uses the FPU while `TS` is set, and loads that
+/// task's FPU state.
+#[no_mangle] #[inline(never)]
+pub extern "x86-interrupt" fn device_not_available(frame: &InterruptFrame) {
+    let _gs = KernelGs::enter(frame);
+    if let Err(why) = unsafe { fpu::handle_device_not_available() } {
+        panic!("Device Not Available: {}\n{:?}", why, frame);
+    }
+}
+
 #[no_mangle] #[inline(never)]
 pub extern "x86-interrupt" fn breakpoint(frame: &InterruptFrame) {
+    let _gs = KernelGs::enter(frame);
     println!("Breakpoint! Frame: {:#?}", frame);
    // send the PICs the end interrupt signal
    unsafe {
@@ -207,7 +389,8 @@ pub extern "x86-interrupt" fn breakpoint(frame: &InterruptFrame) {
 
 /// Empty dummy handler for undefined interrupts.
 #[no_mangle] #[inline(never)]
-pub extern "x86-interrupt" fn empty_handler(_frame: &InterruptFrame) {
+pub extern "x86-interrupt" fn empty_handler(frame: &InterruptFrame) {
+    let _gs = KernelGs::enter(frame);
     // TODO: it would be nice to know *which vector* the dummy interrupt
     //      fired on, for debugging purposes...
     //          - eliza, 05/25/2017
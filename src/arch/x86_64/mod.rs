@@ -8,14 +8,24 @@
 //
 //! `x86_64` architecture-specific implementation.
 // pub mod cpu;
-pub mod drivers;
+#[path = "../x86_all/drivers/mod.rs"] pub mod drivers;
 pub mod interrupts;
+pub mod smp;
+pub mod syscall;
+pub mod timer;
+pub mod user;
 
+#[path = "../x86_all/acpi.rs"] pub mod acpi;
 #[path = "../x86_all/bda.rs"] pub mod bda;
 #[path = "../x86_all/multiboot2.rs"] pub mod multiboot2;
 
 pub const ARCH_BITS: u8 = 64;
 
+/// Start of the virtual address range which kernel thread stacks are mapped
+/// into. This is the second-to-last PML4 entry, just below the recursive
+/// mapping.
+pub const THREAD_STACKS: usize = 0xffff_ff00_0000_0000;
+
 extern {
     // TODO: It would be really nice if there was a less ugly way of doing
     // this... (read: after the Revolution when we add memory regions to the
@@ -41,6 +51,9 @@ use memory::PAddr;
 ///
 /// I have no idea why this works, but it does.
 ///
+/// This also zeroes the frame pointer, so that stack backtraces terminate at
+/// `arch_init`.
+///
 /// [`arch_init`]: fn.arch_init
 #[naked]
 #[no_mangle]
@@ -52,11 +65,42 @@ pub unsafe extern "C" fn long_mode_init() {
           mov es, ax
           mov fs, ax
           mov gs, ax
+          xor rbp, rbp
           call arch_init"
         :::: "intel");
 
 }
 
+/// Enable the CPU's memory protection features, if they're supported.
+///
+/// # Safety
+/// + This must be called before any user pages are mapped, since
+///   afterwards the kernel can only access them through
+///   [`cpu::smap`](../../../cpu/smap/index.html).
+unsafe fn enable_protection() {
+    use cpu::control_regs::{cr0, cr4};
+    use cpu::{cpuid, smap};
+
+    cr0::enable_write_protect(true);
+    kinfoln!(dots: " . ", "Page write protect ENABLED");
+
+    if cpuid::has(cpuid::PGE) {
+        cr4::enable_global_pages(true);
+        kinfoln!(dots: " . ", "Global pages ENABLED");
+    }
+    if cpuid::has(cpuid::SMEP) {
+        cr4::enable_smep(true);
+        kinfoln!(dots: " . ", "Supervisor mode execution prevention ENABLED");
+    }
+    if smap::enable().is_ok() {
+        kinfoln!(dots: " . ", "Supervisor mode access prevention ENABLED");
+    }
+    if cpuid::has(cpuid::UMIP) {
+        cr4::enable_umip(true);
+        kinfoln!(dots: " . ", "User mode instruction prevention ENABLED");
+    }
+}
+
 /// Entry point for architecture-specific kernel init
 ///
 /// This expects to be passed the address of a valid
@@ -66,8 +110,9 @@ pub unsafe extern "C" fn long_mode_init() {
 /// bad problem and not go to space today.
 #[no_mangle]
 pub extern "C" fn arch_init(multiboot_addr: PAddr) {
-    use cpu::{control_regs, msr};
-    use params::{InitParams, mem};
+    use cpu::{cpuid, msr};
+    use params::{InitParams, Module, mem};
+    use spin::Once;
 
     kinfoln!(dots: " . ", "Beginning `arch_init()` for x86_64");
 
@@ -76,6 +121,16 @@ pub extern "C" fn arch_init(multiboot_addr: PAddr) {
         .expect("Could not initialize logger!");
 
 
+    // -- Identify the CPU ----------------------------------------------------
+    let cpu = cpuid::info();
+    kinfoln!(dots: " . ", "Detected CPU: {}", cpu);
+    kinfoln!( dots: " . . ", "features: [ {:?} ]", cpu.features);
+    kinfoln!( dots: " . . ", "APIC ID {}, {} logical processors per package"
+            , cpu.topology.apic_id, cpu.topology.max_logical);
+    for cache in cpu.caches() {
+        kinfoln!(dots: " . . ", "{}", cache);
+    }
+
     // -- Unpack multiboot tag ------------------------------------------------
     kinfoln!( dots: " . "
             , "trying to unpack multiboot info at {:?}"
@@ -92,6 +147,12 @@ pub extern "C" fn arch_init(multiboot_addr: PAddr) {
         = boot_info.elf_sections()
                    .expect("ELF sections tag required!");
 
+    // Load the kernel's symbol table, if the bootloader gave it to us
+    match elf_sections_tag.symbol_table() {
+        Some(table) => ::symbols::initialize(table)
+      , None => warn!("No kernel symbol table; backtraces won't have names.")
+    }
+
     kinfoln!(dots: " . ", "Detecting kernel ELF sections:");
 
     // Extract kernel ELF sections from  multiboot info
@@ -130,6 +191,8 @@ pub extern "C" fn arch_init(multiboot_addr: PAddr) {
                             , kernel_top: kernel_end
                             , multiboot_start: Some(multiboot_addr)
                             , multiboot_end: Some(multiboot_end)
+                            , acpi_rsdp: boot_info.rsdp()
+                            , command_line: boot_info.command_line()
                             , heap_base: unsafe { PAddr::from(HEAP_BASE) }
                             , heap_top: unsafe { PAddr::from(HEAP_TOP) }
                             , stack_base: unsafe { PAddr::from(STACK_BASE) }
@@ -149,19 +212,54 @@ pub extern "C" fn arch_init(multiboot_addr: PAddr) {
         if a.is_usable == true { params.mem_map.push(a); }
     }
 
+    // Extract the modules the bootloader loaded along with the kernel
+    for tag in boot_info.modules() {
+        let module: Module = tag.into();
+        kinfoln!( dots: " . ", "Found module {:?} from {:#x} to {:#x}."
+                , module.command_line.unwrap_or(""), module.start, module.end);
+        if params.modules.push(module).is_some() {
+            warn!("Too many boot modules, ignoring {:?}", module);
+        }
+    }
+
      //-- enable flags needed for paging ------------------------------------
-     unsafe {
-        //  control_regs::cr0::enable_write_protect(true);
-        //  kinfoln!(dots: " . ", "Page write protect ENABED" );
-
-        let efer = msr::read(msr::IA32_EFER);
-        trace!("EFER = {:#x}", efer);
-        msr::write(msr::IA32_EFER, efer | (1 << 11));
-        let efer = msr::read(msr::IA32_EFER);
-        trace!("EFER = {:#x}", efer);
-        kinfoln!(dots: " . ", "Page no execute bit ENABLED");
+     unsafe { enable_protection(); }
+
+     match unsafe { ::paging::arch::pat::initialize() } {
+         Ok(()) => kinfoln!(dots: " . ", "Page attribute table ENABLED")
+       , Err(why) => warn!("Could not program the PAT: {}", why)
+     }
+
+     if cpuid::has(cpuid::PCID) {
+         match unsafe { ::paging::arch::pcid::initialize() } {
+             Ok(()) => kinfoln!(dots: " . ", "Process-context IDs ENABLED")
+           , Err(why) => warn!("Could not enable PCIDs: {}", why)
+         }
+     }
+
+     match unsafe { cpu::fpu::initialize() } {
+         Ok(()) => kinfoln!( dots: " . ", "FPU and SSE ENABLED ({})"
+                           , if cpu::fpu::uses_xsave() { "xsave" }
+                             else { "fxsave" })
+       , Err(why) => warn!("Could not initialize FPU: {}", why)
      }
 
+     if cpuid::has(cpuid::NX) {
+         unsafe {
+            trace!("EFER = {:?}", msr::efer::read());
+            msr::enable_nxe();
+            trace!("EFER = {:?}", msr::efer::read());
+         }
+         kinfoln!(dots: " . ", "Page no execute bit ENABLED");
+     } else {
+         warn!("CPU does not support the no execute bit; all pages will be \
+                executable.");
+     }
+
+    // the kernel keeps using the parameters for as long as it runs, so
+    // they have to outlive `arch_init`'s stack frame.
+    static PARAMS: Once<InitParams> = Once::new();
+
     kinfoln!(dots: " . ", "Transferring to `kernel_init()`.");
-    ::kernel_init(&params);
+    ::kernel_init(PARAMS.call_once(|| params));
 }
@@ -0,0 +1,325 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Bringing up application processors.
+//!
+//! The bootloader only starts the bootstrap processor (BSP). The others, the
+//! application processors (APs), wait for the BSP to send them an INIT IPI
+//! followed by startup IPIs (SIPIs), after which they start executing in
+//! real mode at an address given by the SIPI.
+//!
+//! Since that address has to be in the first megabyte of memory, we copy a
+//! small trampoline to [`TRAMPOLINE`](constant.TRAMPOLINE.html). The
+//! trampoline switches to protected mode and then long mode, using the same
+//! control register values and page tables as the BSP, and then calls
+//! [`ap_entry`](fn.ap_entry.html) on the AP's own stack. APs are started one
+//! at a time, since they share the trampoline's parameter block.
+//!
+//! To try this out in QEMU, run it with more than one CPU (e.g.
+//! `qemu-system-x86_64 -smp 4`; `make run` does this by default).
+use core::ptr;
+
+use cpu::{apic, fpu, gdt, percpu, smp};
+use cpu::control_regs::{cr0, cr3, cr4};
+use cpu::msr;
+use memory::{PAddr, PhysicalPage};
+use paging::Mapper;
+use paging::arch::{pcid, ActivePageTable};
+use paging::arch::table::WRITABLE;
+use sos_alloc::FrameAllocator;
+use sos_alloc::frame::mem_map;
+use params::InitParams;
+
+use super::acpi;
+use super::{interrupts, syscall};
+
+/// Physical address the trampoline is copied to.
+///
+/// This must be page-aligned and in the first megabyte of memory, and below
+/// [`mem_map::RESERVED_BELOW`], so that the frame allocator never hands it
+/// out.
+///
+/// [`mem_map::RESERVED_BELOW`]:
+/// ../../../sos_alloc/frame/mem_map/constant.RESERVED_BELOW.html
+pub const TRAMPOLINE: u64 = 0x8000;
+
+/// Size of each AP's kernel stack, in bytes.
+pub const STACK_SIZE: usize = 16 * 1024;
+
+/// How long to wait after an INIT IPI before sending a SIPI, in nanoseconds.
+const INIT_DELAY: u64 = 10_000_000;
+/// How long to wait after a SIPI for the AP to start, in nanoseconds.
+const SIPI_DELAY: u64 = 200_000;
+/// How long to wait for an AP to come online, in nanoseconds.
+const ONLINE_TIMEOUT: u64 = 100_000_000;
+
+/// The BSP's `%cr3`, including its PCID, for APs to switch to once they've
+/// enabled PCIDs.
+static mut KERNEL_CR3: u64 = 0;
+
+/// Kernel stacks for the application processors, indexed by CPU number.
+static mut STACKS: [[u8; STACK_SIZE]; smp::MAX_CPUS]
+    = [[0; STACK_SIZE]; smp::MAX_CPUS];
+
+/// Values the trampoline loads before jumping to `ap_entry`.
+///
+/// This must match the layout of `ap_trampoline_params` in the trampoline.
+#[repr(C)]
+struct Params { cr0: u64
+              , cr3: u64
+              , cr4: u64
+              , efer: u64
+              , stack_top: u64
+              , entry: u64
+              , cpu: u64
+              }
+
+extern {
+    static ap_trampoline_start: u8;
+    static ap_trampoline_params: u8;
+    static ap_trampoline_end: u8;
+}
+
+global_asm!(r#"
+    .pushsection .text.ap_trampoline, "ax"
+    .global ap_trampoline_start
+    .global ap_trampoline_params
+    .global ap_trampoline_end
+
+    .set ap_base, ap_trampoline_start - 0x8000
+
+    .code16
+ap_trampoline_start:
+    cli
+    cld
+    xorw    %ax, %ax
+    movw    %ax, %ds
+    movw    %ax, %es
+    movw    %ax, %ss
+    lgdtl   (ap_gdt_ptr - ap_base)
+    movl    %cr0, %eax
+    orl     $1, %eax
+    movl    %eax, %cr0
+    ljmpl   $0x08, $(ap_protected - ap_base)
+
+    .code32
+ap_protected:
+    movw    $0x10, %ax
+    movw    %ax, %ds
+    movw    %ax, %es
+    movw    %ax, %ss
+    movl    (ap_trampoline_params + 16 - ap_base), %eax
+    movl    %eax, %cr4
+    movl    (ap_trampoline_params + 8 - ap_base), %eax
+    movl    %eax, %cr3
+    movl    $0xc0000080, %ecx
+    movl    (ap_trampoline_params + 24 - ap_base), %eax
+    xorl    %edx, %edx
+    wrmsr
+    # this enables paging, which puts us in compatibility mode
+    movl    (ap_trampoline_params - ap_base), %eax
+    movl    %eax, %cr0
+    ljmpl   $0x18, $(ap_long - ap_base)
+
+    .code64
+ap_long:
+    xorw    %ax, %ax
+    movw    %ax, %ds
+    movw    %ax, %es
+    movw    %ax, %ss
+    movq    (ap_trampoline_params + 32 - ap_base), %rsp
+    movq    (ap_trampoline_params + 48 - ap_base), %rdi
+    movq    (ap_trampoline_params + 40 - ap_base), %rax
+    xorq    %rbp, %rbp
+    callq   *%rax
+1:  hlt
+    jmp     1b
+
+    .align 8
+ap_gdt:
+    .quad   0
+    .quad   0x00cf9a000000ffff
+    .quad   0x00cf92000000ffff
+    .quad   0x00af9a000000ffff
+ap_gdt_ptr:
+    .word   ap_gdt_ptr - ap_gdt - 1
+    .long   ap_gdt - ap_base
+
+    .align 8
+ap_trampoline_params:
+    .fill   7, 8, 0
+ap_trampoline_end:
+    .popsection
+"#);
+
+/// Returns a pointer to the trampoline's parameter block, in the copy of the
+/// trampoline at `TRAMPOLINE`.
+#[inline]
+unsafe fn trampoline_params() -> *mut Params {
+    let offset = &ap_trampoline_params as *const u8 as usize
+               - &ap_trampoline_start as *const u8 as usize;
+    (TRAMPOLINE as usize + offset) as *mut Params
+}
+
+/// Copy the trampoline to low memory.
+unsafe fn install_trampoline<A>( page_table: &mut ActivePageTable
+                               , alloc: &mut A)
+                               -> Result<(), &'static str>
+where A: FrameAllocator {
+    let start = &ap_trampoline_start as *const u8;
+    let len = &ap_trampoline_end as *const u8 as usize - start as usize;
+    if TRAMPOLINE + len as u64 > mem_map::RESERVED_BELOW {
+        return Err("The AP trampoline overlaps allocatable memory")
+    }
+    let frame = PhysicalPage::containing_addr(PAddr::from(TRAMPOLINE));
+    // the trampoline keeps executing from this page after it turns on
+    // paging, so it has to be identity-mapped and executable.
+    match page_table.identity_map(frame, WRITABLE, alloc) {
+        Ok(()) | Err(::paging::MapErr::AlreadyInUse { .. }) => {}
+      , Err(_) => return Err("Could not map the AP trampoline")
+    }
+    ptr::copy_nonoverlapping(start, TRAMPOLINE as *mut u8, len);
+    Ok(())
+}
+
+/// Start the AP with local APIC ID `apic_id` as CPU number `cpu`.
+///
+/// # Returns
+/// + `true` if the AP came online
+/// + `false` if it didn't come online in time.
+unsafe fn start(cpu: usize, apic_id: u8) -> bool {
+    let stack_top = (&STACKS[cpu] as *const _ as usize + STACK_SIZE) & !0xf;
+    let params = &mut *trampoline_params();
+    params.stack_top = stack_top as u64;
+    params.cpu = cpu as u64;
+
+    apic::send_init(apic_id);
+    ::time::busy_wait(INIT_DELAY);
+
+    // the spec says to send a second SIPI if the first one doesn't take
+    for _ in 0..2 {
+        apic::send_startup(apic_id, (TRAMPOLINE >> 12) as u8);
+        ::time::busy_wait(SIPI_DELAY);
+        if smp::is_online(cpu) { return true }
+    }
+
+    let deadline = ::time::Instant::now() + ONLINE_TIMEOUT;
+    while !deadline.has_passed() {
+        if smp::is_online(cpu) { return true }
+        asm!("pause" :::: "volatile");
+    }
+    false
+}
+
+/// Bring up the application processors listed in the MADT.
+///
+/// This also switches the BSP to its own GDT and TSS.
+///
+/// # Returns
+/// + the number of CPUs which are online.
+pub fn initialize<A>( params: &InitParams, page_table: &mut ActivePageTable
+                    , alloc: &mut A)
+                    -> Result<usize, &'static str>
+where A: FrameAllocator {
+    // the BSP needs its per-CPU block whether or not there are other CPUs
+    unsafe {
+        gdt::load(smp::BSP);
+        percpu::initialize(smp::BSP)?;
+    }
+    if !apic::is_initialized() {
+        return Err("Local APIC is not enabled")
+    }
+    unsafe { smp::initialize_bsp(); }
+
+    let rsdp = params.acpi_rsdp.ok_or("No ACPI RSDP")?;
+    let madt = acpi::find_madt(rsdp, page_table, alloc)?;
+
+    // the trampoline can't load a PCID, so it starts out untagged
+    let cr3 = unsafe { *cr3::current_pagetable_frame().base_addr() };
+    if cr3 > u32::max_value() as u64 {
+        return Err("Page tables are too high for the AP trampoline")
+    }
+
+    unsafe {
+        install_trampoline(page_table, alloc)?;
+        let params = &mut *trampoline_params();
+        params.cr0 = cr0::read().bits() as u64;
+        params.cr3 = cr3;
+        KERNEL_CR3 = *cr3::read();
+        // PCID can't be enabled outside of long mode
+        params.cr4 = (cr4::read() - cr4::PCIDE).bits() as u64;
+        params.efer = msr::efer::read().bits();
+        params.entry = ap_entry as usize as u64;
+    }
+
+    let bsp_id = apic::id();
+    for processor in madt.processors() {
+        if !processor.enabled || processor.apic_id == bsp_id as u32 {
+            continue
+        }
+        if processor.apic_id > 0xff {
+            warn!( "Can't start CPU with x2APIC ID {} in xAPIC mode"
+                 , processor.apic_id);
+            continue
+        }
+        let apic_id = processor.apic_id as u8;
+        let cpu = match unsafe { smp::assign(apic_id) } {
+            Some(cpu) => cpu
+          , None => {
+                warn!( "Only {} CPUs are supported; not starting the rest"
+                     , smp::MAX_CPUS);
+                break
+            }
+        };
+        if unsafe { start(cpu, apic_id) } {
+            kinfoln!( dots: " . . ", "Started CPU {} (APIC ID {})"
+                    , cpu, apic_id);
+        } else {
+            warn!("CPU {} (APIC ID {}) didn't start", cpu, apic_id);
+        }
+    }
+
+    Ok(smp::online_count())
+}
+
+/// The first Rust function an AP runs, called by the trampoline.
+extern "C" fn ap_entry(cpu: usize) -> ! {
+    unsafe {
+        gdt::load(cpu);
+        if let Err(why) = percpu::initialize(cpu) {
+            panic!("CPU {}: could not set up per-CPU data: {}", cpu, why);
+        }
+        if pcid::is_enabled() {
+            match pcid::initialize() {
+                Ok(()) => cr3::write(PAddr::from(KERNEL_CR3))
+              , Err(why) =>
+                    panic!("CPU {}: could not enable PCIDs: {}", cpu, why)
+            }
+        }
+        ::paging::arch::tlb::set_active();
+        interrupts::load();
+        if let Err(why) = syscall::initialize() {
+            warn!("CPU {}: could not enable system calls: {}", cpu, why);
+        }
+        if let Err(why) = fpu::enable() {
+            warn!("CPU {}: could not enable FPU: {}", cpu, why);
+        }
+        if ::paging::arch::pat::is_initialized() {
+            let _ = ::paging::arch::pat::initialize();
+        }
+        if let Err(why) = apic::initialize() {
+            panic!("CPU {}: could not enable local APIC: {}", cpu, why);
+        }
+    }
+    // the PIT only interrupts the BSP, so the scheduler needs our own timer
+    if let Err(why) = apic::timer::periodic(::thread::TICK) {
+        warn!("CPU {}: threads won't be preempted: {}", cpu, why);
+    }
+    smp::mark_online(cpu);
+    ::ap_main(cpu)
+}
@@ -0,0 +1,148 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Entry points for system calls.
+//!
+//! The fast path is `syscall`, whose entry stub is described in
+//! [`cpu::syscall`](../../../cpu/syscall/index.html). For debugging, system
+//! calls can also be made with `int 0x80`, which goes through an ordinary
+//! interrupt gate that user code is permitted to use. Both end up in
+//! [`::syscall::dispatch`](../../../syscall/fn.dispatch.html), with the same
+//! calling convention.
+use cpu::context::{InterruptFrame, Registers};
+use cpu::{flags, syscall};
+use cpu::syscall::Frame;
+
+/// Interrupt vector of the `int 0x80` entry point.
+pub const VECTOR: u8 = 0x80;
+
+/// Enable `syscall` on the current CPU.
+///
+/// # Safety
+/// + This should be called once on each CPU, once its per-CPU data has
+///   been set up.
+pub unsafe fn initialize() -> Result<(), &'static str> {
+    syscall::initialize(syscall_entry);
+    Ok(())
+}
+
+/// Decode a system call from the user's saved registers, make it, and
+/// return its result and error code in `%rax` and `%rdx`.
+///
+/// The entry stubs call this with interrupts disabled. They're enabled
+/// while the call runs if `interrupts` is true, and disabled again
+/// afterwards, since the stubs can't be interrupted on the way out.
+fn handle(registers: &mut Registers, interrupts: bool) {
+    let number = registers.rax as usize;
+    let args = [ registers.rdi as usize, registers.rsi as usize
+               , registers.rdx as usize, registers.r10 as usize
+               , registers.r8 as usize, registers.r9 as usize ];
+    if interrupts { unsafe { asm!("sti" :::: "volatile"); } }
+    let (value, error) = match ::syscall::dispatch(number, args) {
+        Ok(value) => (value, 0)
+      , Err(why) => (0, why.code())
+    };
+    unsafe { asm!("cli" :::: "volatile"); }
+    registers.rax = value as u64;
+    registers.rdx = error as u64;
+}
+
+/// The Rust half of the `syscall` entry stub.
+extern "C" fn handle_syscall(frame: &mut Frame) {
+    // user code always runs with interrupts enabled
+    handle(&mut frame.registers, true)
+}
+
+/// The Rust half of the `int 0x80` entry stub.
+extern "C" fn handle_interrupt( registers: &mut Registers
+                              , frame: &mut InterruptFrame) {
+    // `int 0x80` may also be used by the kernel, which might have had
+    // interrupts disabled
+    handle(registers, frame.rflags.contains(flags::IF))
+}
+
+/// Entry point for `syscall`.
+///
+/// THIS FUNCTION IS NAKED. DO NOT CALL IT NORMALLY.
+#[naked]
+unsafe extern "C" fn syscall_entry() {
+    // switch to the kernel stack, and push the user's stack pointer,
+    // `%rflags` and return address to make a `Frame`. the offsets are
+    // `percpu::USER_STACK` and `percpu::SYSCALL_STACK`.
+    asm!( "swapgs
+           movq %rsp, %gs:24
+           movq %gs:16, %rsp
+           pushq %gs:24
+           pushq %r11
+           pushq %rcx"
+        :::: "volatile");
+    Registers::push();
+    // the kernel stack is page-aligned, and the frame is 18 words long, so
+    // the stack is aligned for the call
+    asm!( "mov rdi, rsp
+           call $0"
+        :: "i"(handle_syscall as extern "C" fn(&mut Frame))
+        :  "rdi", "memory"
+        :  "intel", "volatile");
+    Registers::pop();
+    // on Intel CPUs, `sysret` to a non-canonical address faults in ring 0,
+    // on the user's stack, so unless the return address is below the last
+    // user page (which also rules out non-canonical addresses), return
+    // with `iretq` instead. `%rcx` is the user's return address anyway.
+    // the selectors are `gdt::USER_DATA` and `gdt::USER_CODE`.
+    asm!( "movabsq $$0x7ffffffff000, %rcx
+           cmpq %rcx, (%rsp)
+           jae 1f
+           popq %rcx
+           popq %r11
+           popq %rsp
+           swapgs
+           sysretq
+           1:
+           popq %rcx
+           popq %r11
+           pushq (%rsp)
+           movq $$0x1b, 8(%rsp)
+           pushq %r11
+           pushq $$0x23
+           pushq %rcx
+           swapgs
+           iretq"
+        :::: "volatile");
+}
+
+/// Entry point for `int 0x80`.
+///
+/// THIS FUNCTION IS NAKED. DO NOT CALL IT NORMALLY.
+#[naked]
+pub unsafe extern "C" fn interrupt_entry() {
+    // there's no error code, so the code segment is just above the return
+    // address
+    asm!( "testb $$3, 8(%rsp)
+           jz 1f
+           swapgs
+           1:"
+        :::: "volatile");
+    Registers::push();
+    // the CPU aligns the stack before pushing the 5-word interrupt frame,
+    // so after 15 registers it's aligned for the call
+    asm!( "mov rdi, rsp
+           lea rsi, [rsp + 15 * 8]
+           call $0"
+        :: "i"(handle_interrupt as extern "C" fn( &mut Registers
+                                                , &mut InterruptFrame))
+        :  "rdi", "rsi", "memory"
+        :  "intel", "volatile");
+    Registers::pop();
+    asm!( "testb $$3, 8(%rsp)
+           jz 2f
+           swapgs
+           2:
+           iretq"
+        :::: "volatile");
+}
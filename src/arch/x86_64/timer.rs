@@ -0,0 +1,53 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! High-resolution timer events.
+//!
+//! On `x86_64`, timer events are provided by the local APIC timer (in
+//! TSC-deadline mode, if the CPU supports it). The PIT continues to provide
+//! the periodic kernel tick.
+use cpu::apic;
+use memory::PhysicalPage;
+use paging::{CacheType, Mapper, MapErr};
+use paging::arch::ActivePageTable;
+use paging::arch::table::{WRITABLE, NO_EXECUTE};
+use sos_alloc::FrameAllocator;
+
+pub use cpu::apic::timer::{Mode, oneshot, periodic, cancel, set_handler};
+
+/// Initialize the local APIC and its timer.
+///
+/// This maps the local APIC's register page into the kernel's address space,
+/// enables the APIC, and calibrates the APIC timer.
+pub fn initialize<A>(page_table: &mut ActivePageTable, alloc: &mut A)
+                    -> Result<(), &'static str>
+where A: FrameAllocator {
+    if !apic::is_available() {
+        return Err("CPU does not have a local APIC")
+    }
+
+    let frame = PhysicalPage::containing(apic::base());
+    match page_table.identity_map_with_cache( frame
+                                            , WRITABLE | NO_EXECUTE
+                                            , CacheType::Uncacheable
+                                            , alloc) {
+        Ok(()) | Err(MapErr::AlreadyInUse { .. }) => {}
+      , Err(_) => return Err("Could not map local APIC registers")
+    }
+
+    unsafe {
+        apic::initialize()?;
+        let hz = apic::timer::initialize()?;
+        kinfoln!( dots: " . . ", "APIC timer runs at {} MHz, TSC-deadline \
+                                  mode {}"
+                , hz / 1_000_000
+                , if apic::timer::uses_tsc_deadline() { "enabled" }
+                  else { "disabled" });
+    }
+    Ok(())
+}
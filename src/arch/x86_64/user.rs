@@ -0,0 +1,42 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Running threads in user mode.
+//!
+//! Interrupts and exceptions which arrive in user mode switch to the stack
+//! in the current CPU's TSS, and `syscall` switches to the stack in the
+//! current CPU's per-CPU block, so whenever a thread is switched to, its
+//! kernel stack has to be put in both with
+//! [`set_kernel_stack`](fn.set_kernel_stack.html).
+use cpu::{gdt, percpu, user};
+use memory::VAddr;
+
+/// Drop to user mode, and start running at `entry` with the stack pointer
+/// set to `stack`.
+///
+/// # Safety
+/// + `entry` and `stack` must be mapped user-accessible in the current
+///   address space, and the current thread's kernel stack must have been
+///   passed to [`set_kernel_stack`](fn.set_kernel_stack.html).
+#[inline]
+pub unsafe fn enter(entry: VAddr, stack: VAddr) -> ! {
+    user::enter(entry, stack)
+}
+
+/// Set the stack the current CPU switches to when an interrupt, exception
+/// or system call arrives in user mode.
+///
+/// # Safety
+/// + The current CPU's per-CPU data must be set up.
+/// + `top` must be the top of the kernel stack of the thread which is
+///   about to run.
+#[inline]
+pub unsafe fn set_kernel_stack(top: VAddr) {
+    gdt::set_kernel_stack(percpu::cpu(), top);
+    percpu::set_syscall_stack(top);
+}
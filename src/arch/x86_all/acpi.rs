@@ -0,0 +1,294 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Advanced Configuration and Power Interface (ACPI) tables.
+//!
+//! We only understand as much of ACPI as we need to: enough to find the
+//! Multiple APIC Description Table (MADT), which lists the system's CPUs.
+//! The bootloader gives us a copy of the Root System Description Pointer
+//! (RSDP), which points at the root table, which points at all the others.
+//!
+//! The tables live in firmware-reserved memory which isn't mapped after the
+//! kernel is remapped, so we identity-map each table (read-only) before
+//! looking at it.
+//!
+//! See the _Advanced Configuration and Power Interface Specification_,
+//! section 5.2, "ACPI System Description Tables".
+use core::{fmt, mem, ptr, slice, str};
+
+use memory::{PAddr, PhysicalPage, PAGE_SIZE};
+use paging::{Mapper, MapErr};
+use paging::arch::ActivePageTable;
+use paging::arch::table::NO_EXECUTE;
+use sos_alloc::FrameAllocator;
+
+/// Signature of the Root System Description Pointer.
+const RSDP_SIGNATURE: &'static [u8; 8] = b"RSD PTR ";
+/// Signature of the Multiple APIC Description Table.
+const MADT_SIGNATURE: &'static [u8; 4] = b"APIC";
+/// Length of the part of the RSDP which is covered by the ACPI 1.0
+/// checksum.
+const RSDP_V1_LENGTH: usize = 20;
+
+/// Returns true if the bytes in `bytes` sum to zero, as every ACPI
+/// structure's bytes should.
+fn checksum(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) == 0
+}
+
+/// Identity-map the `len` bytes of physical memory starting at `addr` as
+/// read-only.
+fn map_physical<A>( addr: PAddr, len: usize
+                  , page_table: &mut ActivePageTable, alloc: &mut A)
+                  -> Result<(), &'static str>
+where A: FrameAllocator {
+    let end = *addr + len as u64;
+    let mut frame_addr = *addr & !(PAGE_SIZE - 1);
+    while frame_addr < end {
+        let frame = PhysicalPage::containing_addr(PAddr::from(frame_addr));
+        match page_table.identity_map(frame, NO_EXECUTE, alloc) {
+            Ok(()) | Err(MapErr::AlreadyInUse { .. }) => {}
+          , Err(_) => return Err("Could not map ACPI table")
+        }
+        frame_addr += PAGE_SIZE;
+    }
+    Ok(())
+}
+
+/// The Root System Description Pointer.
+///
+/// The last four fields are only present in ACPI 2.0 and later.
+#[repr(C, packed)]
+struct Rsdp { signature: [u8; 8]
+            , _checksum: u8
+            , _oem_id: [u8; 6]
+            , revision: u8
+            , rsdt_address: u32
+            , length: u32
+            , xsdt_address: u64
+            , _extended_checksum: u8
+            , _reserved: [u8; 3]
+            }
+
+impl Rsdp {
+    /// Returns true if this RSDP's signature and checksums are correct.
+    fn is_valid(&self) -> bool {
+        let bytes = |len| unsafe {
+            slice::from_raw_parts(self as *const Rsdp as *const u8, len)
+        };
+        &self.signature == RSDP_SIGNATURE
+            && checksum(bytes(RSDP_V1_LENGTH))
+            && (self.revision < 2 || checksum(bytes(self.length as usize)))
+    }
+}
+
+/// The header shared by every ACPI system description table.
+#[repr(C, packed)]
+pub struct SdtHeader { /// identifies what kind of table this is
+                       pub signature: [u8; 4]
+                     , /// length of the entire table, including the header
+                       pub length: u32
+                     , pub revision: u8
+                     , _checksum: u8
+                     , pub oem_id: [u8; 6]
+                     , pub oem_table_id: [u8; 8]
+                     , pub oem_revision: u32
+                     , pub creator_id: u32
+                     , pub creator_revision: u32
+                     }
+
+impl SdtHeader {
+    /// Returns this table's signature as a string.
+    pub fn signature(&self) -> &str {
+        str::from_utf8(&self.signature).unwrap_or("????")
+    }
+
+    /// Returns true if this table's checksum is correct.
+    pub fn is_valid(&self) -> bool {
+        checksum(unsafe {
+            slice::from_raw_parts( self as *const SdtHeader as *const u8
+                                 , self.length as usize)
+        })
+    }
+
+    /// Returns a pointer to the first byte after the header.
+    #[inline]
+    fn body(&self) -> usize {
+        self as *const SdtHeader as usize + mem::size_of::<SdtHeader>()
+    }
+
+    /// Returns the length of the table after the header.
+    #[inline]
+    fn body_len(&self) -> usize {
+        (self.length as usize).saturating_sub(mem::size_of::<SdtHeader>())
+    }
+}
+
+impl fmt::Display for SdtHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!( f, "{} revision {}, {} bytes, OEM {}"
+              , self.signature(), self.revision, self.length
+              , str::from_utf8(&self.oem_id).unwrap_or("??????").trim())
+    }
+}
+
+/// Map the table at `addr` and check that it's valid.
+fn map_table<A>( addr: PAddr, page_table: &mut ActivePageTable
+               , alloc: &mut A)
+               -> Result<&'static SdtHeader, &'static str>
+where A: FrameAllocator {
+    // we don't know how long the table is until we've mapped its header
+    map_physical(addr, mem::size_of::<SdtHeader>(), page_table, alloc)?;
+    let header = unsafe { &*(*addr as *const SdtHeader) };
+    map_physical(addr, header.length as usize, page_table, alloc)?;
+    if header.is_valid() {
+        Ok(header)
+    } else {
+        Err("ACPI table has a bad checksum")
+    }
+}
+
+/// The Multiple APIC Description Table.
+///
+/// The fixed part of the table is followed by a list of variable-length
+/// [entries](struct.MadtEntries.html).
+#[repr(C, packed)]
+pub struct Madt { pub header: SdtHeader
+                , /// physical address of the local APIC
+                  pub local_apic_address: u32
+                , pub flags: u32
+                }
+
+impl Madt {
+    /// Returns an iterator over this table's entries.
+    pub fn entries(&'static self) -> MadtEntries {
+        let start = self.header.body() + 8;
+        let end = self.header.body() + self.header.body_len();
+        MadtEntries { current: start, end: end }
+    }
+
+    /// Returns an iterator over the processors listed in this table.
+    #[inline]
+    pub fn processors(&'static self) -> Processors {
+        Processors(self.entries())
+    }
+}
+
+/// A MADT entry.
+#[derive(Copy, Clone, Debug)]
+pub enum MadtEntry { /// a processor with a local APIC
+                     LocalApic(Processor)
+                   , /// a processor with a local x2APIC
+                     LocalX2Apic(Processor)
+                   , /// an entry of a type we don't care about
+                     Other(u8)
+                   }
+
+/// An iterator over the entries of a [`Madt`](struct.Madt.html).
+pub struct MadtEntries { current: usize, end: usize }
+
+impl Iterator for MadtEntries {
+    type Item = MadtEntry;
+
+    fn next(&mut self) -> Option<MadtEntry> {
+        // every entry starts with a type byte and a length byte
+        if self.current + 2 > self.end { return None }
+        let ty = unsafe { *(self.current as *const u8) };
+        let len = unsafe { *((self.current + 1) as *const u8) } as usize;
+        if len < 2 || self.current + len > self.end { return None }
+        let entry = self.current;
+        self.current += len;
+
+        let read_u32 = |offset: usize| unsafe {
+            ptr::read_unaligned((entry + offset) as *const u32)
+        };
+        Some(match (ty, len) {
+            (0, 8) => MadtEntry::LocalApic(Processor {
+                acpi_id: unsafe { *((entry + 2) as *const u8) } as u32
+              , apic_id: unsafe { *((entry + 3) as *const u8) } as u32
+              , enabled: read_u32(4) & 1 != 0
+            })
+          , (9, 16) => MadtEntry::LocalX2Apic(Processor {
+                acpi_id: read_u32(12)
+              , apic_id: read_u32(4)
+              , enabled: read_u32(8) & 1 != 0
+            })
+          , (ty, _) => MadtEntry::Other(ty)
+        })
+    }
+}
+
+/// A processor listed in the MADT.
+#[derive(Copy, Clone, Debug)]
+pub struct Processor { /// the processor's ACPI processor UID
+                       pub acpi_id: u32
+                     , /// the ID of the processor's local APIC
+                       pub apic_id: u32
+                     , /// false if the processor is unusable
+                       pub enabled: bool
+                     }
+
+/// An iterator over the processors listed in a [`Madt`](struct.Madt.html).
+pub struct Processors(MadtEntries);
+
+impl Iterator for Processors {
+    type Item = Processor;
+
+    fn next(&mut self) -> Option<Processor> {
+        loop {
+            match self.0.next() {
+                Some(MadtEntry::LocalApic(cpu)) |
+                Some(MadtEntry::LocalX2Apic(cpu)) => return Some(cpu)
+              , Some(MadtEntry::Other(_)) => continue
+              , None => return None
+            }
+        }
+    }
+}
+
+/// Find the MADT, starting from the RSDP at `rsdp`.
+///
+/// Every table we look at on the way is identity-mapped into `page_table`.
+pub fn find_madt<A>( rsdp: PAddr, page_table: &mut ActivePageTable
+                   , alloc: &mut A)
+                   -> Result<&'static Madt, &'static str>
+where A: FrameAllocator {
+    map_physical(rsdp, mem::size_of::<Rsdp>(), page_table, alloc)?;
+    let rsdp = unsafe { &*(*rsdp as *const Rsdp) };
+    if !rsdp.is_valid() {
+        return Err("ACPI RSDP is invalid")
+    }
+
+    // ACPI 2.0 added the XSDT, which has 64-bit pointers to the other
+    // tables. if it's there, we should prefer it to the RSDT.
+    let (root, entry_size)
+        = if rsdp.revision >= 2 && rsdp.xsdt_address != 0 {
+            (PAddr::from(rsdp.xsdt_address), 8)
+        } else {
+            (PAddr::from(rsdp.rsdt_address as u64), 4)
+        };
+    let root = map_table(root, page_table, alloc)?;
+    trace!("ACPI root table: {}", root);
+
+    for i in 0..root.body_len() / entry_size {
+        let entry = root.body() + i * entry_size;
+        let addr = unsafe {
+            if entry_size == 8 { ptr::read_unaligned(entry as *const u64) }
+            else { ptr::read_unaligned(entry as *const u32) as u64 }
+        };
+        let table = match map_table(PAddr::from(addr), page_table, alloc) {
+            Ok(table) => table
+          , Err(why) => { warn!("Skipping ACPI table: {}", why); continue }
+        };
+        trace!("ACPI table: {}", table);
+        if &table.signature == MADT_SIGNATURE {
+            return Ok(unsafe { &*(table as *const SdtHeader as *const Madt) })
+        }
+    }
+    Err("No MADT found")
+}
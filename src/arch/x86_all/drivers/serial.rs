@@ -16,12 +16,10 @@
 //! See [the OS Dev wiki](http://wiki.osdev.org/Serial_Ports) for more
 //! information.
 
-use spin::Mutex;
-
 use core::fmt;
 
 use ::arch::bda;
-use cpu::Port;
+use cpu::{IrqSpinlock, Port};
 // use ::io;
 use util::{io, Void};
 //
@@ -50,17 +48,19 @@ lazy_static! {
     //       locked instead? I think multiple threads should be able to read
     //       from a serial port at the same time without causing trouble?
     //          - eliza, 10/9/2016
-    pub static ref COM1: Mutex<Serial>
-        = Mutex::new(Serial(bda::ports::com1().map(SerialPort::new)));
+    // NOTE: the kernel log writes to COM1 from interrupt handlers, so these
+    //       have to be `IrqSpinlock`s.
+    pub static ref COM1: IrqSpinlock<Serial>
+        = IrqSpinlock::new(Serial(bda::ports::com1().map(SerialPort::new)));
 
-    pub static ref COM2: Mutex<Serial>
-        = Mutex::new(Serial(bda::ports::com2().map(SerialPort::new)));
+    pub static ref COM2: IrqSpinlock<Serial>
+        = IrqSpinlock::new(Serial(bda::ports::com2().map(SerialPort::new)));
 
-    pub static ref COM3: Mutex<Serial>
-        = Mutex::new(Serial(bda::ports::com3().map(SerialPort::new)));
+    pub static ref COM3: IrqSpinlock<Serial>
+        = IrqSpinlock::new(Serial(bda::ports::com3().map(SerialPort::new)));
 
-    pub static ref COM4: Mutex<Serial>
-        = Mutex::new(Serial(bda::ports::com4().map(SerialPort::new)));
+    pub static ref COM4: IrqSpinlock<Serial>
+        = IrqSpinlock::new(Serial(bda::ports::com4().map(SerialPort::new)));
 }
 
 
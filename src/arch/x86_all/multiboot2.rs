@@ -11,12 +11,13 @@
 //! Consult the [Multiboot Specification](http://nongnu.askapache.com/grub/phcoder/multiboot.pdf)
 //! for more information.
 use memory::{PAddr, PhysicalPage, FrameRange};
-use elf::section::{Sections, HeaderRepr as SectionHeader};
-use params::mem;
+use elf::section::{self, Header, Sections, HeaderRepr as SectionHeader};
+use elf::symbol::SymbolTable;
+use params::{mem, Module};
 
 use core::convert::Into;
 use core::iter::IntoIterator;
-use core::fmt;
+use core::{fmt, slice, str};
 
 const END_TAG_LEN: u32 = 8;
 
@@ -123,6 +124,44 @@ impl Info {
             })
     }
 
+    /// Finds the bootloader's copy of the ACPI Root System Description
+    /// Pointer, preferring the ACPI 2.0+ version.
+    ///
+    ///  # Returns
+    ///  - `Some(PAddr)` with the address of the RSDP copy, if one was found
+    ///  - `None` if the bootloader didn't give us an RSDP.
+    pub fn rsdp(&'static self) -> Option<PAddr> {
+        self.get_tag(TagType::AcpiNewRsdp)
+            .or_else(|| self.get_tag(TagType::AcpiOldRsdp))
+            // the RSDP immediately follows the tag header
+            .map(|tag| PAddr::from(tag as *const Tag as u64 + 8))
+    }
+
+    /// Finds the kernel command line the bootloader passed us.
+    ///
+    ///  # Returns
+    ///  - `Some(&str)` with the command line, if there was one
+    ///  - `None` if there was no command line tag, or it wasn't UTF-8.
+    pub fn command_line(&'static self) -> Option<&'static str> {
+        self.get_tag(TagType::CommandLine)
+            .and_then(|tag| unsafe {
+                // the zero-terminated string immediately follows the tag
+                // header
+                let start = (tag as *const Tag as *const u8).offset(8);
+                let bytes = slice::from_raw_parts( start
+                                                 , tag.length as usize - 8);
+                let len = bytes.iter()
+                               .position(|&b| b == 0)
+                               .unwrap_or(bytes.len());
+                str::from_utf8(&bytes[..len]).ok()
+            })
+    }
+
+    /// Returns an iterator over the modules the bootloader loaded, in the
+    /// order they were given in its configuration.
+    #[inline]
+    pub fn modules(&'static self) -> Modules { Modules(self.tags()) }
+
     /// Returns an iterator over all Multiboot tags.
     #[inline]
     fn tags(&'static self) -> Tags { Tags(&self.tag_start as *const Tag) }
@@ -221,6 +260,19 @@ pub enum TagType { /// Tag that indicates the end of multiboot tags
                  , FramebufferInfo  = 8
                  , ELFSections      = 9
                  , APMTable         = 10
+                 , EFI32SystemTable = 11
+                 , EFI64SystemTable = 12
+                 , SMBIOSTables     = 13
+                 , /// A copy of the ACPI 1.0 RSDP
+                   AcpiOldRsdp      = 14
+                 , /// A copy of the ACPI 2.0+ RSDP
+                   AcpiNewRsdp      = 15
+                 , NetworkInfo      = 16
+                 , EFIMemoryMap     = 17
+                 , EFIBootServices  = 18
+                 , EFI32ImageHandle = 19
+                 , EFI64ImageHandle = 20
+                 , LoadBaseAddr     = 21
                  }
 
 /// An iterator over Multiboot 2 tags.
@@ -291,16 +343,73 @@ pub struct CommandLineTag { tag: Tag
                           }
 
 
+/// A tag describing a module the bootloader loaded.
+///
+/// There's one of these for each module. The addresses are always 32 bits
+/// wide, and are followed by a zero-terminated string, which is usually the
+/// module's command line.
 #[repr(C)]
 pub struct ModulesTag { tag: Tag
                       , /// The address at which the module begins.
-                        pub mod_begin: PAddr
-                      , /// The address at which the module ends.
-                        pub mod_end: PAddr
-                      , /// A string (typically a command line)
-                        pub string: [u8]
+                        pub mod_begin: u32
+                      , /// The address just past the end of the module.
+                        pub mod_end: u32
                       }
 
+impl ModulesTag {
+    /// Returns the address of the module's first byte.
+    #[inline]
+    pub fn start(&self) -> PAddr { PAddr::from(self.mod_begin as u64) }
+
+    /// Returns the address just past the module's last byte.
+    #[inline]
+    pub fn end(&self) -> PAddr { PAddr::from(self.mod_end as u64) }
+
+    /// Returns the string the bootloader passed with the module.
+    ///
+    ///  # Returns
+    ///  - `Some(&str)` with the string, if it's non-empty
+    ///  - `None` if the string was empty or wasn't UTF-8.
+    pub fn string(&'static self) -> Option<&'static str> {
+        unsafe {
+            // the zero-terminated string follows the addresses
+            let start = (self as *const ModulesTag as *const u8).offset(16);
+            let bytes = slice::from_raw_parts( start
+                                             , self.tag.length as usize - 16);
+            let len = bytes.iter()
+                           .position(|&b| b == 0)
+                           .unwrap_or(bytes.len());
+            str::from_utf8(&bytes[..len]).ok()
+                .and_then(|s| if s.is_empty() { None } else { Some(s) })
+        }
+    }
+}
+
+impl Into<Module> for &'static ModulesTag {
+    #[inline]
+    fn into(self) -> Module {
+        Module { start: self.start()
+               , end: self.end()
+               , command_line: self.string()
+               }
+    }
+}
+
+/// An iterator over the Multiboot 2 module tags.
+pub struct Modules(Tags);
+
+impl Iterator for Modules {
+    type Item = &'static ModulesTag;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.by_ref()
+            .find(|tag| tag.ty == TagType::Modules)
+            .map(|tag| unsafe {
+                &*((tag as *const Tag) as *const ModulesTag)
+            })
+    }
+}
+
 #[repr(u32)]
 #[derive(Debug, Eq, PartialEq, Ord, PartialOrd)]
 pub enum MemAreaType { Available = 1
@@ -383,6 +492,11 @@ pub type Word = u32;
 #[cfg(target_pointer_width = "64")]
 pub type Word = u64;
 
+#[cfg(target_pointer_width = "32")]
+pub type Symbol = ::elf::symbol::SymbolRepr32;
+#[cfg(target_pointer_width = "64")]
+pub type Symbol = ::elf::symbol::SymbolRepr64;
+
 /// A Multiboot 2 ELF sections tag
 #[derive(Debug)]
 #[repr(packed)]
@@ -405,6 +519,40 @@ impl ElfSectionsTag {
                      , self.section_size
                      )
     }
+
+    /// Returns the section header at index `idx`, if there is one.
+    ///
+    /// Unlike the [`sections`](#method.sections) iterator, this does not skip
+    /// null sections, so indices match the ELF section header table (e.g.
+    /// for following a section's `link`).
+    pub fn section(&'static self, idx: u32)
+                   -> Option<&'static SectionHeader<Word>> {
+        if idx >= self.n_sections {
+            None
+        } else {
+            let addr = &self.first_section as *const _ as usize
+                     + (idx * self.section_size) as usize;
+            Some(unsafe { &*(addr as *const SectionHeader<Word>) })
+        }
+    }
+
+    /// Returns the kernel's symbol table, if the bootloader loaded it.
+    pub fn symbol_table(&'static self)
+                        -> Option<SymbolTable<'static, Symbol>> {
+        use core::slice::from_raw_parts;
+        let symtab = (0..self.n_sections)
+            .filter_map(|i| self.section(i))
+            .find(|s| s.get_type() == Ok(section::Type::SymbolTable))?;
+        let strtab = self.section(symtab.link())?;
+
+        unsafe {
+            let symbols = from_raw_parts( *symtab.address() as *const u8
+                                        , symtab.length());
+            let strings = from_raw_parts( *strtab.address() as *const u8
+                                        , strtab.length());
+            SymbolTable::new(symbols, strings).ok()
+        }
+    }
 }
 
 impl IntoIterator for &'static ElfSectionsTag {
@@ -0,0 +1,88 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Loading user programs.
+//!
+//! A program is a statically-linked ELF64 executable, which
+//! [`elf::loader`](../../elf/loader/index.html) checks and picks apart.
+//! Each of its loadable segments is mapped into a fresh
+//! [`AddressSpace`](../vm/struct.AddressSpace.html), with the permissions
+//! its program header asks for, and a stack is mapped at the top of the
+//! user half, holding the program's arguments, environment and auxiliary
+//! vector.
+//!
+//! [`Process::spawn`](../process/struct.Process.html#method.spawn) loads a
+//! program and starts running it.
+use core::{ptr, slice};
+
+use elf::loader::{self, Executable};
+use memory::{PAGE_SIZE, VAddr};
+use paging::arch::table::{EntryFlags, NO_EXECUTE, PRESENT, WRITABLE};
+
+use vm::AddressSpace;
+
+/// The address just above every program's stack.
+///
+/// This leaves an unmapped page at the top of the user half.
+pub const STACK_TOP: usize = 0x7fff_ffff_f000;
+
+/// The size of every program's stack, in bytes.
+pub const STACK_SIZE: usize = 16 * PAGE_SIZE as usize;
+
+/// A program which has been loaded, but isn't running yet.
+#[derive(Debug)]
+pub struct Program { /// the address space the program is loaded into
+                     pub space: AddressSpace
+                   , /// the address the program starts at
+                     pub entry: VAddr
+                   , /// the program's initial stack pointer
+                     pub stack: VAddr
+                   }
+
+/// Load the executable `binary` into a new address space, with the
+/// arguments `args` and environment `env` on its stack.
+///
+/// `args` and `env` must be in kernel memory, since the new address space
+/// is loaded while they're copied.
+pub fn load(binary: &[u8], args: &[&[u8]], env: &[&[u8]])
+           -> Result<Program, &'static str> {
+    let exe = Executable::parse(binary)?;
+    let space = AddressSpace::new()?;
+
+    for segment in exe.segments() {
+        // the rest of the segment is already zeroed
+        space.map_with( VAddr::from(segment.vaddr), segment.mem_size
+                      , EntryFlags::from(segment.flags)
+                      , || unsafe {
+                            ptr::copy_nonoverlapping( segment.data.as_ptr()
+                                                    , segment.vaddr as *mut u8
+                                                    , segment.data.len());
+                            Ok(())
+                      })?;
+    }
+
+    let auxv = exe.aux_vector();
+    let bottom = STACK_TOP - STACK_SIZE;
+    let mut sp = 0;
+    space.map_with( VAddr::from(bottom), STACK_SIZE
+                  , PRESENT | WRITABLE | NO_EXECUTE
+                  , || {
+                        let stack = unsafe {
+                            slice::from_raw_parts_mut( bottom as *mut u8
+                                                     , STACK_SIZE)
+                        };
+                        sp = loader::build_stack( stack, STACK_TOP
+                                                , args, env, auxv.as_slice())?;
+                        Ok(())
+                  })?;
+
+    Ok(Program { space: space
+               , entry: VAddr::from(exe.entry_point())
+               , stack: VAddr::from(sp)
+               })
+}
@@ -0,0 +1,100 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! The initial ramdisk, and the other boot modules.
+//!
+//! The bootloader can load modules into memory along with the kernel. A
+//! module which is an ELF executable is started as a user program, with
+//! the module's command line as its arguments. A module which is a `ustar`
+//! archive becomes the initial ramdisk, which is read-only, and lives as
+//! long as the kernel does. If the kernel command line has an `init=path`
+//! option, the program at `path` in the ramdisk is started too.
+//!
+//! For example, with GRUB:
+//!
+//! ```text
+//! multiboot2 /boot/sos_kernel.bin init=bin/hello
+//! module2 /boot/initrd.tar initrd
+//! ```
+use alloc::vec::Vec;
+use core::slice;
+
+use elf::file::MAGIC as ELF_MAGIC;
+use params::InitParams;
+use sos_tar::Archive;
+use spin::Once;
+
+use process::Process;
+
+/// The initial ramdisk, if the bootloader loaded one.
+static INITRD: Once<Archive<'static>> = Once::new();
+
+/// Returns the initial ramdisk, if the bootloader loaded one.
+#[inline]
+pub fn initrd() -> Option<&'static Archive<'static>> { INITRD.try() }
+
+/// Find the initial ramdisk among the boot modules, and start the modules
+/// which are programs, and then the `init` program, if there is one.
+///
+/// The modules are still identity mapped where the bootloader put them,
+/// and threads must be running on the current CPU.
+///
+/// # Returns
+/// + the number of programs started.
+pub fn load_modules(params: &InitParams) -> usize {
+    let mut started = 0;
+    for module in params.modules() {
+        let bytes: &'static [u8] = unsafe {
+            slice::from_raw_parts( *module.start as usize as *const u8
+                                 , module.len())
+        };
+        let line = module.command_line.unwrap_or("");
+        if bytes.starts_with(&ELF_MAGIC) {
+            let name = line.split_whitespace().next().unwrap_or("module");
+            let args = line.split_whitespace()
+                           .map(|arg| arg.as_bytes())
+                           .collect::<Vec<_>>();
+            if start(name, bytes, &args) { started += 1; }
+        } else if let Ok(archive) = Archive::new(bytes) {
+            if INITRD.try().is_some() {
+                warn!("Ignoring module {:?}: there's already a ramdisk", line);
+                continue
+            }
+            INITRD.call_once(|| archive);
+            kinfoln!( dots: " . ", "Initial ramdisk {:?} has {} files"
+                    , line, archive.files().count());
+        } else {
+            warn!("Ignoring module {:?}: it's not a program or an archive"
+                 , line);
+        }
+    }
+
+    if let Some(path) = params.boot_option("init") {
+        match initrd().and_then(|archive| archive.get(path)) {
+            Some(bytes) =>
+                if start(path, bytes, &[path.as_bytes()]) { started += 1; }
+          , None => warn!("Could not find init program {:?}", path)
+        }
+    }
+    started
+}
+
+/// Start the program `binary`, logging whether it worked.
+fn start(name: &'static str, binary: &[u8], args: &[&[u8]]) -> bool {
+    match Process::spawn(name, binary, args, &[]) {
+        Ok(process) => {
+            kinfoln!( dots: " . ", "Started {} as process {}"
+                    , name, process.id());
+            true
+        }
+      , Err(why) => {
+            warn!("Could not start {}: {}", name, why);
+            false
+        }
+    }
+}
@@ -7,8 +7,7 @@
 //  directory of this repository for more information.
 //
 //! PS/2 keyboard driver
-use cpu::Port;
-use spin::Mutex;
+use cpu::{IrqSpinlock, Port};
 
 use core::default::Default;
 
@@ -119,9 +118,10 @@ impl Modifiers {
     }
 }
 
-/// Our global keyboard state, protected by a mutex.
+/// Our global keyboard state, protected by a lock which the keyboard
+/// interrupt handler can take.
 //  TODO: can this be thread local?
-static KEYBOARD: Mutex<Keyboard> = Mutex::new(Keyboard {
+static KEYBOARD: IrqSpinlock<Keyboard> = IrqSpinlock::new(Keyboard {
     data_port: Port::<u8>::new(0x60)
   , state: Modifiers::new()
 });
@@ -23,7 +23,7 @@
 
 #![doc(html_root_url = "https://hawkw.github.io/sos-kernel/")]
 
-#![feature( lang_items, asm, naked_functions )]
+#![feature( lang_items, asm, global_asm, naked_functions )]
 #![feature( linkage )]
 #![feature( const_fn
           , slice_patterns
@@ -31,6 +31,7 @@
           , type_ascription
           , custom_derive )]
 #![feature(alloc)]
+#![feature(unique)]
 
 #![cfg_attr(feature="clippy", feature(plugin))]
 #![cfg_attr(feature="clippy", plugin(clippy))]
@@ -53,7 +54,12 @@ extern crate spin;
 #[macro_use] extern crate vga;
 
 extern crate sos_alloc;
-extern crate cpu;
+extern crate sos_handles;
+extern crate sos_intrusive;
+extern crate sos_sched;
+extern crate sos_tar;
+extern crate sos_timers;
+#[macro_use] extern crate cpu;
 extern crate elf;
 extern crate paging;
 extern crate params;
@@ -62,9 +68,19 @@ extern crate util;
 
 #[macro_use] pub mod io;
 
+pub mod exec;
 pub mod heap;
+pub mod initrd;
 pub mod arch;
 pub mod logger;
+pub mod panic;
+pub mod process;
+pub mod symbols;
+pub mod sync;
+pub mod syscall;
+pub mod thread;
+pub mod time;
+pub mod vm;
 
 use params::InitParams;
 
@@ -74,18 +90,23 @@ pub const VERSION_STRING: &'static str
 
 
 /// Kernel main loop
+///
+/// This becomes the bootstrap processor's idle thread, which runs whenever no
+/// other threads are ready.
 pub fn kernel_main() -> ! {
-    // let mut a_vec = collections::vec::Vec::<usize>::new();
-    // info!(target: "test", "Created a vector in kernel space! {:?}", a_vec);
-    // a_vec.push(1);
-    // info!(target: "test", "pushed to vec: {:?}", a_vec);
-    // a_vec.push(2);
-    // info!(target: "test", "pushed to vec: {:?}", a_vec);
-
-    // let mut frame_allocator = frame_alloc::FrameAllocator::new();
-    // paging::test_paging(&mut frame_allocator);
+    thread::idle()
+}
 
-    loop { }
+/// Main loop for application processors.
+///
+/// Each application processor calls this once it has been brought up by
+/// `arch::smp`, with its CPU number, and then becomes that CPU's idle thread.
+pub fn ap_main(cpu: usize) -> ! {
+    if let Err(why) = thread::initialize_cpu() {
+        panic!("CPU {}: could not start threads: {}", cpu, why);
+    }
+    kinfoln!(dots: " . . ", "CPU {} is online", cpu);
+    thread::idle()
 }
 
 /// Kernel initialization function called into by architecture-specific init
@@ -111,7 +132,7 @@ pub fn kernel_main() -> ! {
 /// |   configuration                                               |
 /// +---------------------------------------------------------------+
 /// ```
-pub fn kernel_init(params: &InitParams) {
+pub fn kernel_init(params: &'static InitParams) {
     use sos_alloc::frame::mem_map::MemMapAllocator;
     use ::paging::kernel_remap;
 
@@ -121,7 +142,7 @@ pub fn kernel_init(params: &InitParams) {
     // -- remap the kernel ----------------------------------------------------
     let mut frame_allocator = MemMapAllocator::from(params);
     kinfoln!(dots: " . ", "Remapping the kernel...");
-    let page_table = match kernel_remap(&params, &mut frame_allocator) {
+    let mut page_table = match kernel_remap(&params, &mut frame_allocator) {
         Ok(p) => {
             kinfoln!(dots: " . ", target: "Remapping the kernel", "[ OKAY ]");
             p
@@ -144,13 +165,55 @@ pub fn kernel_init(params: &InitParams) {
 
 
     // -- initialize interrupts ----------------------------------------------
-    // attempt!( unsafe { arch::interrupts::initialize() } =>
-    //           "Initializing interrupts...", dots: " . " );
+    attempt!( unsafe { arch::interrupts::initialize() } =>
+              dots: " . ", "Initializing interrupts...");
+
+    // -- initialize high-resolution timers ---------------------------------
+    attempt!( arch::timer::initialize(&mut page_table, &mut frame_allocator) =>
+              dots: " . ", "Initializing timers...");
+
+    // -- choose a scheduling policy ------------------------------------------
+    // this has to happen before the other CPUs start running threads
+    if let Some(name) = params.boot_option("sched") {
+        match thread::Policy::from_name(name) {
+            Some(policy) => thread::set_policy(policy)
+                                   .expect("Threads started too early!")
+          , None => warn!( "Unknown scheduling policy {:?}, using {}"
+                         , name, thread::policy())
+        }
+    }
+    kinfoln!(dots: " . ", "Scheduling policy: {}", thread::policy());
+
+    // -- bring up the other CPUs --------------------------------------------
+    match arch::smp::initialize(params, &mut page_table, &mut frame_allocator) {
+        Ok(n) => kinfoln!(dots: " . ", "{} CPUs online", n)
+      , Err(why) => warn!("Could not start other CPUs: {}", why)
+    }
+
+    // -- start threading -----------------------------------------------------
+    let n_threads = attempt!( thread::initialize( &mut page_table
+                                                , &mut frame_allocator) =>
+                              dots: " . ", "Initializing threads...");
+    kinfoln!(dots: " . . ", "Room for {} kernel threads", n_threads);
+
+    // -- hand the page tables and frames over to the memory manager ---------
+    attempt!( vm::initialize(page_table, frame_allocator) =>
+              dots: " . ", "Initializing the memory manager...");
+
+    // -- enable system calls -------------------------------------------------
+    // the other CPUs enable them for themselves as they come up
+    match unsafe { arch::syscall::initialize() } {
+        Ok(()) => kinfoln!(dots: " . ", "System calls ENABLED")
+      , Err(why) => warn!("Could not enable system calls: {}", why)
+    }
+
+    // -- start the boot modules ---------------------------------------------
+    let n_programs = initrd::load_modules(params);
+    kinfoln!(dots: " . ", "Started {} user programs", n_programs);
 
     println!("\n{} {}-bit\n", VERSION_STRING, arch::ARCH_BITS);
 
     // -- call into kernel main loop ------------------------------------------
-    // (currently, this does nothing)
     kernel_main()
 }
 
@@ -12,28 +12,34 @@
 //! panics at runtime.
 
 use core::fmt::{Arguments, Write};
-use super::{Color, CONSOLE};
+use cpu::backtrace::Backtrace;
+use vga::{Color, CONSOLE};
 
 /// Called to handle a panic.
 ///
 /// Since kernel panics are non-recoverable, this function prints out
-/// the error message and hangs forever.
+/// the error message and a backtrace, and hangs forever.
 ///
 /// Eventually – way in the future – when we have disk I/O and stuff,
 /// we'll probably want to write out some core dumps here as well.
+#[cfg(not(test))]
 #[lang = "panic_fmt"]
 #[no_mangle] #[inline(never)] #[cold]
 pub extern "C" fn rust_begin_unwind( args: Arguments
                                    , file: &'static str
                                    , line: usize )
                                    -> ! {
+    let backtrace = Backtrace::current();
+    // we may have panicked while holding the console, and we're never going
+    // to give it back anyway
+    unsafe { CONSOLE.force_unlock(); }
     let _ = write!( CONSOLE.lock()
                         .set_colors(Color::White, Color::Red)
                   , "Something has gone horribly wrong in {} at line {}. \
-                    \n{}\n\
+                    \n{}\n{}\n\
                     This is fine."
-                  , file, line, args
+                  , file, line, args, backtrace
                   );
-    error!(target: file, "{}", args);
+    error!(target: file, "{}\n{}", args, backtrace);
     loop { }
 }
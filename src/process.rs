@@ -0,0 +1,262 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! User processes.
+//!
+//! A [`Process`](struct.Process.html) is a program loaded into its own
+//! [`AddressSpace`](../vm/struct.AddressSpace.html), with the user
+//! [threads](../thread/index.html) running it, and a table of
+//! [handles](struct.Handle.html) to the things it may use, such as the
+//! processes it has spawned.
+//!
+//! A process exits when the last of its threads does, with the status the
+//! first of them passed to [`exit`](fn.exit.html). Its address space is
+//! freed then, but the process itself lingers as a _zombie_ until its
+//! parent [`wait`](struct.Process.html#method.wait)s for it, which reaps
+//! it. A process with no parent, such as one the kernel started, or one
+//! whose parent has exited, is reaped as soon as it exits.
+use alloc::arc::Arc;
+use alloc::vec::Vec;
+use core::{fmt, mem};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use cpu::IrqSpinlock;
+use sos_handles;
+
+use exec;
+use sync::WaitQueue;
+use thread::{self, ThreadId};
+use vm::AddressSpace;
+
+pub use sos_handles::Handle;
+
+/// The exit status of a process whose threads were killed before any of
+/// them called [`exit`](fn.exit.html).
+pub const KILLED: usize = !0;
+
+/// Uniquely identifies a process.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct ProcessId(usize);
+
+impl fmt::Display for ProcessId {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+/// Something a [`Handle`](struct.Handle.html) may refer to.
+#[derive(Clone, Debug)]
+pub enum Object { /// A child process
+                  Process(Arc<Process>)
+                }
+
+/// A table of the handles a process holds.
+pub type Handles = sos_handles::Handles<Object>;
+
+/// What a process is doing.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum State { /// Some of its threads are still running
+                 Running
+               , /// Exited with a status, and waiting to be reaped
+                 Zombie(usize)
+               }
+
+/// The parts of a process which change.
+#[derive(Debug)]
+struct Inner { state: State
+             , /// the status the first thread to exit gave
+               status: Option<usize>
+             , threads: Vec<ThreadId>
+             , /// `None` once the process has exited
+               space: Option<Arc<AddressSpace>>
+             , handles: Handles
+             }
+
+/// A user process.
+pub struct Process { id: ProcessId
+                   , name: &'static str
+                   , /// the process which spawned this one, if any. it may
+                     /// have exited since.
+                     parent: Option<ProcessId>
+                   , /// this is only held for a moment, and never while
+                     /// blocking or freeing memory. `wait` checks the
+                     /// state with interrupts disabled, so this has to
+                     /// keep them disabled too.
+                     inner: IrqSpinlock<Inner>
+                   , /// woken when the process exits
+                     exited: WaitQueue
+                   }
+
+/// The next process ID to hand out.
+static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+
+impl Process {
+    /// Load the ELF executable `binary` into a new process called `name`,
+    /// with no parent, and start its first thread on the current CPU.
+    ///
+    /// See [`exec::load`](../exec/fn.load.html) for what `args` and `env`
+    /// should be.
+    pub fn spawn( name: &'static str, binary: &[u8]
+                , args: &[&[u8]], env: &[&[u8]])
+                -> Result<Arc<Process>, &'static str> {
+        Process::start(name, None, binary, args, env)
+    }
+
+    /// Load the ELF executable `binary` into a new child of this process,
+    /// and start its first thread on the current CPU.
+    ///
+    /// # Returns
+    /// + `Ok(handle)` with this process's handle on the child
+    /// + `Err` if the child couldn't be started, or this process has
+    ///   exited.
+    pub fn spawn_child( &self, name: &'static str, binary: &[u8]
+                      , args: &[&[u8]], env: &[&[u8]])
+                      -> Result<Handle, &'static str> {
+        let child = Process::start(name, Some(self.id), binary, args, env)?;
+        let mut inner = self.inner.lock();
+        if inner.state != State::Running {
+            return Err("The process has exited")
+        }
+        Ok(inner.handles.insert(Object::Process(child)))
+    }
+
+    fn start( name: &'static str, parent: Option<ProcessId>
+            , binary: &[u8], args: &[&[u8]], env: &[&[u8]])
+            -> Result<Arc<Process>, &'static str> {
+        let program = exec::load(binary, args, env)?;
+        let process = Arc::new(Process {
+            id: ProcessId(NEXT_ID.fetch_add(1, Ordering::SeqCst))
+          , name: name
+          , parent: parent
+          , inner: IrqSpinlock::new(Inner { state: State::Running
+                                    , status: None
+                                    , threads: Vec::new()
+                                    , space: Some(Arc::new(program.space))
+                                    , handles: Handles::default()
+                                    })
+          , exited: WaitQueue::new()
+        });
+
+        // the thread can't run, and leave the process, before it's added
+        let _preempt = thread::disable_preemption();
+        let id = thread::spawn_user( name, process.clone()
+                                   , program.entry, program.stack)?;
+        process.inner.lock().threads.push(id);
+        trace!("Started {:?} with thread {}", process, id);
+        Ok(process)
+    }
+
+    /// Returns this process's ID.
+    #[inline] pub fn id(&self) -> ProcessId { self.id }
+
+    /// Returns this process's name.
+    #[inline] pub fn name(&self) -> &'static str { self.name }
+
+    /// Returns the ID of the process which spawned this one, if any.
+    #[inline] pub fn parent(&self) -> Option<ProcessId> { self.parent }
+
+    /// Returns what this process is doing.
+    #[inline] pub fn state(&self) -> State { self.inner.lock().state }
+
+    /// Returns this process's address space, or `None` if it has exited.
+    #[inline]
+    pub fn space(&self) -> Option<Arc<AddressSpace>> {
+        self.inner.lock().space.clone()
+    }
+
+    /// Returns the IDs of this process's threads.
+    #[inline]
+    pub fn threads(&self) -> Vec<ThreadId> {
+        self.inner.lock().threads.clone()
+    }
+
+    /// Call `f` with this process's handle table.
+    ///
+    /// The table is locked, with interrupts disabled, while `f` runs, so it
+    /// mustn't block, or drop any of the objects in the table.
+    pub fn with_handles<F, R>(&self, f: F) -> R
+    where F: FnOnce(&mut Handles) -> R {
+        f(&mut self.inner.lock().handles)
+    }
+
+    /// Wait for the child process `handle` refers to to exit, and reap it.
+    ///
+    /// # Returns
+    /// + `Ok(status)` with the child's exit status
+    /// + `Err` if `handle` isn't a child process.
+    pub fn wait(&self, handle: Handle) -> Result<usize, &'static str> {
+        let child = match self.inner.lock().handles.get(handle) {
+            Some(&Object::Process(ref child)) => child.clone()
+          , None => return Err("No such handle")
+        };
+        let mut status = 0;
+        child.exited.wait_until(|| match child.inner.lock().state {
+            State::Zombie(s) => { status = s; true }
+          , State::Running => false
+        });
+        // dropping the handle reaps the child
+        let object = self.inner.lock().handles.remove(handle);
+        mem::drop(object);
+        trace!("Reaped {:?}, which exited with {}", child, status);
+        Ok(status)
+    }
+
+    /// Record `status` as this process's exit status, unless one of its
+    /// threads already has.
+    #[inline]
+    fn set_status(&self, status: usize) {
+        let mut inner = self.inner.lock();
+        if inner.status.is_none() { inner.status = Some(status); }
+    }
+
+    /// Take the thread `id` out of this process, which exits if it was the
+    /// last.
+    ///
+    /// [`thread::exit`](../thread/fn.exit.html) calls this.
+    pub fn leave(&self, id: ThreadId) {
+        let (space, handles) = {
+            let mut inner = self.inner.lock();
+            inner.threads.retain(|&thread| thread != id);
+            if !inner.threads.is_empty() { return }
+            inner.state = State::Zombie(inner.status.unwrap_or(KILLED));
+            (inner.space.take(), mem::replace( &mut inner.handles
+                                              , Handles::default()))
+        };
+        // this may free the address space, and reap zombie children
+        mem::drop(space);
+        mem::drop(handles);
+        trace!("{:?} exited", self);
+        self.exited.notify_all();
+    }
+}
+
+impl fmt::Debug for Process {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Process {} ({})", self.id, self.name)
+    }
+}
+
+/// Returns the process the running thread belongs to, or `None` if it's a
+/// kernel thread.
+pub fn current() -> Option<Arc<Process>> {
+    let thread = thread::current_thread();
+    if thread.is_null() { return None }
+    unsafe { (*thread).process().cloned() }
+}
+
+/// Exit the running thread, and its process with `status` if it's the
+/// last thread.
+///
+/// This must be called with interrupts enabled.
+pub fn exit(status: usize) -> ! {
+    if let Some(process) = current() {
+        process.set_status(status);
+    }
+    thread::exit()
+}
@@ -0,0 +1,34 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Kernel symbol lookup.
+//!
+//! If the bootloader loaded the kernel's ELF symbol table, we keep a handle to
+//! it here so that backtraces can be printed as `function+offset` rather than
+//! as raw return addresses.
+use cpu::backtrace;
+use elf::symbol::SymbolTable;
+use spin::Once;
+
+use arch::multiboot2::Symbol;
+
+/// The kernel's symbol table.
+static SYMBOLS: Once<SymbolTable<'static, Symbol>> = Once::new();
+
+/// Install the kernel's symbol table and use it to resolve backtraces.
+pub fn initialize(table: SymbolTable<'static, Symbol>) {
+    let table = SYMBOLS.call_once(|| table);
+    unsafe { backtrace::set_resolver(resolve); }
+    kinfoln!(dots: " . ", "Loaded {} kernel symbols", table.len());
+}
+
+/// Resolve `addr` to the name of the kernel function containing it, and the
+/// offset of `addr` into that function.
+pub fn resolve(addr: usize) -> Option<(&'static str, usize)> {
+    SYMBOLS.try().and_then(|symbols| symbols.resolve(addr))
+}
@@ -0,0 +1,81 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Condition variables.
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::{MutexGuard, WaitQueue};
+
+/// A condition variable, which lets threads holding a
+/// [`Mutex`](struct.Mutex.html) sleep until another thread tells them that
+/// the data it protects has changed.
+///
+/// As with any condition variable, a waiting thread may wake up without
+/// being notified, so waiting should be done in a loop which checks the
+/// condition it's waiting for.
+pub struct Condvar { /// incremented on every notification
+                     generation: AtomicUsize
+                   , waiters: WaitQueue
+                   }
+
+impl Condvar {
+    /// Returns a new condition variable.
+    pub const fn new() -> Self {
+        Condvar { generation: AtomicUsize::new(0)
+                , waiters: WaitQueue::new()
+                }
+    }
+
+    /// Unlock the mutex `guard` holds, and sleep until this condition
+    /// variable is notified, and then lock the mutex again.
+    ///
+    /// # Panics
+    /// + If not called from a thread.
+    pub fn wait<'a, T: ?Sized>(&self, mut guard: MutexGuard<'a, T>)
+                              -> MutexGuard<'a, T> {
+        // a notification after this point has to happen after we unlock the
+        // mutex, so we can't miss it
+        let generation = self.generation.load(Ordering::Acquire);
+        MutexGuard::unlocked(&mut guard, || {
+            self.waiters.wait_until(|| {
+                self.generation.load(Ordering::Acquire) != generation
+            })
+        });
+        guard
+    }
+
+    /// Wake one thread waiting on this condition variable.
+    ///
+    /// # Returns
+    /// + `true` if a thread was woken
+    /// + `false` if no threads were waiting.
+    pub fn notify_one(&self) -> bool {
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        self.waiters.notify_one()
+    }
+
+    /// Wake every thread waiting on this condition variable.
+    ///
+    /// # Returns
+    /// The number of threads woken.
+    pub fn notify_all(&self) -> usize {
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        self.waiters.notify_all()
+    }
+}
+
+impl Default for Condvar {
+    #[inline] fn default() -> Self { Condvar::new() }
+}
+
+impl fmt::Debug for Condvar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("Condvar { .. }")
+    }
+}
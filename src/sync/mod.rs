@@ -0,0 +1,29 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Blocking synchronization primitives.
+//!
+//! Unlike `spin::Mutex`, these put a thread which has to wait to sleep, so
+//! that other threads can run in the meantime, and they may be held across
+//! a context switch. Since they block, they may only be waited on by
+//! [threads](../thread/index.html), and never by interrupt handlers or idle
+//! threads. Waking waiters is fine from anywhere.
+//!
+//! Everything here is built on the [`WaitQueue`](struct.WaitQueue.html),
+//! which keeps an intrusive list of the threads waiting on it.
+pub mod condvar;
+pub mod mutex;
+pub mod rwlock;
+pub mod semaphore;
+mod wait_queue;
+
+pub use self::condvar::Condvar;
+pub use self::mutex::{Mutex, MutexGuard};
+pub use self::rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use self::semaphore::{Semaphore, SemaphoreGuard};
+pub use self::wait_queue::WaitQueue;
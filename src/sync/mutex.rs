@@ -0,0 +1,230 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! A mutual exclusion lock which puts waiting threads to sleep.
+//!
+//! # Priority inheritance
+//! If a low-priority thread holds a lock which a high-priority thread is
+//! waiting for, then any medium-priority threads can keep the high-priority
+//! thread waiting, by keeping the low-priority thread from running. A mutex
+//! made with [`with_inheritance`](struct.Mutex.html#method.with_inheritance)
+//! prevents this by having each waiter lend the thread holding the lock its
+//! priority, if it's higher. The holder gets its own priority back when it
+//! unlocks the mutex.
+//!
+//! Priorities are only lent one level deep: if the holder is itself waiting
+//! for another mutex, its priority isn't passed on. And if a thread holds
+//! more than one mutex, it gets its own priority back as soon as it unlocks
+//! any of them that lent it a priority.
+use core::cell::UnsafeCell;
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut};
+use core::{fmt, ptr};
+
+use cpu::interrupts;
+use spin;
+
+use thread::{self, Thread};
+use super::WaitQueue;
+
+/// Who holds a `Mutex`.
+struct Owner { /// the thread holding the lock, or null if it's unlocked
+               thread: *mut Thread
+             , /// the holder's own priority, if waiters have raised it
+               lent: Option<usize>
+             }
+
+/// A mutual exclusion lock which puts waiting threads to sleep.
+///
+/// Unlike a `spin::Mutex`, this may only be locked by threads, but it may be
+/// held while the thread blocks or is preempted.
+pub struct Mutex<T: ?Sized> { inherit: bool
+                            , owner: spin::Mutex<Owner>
+                            , waiters: WaitQueue
+                            , data: UnsafeCell<T>
+                            }
+
+unsafe impl<T: ?Sized + Send> Send for Mutex<T> { }
+unsafe impl<T: ?Sized + Send> Sync for Mutex<T> { }
+
+/// A locked `Mutex`, which unlocks it when dropped.
+///
+/// This can't be sent to another thread, since the thread which locked the
+/// mutex has to be the one to unlock it.
+#[must_use]
+pub struct MutexGuard<'a, T: ?Sized + 'a> {
+    mutex: &'a Mutex<T>
+  , _not_send: PhantomData<*const ()>
+}
+
+impl<T> Mutex<T> {
+    /// Returns a new, unlocked mutex protecting `data`.
+    pub const fn new(data: T) -> Self {
+        Mutex { inherit: false
+              , owner: spin::Mutex::new(Owner { thread: 0 as *mut Thread
+                                              , lent: None })
+              , waiters: WaitQueue::new()
+              , data: UnsafeCell::new(data)
+              }
+    }
+
+    /// Returns a new, unlocked mutex protecting `data`, whose waiters lend
+    /// their priority to the thread holding it.
+    pub const fn with_inheritance(data: T) -> Self {
+        Mutex { inherit: true
+              , owner: spin::Mutex::new(Owner { thread: 0 as *mut Thread
+                                              , lent: None })
+              , waiters: WaitQueue::new()
+              , data: UnsafeCell::new(data)
+              }
+    }
+
+    /// Consumes the mutex, returning the data it protected.
+    #[inline]
+    pub fn into_inner(self) -> T {
+        unsafe { self.data.into_inner() }
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    /// Lock the mutex, sleeping until it's available.
+    ///
+    /// # Panics
+    /// + If not called from a thread.
+    /// + If the running thread already holds the lock.
+    pub fn lock(&self) -> MutexGuard<T> {
+        let me = thread::current_thread();
+        assert!(!me.is_null(), "Only threads may lock a Mutex");
+        self.lock_as(me);
+        MutexGuard { mutex: self, _not_send: PhantomData }
+    }
+
+    /// Lock the mutex, if it's available.
+    ///
+    /// # Returns
+    /// + `Some(guard)` if the mutex was locked
+    /// + `None` if it's held by another thread, or this isn't called from a
+    ///   thread.
+    pub fn try_lock(&self) -> Option<MutexGuard<T>> {
+        let me = thread::current_thread();
+        if !me.is_null() && self.try_acquire(me) {
+            Some(MutexGuard { mutex: self, _not_send: PhantomData })
+        } else {
+            None
+        }
+    }
+
+    /// Returns true if the mutex is held by any thread.
+    #[inline]
+    pub fn is_locked(&self) -> bool {
+        let _guard = interrupts::disable();
+        !self.owner.lock().thread.is_null()
+    }
+
+    /// Borrows the protected data mutably.
+    ///
+    /// Since this borrows the mutex mutably, no locking is needed.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.data.get() }
+    }
+
+    fn lock_as(&self, me: *mut Thread) {
+        self.waiters.wait_until(|| {
+            if self.try_acquire(me) { return true }
+            if self.inherit { self.lend_priority(me) }
+            false
+        })
+    }
+
+    fn try_acquire(&self, me: *mut Thread) -> bool {
+        let _guard = interrupts::disable();
+        let mut owner = self.owner.lock();
+        if owner.thread.is_null() {
+            owner.thread = me;
+            true
+        } else {
+            assert!( owner.thread != me
+                   , "Mutex locked again by the thread holding it");
+            false
+        }
+    }
+
+    /// Lend the holder of the lock our priority, if it's higher.
+    fn lend_priority(&self, me: *mut Thread) {
+        let _guard = interrupts::disable();
+        let mut owner = self.owner.lock();
+        if owner.thread.is_null() { return }
+        // the holder can't unlock, and so can't exit, while we hold `owner`
+        let priority = unsafe { (*me).priority() };
+        let raised = unsafe { thread::boost_priority(owner.thread, priority) };
+        if let Some(old) = raised {
+            // if it's been raised before, `old` isn't its own priority
+            if owner.lent.is_none() { owner.lent = Some(old) }
+        }
+    }
+
+    fn unlock(&self) {
+        let lent = {
+            let _guard = interrupts::disable();
+            let mut owner = self.owner.lock();
+            owner.thread = ptr::null_mut();
+            owner.lent.take()
+        };
+        if let Some(priority) = lent {
+            // we're still running, so this can't fail
+            let _ = thread::set_priority(priority);
+        }
+        self.waiters.notify_one();
+    }
+}
+
+impl<T: Default> Default for Mutex<T> {
+    #[inline] fn default() -> Self { Mutex::new(T::default()) }
+}
+
+impl<T: ?Sized> fmt::Debug for Mutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Mutex")
+         .field("locked", &self.is_locked())
+         .field("inherit", &self.inherit)
+         .finish()
+    }
+}
+
+impl<'a, T: ?Sized> MutexGuard<'a, T> {
+    /// Unlock the mutex while `f` runs, and lock it again afterwards.
+    pub fn unlocked<F, R>(guard: &mut Self, f: F) -> R
+    where F: FnOnce() -> R {
+        guard.mutex.unlock();
+        let result = f();
+        guard.mutex.lock_as(thread::current_thread());
+        result
+    }
+}
+
+impl<'a, T: ?Sized> Deref for MutexGuard<'a, T> {
+    type Target = T;
+    #[inline] fn deref(&self) -> &T { unsafe { &*self.mutex.data.get() } }
+}
+
+impl<'a, T: ?Sized> DerefMut for MutexGuard<'a, T> {
+    #[inline] fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> Drop for MutexGuard<'a, T> {
+    #[inline] fn drop(&mut self) { self.mutex.unlock() }
+}
+
+impl<'a, T: ?Sized + fmt::Debug> fmt::Debug for MutexGuard<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
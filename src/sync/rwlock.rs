@@ -0,0 +1,200 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! A reader-writer lock which puts waiting threads to sleep.
+use core::cell::UnsafeCell;
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut};
+use core::fmt;
+
+use cpu::interrupts;
+use spin;
+
+use super::WaitQueue;
+
+/// Who holds an `RwLock`.
+#[derive(Debug)]
+struct State { /// how many threads hold read locks
+               readers: usize
+             , /// whether a thread holds the write lock
+               writer: bool
+             , /// how many threads are waiting for the write lock
+               writers_waiting: usize
+             }
+
+/// A reader-writer lock, which may be held by any number of readers, or by
+/// one writer.
+///
+/// Threads waiting to write are preferred: once a writer is waiting, new
+/// readers wait too, so that a steady stream of readers can't keep writers
+/// waiting forever.
+pub struct RwLock<T: ?Sized> { state: spin::Mutex<State>
+                             , waiters: WaitQueue
+                             , data: UnsafeCell<T>
+                             }
+
+unsafe impl<T: ?Sized + Send> Send for RwLock<T> { }
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLock<T> { }
+
+/// A read lock on an `RwLock`, which is released when this is dropped.
+#[must_use]
+pub struct RwLockReadGuard<'a, T: ?Sized + 'a> {
+    lock: &'a RwLock<T>
+  , _not_send: PhantomData<*const ()>
+}
+
+/// The write lock on an `RwLock`, which is released when this is dropped.
+#[must_use]
+pub struct RwLockWriteGuard<'a, T: ?Sized + 'a> {
+    lock: &'a RwLock<T>
+  , _not_send: PhantomData<*const ()>
+}
+
+impl<T> RwLock<T> {
+    /// Returns a new, unlocked reader-writer lock protecting `data`.
+    pub const fn new(data: T) -> Self {
+        RwLock { state: spin::Mutex::new(State { readers: 0
+                                               , writer: false
+                                               , writers_waiting: 0 })
+               , waiters: WaitQueue::new()
+               , data: UnsafeCell::new(data)
+               }
+    }
+
+    /// Consumes the lock, returning the data it protected.
+    #[inline]
+    pub fn into_inner(self) -> T {
+        unsafe { self.data.into_inner() }
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    /// Take a read lock, sleeping until there's no writer.
+    ///
+    /// # Panics
+    /// + If this would block, and isn't called from a thread.
+    pub fn read(&self) -> RwLockReadGuard<T> {
+        self.waiters.wait_until(|| self.try_read_inner());
+        RwLockReadGuard { lock: self, _not_send: PhantomData }
+    }
+
+    /// Take a read lock, if there's no writer.
+    pub fn try_read(&self) -> Option<RwLockReadGuard<T>> {
+        if self.try_read_inner() {
+            Some(RwLockReadGuard { lock: self, _not_send: PhantomData })
+        } else {
+            None
+        }
+    }
+
+    /// Take the write lock, sleeping until there are no other readers or
+    /// writers.
+    ///
+    /// # Panics
+    /// + If this would block, and isn't called from a thread.
+    pub fn write(&self) -> RwLockWriteGuard<T> {
+        self.with_state(|state| state.writers_waiting += 1);
+        self.waiters.wait_until(|| self.try_write_inner(true));
+        RwLockWriteGuard { lock: self, _not_send: PhantomData }
+    }
+
+    /// Take the write lock, if there are no other readers or writers.
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<T>> {
+        if self.try_write_inner(false) {
+            Some(RwLockWriteGuard { lock: self, _not_send: PhantomData })
+        } else {
+            None
+        }
+    }
+
+    /// Borrows the protected data mutably.
+    ///
+    /// Since this borrows the lock mutably, no locking is needed.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.data.get() }
+    }
+
+    #[inline]
+    fn with_state<F, R>(&self, f: F) -> R
+    where F: FnOnce(&mut State) -> R {
+        let _guard = interrupts::disable();
+        f(&mut self.state.lock())
+    }
+
+    fn try_read_inner(&self) -> bool {
+        self.with_state(|state| {
+            if state.writer || state.writers_waiting > 0 { return false }
+            state.readers += 1;
+            true
+        })
+    }
+
+    /// Take the write lock if it's free. `waiting` is true if we were
+    /// counted as a waiting writer.
+    fn try_write_inner(&self, waiting: bool) -> bool {
+        self.with_state(|state| {
+            if state.writer || state.readers > 0 { return false }
+            if waiting { state.writers_waiting -= 1 }
+            state.writer = true;
+            true
+        })
+    }
+
+    fn read_unlock(&self) {
+        let last = self.with_state(|state| {
+            state.readers -= 1;
+            state.readers == 0
+        });
+        if last { self.waiters.notify_all(); }
+    }
+
+    fn write_unlock(&self) {
+        self.with_state(|state| state.writer = false);
+        self.waiters.notify_all();
+    }
+}
+
+impl<T: Default> Default for RwLock<T> {
+    #[inline] fn default() -> Self { RwLock::new(T::default()) }
+}
+
+impl<T: ?Sized> fmt::Debug for RwLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.with_state(|state| {
+            f.debug_struct("RwLock")
+             .field("readers", &state.readers)
+             .field("writer", &state.writer)
+             .finish()
+        })
+    }
+}
+
+impl<'a, T: ?Sized> Deref for RwLockReadGuard<'a, T> {
+    type Target = T;
+    #[inline] fn deref(&self) -> &T { unsafe { &*self.lock.data.get() } }
+}
+
+impl<'a, T: ?Sized> Drop for RwLockReadGuard<'a, T> {
+    #[inline] fn drop(&mut self) { self.lock.read_unlock() }
+}
+
+impl<'a, T: ?Sized> Deref for RwLockWriteGuard<'a, T> {
+    type Target = T;
+    #[inline] fn deref(&self) -> &T { unsafe { &*self.lock.data.get() } }
+}
+
+impl<'a, T: ?Sized> DerefMut for RwLockWriteGuard<'a, T> {
+    #[inline] fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> Drop for RwLockWriteGuard<'a, T> {
+    #[inline] fn drop(&mut self) { self.lock.write_unlock() }
+}
@@ -0,0 +1,89 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! A counting semaphore which puts waiting threads to sleep.
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::WaitQueue;
+
+/// A counting semaphore.
+///
+/// A semaphore holds a number of _permits_. Acquiring a permit sleeps until
+/// one is available; releasing a permit wakes a thread waiting for one.
+/// Permits may be released from anywhere, including interrupt handlers.
+pub struct Semaphore { permits: AtomicUsize
+                     , waiters: WaitQueue
+                     }
+
+/// A permit acquired from a `Semaphore`, which is released when this is
+/// dropped.
+#[must_use]
+pub struct SemaphoreGuard<'a> { semaphore: &'a Semaphore }
+
+impl Semaphore {
+    /// Returns a new semaphore with `permits` permits available.
+    pub const fn new(permits: usize) -> Self {
+        Semaphore { permits: AtomicUsize::new(permits)
+                  , waiters: WaitQueue::new()
+                  }
+    }
+
+    /// Take a permit, sleeping until one is available.
+    ///
+    /// # Panics
+    /// + If this would block, and isn't called from a thread.
+    pub fn acquire(&self) {
+        self.waiters.wait_until(|| self.try_acquire())
+    }
+
+    /// Take a permit, if one is available.
+    ///
+    /// # Returns
+    /// + `true` if a permit was taken
+    /// + `false` if there were none available.
+    pub fn try_acquire(&self) -> bool {
+        let mut permits = self.permits.load(Ordering::Acquire);
+        while permits > 0 {
+            let old = self.permits.compare_and_swap( permits, permits - 1
+                                                   , Ordering::AcqRel);
+            if old == permits { return true }
+            permits = old;
+        }
+        false
+    }
+
+    /// Give back a permit, waking a thread waiting for one.
+    pub fn release(&self) {
+        self.permits.fetch_add(1, Ordering::AcqRel);
+        self.waiters.notify_one();
+    }
+
+    /// Take a permit, sleeping until one is available, and give it back
+    /// when the returned guard is dropped.
+    pub fn access(&self) -> SemaphoreGuard {
+        self.acquire();
+        SemaphoreGuard { semaphore: self }
+    }
+
+    /// Returns the number of permits available.
+    #[inline]
+    pub fn available(&self) -> usize { self.permits.load(Ordering::Acquire) }
+}
+
+impl fmt::Debug for Semaphore {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Semaphore")
+         .field("available", &self.available())
+         .finish()
+    }
+}
+
+impl<'a> Drop for SemaphoreGuard<'a> {
+    #[inline] fn drop(&mut self) { self.semaphore.release() }
+}
@@ -0,0 +1,136 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Queues of threads waiting for something to happen.
+use core::ptr;
+
+use cpu::interrupts;
+use sos_intrusive::{List, RawLink};
+use sos_intrusive::list::{Node, OwnedRef};
+use spin;
+
+use thread::{self, Blocked};
+
+/// A thread waiting on a `WaitQueue`.
+///
+/// This lives on the waiting thread's stack, which stays put while the
+/// thread is blocked.
+struct Waiter { thread: Option<Blocked>
+              , next: RawLink<Waiter>
+              , prev: RawLink<Waiter>
+              }
+
+impl Node for Waiter {
+    #[inline] fn next(&self) -> &RawLink<Waiter> { &self.next }
+    #[inline] fn prev(&self) -> &RawLink<Waiter> { &self.prev }
+    #[inline] fn next_mut(&mut self) -> &mut RawLink<Waiter> { &mut self.next }
+    #[inline] fn prev_mut(&mut self) -> &mut RawLink<Waiter> { &mut self.prev }
+}
+
+type Waiters = List<ptr::Unique<Waiter>, Waiter>;
+
+/// A queue of threads waiting for a condition to become true.
+///
+/// Whatever makes the condition true should then call
+/// [`notify_one`](#method.notify_one) or [`notify_all`](#method.notify_all).
+/// Waiters are woken in the order they started waiting.
+pub struct WaitQueue { waiters: spin::Mutex<Waiters> }
+
+impl WaitQueue {
+    /// Returns a new, empty wait queue.
+    pub const fn new() -> Self {
+        WaitQueue { waiters: spin::Mutex::new(List::new()) }
+    }
+
+    /// Block the running thread until `condition` returns true.
+    ///
+    /// `condition` is called with the queue locked and interrupts disabled,
+    /// so it can't miss a notification, but it shouldn't do much. It may
+    /// have side effects, such as taking something it was waiting for, so
+    /// it's never called again once it has returned true.
+    ///
+    /// # Panics
+    /// + If this would block, and isn't called from a thread.
+    pub fn wait_until<F>(&self, mut condition: F)
+    where F: FnMut() -> bool {
+        loop {
+            {
+                let _guard = interrupts::disable();
+                let _waiters = self.waiters.lock();
+                if condition() { return }
+            }
+
+            let mut done = false;
+            let mut waiter = Waiter { thread: None
+                                    , next: RawLink::none()
+                                    , prev: RawLink::none()
+                                    };
+            thread::block(|blocked| {
+                let mut waiters = self.waiters.lock();
+                // we might have been notified since we last looked
+                if condition() {
+                    done = true;
+                    blocked.wake();
+                } else {
+                    waiter.thread = Some(blocked);
+                    waiters.push_back(unsafe {
+                        OwnedRef::from_raw(&mut waiter as *mut Waiter)
+                    });
+                }
+            });
+            // if we got this far without `done`, we were notified, which
+            // took us off the queue
+            if done { return }
+        }
+    }
+
+    /// Wake the thread which has been waiting longest.
+    ///
+    /// # Returns
+    /// + `true` if a thread was woken
+    /// + `false` if no threads were waiting.
+    pub fn notify_one(&self) -> bool {
+        let _guard = interrupts::disable();
+        let mut waiters = self.waiters.lock();
+        wake_front(&mut waiters)
+    }
+
+    /// Wake every waiting thread.
+    ///
+    /// # Returns
+    /// The number of threads woken.
+    pub fn notify_all(&self) -> usize {
+        let _guard = interrupts::disable();
+        let mut waiters = self.waiters.lock();
+        let mut woken = 0;
+        while wake_front(&mut waiters) { woken += 1; }
+        woken
+    }
+
+    /// Returns true if any threads are waiting.
+    #[inline]
+    pub fn has_waiters(&self) -> bool {
+        let _guard = interrupts::disable();
+        !self.waiters.lock().is_empty()
+    }
+}
+
+/// Take the front waiter off the queue, and wake its thread.
+fn wake_front(waiters: &mut Waiters) -> bool {
+    match waiters.pop_front() {
+        Some(mut waiter) => {
+            // once it's woken, the waiter may be gone, so don't touch it
+            // again after this
+            let thread = waiter.get_mut().thread.take()
+                               .expect("waiter had no thread");
+            thread.wake();
+            true
+        }
+      , None => false
+    }
+}
@@ -0,0 +1,267 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! System calls.
+//!
+//! Every system call does one thing, and has a number of its own, rather
+//! than multiplexing several operations on one call's arguments, in the
+//! manner of `ioctl`. The calls are declared in a single table, which
+//! generates each call's number in [`number`](number/index.html), and
+//! decodes each of its arguments into the type of the corresponding
+//! parameter, with that type's [`Arg`](trait.Arg.html) implementation.
+//!
+//! # Calling convention
+//! On `x86_64`, a system call is made with `syscall`, or with `int 0x80`
+//! while debugging. The call's number goes in `%rax`, and up to six
+//! arguments go in `%rdi`, `%rsi`, `%rdx`, `%r10`, `%r8` and `%r9`. The
+//! result comes back in `%rax`, and an [`Error`](enum.Error.html) code in
+//! `%rdx`, which is zero if the call succeeded. `syscall` clobbers `%rcx`
+//! and `%r11`, but every other register is preserved.
+use core::{cmp, fmt, result, str};
+
+use cpu::smap;
+
+use initrd;
+use process::{self, Handle};
+use thread;
+use time::Instant;
+use vm;
+
+/// Why a system call failed.
+///
+/// The code of each error is what's returned to user code.
+#[repr(usize)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Error { /// There's no system call with that number
+                 NoSuchCall = 1
+               , /// A pointer argument isn't to mapped user memory
+                 BadAddress = 2
+               , /// An argument is out of range
+                 InvalidArgument = 3
+               , /// There's no file at that path
+                 NoSuchFile = 4
+               , /// The handle doesn't refer to anything the call can use
+                 NoSuchHandle = 5
+               }
+
+impl Error {
+    /// Returns the code user code sees for this error.
+    #[inline] pub fn code(&self) -> usize { *self as usize }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match *self {
+            Error::NoSuchCall => "No such system call"
+          , Error::BadAddress => "Bad address"
+          , Error::InvalidArgument => "Invalid argument"
+          , Error::NoSuchFile => "No such file"
+          , Error::NoSuchHandle => "No such handle"
+        })
+    }
+}
+
+/// The result of a system call.
+pub type Result<T> = result::Result<T, Error>;
+
+/// A type which system call arguments can be decoded into.
+pub trait Arg: Sized {
+    /// Decode an argument from the raw value of its register.
+    fn decode(raw: usize) -> Result<Self>;
+}
+
+impl Arg for usize {
+    #[inline] fn decode(raw: usize) -> Result<Self> { Ok(raw) }
+}
+
+impl Arg for u64 {
+    #[inline] fn decode(raw: usize) -> Result<Self> { Ok(raw as u64) }
+}
+
+impl Arg for u32 {
+    #[inline]
+    fn decode(raw: usize) -> Result<Self> {
+        if raw > u32::max_value() as usize { Err(Error::InvalidArgument) }
+        else { Ok(raw as u32) }
+    }
+}
+
+impl Arg for bool {
+    #[inline]
+    fn decode(raw: usize) -> Result<Self> {
+        match raw {
+            0 => Ok(false)
+          , 1 => Ok(true)
+          , _ => Err(Error::InvalidArgument)
+        }
+    }
+}
+
+impl Arg for Handle {
+    #[inline]
+    fn decode(raw: usize) -> Result<Self> { Ok(Handle::from_number(raw)) }
+}
+
+/// An address in the user half of the address space.
+///
+/// Decoding one only checks which half of the address space it's in; the
+/// memory it points to still has to be checked before it's used.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct UserAddr(usize);
+
+impl UserAddr {
+    /// Returns this address as a `usize`.
+    #[inline] pub fn as_usize(&self) -> usize { self.0 }
+}
+
+impl Arg for UserAddr {
+    #[inline]
+    fn decode(raw: usize) -> Result<Self> {
+        if vm::is_user_address(raw) { Ok(UserAddr(raw)) }
+        else { Err(Error::BadAddress) }
+    }
+}
+
+/// The longest path a `ustar` archive can hold: a 155-byte prefix, a
+/// slash, and a 100-byte name.
+const MAX_PATH: usize = 256;
+
+/// Copy `dst.len()` bytes from the user address `src` into `dst`.
+///
+/// # Returns
+/// + `Err(BadAddress)` if any of the source isn't mapped user memory.
+fn copy_from_user(dst: &mut [u8], src: usize) -> Result<()> {
+    // the pages can't be unmapped under us, and `UserAccess` can't be held
+    // across a context switch
+    let _preempt = thread::disable_preemption();
+    if !vm::is_mapped_user(src, dst.len()) {
+        return Err(Error::BadAddress)
+    }
+    unsafe { smap::copy_from_user(dst, src as *const u8) }
+        .map_err(|_| Error::BadAddress)
+}
+
+/// Generates the system call table.
+///
+/// Each entry gives a call's number and its signature, which may have at
+/// most six parameters, each of a type which implements `Arg`. The body
+/// returns the call's result.
+macro_rules! syscalls {
+    ( $( $(#[$attr:meta])*
+         $number:tt => fn $name:ident( $($arg:ident: $ty:ty),* )
+                      $body:block )* ) => {
+        /// The number of each system call.
+        #[allow(non_upper_case_globals)]
+        pub mod number {
+            $( $(#[$attr])* pub const $name: usize = $number; )*
+        }
+
+        $( $(#[$attr])*
+           pub fn $name( $($arg: $ty),* ) -> Result<usize> $body )*
+
+        /// Decode the arguments to system call number `number`, and call
+        /// it.
+        fn call(number: usize, args: &[usize; 6]) -> Result<usize> {
+            match number {
+                $( $number => {
+                    let mut _args = args.iter();
+                    $name( $( <$ty as Arg>::decode(*_args.next()
+                                                         .unwrap())? ),* )
+                } )*
+                _ => Err(Error::NoSuchCall)
+            }
+        }
+    }
+}
+
+syscalls! {
+    /// Exit the calling thread.
+    ///
+    /// If it's the first thread in its process to exit, `status` becomes
+    /// the process's exit status.
+    0 => fn exit(status: usize) {
+        process::exit(status)
+    }
+
+    /// Write `len` bytes from `buf` to the console.
+    ///
+    /// Returns the number of bytes written.
+    1 => fn write(buf: UserAddr, len: usize) {
+        let mut chunk = [0u8; 128];
+        let mut done = 0;
+        while done < len {
+            let n = cmp::min(len - done, chunk.len());
+            copy_from_user(&mut chunk[..n], buf.as_usize() + done)?;
+            for &byte in &chunk[..n] {
+                print!("{}", byte as char);
+            }
+            done += n;
+        }
+        Ok(len)
+    }
+
+    /// Give up the CPU to the next thread that's ready to run.
+    2 => fn yield_now() {
+        thread::yield_now();
+        Ok(0)
+    }
+
+    /// Put the calling thread to sleep for `nanos` nanoseconds.
+    3 => fn sleep(nanos: u64) {
+        thread::sleep(nanos);
+        Ok(0)
+    }
+
+    /// Returns the number of nanoseconds since the kernel started.
+    4 => fn time() {
+        Ok(Instant::now().as_nanos() as usize)
+    }
+
+    /// Start the program at the `len`-byte path `path` in the initial
+    /// ramdisk, as a child of the calling process, with its path as its
+    /// only argument.
+    ///
+    /// Returns the calling process's handle on the child. Fails with
+    /// `InvalidArgument` if the file isn't a program that could be loaded,
+    /// or the caller isn't a user process.
+    5 => fn spawn(path: UserAddr, len: usize) {
+        let mut buf = [0u8; MAX_PATH];
+        if len > buf.len() { return Err(Error::InvalidArgument) }
+        copy_from_user(&mut buf[..len], path.as_usize())?;
+        let path = str::from_utf8(&buf[..len])
+                      .map_err(|_| Error::InvalidArgument)?;
+        let file = initrd::initrd()
+            .and_then(|archive| archive.files().find(|file| file.is(path)))
+            .ok_or(Error::NoSuchFile)?;
+        let parent = process::current().ok_or(Error::InvalidArgument)?;
+        parent.spawn_child(file.name, file.data, &[path.as_bytes()], &[])
+              .map(|child| child.number())
+              .map_err(|_| Error::InvalidArgument)
+    }
+
+    /// Wait for the child process `child` to exit, and reap it.
+    ///
+    /// Returns the child's exit status.
+    6 => fn wait(child: Handle) {
+        let parent = process::current().ok_or(Error::NoSuchHandle)?;
+        parent.wait(child).map_err(|_| Error::NoSuchHandle)
+    }
+}
+
+/// Make system call number `number`, with the raw values of the registers
+/// its arguments are passed in.
+///
+/// This is called by the architecture's entry stubs, with interrupts
+/// enabled.
+pub fn dispatch(number: usize, args: [usize; 6]) -> Result<usize> {
+    let result = call(number, &args);
+    if let Err(why) = result {
+        trace!("system call {} failed: {}", number, why);
+    }
+    result
+}
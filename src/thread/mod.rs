@@ -0,0 +1,534 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Kernel threads.
+//!
+//! Each thread has its own guard-paged [stack](stack/index.html), saved
+//! execution [`Context`](../../cpu/context/struct.Context.html), and FPU
+//! state. Threads are scheduled preemptively: each CPU runs the threads on
+//! its run queue in the order its scheduling [`Policy`](enum.Policy.html)
+//! chooses, switching to the next one when the running thread blocks,
+//! yields, exits, or is preempted. Threads never move between CPUs.
+//!
+//! The policy is chosen at boot, with the `sched=` option on the kernel
+//! command line, and defaults to `fair`.
+//!
+//! A thread may also run in user mode, in a user
+//! [`Process`](../process/struct.Process.html), if it's spawned with
+//! [`spawn_user`](fn.spawn_user.html). It still has a kernel stack, which
+//! interrupts and exceptions in user mode run on. Whenever a thread is
+//! switched to, its process's address space is loaded, or the kernel's,
+//! for kernel threads.
+//!
+//! The code which calls [`initialize`](fn.initialize.html) (or
+//! [`initialize_cpu`](fn.initialize_cpu.html), on other CPUs) becomes that
+//! CPU's _idle thread_, which should then call [`idle`](fn.idle.html). The
+//! idle thread runs on the CPU's boot stack, whenever no other threads are
+//! ready to run.
+use alloc::arc::Arc;
+use alloc::boxed::Box;
+use core::{fmt, mem, ptr};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use cpu::context::Context;
+use cpu::{fpu, interrupts, percpu};
+use memory::VAddr;
+use paging::arch::ActivePageTable;
+use sos_alloc::FrameAllocator;
+use sos_intrusive::RawLink;
+use sos_intrusive::list::Node;
+use sos_sched::{Entity, Schedulable};
+
+use arch;
+use process::Process;
+use time::{self, Instant};
+use vm::AddressSpace;
+
+pub mod stack;
+mod sched;
+
+pub use self::sched::{ PreemptGuard, TICK, TIME_SLICE, disable_preemption
+                     , policy, set_policy };
+pub use sos_sched::{DEFAULT_PRIORITY, PRIORITY_LEVELS};
+pub use sos_sched::Kind as Policy;
+
+/// Uniquely identifies a thread.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct ThreadId(usize);
+
+impl fmt::Display for ThreadId {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+/// What a thread is doing.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum State { /// Running, or waiting on a run queue to run
+                 Runnable
+               , /// Waiting for something to [wake](struct.Blocked.html) it
+                 Blocked
+               , /// Waiting for a timer to wake it
+                 Sleeping
+               , /// Exited, and waiting to be freed
+                 Dead
+               }
+
+/// A kernel thread's control block.
+pub struct Thread { id: ThreadId
+                  , name: &'static str
+                  , state: State
+                  , /// the CPU this thread runs on
+                    cpu: usize
+                  , /// true from when this thread is switched to until
+                    /// switching away from it is finished
+                    running: bool
+                  , /// saved when the thread is switched away from
+                    context: Context
+                  , fpu: fpu::State
+                  , /// the scheduling policy's bookkeeping
+                    sched: Entity
+                  , /// `None` for idle threads
+                    stack: Option<stack::Stack>
+                  , /// `None` for kernel threads, and exiting threads
+                    space: Option<Arc<AddressSpace>>
+                  , /// `None` for kernel threads, and exiting threads
+                    process: Option<Arc<Process>>
+                  , next: RawLink<Thread>
+                  , prev: RawLink<Thread>
+                  }
+
+// threads are only touched by the CPU running them, or with its run queue
+// locked.
+unsafe impl Send for Thread { }
+
+impl Thread {
+    /// Returns this thread's ID.
+    #[inline] pub fn id(&self) -> ThreadId { self.id }
+
+    /// Returns this thread's name.
+    #[inline] pub fn name(&self) -> &'static str { self.name }
+
+    /// Returns this thread's state.
+    #[inline] pub fn state(&self) -> State { self.state }
+
+    /// Returns this thread's priority. Priority 0 is the highest.
+    #[inline] pub fn priority(&self) -> usize { self.sched.priority() }
+
+    /// Returns the address space this thread runs in, or `None` if it's a
+    /// kernel thread.
+    #[inline] pub fn space(&self) -> Option<&Arc<AddressSpace>> {
+        self.space.as_ref()
+    }
+
+    /// Returns the process this thread belongs to, or `None` if it's a
+    /// kernel thread.
+    #[inline] pub fn process(&self) -> Option<&Arc<Process>> {
+        self.process.as_ref()
+    }
+
+    /// Load this thread's address space and kernel stack on the current
+    /// CPU, before switching to it.
+    unsafe fn load(&self) {
+        if let Some(ref stack) = self.stack {
+            arch::user::set_kernel_stack(stack.top());
+        }
+        match self.space {
+            Some(ref space) => space.activate()
+          , None => ::vm::activate_kernel()
+        }
+    }
+}
+
+impl fmt::Debug for Thread {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Thread {} ({})", self.id, self.name)
+    }
+}
+
+impl Node for Thread {
+    #[inline] fn next(&self) -> &RawLink<Thread> { &self.next }
+    #[inline] fn prev(&self) -> &RawLink<Thread> { &self.prev }
+    #[inline] fn next_mut(&mut self) -> &mut RawLink<Thread> { &mut self.next }
+    #[inline] fn prev_mut(&mut self) -> &mut RawLink<Thread> { &mut self.prev }
+}
+
+impl Schedulable for Thread {
+    #[inline] fn entity(&self) -> &Entity { &self.sched }
+    #[inline] fn entity_mut(&mut self) -> &mut Entity { &mut self.sched }
+}
+
+/// A blocked or sleeping thread, which may be woken.
+///
+/// If this is dropped without calling [`wake`](#method.wake), the thread
+/// stays blocked forever.
+pub struct Blocked { thread: *mut Thread }
+
+// waking a thread locks its CPU's run queue
+unsafe impl Send for Blocked { }
+
+impl Blocked {
+    /// Returns the blocked thread's ID.
+    #[inline]
+    pub fn id(&self) -> ThreadId { unsafe { (*self.thread).id } }
+
+    /// Make the thread runnable again.
+    #[inline]
+    pub fn wake(self) {
+        unsafe { sched::wake(self.thread); }
+    }
+}
+
+impl fmt::Debug for Blocked {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Blocked({})", self.id())
+    }
+}
+
+/// The next thread ID to hand out.
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns a new thread control block, which isn't on any run queue.
+fn new_thread( name: &'static str, cpu: usize, context: Context
+             , stack: Option<stack::Stack>
+             , process: Option<(Arc<Process>, Arc<AddressSpace>)>)
+             -> *mut Thread {
+    let (process, space) = match process {
+        Some((process, space)) => (Some(process), Some(space))
+      , None => (None, None)
+    };
+    Box::into_raw(Box::new(Thread {
+        id: ThreadId(NEXT_ID.fetch_add(1, Ordering::SeqCst))
+      , name: name
+      , state: State::Runnable
+      , cpu: cpu
+      , running: false
+      , context: context
+      , fpu: fpu::State::new()
+      , sched: Entity::default()
+      , stack: stack
+      , space: space
+      , process: process
+      , next: RawLink::none()
+      , prev: RawLink::none()
+    }))
+}
+
+/// Map the thread stacks, and start running threads on the bootstrap
+/// processor.
+///
+/// # Returns
+/// + `Ok(n)` with the number of threads which may be spawned
+/// + `Err` if no thread stacks could be mapped.
+pub fn initialize<A>(page_table: &mut ActivePageTable, frames: &mut A)
+                    -> Result<usize, &'static str>
+where A: FrameAllocator {
+    let n_stacks = stack::initialize(page_table, frames)?;
+    initialize_cpu()?;
+    Ok(n_stacks)
+}
+
+/// Start running threads on the current CPU, turning the caller into its
+/// idle thread.
+///
+/// The caller should then call [`idle`](fn.idle.html).
+pub fn initialize_cpu() -> Result<(), &'static str> {
+    if !percpu::is_initialized() {
+        return Err("Per-CPU data isn't set up")
+    }
+    let cpu = unsafe { percpu::cpu() };
+    if sched::is_running(cpu) {
+        return Err("Threads are already running on this CPU")
+    }
+    let idle = new_thread("idle", cpu, Context::empty(), None, None);
+    unsafe {
+        (*idle).running = true;
+        sched::start_cpu(idle);
+    }
+    Ok(())
+}
+
+/// Run the current CPU's idle thread.
+///
+/// This runs any threads which are ready, and halts the CPU until the next
+/// interrupt when there are none.
+pub fn idle() -> ! {
+    loop {
+        let guard = interrupts::disable();
+        if sched::has_ready(&guard) {
+            sched::schedule(&guard);
+        }
+        // `sti` doesn't take effect until after the next instruction, so
+        // nothing can be woken between checking the run queue and halting
+        mem::forget(guard);
+        unsafe { asm!("sti; hlt" :::: "volatile"); }
+    }
+}
+
+/// Spawn a new thread called `name` on the current CPU, which will run
+/// `entry`.
+///
+/// The thread gets the default priority, and exits when `entry` returns.
+pub fn spawn(name: &'static str, entry: fn())
+            -> Result<ThreadId, &'static str> {
+    if !percpu::is_initialized() {
+        return Err("Threads aren't running on this CPU")
+    }
+    spawn_on(unsafe { percpu::cpu() }, name, entry)
+}
+
+/// Spawn a new thread called `name` on CPU number `cpu`, which will run
+/// `entry`.
+pub fn spawn_on(cpu: usize, name: &'static str, entry: fn())
+               -> Result<ThreadId, &'static str> {
+    spawn_with(cpu, name, start, entry as usize, None)
+}
+
+/// Spawn a new thread called `name` on the current CPU, which will run in
+/// user mode in `process`, starting at `entry` with its stack pointer set
+/// to `user_stack`.
+///
+/// `entry` and `user_stack` should already be mapped in the process's
+/// address space. If the thread causes an exception, it's killed.
+///
+/// This doesn't add the thread to the process; the caller should, before
+/// the thread can run.
+pub fn spawn_user( name: &'static str, process: Arc<Process>
+                 , entry: VAddr, user_stack: VAddr)
+                 -> Result<ThreadId, &'static str> {
+    if !percpu::is_initialized() {
+        return Err("Threads aren't running on this CPU")
+    }
+    let space = process.space().ok_or("The process has exited")?;
+    let cpu = unsafe { percpu::cpu() };
+    let start = Box::into_raw(Box::new((entry, user_stack)));
+    spawn_with( cpu, name, start_user, start as usize
+              , Some((process, space)))
+        .map_err(|why| {
+            unsafe { mem::drop(Box::from_raw(start)); }
+            why
+        })
+}
+
+/// Spawn a new thread called `name` on CPU number `cpu`, which will call
+/// `start(arg)` on its own kernel stack.
+fn spawn_with( cpu: usize, name: &'static str
+             , start: extern "C" fn(usize) -> !, arg: usize
+             , process: Option<(Arc<Process>, Arc<AddressSpace>)>)
+             -> Result<ThreadId, &'static str> {
+    if !sched::is_running(cpu) {
+        return Err("Threads aren't running on that CPU")
+    }
+    let stack = stack::allocate().ok_or("No free thread stacks")?;
+    let context = Context::new(stack.top().as_mut_ptr(), start, arg);
+    let thread = new_thread(name, cpu, context, Some(stack), process);
+    let id = unsafe { (*thread).id };
+    unsafe { sched::enqueue(thread); }
+    Ok(id)
+}
+
+/// Returns the ID of the running thread, or `None` if the current CPU isn't
+/// running threads.
+pub fn current() -> Option<ThreadId> {
+    if !percpu::is_initialized() { return None }
+    let guard = interrupts::disable();
+    let current = sched::current(&guard);
+    if current.is_null() { None }
+    else { Some(unsafe { (*current).id }) }
+}
+
+/// Give up the CPU to the next thread the scheduling policy picks.
+///
+/// The running thread goes back on the run queue. If no other threads are
+/// ready, this returns immediately.
+pub fn yield_now() {
+    if !percpu::is_initialized() { return }
+    let guard = interrupts::disable();
+    sched::schedule(&guard);
+}
+
+/// Change the running thread's priority.
+///
+/// # Returns
+/// + `Ok(())` if the priority was changed
+/// + `Err` if the priority is out of range, or this isn't called from a
+///   thread.
+pub fn set_priority(priority: usize) -> Result<(), &'static str> {
+    if priority >= PRIORITY_LEVELS {
+        return Err("Priority is out of range")
+    }
+    if !percpu::is_initialized() {
+        return Err("Only threads have priorities")
+    }
+    let current = current_thread();
+    if current.is_null() {
+        return Err("Only threads have priorities")
+    }
+    unsafe { sched::update_priority(current, |_| Some(priority)); }
+    Ok(())
+}
+
+/// Returns a pointer to the running thread's control block.
+///
+/// This is null if the current CPU isn't running threads, or is running
+/// its idle thread.
+pub fn current_thread() -> *mut Thread {
+    if !percpu::is_initialized() { return ptr::null_mut() }
+    let guard = interrupts::disable();
+    let current = sched::current(&guard);
+    if current == sched::idle(&guard) { ptr::null_mut() }
+    else { current }
+}
+
+/// Raise the priority of `thread`, which may be on any CPU, to at least
+/// `priority`.
+///
+/// This is for priority inheritance: a thread which is blocked waiting on
+/// `thread` can lend it its priority.
+///
+/// # Returns
+/// + `Some(old)` with the thread's previous priority, if it was raised
+/// + `None` if its priority was already at least `priority`.
+///
+/// # Safety
+/// + `thread` must be valid, and must not exit during this call.
+pub unsafe fn boost_priority(thread: *mut Thread, priority: usize)
+                            -> Option<usize> {
+    // priority 0 is the highest
+    sched::update_priority(thread, |old| {
+        if old > priority { Some(priority) } else { None }
+    })
+}
+
+/// Block the running thread until it's woken.
+///
+/// `park` is called with interrupts disabled, with a handle which will wake
+/// the thread. It should store the handle somewhere that whatever will wake
+/// the thread can find it. If the handle is used before the thread has
+/// finished blocking, this returns immediately.
+///
+/// # Panics
+/// + If called from an idle thread, or a CPU which isn't running threads.
+pub fn block<F>(park: F)
+where F: FnOnce(Blocked) {
+    block_as(State::Blocked, park)
+}
+
+fn block_as<F>(state: State, park: F)
+where F: FnOnce(Blocked) {
+    assert!(percpu::is_initialized(), "Only threads may block");
+    let guard = interrupts::disable();
+    let current = sched::current(&guard);
+    assert!( !current.is_null() && current != sched::idle(&guard)
+           , "Only threads may block");
+    unsafe { sched::set_state(current, state); }
+    park(Blocked { thread: current });
+    sched::schedule(&guard);
+}
+
+/// Put the running thread to sleep until `deadline` has passed.
+///
+/// If the kernel's timer queue is full, this yields the CPU instead, until
+/// `deadline` has passed.
+pub fn sleep_until(deadline: Instant) {
+    while !deadline.has_passed() {
+        block_as(State::Sleeping, |thread| {
+            let data = thread.thread as usize;
+            if time::add_timer_at(deadline, wake_sleeper, data).is_err() {
+                thread.wake();
+            }
+        });
+    }
+}
+
+/// Put the running thread to sleep for `nanos` nanoseconds.
+#[inline]
+pub fn sleep(nanos: u64) {
+    sleep_until(Instant::now() + nanos)
+}
+
+/// Timer callback which wakes a sleeping thread.
+fn wake_sleeper(thread: usize) {
+    Blocked { thread: thread as *mut Thread }.wake()
+}
+
+/// Exit the running thread.
+///
+/// If it's a user thread, it leaves its process first, which may free the
+/// process and its address space, so this must be called with interrupts
+/// enabled. Its stack and control block are freed by the next thread to
+/// run.
+///
+/// # Panics
+/// + If called from an idle thread, or a CPU which isn't running threads.
+pub fn exit() -> ! {
+    let current = current_thread();
+    assert!(!current.is_null(), "Only threads may exit");
+    unsafe { leave_process(current); }
+
+    let guard = interrupts::disable();
+    unsafe { sched::set_state(current, State::Dead); }
+    sched::schedule(&guard);
+    unreachable!("Exited thread was switched back to!")
+}
+
+/// Take the running thread out of its process, and give up its address
+/// space, before it exits.
+///
+/// This can't wait until the thread is freed, since that happens with
+/// interrupts disabled.
+unsafe fn leave_process(thread: *mut Thread) {
+    let space = {
+        // the thread mustn't be switched back to with its space loaded
+        let _preempt = disable_preemption();
+        ::vm::activate_kernel();
+        (*thread).space.take()
+    };
+    mem::drop(space);
+    if let Some(process) = (*thread).process.take() {
+        process.leave((*thread).id);
+    }
+}
+
+/// Handle a timer interrupt, preempting the running thread if its time
+/// slice is up.
+///
+/// This should be called at the end of timer interrupt handlers, after the
+/// end of the interrupt is signalled.
+#[inline]
+pub fn tick() { sched::tick() }
+
+/// Entry point for new threads, called on the thread's own stack with the
+/// address of its entry function.
+extern "C" fn start(entry: usize) -> ! {
+    {
+        let guard = interrupts::disable();
+        sched::finish_switch(&guard);
+    }
+    // we were switched to with interrupts disabled, by a thread whose
+    // interrupt guard is still on its own stack.
+    unsafe { interrupts::idt::Idt::enable_interrupts() };
+
+    let entry: fn() = unsafe { mem::transmute(entry) };
+    entry();
+    exit()
+}
+
+/// Entry point for new user threads, called on the thread's kernel stack
+/// with a boxed pair of its user entry point and stack pointer.
+extern "C" fn start_user(start: usize) -> ! {
+    {
+        let guard = interrupts::disable();
+        sched::finish_switch(&guard);
+    }
+    let start = unsafe { Box::from_raw(start as *mut (VAddr, VAddr)) };
+    let (entry, stack) = *start;
+    // entering user mode enables interrupts
+    unsafe { arch::user::enter(entry, stack) }
+}
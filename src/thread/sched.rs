@@ -0,0 +1,393 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! The per-CPU scheduler.
+//!
+//! Each CPU has its own run queue, and a thread only ever runs on the CPU it
+//! was spawned on. Other CPUs may put threads on a CPU's run queue to wake
+//! them, so a thread's state and `running` flag are only changed with its
+//! CPU's run queue locked.
+//!
+//! The order in which threads run is up to each CPU's scheduling
+//! [policy](../../sos_sched/index.html), which is chosen at boot. The idle
+//! thread is never on the run queue, and the policy doesn't know about it.
+//!
+//! When a thread is switched away from, the thread switched to _finishes_
+//! the switch: it puts the previous thread back on the run queue if it's
+//! still runnable, or frees it if it's dead. Until then, the previous thread
+//! is still marked as `running`, so that a thread which is woken while it's
+//! still switching away won't be put on the run queue twice.
+use alloc::boxed::Box;
+use core::marker::PhantomData;
+use core::{mem, ptr};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use cpu::context;
+use cpu::{fpu, interrupts, percpu};
+use cpu::percpu::Pinned;
+use sos_intrusive::list::OwnedRef;
+use sos_sched::{Fair, FixedPriority, Kind, Policy, RoundRobin};
+use spin::Mutex;
+
+use time::{self, Instant};
+use super::{State, Thread};
+
+/// How often the scheduler should be ticked, in nanoseconds.
+pub const TICK: u64 = time::NANOS_PER_MILLI;
+
+/// How long a thread may run before it's preempted by another thread of
+/// the same priority, in nanoseconds.
+pub const TIME_SLICE: u64 = 10 * time::NANOS_PER_MILLI;
+
+/// How long a thread may wait before its priority is raised, under the
+/// priority policy.
+const AGING_INTERVAL: u64 = 20 * TIME_SLICE;
+
+/// How often every thread should run, under the fair policy.
+const LATENCY: u64 = 2 * TIME_SLICE;
+
+/// The shortest time a thread may run before it's preempted, under the
+/// fair policy.
+const MIN_GRANULARITY: u64 = 2 * TICK;
+
+type RunQueue = Box<Policy<Thread> + Send>;
+
+/// This CPU's scheduler state.
+struct Cpu { /// the running thread, or null if this CPU isn't running threads
+             current: *mut Thread
+           , /// the thread to run when nothing else is runnable
+             idle: *mut Thread
+           , /// the thread switched away from, until the switch is finished
+             prev: *mut Thread
+           , /// whether the running thread should be preempted
+             need_resched: bool
+           , /// how many `PreemptGuard`s are alive
+             preempt_count: usize
+           }
+
+// only ever accessed by its own CPU
+unsafe impl Send for Cpu { }
+
+percpu! {
+    /// Threads which are ready to run on this CPU, or `None` if it isn't
+    /// running threads.
+    static RUN_QUEUE: Mutex<Option<RunQueue>> = Mutex::new(None);
+    /// This CPU's scheduler state.
+    static CPU: Cpu = Cpu { current: 0 as *mut Thread
+                          , idle: 0 as *mut Thread
+                          , prev: 0 as *mut Thread
+                          , need_resched: false
+                          , preempt_count: 0
+                          };
+}
+
+/// The policy CPUs use when they start running threads.
+static POLICY: Mutex<Kind> = Mutex::new(Kind::Fair);
+
+/// Bitmap of the CPUs which are running threads.
+static RUNNING_CPUS: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns true if CPU number `cpu` is running threads.
+#[inline]
+pub fn is_running(cpu: usize) -> bool {
+    cpu < 8 * mem::size_of::<usize>()
+        && RUNNING_CPUS.load(Ordering::SeqCst) & (1 << cpu) != 0
+}
+
+/// Returns the scheduling policy which CPUs use.
+#[inline]
+pub fn policy() -> Kind { *POLICY.lock() }
+
+/// Set the scheduling policy which CPUs use.
+///
+/// # Returns
+/// + `Ok(())` if the policy was set
+/// + `Err` if any CPU is already running threads, since each CPU's policy
+///   is chosen when it starts.
+pub fn set_policy(kind: Kind) -> Result<(), &'static str> {
+    let mut policy = POLICY.lock();
+    if RUNNING_CPUS.load(Ordering::SeqCst) != 0 {
+        return Err("Threads are already running")
+    }
+    *policy = kind;
+    Ok(())
+}
+
+/// Returns a new, empty run queue which uses the policy `kind`.
+fn new_run_queue(kind: Kind) -> RunQueue {
+    match kind {
+        Kind::RoundRobin => Box::new(RoundRobin::new(TIME_SLICE))
+      , Kind::Priority =>
+            Box::new(FixedPriority::new(TIME_SLICE, AGING_INTERVAL))
+      , Kind::Fair => Box::new(Fair::new(LATENCY, MIN_GRANULARITY))
+    }
+}
+
+/// Returns the current time, as the scheduling policies count it.
+#[inline]
+fn now() -> u64 { Instant::now().as_nanos() }
+
+/// Start running threads on the current CPU, with `idle` (the code which is
+/// already running) as its idle thread.
+///
+/// # Safety
+/// + The current CPU's per-CPU data must be set up.
+/// + `idle` must be a valid thread which never exits.
+pub unsafe fn start_cpu(idle: *mut Thread) {
+    let guard = interrupts::disable();
+    {
+        // hold the policy lock, so that it can't change under us
+        let policy = POLICY.lock();
+        *RUN_QUEUE.get(&guard).lock() = Some(new_run_queue(*policy));
+        RUNNING_CPUS.fetch_or(1 << percpu::cpu(), Ordering::SeqCst);
+    }
+    CPU.with_mut(&guard, |cpu| {
+        cpu.current = idle;
+        cpu.idle = idle;
+    });
+    fpu::switch_to(&mut (*idle).fpu);
+}
+
+/// Returns the thread running on the current CPU, or null if it isn't
+/// running threads.
+#[inline]
+pub fn current<P: Pinned>(pinned: &P) -> *mut Thread {
+    CPU.with_mut(pinned, |cpu| cpu.current)
+}
+
+/// Returns the current CPU's idle thread.
+#[inline]
+pub fn idle<P: Pinned>(pinned: &P) -> *mut Thread {
+    CPU.with_mut(pinned, |cpu| cpu.idle)
+}
+
+/// Returns true if there are threads waiting to run on the current CPU.
+#[inline]
+pub fn has_ready<P: Pinned>(pinned: &P) -> bool {
+    RUN_QUEUE.get(pinned).lock()
+             .as_ref()
+             .map_or(false, |queue| !queue.is_empty())
+}
+
+/// Call `f` with CPU number `cpu`'s run queue locked.
+///
+/// Interrupts must be disabled, so that the lock can't be taken by an
+/// interrupt handler on this CPU while it's held.
+fn with_run_queue<F, R>(cpu: usize, f: F) -> R
+where F: FnOnce(&mut RunQueue) -> R {
+    let mut queue = RUN_QUEUE.on(cpu).lock();
+    f(queue.as_mut().expect("CPU isn't running threads"))
+}
+
+/// Set the state of `thread`, which must be running on the current CPU.
+///
+/// # Safety
+/// + `thread` must be valid.
+pub unsafe fn set_state(thread: *mut Thread, state: State) {
+    let _guard = interrupts::disable();
+    with_run_queue((*thread).cpu, |_| (*thread).state = state)
+}
+
+/// Change the priority of `thread`, which may be on any CPU.
+///
+/// `f` is called with the thread's priority, and returns its new priority,
+/// or `None` to leave it alone.
+///
+/// # Returns
+/// + `Some(old)` with the thread's previous priority, if it was changed
+/// + `None` if `f` left it alone.
+///
+/// # Safety
+/// + `thread` must be valid.
+pub unsafe fn update_priority<F>(thread: *mut Thread, f: F) -> Option<usize>
+where F: FnOnce(usize) -> Option<usize> {
+    let _guard = interrupts::disable();
+    with_run_queue((*thread).cpu, |queue| {
+        let old = (*thread).sched.priority();
+        let priority = match f(old) {
+            Some(priority) => priority
+          , None => return None
+        };
+        // the policy may order its run queue by priority, so a waiting
+        // thread has to be taken off it while its priority changes
+        match queue.remove(&*thread) {
+            Some(mut link) => {
+                link.get_mut().sched.set_priority(priority);
+                queue.enqueue(link, now());
+            }
+          , None => (*thread).sched.set_priority(priority)
+        }
+        Some(old)
+    })
+}
+
+/// Put a new thread on its CPU's run queue.
+///
+/// # Safety
+/// + `thread` must be a new, runnable thread, allocated with `Box`.
+pub unsafe fn enqueue(thread: *mut Thread) {
+    let _guard = interrupts::disable();
+    with_run_queue((*thread).cpu, |queue| {
+        queue.enqueue(OwnedRef::from_raw(thread), now())
+    })
+}
+
+/// Make a blocked or sleeping thread runnable again.
+///
+/// # Returns
+/// + `true` if the thread was woken
+/// + `false` if it was already runnable (or dead).
+///
+/// # Safety
+/// + `thread` must be valid.
+pub unsafe fn wake(thread: *mut Thread) -> bool {
+    let _guard = interrupts::disable();
+    with_run_queue((*thread).cpu, |queue| {
+        match (*thread).state {
+            State::Blocked | State::Sleeping => {
+                (*thread).state = State::Runnable;
+                // if it's still switching away, finishing the switch will
+                // put it on the run queue
+                if !(*thread).running {
+                    queue.enqueue(OwnedRef::from_raw(thread), now());
+                }
+                true
+            }
+          , _ => false
+        }
+    })
+}
+
+/// Switch to the thread which the current CPU's policy picks to run next.
+///
+/// If the running thread is still runnable, it goes back on the run queue,
+/// and keeps running if no other threads are ready. Otherwise, the idle
+/// thread runs if no other threads are ready.
+///
+/// Interrupts must be disabled, which taking an interrupt guard proves.
+pub fn schedule(guard: &interrupts::Guard) {
+    let current = current(guard);
+    if current.is_null() { return }
+    let idle = idle(guard);
+
+    let next = with_run_queue(unsafe { percpu::cpu() }, |queue| {
+        let now = now();
+        if current != idle {
+            queue.put_prev(unsafe { &mut *current }, now);
+        }
+        let next = match queue.pick_next(now) {
+            Some(mut next) => next.get_mut() as *mut Thread
+          , None if unsafe { (*current).state } == State::Runnable => current
+          , None => idle
+        };
+        if next != current { unsafe { (*next).running = true; } }
+        next
+    });
+    if next == current {
+        CPU.with_mut(guard, |cpu| cpu.need_resched = false);
+        return
+    }
+
+    CPU.with_mut(guard, |cpu| {
+        cpu.prev = current;
+        cpu.current = next;
+        cpu.need_resched = false;
+    });
+    unsafe {
+        fpu::switch_to(&mut (*next).fpu);
+        (*next).load();
+        context::switch(&mut (*current).context, &(*next).context);
+    }
+    // we've been switched back to
+    finish_switch(guard);
+}
+
+/// Finish switching away from the previous thread.
+///
+/// This must be called by every thread after it's switched to, including
+/// new threads.
+pub fn finish_switch<P: Pinned>(pinned: &P) {
+    let (prev, idle) = CPU.with_mut(pinned, |cpu| {
+        (mem::replace(&mut cpu.prev, ptr::null_mut()), cpu.idle)
+    });
+    if prev.is_null() { return }
+
+    let dead = with_run_queue(unsafe { percpu::cpu() }, |queue| unsafe {
+        (*prev).running = false;
+        match (*prev).state {
+            State::Runnable if prev != idle => {
+                queue.enqueue(OwnedRef::from_raw(prev), now());
+                false
+            }
+          , State::Dead => true
+          , _ => false
+        }
+    });
+
+    if dead {
+        unsafe {
+            trace!("freeing exited {:?}", &*prev);
+            fpu::release(&mut (*prev).fpu);
+            mem::drop(Box::from_raw(prev));
+        }
+    }
+}
+
+/// Handle a timer interrupt.
+///
+/// If the current CPU's policy says the running thread should be preempted,
+/// and preemption isn't disabled, this switches to the next thread. It
+/// should be called at the end of timer interrupt handlers, after the end of
+/// the interrupt is signalled.
+pub fn tick() {
+    if !percpu::is_initialized() { return }
+    let guard = interrupts::disable();
+    let (current, idle) = CPU.with_mut(&guard, |cpu| (cpu.current, cpu.idle));
+    // the idle thread checks the run queue itself after every interrupt
+    if current.is_null() || current == idle { return }
+
+    let resched = with_run_queue(unsafe { percpu::cpu() }, |queue| {
+        queue.tick(unsafe { &mut *current }, now())
+    });
+    let preempt = CPU.with_mut(&guard, |cpu| {
+        cpu.need_resched = cpu.need_resched || resched;
+        cpu.need_resched && cpu.preempt_count == 0
+    });
+    if preempt { schedule(&guard) }
+}
+
+/// Disable preemption on the current CPU until the returned guard is
+/// dropped.
+///
+/// The running thread may still block or yield.
+pub fn disable_preemption() -> PreemptGuard {
+    let guard = interrupts::disable();
+    CPU.with_mut(&guard, |cpu| cpu.preempt_count += 1);
+    PreemptGuard { _not_send: PhantomData }
+}
+
+/// A guard which keeps the running thread from being preempted while it's
+/// alive. See [`disable_preemption`](fn.disable_preemption.html).
+///
+/// Since the thread can't be preempted, it can't be moved to another CPU,
+/// so this also permits access to per-CPU variables.
+#[must_use]
+pub struct PreemptGuard { _not_send: PhantomData<*const ()> }
+
+unsafe impl Pinned for PreemptGuard { }
+
+impl Drop for PreemptGuard {
+    fn drop(&mut self) {
+        let guard = interrupts::disable();
+        let preempt = CPU.with_mut(&guard, |cpu| {
+            cpu.preempt_count -= 1;
+            cpu.preempt_count == 0 && cpu.need_resched
+        });
+        if preempt { schedule(&guard) }
+    }
+}
@@ -0,0 +1,106 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Kernel thread stacks.
+//!
+//! We can't map pages once the kernel is up and running, since nothing
+//! outside of `kernel_init` has the page tables or the frame allocator. So,
+//! a fixed number of stacks are mapped at boot, starting at
+//! [`arch::THREAD_STACKS`](../../arch/constant.THREAD_STACKS.html), and
+//! handed out to threads as they're spawned. Each stack has an unmapped
+//! guard page below it.
+use memory::{PAGE_SIZE, Page, VAddr, VirtualPage};
+use paging::arch::ActivePageTable;
+use paging::stack::StackAllocator;
+use sos_alloc::FrameAllocator;
+use spin::Mutex;
+
+use arch;
+
+/// The maximum number of thread stacks, and therefore of threads.
+pub const MAX_STACKS: usize = 64;
+
+/// The number of pages in each stack, not counting its guard page.
+pub const STACK_PAGES: usize = 4;
+
+/// The size of each stack, in bytes.
+pub const STACK_SIZE: usize = STACK_PAGES * PAGE_SIZE as usize;
+
+struct Pool { /// the lowest address of each stack
+              bottoms: [usize; MAX_STACKS]
+            , /// bitmap of the stacks which aren't in use
+              free: u64
+            }
+
+static POOL: Mutex<Pool>
+    = Mutex::new(Pool { bottoms: [0; MAX_STACKS], free: 0 });
+
+/// A thread's stack.
+///
+/// The stack is returned to the pool when this is dropped, so it must not
+/// be dropped while it's still in use.
+#[derive(Debug)]
+pub struct Stack { index: usize
+                 , bottom: usize
+                 }
+
+impl Stack {
+    /// Returns the lowest address in this stack.
+    #[inline]
+    pub fn bottom(&self) -> VAddr { VAddr::from(self.bottom) }
+
+    /// Returns the address just past the top of this stack.
+    #[inline]
+    pub fn top(&self) -> VAddr { VAddr::from(self.bottom + STACK_SIZE) }
+}
+
+impl Drop for Stack {
+    fn drop(&mut self) {
+        POOL.lock().free |= 1 << self.index;
+    }
+}
+
+/// Map the thread stacks.
+///
+/// # Returns
+/// + `Ok(n)` with the number of stacks mapped, which may be fewer than
+///   `MAX_STACKS` if we run out of frames
+/// + `Err` if no stacks could be mapped.
+pub fn initialize<A>(page_table: &mut ActivePageTable, frames: &mut A)
+                    -> Result<usize, &'static str>
+where A: FrameAllocator {
+    let start = VirtualPage::containing(VAddr::from(arch::THREAD_STACKS));
+    let mut pages = start .. start + MAX_STACKS * (STACK_PAGES + 1);
+    let mut pool = POOL.lock();
+
+    for i in 0..MAX_STACKS {
+        match pages.allocate(page_table, frames, STACK_PAGES) {
+            Ok(stack) => {
+                pool.bottoms[i] = stack.start.as_usize();
+                pool.free |= 1 << i;
+            }
+          , Err(_) if i == 0 => return Err("Could not map any thread stacks")
+          , Err(_) => return Ok(i)
+        }
+    }
+    Ok(MAX_STACKS)
+}
+
+/// Take a stack from the pool.
+///
+/// # Returns
+/// + `Some(Stack)` if there was a free stack
+/// + `None` if every stack is in use.
+pub fn allocate() -> Option<Stack> {
+    let mut pool = POOL.lock();
+    if pool.free == 0 { return None }
+
+    let index = pool.free.trailing_zeros() as usize;
+    pool.free &= !(1 << index);
+    Some(Stack { index: index, bottom: pool.bottoms[index] })
+}
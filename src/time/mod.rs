@@ -0,0 +1,234 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Kernel timekeeping, timers, and sleeping.
+//!
+//! Time is measured in nanoseconds since the kernel's clock was chosen,
+//! early in boot. Kernel code can register callbacks to run at an absolute
+//! [`Instant`](struct.Instant.html) or after a relative delay; pending
+//! timers are checked every time the system timer ticks, so their
+//! resolution is one tick.
+//!
+//! The clock is chosen once, by [`initialize`](fn.initialize.html), and
+//! never changes afterwards, so every `Instant` is measured from the same
+//! epoch. Time never goes backwards, even on different CPUs.
+use core::{cmp, fmt, ops};
+
+use cpu::{IrqSpinlock, cpuid, pit};
+use cpu::timer::timestamp;
+use spin::Once;
+use sos_timers::Queue;
+
+pub use sos_timers::{Callback, TimerId};
+
+/// Number of nanoseconds in a microsecond.
+pub const NANOS_PER_MICRO: u64 = 1_000;
+/// Number of nanoseconds in a millisecond.
+pub const NANOS_PER_MILLI: u64 = 1_000_000;
+/// Number of nanoseconds in a second.
+pub const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// Pending kernel timers.
+///
+/// The timer interrupt takes this lock, so it's an `IrqSpinlock`.
+static TIMERS: IrqSpinlock<Queue> = IrqSpinlock::new(Queue::new());
+
+/// The clock which [`Instant::now`](struct.Instant.html#method.now) reads.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Clock { /// The system timer tick, which every CPU shares. Its
+                 /// resolution is one tick.
+                 Tick
+               , /// The timestamp counter, which is only used if it has
+                 /// been calibrated, and runs at a constant rate.
+                 Timestamp { /// the timestamp when the clock was chosen
+                             epoch: u64
+                           }
+               }
+
+/// The clock `Instant::now` reads, once it's been chosen.
+static CLOCK: Once<Clock> = Once::new();
+
+/// The latest time any CPU has read.
+///
+/// The CPUs' timestamp counters may not be quite in step, so each reading
+/// is clamped to this, to keep time from going backwards.
+static LATEST: IrqSpinlock<u64> = IrqSpinlock::new(0);
+
+/// Choose the clock which `Instant::now` reads.
+///
+/// This should be called once, on the boot CPU, after the timestamp
+/// counter is calibrated and before the system timer starts ticking. Until
+/// it's called, the time is always zero.
+pub fn initialize() -> Clock {
+    *CLOCK.call_once(|| {
+        let invariant = cpuid::has(cpuid::INVARIANT_TSC)
+                     && timestamp::frequency().is_some();
+        match timestamp::get_timestamp() {
+            Ok(epoch) if invariant => Clock::Timestamp { epoch: epoch }
+          , _ => Clock::Tick
+        }
+    })
+}
+
+/// A point in time, measured in nanoseconds since the clock was chosen.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Instant(u64);
+
+impl Instant {
+    /// Returns the current time.
+    ///
+    /// This reads the clock chosen by [`initialize`](fn.initialize.html),
+    /// and is zero before it's called.
+    pub fn now() -> Self {
+        let nanos = match CLOCK.try() {
+            Some(&Clock::Timestamp { epoch }) => {
+                // the counter was readable when it was chosen, and the
+                // kernel always runs in ring 0
+                let ticks = unsafe { timestamp::rtdsc() };
+                timestamp::to_nanos(ticks.saturating_sub(epoch))
+                    .unwrap_or(0)
+            }
+          , Some(&Clock::Tick) => pit::uptime_nanos()
+          , None => 0
+        };
+        let mut latest = LATEST.lock();
+        *latest = cmp::max(*latest, nanos);
+        Instant(*latest)
+    }
+
+    /// Returns an `Instant` `nanos` nanoseconds after the clock was chosen.
+    #[inline]
+    pub const fn from_nanos(nanos: u64) -> Self { Instant(nanos) }
+
+    /// Returns the number of nanoseconds between the clock being chosen
+    /// and this `Instant`.
+    #[inline]
+    pub fn as_nanos(&self) -> u64 { self.0 }
+
+    /// Returns the number of nanoseconds elapsed since this `Instant`.
+    #[inline]
+    pub fn elapsed(&self) -> u64 {
+        Instant::now().0.saturating_sub(self.0)
+    }
+
+    /// Returns true if this `Instant` is in the past.
+    #[inline]
+    pub fn has_passed(&self) -> bool { *self <= Instant::now() }
+}
+
+impl ops::Add<u64> for Instant {
+    type Output = Instant;
+    #[inline]
+    fn add(self, nanos: u64) -> Instant {
+        Instant(self.0.saturating_add(nanos))
+    }
+}
+
+impl ops::Sub for Instant {
+    type Output = u64;
+    #[inline]
+    fn sub(self, other: Instant) -> u64 { self.0.saturating_sub(other.0) }
+}
+
+impl fmt::Debug for Instant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!( f, "{}.{:09}s"
+              , self.0 / NANOS_PER_SEC, self.0 % NANOS_PER_SEC)
+    }
+}
+
+/// Register `callback` to be called with `data` at `deadline`.
+///
+/// The callback is run in interrupt context, so it must not block.
+pub fn add_timer_at(deadline: Instant, callback: Callback, data: usize)
+                    -> Result<TimerId, &'static str> {
+    TIMERS.lock().add(deadline.as_nanos(), callback, data)
+}
+
+/// Register `callback` to be called with `data` in `nanos` nanoseconds.
+///
+/// The callback is run in interrupt context, so it must not block.
+#[inline]
+pub fn add_timer(nanos: u64, callback: Callback, data: usize)
+                 -> Result<TimerId, &'static str> {
+    add_timer_at(Instant::now() + nanos, callback, data)
+}
+
+/// Cancel a pending timer.
+///
+/// Returns true if the timer was cancelled, or false if it had already fired.
+pub fn cancel_timer(id: TimerId) -> bool {
+    TIMERS.lock().cancel(id).is_some()
+}
+
+/// Fire all expired timers.
+///
+/// This is called from the system timer interrupt handler. The queue's lock is
+/// released before each callback runs, so callbacks may add new timers.
+pub fn tick() {
+    let now = Instant::now();
+    loop {
+        // bind the timer first, so the lock guard is dropped before it fires
+        let expired = TIMERS.lock().pop_expired(now.as_nanos());
+        match expired {
+            Some(timer) => timer.fire()
+          , None => break
+        }
+    }
+}
+
+/// Serializes busy waits on PIT channel 2, which only one CPU may use at
+/// a time.
+static PIT_CHANNEL_2: IrqSpinlock<()> = IrqSpinlock::new(());
+
+/// Spin until `deadline` has passed, without relying on interrupts.
+///
+/// If the clock is the timestamp counter, this polls it. Otherwise the
+/// clock only advances when the timer interrupt fires, so this counts down
+/// the time left on PIT channel 2 instead, with interrupts disabled.
+pub fn busy_wait_until(deadline: Instant) {
+    match CLOCK.try() {
+        Some(&Clock::Timestamp { .. }) =>
+            while !deadline.has_passed() {
+                unsafe { asm!("pause" :::: "volatile"); }
+            }
+      , _ => {
+            let nanos = deadline - Instant::now();
+            let _channel = PIT_CHANNEL_2.lock();
+            // channel 2 is only used for calibration otherwise, which is
+            // over before anything else could wait on it
+            unsafe {
+                pit::spin_wait_micros( (nanos + NANOS_PER_MICRO - 1)
+                                     / NANOS_PER_MICRO)
+            }
+        }
+    }
+}
+
+/// Spin for `nanos` nanoseconds, without relying on interrupts.
+#[inline]
+pub fn busy_wait(nanos: u64) {
+    busy_wait_until(Instant::now() + nanos)
+}
+
+/// Wait until `deadline` has passed, halting the CPU between timer ticks.
+///
+/// Interrupts must be enabled, or this will never return.
+pub fn sleep_until(deadline: Instant) {
+    while !deadline.has_passed() {
+        unsafe { asm!("hlt" :::: "volatile"); }
+    }
+}
+
+/// Wait for `nanos` nanoseconds, halting the CPU between timer ticks.
+///
+/// Interrupts must be enabled, or this will never return.
+#[inline]
+pub fn sleep(nanos: u64) {
+    sleep_until(Instant::now() + nanos)
+}
@@ -0,0 +1,281 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! User address spaces.
+//!
+//! Once the kernel is up, the frame allocator and the kernel's page tables
+//! are handed over to this module, so that address spaces can be created
+//! and mapped after `kernel_init` is done.
+//!
+//! Each [`AddressSpace`](struct.AddressSpace.html) has its own page tables.
+//! The user half of them, from
+//! [`USER_BASE`](../../paging/arch/constant.USER_BASE.html), is the user's
+//! own, and the rest is shared with the kernel. An address space is mapped
+//! by loading its page tables for a moment, so that the usual recursive
+//! mapping can be used. When an address space is dropped, every frame in
+//! its user half, and the page tables mapping them, are freed, and handed
+//! out again before any frames the allocator hasn't used yet.
+use alloc::vec::Vec;
+use core::{fmt, ptr};
+
+use cpu::smap::UserAccess;
+use memory::{FrameRange, Page, PAGE_SIZE, PhysicalPage, VAddr, VirtualPage};
+use paging::{Mapper, MapErr};
+use paging::arch::{ActivePageTable, InactivePageTable};
+use paging::arch::table::{EntryFlags, USER_ACCESSIBLE, WRITABLE};
+use paging::arch::temp::TempPage;
+use sos_alloc::{AllocResult, FrameAllocator};
+use sos_alloc::frame::mem_map::MemMapAllocator;
+use spin::{Mutex, Once};
+
+use thread;
+
+pub use paging::arch::{USER_BASE, is_user_address};
+
+/// Page number of the temporary page used to set up new page tables.
+///
+/// This is the page after the one `kernel_remap` uses.
+const TEMP_PAGE_NUMBER: usize = 0xfacadf;
+
+/// The frame allocator, once `kernel_init` is done with it.
+pub type Frames = MemMapAllocator<'static>;
+
+/// A frame allocator which hands out frames that have been freed before
+/// asking `Frames` for new ones.
+struct Recycler { frames: Frames
+                , /// frames which have been freed
+                  free: Vec<PhysicalPage>
+                }
+
+impl FrameAllocator for Recycler {
+    unsafe fn allocate(&mut self) -> AllocResult<PhysicalPage> {
+        match self.free.pop() {
+            Some(frame) => Ok(frame)
+          , None => self.frames.allocate()
+        }
+    }
+
+    unsafe fn deallocate(&mut self, frame: PhysicalPage) {
+        self.free.push(frame)
+    }
+
+    unsafe fn allocate_range(&mut self, num: usize)
+                            -> AllocResult<FrameRange> {
+        self.frames.allocate_range(num)
+    }
+
+    unsafe fn deallocate_range(&mut self, range: FrameRange) {
+        for frame in range { self.free.push(frame) }
+    }
+}
+
+/// Everything needed to map memory.
+struct Vm { frames: Recycler
+          , /// for setting up new page tables
+            temp_page: TempPage
+          }
+
+/// The memory manager, or `None` before [`initialize`](fn.initialize.html).
+///
+/// Mapping and unmapping pages can shoot down TLB entries, which waits for
+/// the other CPUs, so this must not be held with interrupts disabled.
+static VM: Mutex<Option<Vm>> = Mutex::new(None);
+
+/// The kernel's own page tables, which kernel threads use.
+///
+/// This is separate from `VM`, since the scheduler loads it with interrupts
+/// disabled.
+static KERNEL_TABLE: Once<InactivePageTable> = Once::new();
+
+/// Take over the frame allocator and the kernel's page tables.
+///
+/// This should be called at the end of `kernel_init`, with the page table
+/// that `kernel_remap` returned, once nothing else needs them.
+pub fn initialize(_page_table: ActivePageTable, mut frames: Frames)
+                 -> Result<(), &'static str> {
+    let mut vm = VM.lock();
+    if vm.is_some() {
+        return Err("The memory manager is already initialized")
+    }
+    KERNEL_TABLE.call_once(|| unsafe { InactivePageTable::current() });
+    let temp_page = TempPage::new(TEMP_PAGE_NUMBER, &mut frames);
+    *vm = Some(Vm { frames: Recycler { frames: frames, free: Vec::new() }
+                  , temp_page: temp_page });
+    Ok(())
+}
+
+/// Call `f` with the memory manager locked.
+///
+/// # Panics
+/// + If the memory manager isn't initialized.
+fn with_vm<F, R>(f: F) -> R
+where F: FnOnce(&mut Vm) -> R {
+    f(VM.lock().as_mut().expect("The memory manager isn't initialized"))
+}
+
+/// Load the kernel's own page tables on the current CPU.
+///
+/// The scheduler calls this when switching to a kernel thread.
+pub fn activate_kernel() {
+    if let Some(table) = KERNEL_TABLE.try() {
+        unsafe { table.activate(); }
+    }
+}
+
+/// Returns true if the `len` bytes starting at `addr` are all in the user
+/// half of the current address space, and mapped.
+///
+/// This is for checking pointers from user code before the kernel follows
+/// them.
+pub fn is_mapped_user(addr: usize, len: usize) -> bool {
+    let end = match addr.checked_add(len) {
+        Some(end) => end
+      , None => return false
+    };
+    if len == 0 { return true }
+    if !is_user_address(addr) || !is_user_address(end - 1) { return false }
+    let table = unsafe { ActivePageTable::new() };
+    let mut pages = VirtualPage::containing(VAddr::from(addr))
+                 .. VirtualPage::containing(VAddr::from(end - 1)) + 1;
+    pages.all(|page| table.is_mapped(&page))
+}
+
+/// Returns a description of why mapping failed.
+fn map_err(err: MapErr) -> &'static str {
+    match err {
+        MapErr::Alloc { .. } => "Out of physical memory"
+      , MapErr::Other { cause, .. } | MapErr::NoPage { cause, .. } => cause
+      , MapErr::TableNotFound { .. } => "A page table is missing"
+      , MapErr::AlreadyInUse { .. } => "The page is already mapped"
+    }
+}
+
+/// A user address space.
+///
+/// Dropping an address space frees every frame mapped into its user half.
+/// That locks the memory manager, so it mustn't be dropped with interrupts
+/// disabled, and it mustn't be loaded on any CPU.
+pub struct AddressSpace { table: InactivePageTable }
+
+impl AddressSpace {
+    /// Returns a new address space, whose user half is empty.
+    pub fn new() -> Result<Self, &'static str> {
+        let _preempt = thread::disable_preemption();
+        with_vm(|vm| {
+            let frame = unsafe { vm.frames.allocate() }
+                .map_err(|_| "Out of physical memory")?;
+            let mut active = unsafe { ActivePageTable::new() };
+            InactivePageTable::new_user(frame, &mut active, &mut vm.temp_page)
+                .map(|table| AddressSpace { table: table })
+                .map_err(map_err)
+        })
+    }
+
+    /// Load this address space on the current CPU.
+    ///
+    /// The scheduler calls this when switching to a thread in this address
+    /// space.
+    ///
+    /// # Safety
+    /// + The running thread must be pinned to the current CPU, or the next
+    ///   context switch will load its own address space again.
+    #[inline]
+    pub unsafe fn activate(&self) { self.table.activate() }
+
+    /// Call `f` with this address space loaded, and with the kernel
+    /// permitted to access its user pages.
+    ///
+    /// Whatever was loaded before is loaded again afterwards. The running
+    /// thread can't be preempted while `f` runs.
+    pub fn with<F, R>(&self, f: F) -> R
+    where F: FnOnce() -> R {
+        let _preempt = thread::disable_preemption();
+        let previous = unsafe { InactivePageTable::current() };
+        unsafe { self.table.activate(); }
+        let result = {
+            let _access = UserAccess::new();
+            f()
+        };
+        unsafe { previous.activate(); }
+        result
+    }
+
+    /// Map `len` bytes starting at `start` to new, zeroed frames, with the
+    /// given flags.
+    ///
+    /// The range is rounded out to whole pages, and the pages are always
+    /// user-accessible.
+    ///
+    /// # Returns
+    /// + `Err` if any of the range isn't in the user half of the address
+    ///   space, or we run out of frames, or any of it is already mapped.
+    #[inline]
+    pub fn map(&self, start: VAddr, len: usize, flags: EntryFlags)
+              -> Result<(), &'static str> {
+        self.map_with(start, len, flags, || Ok(()))
+    }
+
+    /// Map `len` bytes starting at `start` to new, zeroed frames, and call
+    /// `init` to fill them in before giving them the flags `flags`.
+    ///
+    /// `init` is called as by [`with`](#method.with), and the pages are
+    /// writable while it runs, even if `flags` doesn't make them writable.
+    pub fn map_with<F>( &self, start: VAddr, len: usize, flags: EntryFlags
+                      , init: F)
+                      -> Result<(), &'static str>
+    where F: FnOnce() -> Result<(), &'static str> {
+        let end = start.as_usize().checked_add(len)
+                       .ok_or("The range wraps around")?;
+        if len == 0 { return Ok(()) }
+        if !is_user_address(start.as_usize()) || !is_user_address(end - 1) {
+            return Err("The range isn't in user memory")
+        }
+        let pages = VirtualPage::containing(start)
+                 .. VirtualPage::containing(VAddr::from(end - 1)) + 1;
+        let flags = flags | USER_ACCESSIBLE;
+
+        let _preempt = thread::disable_preemption();
+        with_vm(|vm| self.with(|| {
+            let mut active = unsafe { ActivePageTable::new() };
+            for page in pages.clone() {
+                active.map_to_any(page, flags | WRITABLE, &mut vm.frames)
+                      .map_err(map_err)?;
+                unsafe {
+                    let base = page.base().as_mut_ptr::<u8>();
+                    ptr::write_bytes(base, 0, PAGE_SIZE as usize);
+                }
+            }
+            init()?;
+            if !flags.contains(WRITABLE) {
+                for page in pages {
+                    active.update_flags(page, flags).map_err(map_err)?;
+                }
+            }
+            Ok(())
+        }))
+    }
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        let _preempt = thread::disable_preemption();
+        with_vm(|vm| {
+            self.with(|| unsafe {
+                ActivePageTable::new().free_user(&mut vm.frames)
+            });
+            unsafe { self.table.free(&mut vm.frames) }
+        });
+        trace!("Freed {:?}", self);
+    }
+}
+
+impl fmt::Debug for AddressSpace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "AddressSpace({:?})", self.table.frame())
+    }
+}
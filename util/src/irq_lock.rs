@@ -0,0 +1,268 @@
+//
+//  SOS: the Stupid Operating System
+//  by Eliza Weisman (eliza@elizas.website)
+//
+//  Copyright (c) 2015-2017 Eliza Weisman
+//  Released under the terms of the MIT license. See `LICENSE` in the root
+//  directory of this repository for more information.
+//
+//! Spinlocks which disable interrupts while they're held.
+//!
+//! If an interrupt handler takes a `spin::Mutex` which the code it
+//! interrupted holds, the handler spins forever, since the holder can't run
+//! until the handler returns. An `IrqSpinlock` saves the interrupt flag and
+//! disables interrupts on the current CPU before taking the lock, and puts
+//! the interrupt flag back once the lock is released, so this can't happen.
+//!
+//! This lives here rather than in `cpu` so that the crates `cpu` depends on,
+//! like `vga`, can use it. It's re-exported as `cpu::IrqSpinlock`.
+//!
+//! # Debugging
+//! An interrupt handler on another CPU may still take the lock, but a
+//! handler which runs while interrupts are disabled can't, so with
+//! interrupts disabled the only way to deadlock on an `IrqSpinlock` is to
+//! lock it again on the CPU already holding it. When debug assertions are
+//! enabled, each lock remembers which CPU holds it, and locking it again on
+//! that CPU panics rather than hanging.
+//!
+//! The CPU number is read from the current CPU's per-CPU block, at
+//! `CPU_NUMBER` from `%gs`, once `cpu::percpu` calls
+//! [`use_per_cpu`](fn.use_per_cpu.html). Until then, only the BSP is
+//! running, so every lock is taken by CPU 0.
+use core::cell::UnsafeCell;
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::fmt;
+
+/// The interrupt enable flag in `EFLAGS`/`RFLAGS`.
+const IF: usize = 1 << 9;
+
+/// Marks an `IrqSpinlock` as held by no CPU.
+const NO_HOLDER: usize = !0;
+
+/// Offset from `%gs` of the current CPU's number, in its per-CPU block.
+pub const CPU_NUMBER: usize = 8;
+
+/// Whether the CPUs' per-CPU blocks have been set up.
+static PER_CPU: AtomicBool = AtomicBool::new(false);
+
+/// Read the current CPU's number from its per-CPU block from now on.
+///
+/// # Safety
+/// + The calling CPU's `%gs` must point at its per-CPU block, with its CPU
+///   number at `CPU_NUMBER`.
+/// + Every other CPU must set up its per-CPU block before it takes an
+///   `IrqSpinlock`.
+pub unsafe fn use_per_cpu() {
+    PER_CPU.store(true, Ordering::Relaxed);
+}
+
+/// A spinlock which keeps interrupts disabled on the CPU holding it.
+pub struct IrqSpinlock<T: ?Sized> { locked: AtomicBool
+                                  , /// the number of the CPU holding
+                                    /// the lock, if debug assertions
+                                    /// are on
+                                    holder: AtomicUsize
+                                  , data: UnsafeCell<T>
+                                  }
+
+unsafe impl<T: ?Sized + Send> Send for IrqSpinlock<T> { }
+unsafe impl<T: ?Sized + Send> Sync for IrqSpinlock<T> { }
+
+/// A held `IrqSpinlock`, which releases the lock and restores the interrupt
+/// flag when dropped.
+///
+/// This can't be sent to another thread, since it restores the interrupt
+/// flag of the CPU which locked it.
+#[must_use]
+pub struct IrqSpinlockGuard<'a, T: ?Sized + 'a> {
+    lock: &'a IrqSpinlock<T>
+  , were_enabled: bool
+  , _not_send: PhantomData<*const ()>
+}
+
+impl<T> IrqSpinlock<T> {
+    /// Returns a new, unlocked spinlock protecting `data`.
+    pub const fn new(data: T) -> Self {
+        IrqSpinlock { locked: AtomicBool::new(false)
+                    , holder: AtomicUsize::new(NO_HOLDER)
+                    , data: UnsafeCell::new(data)
+                    }
+    }
+
+    /// Consumes the lock, returning the data it protected.
+    #[inline]
+    pub fn into_inner(self) -> T {
+        unsafe { self.data.into_inner() }
+    }
+}
+
+impl<T: ?Sized> IrqSpinlock<T> {
+    /// Disable interrupts on this CPU and take the lock, spinning until it's
+    /// available.
+    ///
+    /// # Panics
+    /// + If debug assertions are enabled, and this CPU already holds the
+    ///   lock.
+    pub fn lock(&self) -> IrqSpinlockGuard<T> {
+        let were_enabled = disable_interrupts();
+        while !self.try_acquire() {
+            self.check_reentrant();
+            while self.locked.load(Ordering::Relaxed) {
+                unsafe { asm!("pause" :::: "volatile"); }
+            }
+        }
+        self.set_holder();
+        IrqSpinlockGuard { lock: self
+                         , were_enabled: were_enabled
+                         , _not_send: PhantomData
+                         }
+    }
+
+    /// Take the lock if it's available, disabling interrupts on this CPU
+    /// for as long as it's held.
+    ///
+    /// If the lock is held, interrupts are left as they were.
+    pub fn try_lock(&self) -> Option<IrqSpinlockGuard<T>> {
+        let were_enabled = disable_interrupts();
+        if self.try_acquire() {
+            self.set_holder();
+            Some(IrqSpinlockGuard { lock: self
+                                  , were_enabled: were_enabled
+                                  , _not_send: PhantomData
+                                  })
+        } else {
+            restore_interrupts(were_enabled);
+            None
+        }
+    }
+
+    /// Returns true if the lock is held.
+    #[inline]
+    pub fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+    }
+
+    /// Forcibly release the lock.
+    ///
+    /// This is for the panic handler and the like, which may have to write
+    /// to the console no matter who was holding it. Interrupts are left as
+    /// they are.
+    ///
+    /// # Safety
+    /// + Whoever held the lock may still be using the data it protects.
+    pub unsafe fn force_unlock(&self) {
+        self.holder.store(NO_HOLDER, Ordering::Relaxed);
+        self.locked.store(false, Ordering::Release);
+    }
+
+    /// Borrows the protected data mutably.
+    ///
+    /// Since this borrows the lock mutably, no locking is needed.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.data.get() }
+    }
+
+    #[inline]
+    fn try_acquire(&self) -> bool {
+        !self.locked.compare_and_swap(false, true, Ordering::Acquire)
+    }
+
+    #[inline]
+    fn set_holder(&self) {
+        if cfg!(debug_assertions) {
+            self.holder.store(cpu_id(), Ordering::Relaxed);
+        }
+    }
+
+    /// Panic if this CPU is the one holding the lock, since it will never
+    /// be released.
+    #[inline]
+    fn check_reentrant(&self) {
+        if cfg!(debug_assertions) {
+            let id = cpu_id();
+            assert!( self.holder.load(Ordering::Relaxed) != id
+                   , "IrqSpinlock locked again on CPU {}, which holds it"
+                   , id);
+        }
+    }
+}
+
+impl<T: Default> Default for IrqSpinlock<T> {
+    #[inline] fn default() -> Self { IrqSpinlock::new(T::default()) }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for IrqSpinlock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.try_lock() {
+            Some(guard) => write!(f, "IrqSpinlock {{ data: {:?} }}", &*guard)
+          , None => f.pad("IrqSpinlock { <locked> }")
+        }
+    }
+}
+
+impl<'a, T: ?Sized> Deref for IrqSpinlockGuard<'a, T> {
+    type Target = T;
+    #[inline] fn deref(&self) -> &T { unsafe { &*self.lock.data.get() } }
+}
+
+impl<'a, T: ?Sized> DerefMut for IrqSpinlockGuard<'a, T> {
+    #[inline] fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> Drop for IrqSpinlockGuard<'a, T> {
+    #[inline]
+    fn drop(&mut self) {
+        self.lock.holder.store(NO_HOLDER, Ordering::Relaxed);
+        self.lock.locked.store(false, Ordering::Release);
+        restore_interrupts(self.were_enabled);
+    }
+}
+
+/// Disable interrupts on this CPU, returning whether they were enabled.
+#[inline]
+fn disable_interrupts() -> bool {
+    let flags: usize;
+    unsafe {
+        asm!( "pushf
+               pop $0
+               cli"
+            : "=r"(flags)
+            :: "memory"
+            : "volatile" );
+    }
+    flags & IF != 0
+}
+
+/// Re-enable interrupts on this CPU, if `were_enabled`.
+#[inline]
+fn restore_interrupts(were_enabled: bool) {
+    if were_enabled {
+        unsafe { asm!("sti" ::: "memory" : "volatile"); }
+    }
+}
+
+/// Returns the current CPU's number.
+#[cfg(target_arch = "x86_64")]
+#[inline]
+fn cpu_id() -> usize {
+    if !PER_CPU.load(Ordering::Relaxed) { return 0 }
+    let cpu: usize;
+    unsafe {
+        asm!( "mov $0, gs:[8]"
+            : "=r"(cpu)
+            ::: "intel");
+    }
+    cpu
+}
+
+/// Returns the current CPU's number.
+///
+/// The 32-bit kernel only runs on the BSP.
+#[cfg(target_arch = "x86")]
+#[inline]
+fn cpu_id() -> usize { 0 }
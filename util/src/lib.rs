@@ -11,6 +11,7 @@
 #![no_std]
 
 #![feature(step_trait)]
+#![feature(asm, const_fn)]
 // #[cfg(not(test))] extern crate vga;
 
 use core::{fmt, ops};
@@ -19,6 +20,8 @@ use core::iter::Step;
 // use core::num::One;
 
 pub mod io;
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+pub mod irq_lock;
 
 #[macro_use] pub mod macros;
 
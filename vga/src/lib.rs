@@ -21,13 +21,12 @@
 #![feature(ptr_internals)]
 #![feature( const_fn
           , const_unique_new )]
-#![cfg_attr(feature = "system_term", feature(lang_items))]
 #![cfg_attr(feature = "clippy", feature(plugin))]
 #![cfg_attr(feature = "clippy", plugin(clippy))]
 #![no_std]
 
 #[cfg(feature = "system_term")]
-extern crate spin;
+extern crate util;
 
 
 
@@ -36,14 +35,10 @@ use core::fmt::{Write, Result};
 // use core::ptr::Unique;
 
 #[cfg(feature = "system_term")]
-use spin::Mutex;
+use util::irq_lock::IrqSpinlock;
 #[cfg(feature = "kinfo")]
 #[macro_use] extern crate log;
 
-#[cfg(all( feature = "system_term"
-          , not(test)))]
-pub mod panic;
-
 /// Macro for printing to the standard output.
 ///
 /// Equivalent to the `println!` macro except that a newline is not printed at
@@ -86,10 +81,13 @@ pub mod status;
 
 
 /// The system's global VGA terminal
+///
+/// This is an `IrqSpinlock`, so that interrupt handlers (such as the
+/// exception printer) can write to it without deadlocking.
 /// TODO: should this live in the kernel instead?
 #[cfg(feature = "system_term")]
-pub static CONSOLE: Mutex<Terminal>
-    = Mutex::new(unsafe { Terminal::new(
+pub static CONSOLE: IrqSpinlock<Terminal>
+    = IrqSpinlock::new(unsafe { Terminal::new(
          Palette::new(Color::LightGrey, Color::Black )
        , 0xB8000
     )});